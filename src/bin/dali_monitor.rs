@@ -1,10 +1,15 @@
-use dali::drivers::driver::{DaliBusEvent, DaliBusEventType};
-use dali::utils::decode;
+use dali::utils::bus_stats::BusStats;
+use dali::utils::capture::{CaptureInfo, CaptureWriter};
+use dali::utils::frame_filter::Filter;
+use dali::utils::frame_printer::FramePrinter;
+use dali::utils::pcapng::PcapngWriter;
 use dali_tools as dali;
-use std::time::Instant;
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Duration;
 
 extern crate clap;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command, value_parser};
 
 #[tokio::main]
 async fn main() {
@@ -21,9 +26,54 @@ async fn main() {
                 .default_value("default")
                 .help("Select DALI-device"),
         )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print one JSON object per frame"),
+        )
+        .arg(
+            Arg::new("capture")
+                .long("capture")
+                .value_name("FILE")
+                .help("Record all bus events to a capture file"),
+        )
+        .arg(
+            Arg::new("pcapng")
+                .long("pcapng")
+                .value_name("FILE")
+                .help("Record all bus events to a pcapng file for Wireshark"),
+        )
+        .arg(
+            Arg::new("site")
+                .long("site")
+                .default_value("")
+                .help("Site description stored in the capture or pcapng file"),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("EXPR")
+                .help("Only print frames matching the expression, e.g. \"group 3 or type event\""),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .value_name("SECONDS")
+                .num_args(0..=1)
+                .default_missing_value("60")
+                .value_parser(value_parser!(u64))
+                .help("Print a summary of the bus traffic periodically instead of each frame"),
+        )
+        .arg(
+            Arg::new("stats_window")
+                .long("stats-window")
+                .value_name("SECONDS")
+                .value_parser(value_parser!(u64))
+                .help("Length of the traffic window summarized, default is the --stats interval"),
+        )
         .get_matches();
 
-    let mut last_ts = Instant::now();
     let device_name = matches.get_one::<String>("DEVICE").unwrap();
     let mut driver = match dali::drivers::open(device_name) {
         Ok(d) => d,
@@ -32,33 +82,102 @@ async fn main() {
             return;
         }
     };
-    let mut decoder = decode::DecoderState::new();
+    let filter = match matches
+        .get_one::<String>("filter")
+        .map(|f| f.parse::<Filter>())
+    {
+        Some(Ok(filter)) => Some(filter),
+        Some(Err(e)) => {
+            eprintln!("Invalid filter: {}", e);
+            return;
+        }
+        None => None,
+    };
+    let mut capture = match matches.get_one::<String>("capture") {
+        Some(path) => {
+            let info = CaptureInfo::new(device_name, matches.get_one::<String>("site").unwrap());
+            match File::create(path).and_then(|file| {
+                CaptureWriter::new(BufWriter::new(file), &info, driver.current_timestamp())
+            }) {
+                Ok(capture) => Some(capture),
+                Err(e) => {
+                    eprintln!("Failed to create capture file '{}': {}", path, e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let mut pcapng = match matches.get_one::<String>("pcapng") {
+        Some(path) => {
+            let site = matches.get_one::<String>("site").unwrap();
+            match File::create(path).and_then(|file| {
+                PcapngWriter::new(
+                    BufWriter::new(file),
+                    device_name,
+                    site,
+                    driver.current_timestamp(),
+                )
+            }) {
+                Ok(pcapng) => Some(pcapng),
+                Err(e) => {
+                    eprintln!("Failed to create pcapng file '{}': {}", path, e);
+                    return;
+                }
+            }
+        }
+        None => None,
+    };
+    let stats_interval = matches
+        .get_one::<u64>("stats")
+        .map(|s| Duration::from_secs(*s));
+    let mut stats = stats_interval.map(|interval| {
+        let window = matches
+            .get_one::<u64>("stats_window")
+            .map(|s| Duration::from_secs(*s))
+            .unwrap_or(interval);
+        BusStats::new(window)
+    });
+    let interval = stats_interval.unwrap_or(Duration::from_secs(60));
+    let mut stats_timer =
+        tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    let mut printer = FramePrinter::new(matches.get_flag("json"), filter);
+    printer.set_start(driver.current_timestamp());
     loop {
-        match driver.next_bus_event().await {
-            Ok(DaliBusEvent {
-                timestamp,
-                event_type,
-                ..
-            }) => {
-                print!("{:5}:", timestamp.duration_since(last_ts).as_millis());
-                last_ts = timestamp;
-                match event_type {
-                    DaliBusEventType::Frame24(ref pkt) => {
-                        for b in pkt {
-                            print!(" {:02x}", b);
-                        }
-                        print!(" ");
-                        println!("{}", decoder.decode_packet(pkt))
-                    }
-                    DaliBusEventType::Frame16(ref pkt) => {
-                        for b in pkt {
-                            print!(" {:02x}", b);
-                        }
-                        print!("    ");
-                        println!("{}", decoder.decode_packet(pkt))
+        let event = tokio::select! {
+            event = driver.next_bus_event() => Some(event),
+            _ = stats_timer.tick(), if stats.is_some() => None,
+        };
+        let Some(event) = event else {
+            if let Some(stats) = &mut stats {
+                println!("{}", stats.summary(driver.current_timestamp()));
+            }
+            continue;
+        };
+        match event {
+            Ok(event) => {
+                if let Some(capture) = &mut capture
+                    && let Err(e) = capture.write_event(&event).and_then(|_| capture.flush())
+                {
+                    eprintln!("Failed to write to capture file: {}", e);
+                    break;
+                }
+                let decoded = printer.decode(&event);
+                if let Some(pcapng) = &mut pcapng {
+                    let comment = decoded.frame.as_ref().map(|frame| frame.to_string());
+                    if let Err(e) = pcapng
+                        .write_event(&event, comment.as_deref())
+                        .and_then(|_| pcapng.flush())
+                    {
+                        eprintln!("Failed to write to pcapng file: {}", e);
+                        break;
                     }
-                    _ => println!("{:?}", event_type),
                 }
+                if let Some(stats) = &mut stats {
+                    stats.update(&event);
+                    continue;
+                }
+                printer.print(&event, &decoded);
             }
             Err(e) => {
                 eprintln!("Failed to wait for event: {}", e);
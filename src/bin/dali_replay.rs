@@ -0,0 +1,136 @@
+use dali::utils::capture::{CaptureReader, CapturedEvent};
+use dali::utils::frame_filter::Filter;
+use dali::utils::frame_printer::FramePrinter;
+use dali_tools as dali;
+use std::fs::File;
+use std::io::BufReader;
+use std::time::Instant;
+
+extern crate clap;
+use clap::{Arg, ArgAction, Command};
+
+type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn read_capture(path: &str) -> DynResult<Vec<CapturedEvent>> {
+    let reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+    let info = reader.info();
+    eprintln!(
+        "Captured with driver '{}' at site '{}'",
+        info.driver, info.site
+    );
+    let events = reader.collect::<Result<Vec<_>, _>>()?;
+    Ok(events)
+}
+
+fn decode_capture(events: &[CapturedEvent], printer: &mut FramePrinter) {
+    let start = Instant::now();
+    for event in events {
+        printer.print_event(&event.to_bus_event(start));
+    }
+}
+
+// Send the forward frames of the capture to a simulated installation and
+// print the resulting bus traffic. Answers are generated by the simulated
+// gears, not taken from the capture.
+#[cfg(feature = "simulator")]
+async fn replay_simulated(
+    installation: &str,
+    events: &[CapturedEvent],
+    printer: &mut FramePrinter,
+) -> DynResult<()> {
+    use dali::drivers::driver::{DaliBusEventType, DaliDriver};
+    use dali::drivers::simulator::device::DaliSimEvent;
+    use dali::drivers::simulator::installation::Installation;
+    use dali::drivers::simulator::simulator::DaliBusSim;
+    use dali::drivers::simulator::simulator_driver::DaliSimDriver;
+    use futures::FutureExt;
+    use std::path::Path;
+
+    let installation = Installation::read(Path::new(installation))?;
+    let sim = DaliBusSim::with_real_time(false).await?;
+    sim.load_installation(&installation).await?;
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await?;
+    let start = sim.current_time();
+    for event in events {
+        if matches!(
+            event.event_type,
+            DaliBusEventType::Frame8(_)
+                | DaliBusEventType::FramingError
+                | DaliBusEventType::Overrun
+        ) {
+            continue;
+        }
+        sim.add_event(DaliSimEvent {
+            source_id: 0,
+            timestamp: start + event.offset,
+            event_type: event.event_type.clone(),
+        })
+        .await?;
+    }
+    // In virtual time the next event is ready immediately until all
+    // events have been dispatched
+    while let Some(event) = driver.next_bus_event().now_or_never() {
+        printer.print_event(&event?);
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    let command = Command::new("dali_replay")
+        .about("Decode a capture file recorded with dali_monitor.")
+        .arg(Arg::new("FILE").required(true).help("Capture file"))
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .help("Print one JSON object per frame"),
+        )
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .value_name("EXPR")
+                .help("Only print frames matching the expression, e.g. \"group 3 or type event\""),
+        );
+    #[cfg(feature = "simulator")]
+    let command = command.arg(
+        Arg::new("simulate")
+            .long("simulate")
+            .value_name("INSTALLATION")
+            .help("Replay the forward frames into a simulated installation"),
+    );
+    let matches = command.get_matches();
+
+    let path = matches.get_one::<String>("FILE").unwrap();
+    let events = match read_capture(path) {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("Failed to read capture '{}': {}", path, e);
+            return;
+        }
+    };
+    let filter = match matches
+        .get_one::<String>("filter")
+        .map(|f| f.parse::<Filter>())
+    {
+        Some(Ok(filter)) => Some(filter),
+        Some(Err(e)) => {
+            eprintln!("Invalid filter: {}", e);
+            return;
+        }
+        None => None,
+    };
+    let mut printer = FramePrinter::new(matches.get_flag("json"), filter);
+    #[cfg(feature = "simulator")]
+    if let Some(installation) = matches.get_one::<String>("simulate") {
+        if let Err(e) = replay_simulated(installation, &events, &mut printer).await {
+            eprintln!("Replay failed: {}", e);
+        }
+        printer.finish();
+        return;
+    }
+    decode_capture(&events, &mut printer);
+    printer.finish();
+}
@@ -0,0 +1,125 @@
+use dali::drivers::driver::{DaliDriver, OpenError};
+use dali::utils::snapshot::{self, BusSnapshot, GearDiff};
+use dali_tools as dali;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+extern crate clap;
+use clap::{Arg, ArgMatches, Command};
+
+type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn load(path: &str) -> DynResult<BusSnapshot> {
+    BusSnapshot::load(BufReader::new(File::open(path)?))
+}
+
+fn print_diffs(diffs: &[GearDiff]) {
+    if diffs.is_empty() {
+        println!("No differences");
+    }
+    for diff in diffs {
+        print!("{}", diff);
+    }
+}
+
+async fn backup(driver: &mut dyn DaliDriver, args: &ArgMatches) -> DynResult<()> {
+    let path = args.get_one::<String>("FILE").unwrap();
+    let site = args.get_one::<String>("site").map_or("", |s| s.as_str());
+    let snapshot = snapshot::read_snapshot(driver, site).await?;
+    snapshot.save(BufWriter::new(File::create(path)?))?;
+    for gear in &snapshot.gear {
+        println!("{}", gear);
+    }
+    println!("Saved {} gear to {}", snapshot.gear.len(), path);
+    Ok(())
+}
+
+async fn diff(driver: &mut dyn DaliDriver, args: &ArgMatches) -> DynResult<()> {
+    let saved = load(args.get_one::<String>("FILE").unwrap())?;
+    let live = snapshot::read_snapshot(driver, &saved.site).await?;
+    print_diffs(&snapshot::diff(&saved, &live));
+    Ok(())
+}
+
+async fn restore(driver: &mut dyn DaliDriver, args: &ArgMatches) -> DynResult<()> {
+    let saved = load(args.get_one::<String>("FILE").unwrap())?;
+    let diffs = snapshot::restore(driver, &saved).await?;
+    let restored = diffs
+        .iter()
+        .filter(|d| matches!(d, GearDiff::Changed { settings, .. } if !settings.is_empty()))
+        .count();
+    println!("Restored settings of {} gear", restored);
+    // Verify by reading back
+    let live = snapshot::read_snapshot(driver, &saved.site).await?;
+    let remaining = snapshot::diff(&saved, &live);
+    println!("Remaining differences:");
+    print_diffs(&remaining);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    if let Err(e) = dali::drivers::init() {
+        eprintln!("Failed to initialize DALI drivers: {}", e);
+    }
+    let file_arg = || Arg::new("FILE").required(true).help("Snapshot file");
+    let matches = Command::new("dali_snapshot")
+        .about("Backup and restore the configuration of all control gear on a bus.")
+        .arg(
+            Arg::new("DEVICE")
+                .short('d')
+                .long("device")
+                .default_value("default")
+                .help("Select DALI-device"),
+        )
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("backup")
+                .about("Save the configuration of all gear to a file")
+                .arg(file_arg())
+                .arg(
+                    Arg::new("site")
+                        .long("site")
+                        .help("Text describing the site or installation"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare a saved configuration to the bus")
+                .arg(file_arg()),
+        )
+        .subcommand(
+            Command::new("restore")
+                .about(
+                    "Restore groups, scenes, levels and fade of all gear found by random address \
+                     or GTIN and serial number",
+                )
+                .arg(file_arg()),
+        )
+        .get_matches();
+
+    let device_name = matches.get_one::<String>("DEVICE").unwrap();
+    let mut driver = match dali::drivers::open(device_name) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Failed to open DALI device: {}", e);
+            if let OpenError::NotFound = e {
+                eprintln!("Available drivers:");
+                for name in dali::drivers::driver_names() {
+                    eprintln!("  {}", name);
+                }
+            }
+            return;
+        }
+    };
+    let res = match matches.subcommand() {
+        Some(("backup", args)) => backup(driver.as_mut(), args).await,
+        Some(("diff", args)) => diff(driver.as_mut(), args).await,
+        Some(("restore", args)) => restore(driver.as_mut(), args).await,
+        _ => unreachable!(),
+    };
+    if let Err(e) = res {
+        eprintln!("{}", e);
+    }
+}
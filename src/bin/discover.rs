@@ -4,47 +4,60 @@ use dali::drivers::send_flags::PRIORITY_1;
 use dali::gear::commands_102::Commands102;
 use dali::utils::address_assignment::{clear_short_address, program_short_address};
 use dali::utils::discover::{self, Discovered};
+use dali::utils::output::{FORMAT_NAMES, Format, RecordWriter};
 use dali_tools as dali;
 use dali_tools::common::commands::Commands;
 use dali_tools::common::driver_commands::DriverCommands;
 use dali_tools::drivers::driver::DaliSendResult;
 use dali_tools::gear::address::Short;
 use dali_tools::utils::address_set::AddressSet;
+use std::io;
 
 extern crate clap;
 use clap::{Arg, Command};
 
-async fn perform_discovery<C>(commands: &mut C, clear_conflicts: bool, allocate: bool)
-where
+async fn perform_discovery<C>(
+    commands: &mut C,
+    clear_conflicts: bool,
+    allocate: bool,
+    format: Option<Format>,
+) where
     C: Commands<Error = DaliSendResult>,
 {
     let mut allocated_addrs = AddressSet::new();
     let mut short_conflicts = Vec::new();
     let mut unallocated = Vec::new();
+    let mut output = format.map(|f| RecordWriter::new(io::stdout(), f));
     let mut found = async |device: Discovered| {
-        println!(
-            "Long: {}, Short: {} {}{}",
-            if let Some(long) = device.long {
-                long.to_string()
-            } else {
-                "None".to_string()
-            },
-            if let Some(short) = device.short {
-                short.to_string()
-            } else {
-                "None".to_string()
-            },
-            if device.short_conflict {
-                ", Short address conflict"
-            } else {
-                ""
-            },
-            if device.long_conflict {
-                ", Long address conflict"
-            } else {
-                ""
-            },
-        );
+        if let Some(output) = &mut output {
+            if let Err(e) = output.write(&device) {
+                eprintln!("Failed to write output: {}", e);
+            }
+        } else {
+            println!(
+                "Long: {}, Short: {} {}{}",
+                if let Some(long) = device.long {
+                    long.to_string()
+                } else {
+                    "None".to_string()
+                },
+                if let Some(short) = device.short {
+                    short.to_string()
+                } else {
+                    "None".to_string()
+                },
+                if device.short_conflict {
+                    ", Short address conflict"
+                } else {
+                    ""
+                },
+                if device.long_conflict {
+                    ", Long address conflict"
+                } else {
+                    ""
+                },
+            );
+        }
         if device.short_conflict {
             short_conflicts.push(device.clone());
         }
@@ -129,12 +142,20 @@ async fn main() {
                 .action(clap::ArgAction::SetTrue)
                 .help("Discover control devices"),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(FORMAT_NAMES)
+                .default_value("text")
+                .help("Output format. Short addresses are 0-63 in JSON and CSV."),
+        )
         .get_matches();
 
     let device_name = matches.get_one::<String>("DEVICE").unwrap();
     let clear_conflicts = *matches.get_one::<bool>("clear_conflicts").unwrap();
     let allocate = *matches.get_one::<bool>("allocate").unwrap();
     let control = *matches.get_one::<bool>("control").unwrap();
+    let format = matches.get_one::<String>("format").unwrap().parse().ok();
     let mut driver = match dali::drivers::open(device_name) {
         Ok(d) => d,
         Err(e) => {
@@ -151,9 +172,9 @@ async fn main() {
 
     if control {
         let mut commands = Commands103::from_driver(driver.as_mut(), PRIORITY_1);
-        perform_discovery(&mut commands, clear_conflicts, allocate).await;
+        perform_discovery(&mut commands, clear_conflicts, allocate, format).await;
     } else {
         let mut commands = Commands102::from_driver(driver.as_mut(), PRIORITY_1);
-        perform_discovery(&mut commands, clear_conflicts, allocate).await;
+        perform_discovery(&mut commands, clear_conflicts, allocate, format).await;
     }
 }
@@ -2,7 +2,19 @@ extern crate futures;
 extern crate libusb_async;
 extern crate tokio;
 
+use clap::{Arg, Command};
+use dali_tools::utils::output::{FORMAT_NAMES, RecordWriter};
 use libusb_async::{Context, DeviceHandle};
+use serde_derive::Serialize;
+use std::io;
+
+/// A HID report read from the device
+#[derive(Serialize)]
+struct Report {
+    len: usize,
+    /// Report payload as hex
+    data: String,
+}
 
 fn send_hid_report(dev: &DeviceHandle, data: &[u8]) -> libusb_async::TransferFuture {
     let mut trans = dev.alloc_transfer(0).unwrap();
@@ -18,6 +30,32 @@ fn read_hid_report(dev: &DeviceHandle) -> libusb_async::TransferFuture {
 
 #[tokio::main]
 async fn main() {
+    let matches = Command::new("helvar_dump")
+        .about("Dump the HID reports from a Helvar 510 USB interface.")
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(FORMAT_NAMES)
+                .default_value("text")
+                .help("Output format"),
+        )
+        .get_matches();
+    let mut output = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .ok()
+        .map(|f| RecordWriter::new(io::stdout(), f));
+    // Keep stdout for the records when writing JSON or CSV
+    let machine_readable = output.is_some();
+    let status = |line: String| {
+        if machine_readable {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+
     let usb_ctxt = Context::new().unwrap();
     // Print out information about all connected devices
     let mut device: Option<DeviceHandle> = None;
@@ -30,7 +68,7 @@ async fn main() {
 
         match (product_id, vendor_id) {
             (0x0510, 0x16eb) => {
-                println!("Device: {:04x}:{:04x}", vendor_id, product_id);
+                status(format!("Device: {:04x}:{:04x}", vendor_id, product_id));
                 match dev.open() {
                     Ok(d) => {
                         device = Some(d);
@@ -61,7 +99,7 @@ async fn main() {
     let send = [2, 0x82, 0x04];
     match send_hid_report(&device, &send).await {
         Ok(_) => {
-            println!("Sent {} bytes", send.len());
+            status(format!("Sent {} bytes", send.len()));
         }
         Err(e) => {
             println!("Failed to send {} bytes: {}", send.len(), e);
@@ -74,11 +112,23 @@ async fn main() {
         let buf = r.get_buffer();
         let buf_len = buf.len();
         if buf_len > 0 {
-            println!("Len: {}", buf_len);
-            for b in buf[1..usize::from(buf[0]) + 1].iter() {
-                print!(" {:02x}", b);
+            let data = &buf[1..usize::from(buf[0]) + 1];
+            if let Some(output) = &mut output {
+                let report = Report {
+                    len: buf_len,
+                    data: data.iter().map(|b| format!("{:02x}", b)).collect(),
+                };
+                if let Err(e) = output.write(&report) {
+                    eprintln!("Failed to write output: {}", e);
+                    return;
+                }
+            } else {
+                println!("Len: {}", buf_len);
+                for b in data.iter() {
+                    print!(" {:02x}", b);
+                }
+                println!("");
             }
-            println!("");
         }
     }
 }
@@ -1,16 +1,35 @@
 use dali::common::address::DisplayValue;
-use dali::common::address::Short;
+use dali::common::address::{Long, Short};
 use dali::control::commands_103::Commands103;
 use dali::drivers::driver::OpenError;
 use dali::gear::commands_102::Commands102;
-use dali::utils::device_info;
-use dali::utils::memory_banks;
+use dali::utils::device_info::{self, ControlInfo, GearInfo};
+use dali::utils::memory_banks::{self, MemoryBank0Info, MemoryBank1Info};
+use dali::utils::output::{FORMAT_NAMES, RecordWriter};
 use dali_tools as dali;
 use dali_tools::common::commands::Commands;
+use serde_derive::Serialize;
+use std::io;
 
 extern crate clap;
 use clap::{Arg, Command, value_parser};
 
+#[derive(Serialize)]
+struct GearRecord<'a> {
+    random_address: Option<Long>,
+    #[serde(flatten)]
+    info: &'a GearInfo,
+    memory_bank_0: Option<MemoryBank0Info>,
+    memory_bank_1: Option<MemoryBank1Info>,
+}
+
+#[derive(Serialize)]
+struct ControlRecord<'a> {
+    random_address: Option<Long>,
+    #[serde(flatten)]
+    info: &'a ControlInfo,
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -60,6 +79,13 @@ async fn main() {
                 .action(clap::ArgAction::SetTrue)
                 .help("Try reading parameters even from devices that doesn't respond woth a long address"),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(FORMAT_NAMES)
+                .default_value("text")
+                .help("Output format. Short addresses are 0-63 in JSON and CSV."),
+        )
         .get_matches();
 
     let mut addr: Short = match matches.get_one::<u8>("ADDR") {
@@ -94,6 +120,12 @@ async fn main() {
     let read_memory = *matches.get_one::<bool>("memory_banks").unwrap();
     let control_device = *matches.get_one::<bool>("control").unwrap();
     let try_all = *matches.get_one::<bool>("try-all").unwrap();
+    let mut output = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .ok()
+        .map(|f| RecordWriter::new(io::stdout(), f));
     let mut driver = match dali::drivers::open(device_name) {
         Ok(d) => d,
         Err(e) => {
@@ -112,7 +144,7 @@ async fn main() {
         if control_device {
             let mut commands = Commands103::new(&mut *driver);
             let long = commands.query_random_address(addr).await;
-            if let Ok(long) = long {
+            if let (Ok(long), None) = (&long, &output) {
                 println!("Long address: 0x{:06x}", long);
             }
             if try_all || long.is_ok() {
@@ -123,12 +155,23 @@ async fn main() {
                         return;
                     }
                 };
-                println!("{}", info);
+                if let Some(output) = &mut output {
+                    let record = ControlRecord {
+                        random_address: long.ok(),
+                        info: &info,
+                    };
+                    if let Err(e) = output.write(&record) {
+                        eprintln!("Failed to write output: {}", e);
+                        return;
+                    }
+                } else {
+                    println!("{}", info);
+                }
             }
         } else {
             let mut commands = Commands102::new(&mut *driver);
             let long = commands.query_random_address(addr).await;
-            if let Ok(long) = long {
+            if let (Ok(long), None) = (&long, &output) {
                 println!("Long address: 0x{:06x}", long);
             }
             if try_all || long.is_ok() {
@@ -139,15 +182,41 @@ async fn main() {
                         return;
                     }
                 };
-                println!("{}", info);
+                if output.is_none() {
+                    println!("{}", info);
+                }
+                let mut bank0 = None;
+                let mut bank1 = None;
                 if read_memory {
                     match memory_banks::read_bank_0(&mut *driver, addr, 0, 0, 0x18).await {
+                        Ok(data) if output.is_some() => bank0 = Some(data),
                         Ok(data) => println!("{}", data),
                         Err(e) => {
                             eprintln!("Failed to read memory banks: {}", e);
                             return;
                         }
                     }
+                    match memory_banks::read_bank_1(&mut *driver, addr).await {
+                        Ok(data) if output.is_some() => bank1 = data,
+                        Ok(Some(data)) => println!("{}", data),
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("Failed to read memory bank 1: {}", e);
+                            return;
+                        }
+                    }
+                }
+                if let Some(output) = &mut output {
+                    let record = GearRecord {
+                        random_address: long.ok(),
+                        info: &info,
+                        memory_bank_0: bank0,
+                        memory_bank_1: bank1,
+                    };
+                    if let Err(e) = output.write(&record) {
+                        eprintln!("Failed to write output: {}", e);
+                        return;
+                    }
                 }
             }
         }
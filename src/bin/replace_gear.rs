@@ -0,0 +1,98 @@
+use dali::drivers::driver::OpenError;
+use dali::utils::replace;
+use dali::utils::snapshot::{self, BusSnapshot};
+use dali_tools as dali;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+
+extern crate clap;
+use clap::{Arg, ArgAction, Command};
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt::init();
+    if let Err(e) = dali::drivers::init() {
+        eprintln!("Failed to initialize DALI drivers: {}", e);
+    }
+    let matches = Command::new("replace_gear")
+        .about(
+            "Give replacement gear the short address and settings of missing gear with the same \
+             GTIN, using a snapshot saved with dali_snapshot.",
+        )
+        .arg(
+            Arg::new("DEVICE")
+                .short('d')
+                .long("device")
+                .default_value("default")
+                .help("Select DALI-device"),
+        )
+        .arg(Arg::new("FILE").required(true).help("Snapshot file"))
+        .arg(
+            Arg::new("update")
+                .long("update")
+                .action(ArgAction::SetTrue)
+                .help("Save a new snapshot to the file after replacing gear"),
+        )
+        .get_matches();
+
+    let path = matches.get_one::<String>("FILE").unwrap();
+    let saved = match File::open(path)
+        .map_err(|e| e.into())
+        .and_then(|f| BusSnapshot::load(BufReader::new(f)))
+    {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to read snapshot '{}': {}", path, e);
+            return;
+        }
+    };
+    let device_name = matches.get_one::<String>("DEVICE").unwrap();
+    let mut driver = match dali::drivers::open(device_name) {
+        Ok(d) => d,
+        Err(e) => {
+            eprintln!("Failed to open DALI device: {}", e);
+            if let OpenError::NotFound = e {
+                eprintln!("Available drivers:");
+                for name in dali::drivers::driver_names() {
+                    eprintln!("  {}", name);
+                }
+            }
+            return;
+        }
+    };
+
+    let report = match replace::replace(driver.as_mut(), &saved).await {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Replacement failed: {}", e);
+            return;
+        }
+    };
+    for replacement in &report.replaced {
+        println!("{}", replacement);
+    }
+    for gear in &report.missing {
+        println!("{}: missing, no replacement found", gear);
+    }
+    for long in &report.unmatched {
+        println!(
+            "0x{:06x}: unaddressed, doesn't match any missing gear",
+            long
+        );
+    }
+    if report.replaced.is_empty() && report.missing.is_empty() {
+        println!("No missing gear");
+    }
+
+    if matches.get_flag("update") && !report.replaced.is_empty() {
+        let res = match snapshot::read_snapshot(driver.as_mut(), &saved.site).await {
+            Ok(snapshot) => File::create(path)
+                .map_err(|e| e.into())
+                .and_then(|f| snapshot.save(BufWriter::new(f))),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            eprintln!("Failed to update snapshot '{}': {}", path, e);
+        }
+    }
+}
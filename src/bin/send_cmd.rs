@@ -1,7 +1,9 @@
-use dali::drivers::driver::{DaliFrame, OpenError};
+use dali::drivers::driver::{DaliFrame, DaliSendResult, OpenError};
 use dali::drivers::send_flags::Flags as SendFlags;
+use dali::utils::output::{FORMAT_NAMES, RecordWriter};
 use dali_tools as dali;
-use futures::FutureExt;
+use serde_derive::Serialize;
+use std::io;
 use tokio::time::Duration;
 
 extern crate clap;
@@ -12,6 +14,41 @@ enum Step {
     Wait(Duration),
 }
 
+#[derive(Serialize)]
+struct ResultRecord {
+    /// Frame as hex
+    frame: String,
+    /// ok, answer, timeout, framing, driver_error or pending
+    result: &'static str,
+    answer: Option<u8>,
+    error: Option<String>,
+}
+
+impl ResultRecord {
+    fn new(frame: &DaliFrame, res: &DaliSendResult) -> ResultRecord {
+        let frame = match frame {
+            DaliFrame::Frame8(b) => format!("{:02x}", b),
+            DaliFrame::Frame16(b) => format!("{:02x}{:02x}", b[0], b[1]),
+            DaliFrame::Frame24(b) => format!("{:02x}{:02x}{:02x}", b[0], b[1], b[2]),
+            DaliFrame::Frame25(b) => format!("{:02x}{:02x}{:02x}{:02x}", b[0], b[1], b[2], b[3]),
+        };
+        let (result, answer, error) = match res {
+            DaliSendResult::Ok => ("ok", None, None),
+            DaliSendResult::Answer(a) => ("answer", Some(*a), None),
+            DaliSendResult::Timeout => ("timeout", None, None),
+            DaliSendResult::Framing => ("framing", None, None),
+            DaliSendResult::DriverError(e) => ("driver_error", None, Some(e.to_string())),
+            DaliSendResult::Pending => ("pending", None, None),
+        };
+        ResultRecord {
+            frame,
+            result,
+            answer,
+            error,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
@@ -67,6 +104,13 @@ async fn main() {
                 .default_missing_value("true")
                 .help("Play sequence this many times"),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(FORMAT_NAMES)
+                .default_value("text")
+                .help("Output format"),
+        )
         .get_matches();
 
     let device_name = matches.get_one::<String>("DEVICE").unwrap();
@@ -80,6 +124,12 @@ async fn main() {
         return;
     }
     let mut repeat = *matches.get_one::<u16>("repeat").unwrap();
+    let mut output = matches
+        .get_one::<String>("format")
+        .unwrap()
+        .parse()
+        .ok()
+        .map(|f| RecordWriter::new(io::stdout(), f));
     let mut steps = Vec::new();
     for cmd_string in cmd_strings {
         let mut frame = 0u32;
@@ -136,12 +186,15 @@ async fn main() {
         for step in steps.iter() {
             match step {
                 Step::Frame(frame) => {
-                    driver
-                        .send_frame(frame.clone(), flags.clone())
-                        .then(|res| async move {
-                            println!("Result: {}", res);
-                        })
-                        .await;
+                    let res = driver.send_frame(frame.clone(), flags.clone()).await;
+                    if let Some(output) = &mut output {
+                        if let Err(e) = output.write(&ResultRecord::new(frame, &res)) {
+                            eprintln!("Failed to write output: {}", e);
+                            return;
+                        }
+                    } else {
+                        println!("Result: {}", res);
+                    }
                 }
                 Step::Wait(dur) => tokio::time::sleep(*dur).await,
             }
@@ -1,6 +1,8 @@
 use super::cmd_defs::AddressByte;
 use core::ops::RangeInclusive;
 use core::str::FromStr;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
 
 /// Value used for display, normally 1 based
 pub trait DisplayValue {
@@ -32,7 +34,20 @@ impl std::fmt::Display for AddressError {
 
 impl std::error::Error for AddressError {}
 
+/// Short address of a gear or control device.
+///
+/// The address is stored in the bus form 0 to 63, as returned by
+/// [`Short::value`]. [`Display`](std::fmt::Display), [`FromStr`] and
+/// [`DisplayValue`] use the display form 1 to 64.
+///
+/// With the `serde` feature the address is serialized as a number in the bus
+/// form.
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "u8", into = "u8")
+)]
 pub struct Short(u8);
 
 impl Short {
@@ -81,6 +96,24 @@ type Error = AddressError;
     }
 }
  */
+impl TryFrom<u8> for Short {
+    type Error = AddressError;
+    /// Convert from the bus form 0 to 63
+    fn try_from(a: u8) -> Result<Short, AddressError> {
+        if a < 64 {
+            Ok(Short(a))
+        } else {
+            Err(AddressError::InvalidAddress)
+        }
+    }
+}
+
+impl From<Short> for u8 {
+    fn from(a: Short) -> u8 {
+        a.0
+    }
+}
+
 impl std::cmp::PartialEq<Short> for Short {
     fn eq(&self, other: &Short) -> bool {
         self.0 == other.0
@@ -154,9 +187,20 @@ impl FromStr for Short {
     }
 }
 
+/// Random address, 24 bits
 pub type Long = u32;
 
+/// Group address 0 to `MAX` - 1, displayed as 1 to `MAX` by
+/// [`DisplayValue`].
+///
+/// With the `serde` feature the group is serialized as a number starting
+/// at 0, like [`Short`].
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "u8", into = "u8")
+)]
 pub struct GroupImpl<const MAX: u8>(u8);
 
 impl<const MAX: u8> GroupImpl<MAX> {
@@ -193,6 +237,23 @@ impl<const MAX: u8> std::convert::TryFrom<i32> for GroupImpl<MAX> {
 }
 */
 
+impl<const MAX: u8> TryFrom<u8> for GroupImpl<MAX> {
+    type Error = AddressError;
+    fn try_from(a: u8) -> Result<GroupImpl<MAX>, AddressError> {
+        if a < MAX {
+            Ok(GroupImpl(a))
+        } else {
+            Err(AddressError::InvalidAddress)
+        }
+    }
+}
+
+impl<const MAX: u8> From<GroupImpl<MAX>> for u8 {
+    fn from(a: GroupImpl<MAX>) -> u8 {
+        a.0
+    }
+}
+
 impl<const MAX_GROUP: u8> std::convert::TryFrom<AddressImpl<MAX_GROUP>> for GroupImpl<MAX_GROUP> {
     type Error = AddressError;
     fn try_from(addr: AddressImpl<MAX_GROUP>) -> Result<GroupImpl<MAX_GROUP>, Self::Error> {
@@ -242,7 +303,15 @@ impl<const MAX: u8> FromStr for GroupImpl<MAX> {
         })
     }
 }
+/// With the `serde` feature addresses are serialized as `{"short": 0}`,
+/// `{"group": 0}`, `"broadcast"` or `"broadcast_unaddressed"`, with the
+/// short address and group in the bus form.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
 pub enum AddressImpl<const MAX_GROUP: u8> {
     Short(Short),
     Group(GroupImpl<MAX_GROUP>),
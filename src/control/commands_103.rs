@@ -130,7 +130,7 @@ impl<'a> Commands for Commands103<'a> {
         let h = self.query(QUERY_RANDOM_ADDRESS_H(dev_addr)).await?;
         let m = self.query(QUERY_RANDOM_ADDRESS_M(dev_addr)).await?;
         let l = self.query(QUERY_RANDOM_ADDRESS_L(dev_addr)).await?;
-        Ok((u32::from(h) << 8) | u32::from(m) << 8 | u32::from(l))
+        Ok((u32::from(h) << 16) | (u32::from(m) << 8) | u32::from(l))
     }
     async fn read_memory_location(&mut self, device: Short) -> Result<u8, Self::Error> {
         self.query(READ_MEMORY_LOCATION(device)).await
@@ -36,6 +36,49 @@ pub mod send16 {
         driver.send_frame16(&cmd.0, flags | EXPECT_ANSWER)
     }
 
+    /// Send an application extended command preceded by ENABLE DEVICE
+    /// TYPE
+    ///
+    /// # Arguments
+    /// * `device_type` - Device type the command belongs to
+    /// * `cmd` - DALI command
+    /// * `flags` - Options for transaction
+    pub async fn device_type_cmd<const T: bool>(
+        driver: &mut dyn DaliDriver,
+        device_type: u8,
+        cmd: Command<false, T>,
+        flags: Flags,
+    ) -> DaliSendResult {
+        match driver
+            .send_frame16(&cmd::ENABLE_DEVICE_TYPE(device_type).0, flags.clone())
+            .await
+        {
+            DaliSendResult::Ok => self::cmd(driver, cmd, flags).await,
+            e => e,
+        }
+    }
+
+    /// Make an application extended query preceded by ENABLE DEVICE TYPE
+    ///
+    /// # Arguments
+    /// * `device_type` - Device type the query belongs to
+    /// * `cmd` - DALI query
+    /// * `flags` - Options for transaction
+    pub async fn device_type_query(
+        driver: &mut dyn DaliDriver,
+        device_type: u8,
+        cmd: Command<true, false>,
+        flags: Flags,
+    ) -> DaliSendResult {
+        match driver
+            .send_frame16(&cmd::ENABLE_DEVICE_TYPE(device_type).0, flags.clone())
+            .await
+        {
+            DaliSendResult::Ok => query(driver, cmd, flags).await,
+            e => e,
+        }
+    }
+
     /// Send DALI DAPC commands
     ///
     /// # Arguments
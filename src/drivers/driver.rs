@@ -56,7 +56,7 @@ impl fmt::Display for FromDaliBusEventTypeError {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DaliFrame {
     Frame8(u8),
     Frame16([u8; 2]),
@@ -112,7 +112,7 @@ impl From<DaliFrame> for DaliBusEventType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DaliBusEvent {
     // For reception this is the time when the frame was accepted. This is the time of the last
     // transition + 2.4ms for stop condition.
@@ -130,7 +130,8 @@ pub trait DaliDriver: Send {
     fn send_frame(&mut self, cmd: DaliFrame, flags: Flags) -> DynFuture<'_, DaliSendResult>;
 
     /// Wait for next_bus_event. If there already are unread events
-    /// queued, then return immeediately.
+    /// queued, then return immeediately. No event may be lost if the
+    /// returned future is dropped before it completes.
     fn next_bus_event(&mut self) -> DynFuture<'_, DaliBusEventResult>;
 
     /// Current time time of driver
@@ -6,9 +6,11 @@ pub use driver_init::init;
 
 pub mod command_utils;
 pub mod driver_utils;
+pub mod monitor;
 pub mod send_flags;
+pub mod shared;
+pub mod timing;
 pub mod utils;
-//pub mod monitor;
 #[cfg(feature = "helvar510_driver")]
 pub mod helvar {
     pub mod helvar510;
@@ -1,25 +1,342 @@
-use tokio::stream::Stream;
+//! Bus monitoring with several subscribers.
+//!
+//! A driver only has a single stream of bus events, read by
+//! [`DaliDriver::next_bus_event`]. [`MonitoredDriver`] runs any driver in a
+//! separate task and distributes the events to any number of subscribers
+//! while still being usable as a driver. [`SharedDriver`] builds on it to
+//! let several clients send frames.
+//!
+//! [`SharedDriver`]: crate::drivers::shared::SharedDriver
+//!
+//! ```no_run
+//! # use dali_tools::drivers::monitor::MonitoredDriver;
+//! # use futures::StreamExt;
+//! # async fn log() -> Result<(), Box<dyn std::error::Error>> {
+//! let driver = MonitoredDriver::new(dali_tools::drivers::open("default")?);
+//! let mut events = driver.monitor().subscribe();
+//! tokio::spawn(async move {
+//!     while let Some(Ok(event)) = events.next().await {
+//!         println!("{:?}", event.event_type);
+//!     }
+//! });
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::drivers::driver::{
+    DaliBusEvent, DaliBusEventResult, DaliBusEventType, DaliDriver, DaliFrame, DaliSendResult,
+};
+use crate::drivers::send_flags::Flags;
+use crate::utils::dyn_future::DynFuture;
+use futures::FutureExt;
+use futures::stream::{self, Stream};
 use std::pin::Pin;
-use tokio::time::Instant;
-
-#[derive(Debug)]
-pub enum DaliBusEventType
-{
-    Recv8bitFrame(u8),
-    Recv16bitFrame([u8;2]),
-    Recv24bitFrame([u8;3]),
-    RecvFramingError,
-    BusPowerOff,
-    BusPowerOn
-}
-
-#[derive(Debug)]
-pub struct DaliBusEvent
-{
-    pub timestamp: Instant,
-    pub event: DaliBusEventType
-}
-    
-pub trait DALImonitor: Send {
-    fn monitor_stream(&mut self) -> Option<Pin<Box<dyn Stream<Item = DaliBusEvent>>>>;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::{mpsc, oneshot};
+
+// Number of events buffered for each subscriber
+const EVENT_QUEUE_LEN: usize = 64;
+
+pub type BusEventStream = Pin<Box<dyn Stream<Item = DaliBusEventResult> + Send>>;
+
+#[derive(Clone)]
+enum MonitorItem {
+    Event(DaliBusEvent),
+    // Errors are passed as strings since they need to be cloned for every
+    // subscriber
+    Error(String),
+    // The driver task has stopped
+    Stopped,
+}
+
+struct BusEventReceiver {
+    events: broadcast::Receiver<MonitorItem>,
+    last_timestamp: Instant,
+}
+
+impl BusEventReceiver {
+    /// Returns None when the driver is gone
+    async fn recv(&mut self) -> Option<DaliBusEventResult> {
+        match self.events.recv().await {
+            Ok(MonitorItem::Event(event)) => {
+                self.last_timestamp = event.timestamp;
+                Some(Ok(event))
+            }
+            Ok(MonitorItem::Error(e)) => Some(Err(e.into())),
+            Ok(MonitorItem::Stopped) => None,
+            // Events were lost because this subscriber didn't keep up
+            Err(RecvError::Lagged(_)) => Some(Ok(DaliBusEvent {
+                timestamp: self.last_timestamp,
+                event_type: DaliBusEventType::Overrun,
+            })),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
+
+/// Handle for subscribing to the bus events of a [`MonitoredDriver`]
+#[derive(Clone)]
+pub struct BusMonitor {
+    events: broadcast::Sender<MonitorItem>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl BusMonitor {
+    // Mark the monitor as stopped and end all streams
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let _ = self.events.send(MonitorItem::Stopped);
+    }
+
+    fn receiver(&self) -> BusEventReceiver {
+        let mut events = self.events.subscribe();
+        // The receiver must exist before checking, otherwise the stop
+        // message could be missed
+        if self.stopped.load(Ordering::SeqCst) {
+            events = broadcast::channel(1).1;
+        }
+        BusEventReceiver {
+            events,
+            last_timestamp: Instant::now(),
+        }
+    }
+
+    /// Get a stream of all bus events from now on. The stream ends when
+    /// all handles to the driver are dropped. A subscriber that doesn't
+    /// keep up gets an [`DaliBusEventType::Overrun`] event in place of the
+    /// lost events.
+    pub fn subscribe(&self) -> BusEventStream {
+        Box::pin(stream::unfold(self.receiver(), |mut receiver| async {
+            receiver.recv().await.map(|event| (event, receiver))
+        }))
+    }
+
+    /// Current number of subscribers
+    pub fn subscriber_count(&self) -> usize {
+        self.events.receiver_count()
+    }
+}
+
+enum Request {
+    Send {
+        frame: DaliFrame,
+        flags: Flags,
+        reply: oneshot::Sender<DaliSendResult>,
+    },
+    WaitUntil {
+        end: Instant,
+        reply: oneshot::Sender<()>,
+    },
+}
+
+// Time of the driver and the local time when it was read
+struct Clock {
+    driver: Instant,
+    local: Instant,
+}
+
+impl Clock {
+    fn update(&mut self, driver: &dyn DaliDriver) {
+        self.driver = driver.current_timestamp();
+        self.local = Instant::now();
+    }
+}
+
+enum Action {
+    Request(Request),
+    Event(DaliBusEventResult),
+    Timeout,
+    Stop,
+}
+
+// Finish the waits that have ended. Returns the earliest remaining end.
+fn finish_waits(
+    driver: &dyn DaliDriver,
+    waits: &mut Vec<(Instant, oneshot::Sender<()>)>,
+) -> Option<Instant> {
+    loop {
+        let now = driver.current_timestamp();
+        let (done, waiting): (Vec<_>, Vec<_>) = std::mem::take(waits)
+            .into_iter()
+            .partition(|(end, _)| *end <= now);
+        *waits = waiting;
+        for (_, reply) in done {
+            let _ = reply.send(());
+        }
+        let end = waits.iter().map(|(end, _)| *end).min()?;
+        // Drivers with virtual time advance immediately, others have to be
+        // waited for in real time
+        if driver.wait_until(end).now_or_never().is_none() {
+            return Some(end);
+        }
+    }
+}
+
+async fn driver_task(
+    mut driver: Box<dyn DaliDriver>,
+    mut requests: mpsc::Receiver<Request>,
+    monitor: BusMonitor,
+    clock: Arc<Mutex<Clock>>,
+) {
+    // Stop reading events after the first error, most drivers fail
+    // permanently
+    let mut read_events = true;
+    // Waits are handled here so that frames can be sent meanwhile
+    let mut waits = Vec::new();
+    loop {
+        let wait_end =
+            finish_waits(driver.as_ref(), &mut waits).map(tokio::time::Instant::from_std);
+        // The event future is dropped when a request arrives, this relies
+        // on next_bus_event being cancel safe
+        let action = tokio::select! {
+            request = requests.recv() => match request {
+                Some(request) => Action::Request(request),
+                None => Action::Stop,
+            },
+            event = driver.next_bus_event(), if read_events => Action::Event(event),
+            _ = tokio::time::sleep_until(wait_end.unwrap_or_else(tokio::time::Instant::now)),
+                if wait_end.is_some() => Action::Timeout,
+        };
+        match action {
+            Action::Request(Request::Send {
+                frame,
+                flags,
+                reply,
+            }) => {
+                let res = driver.send_frame(frame, flags).await;
+                let _ = reply.send(res);
+            }
+            Action::Request(Request::WaitUntil { end, reply }) => waits.push((end, reply)),
+            Action::Event(Ok(event)) => {
+                // No subscribers is not an error
+                let _ = monitor.events.send(MonitorItem::Event(event));
+            }
+            Action::Event(Err(e)) => {
+                read_events = false;
+                let _ = monitor.events.send(MonitorItem::Error(e.to_string()));
+            }
+            Action::Timeout => {}
+            Action::Stop => break,
+        }
+        if let Ok(mut clock) = clock.lock() {
+            clock.update(driver.as_ref());
+        }
+    }
+    monitor.stop();
+}
+
+/// A driver running in a separate task, with bus events distributed to
+/// several subscribers.
+///
+/// [`next_bus_event`](DaliDriver::next_bus_event) of this driver works like
+/// an extra subscriber. Frames can be sent while waiting in
+/// [`wait_until`](DaliDriver::wait_until). The driver must be created
+/// within a Tokio runtime.
+pub struct MonitoredDriver {
+    requests: mpsc::Sender<Request>,
+    monitor: BusMonitor,
+    events: BusEventReceiver,
+    clock: Arc<Mutex<Clock>>,
+}
+
+impl MonitoredDriver {
+    pub fn new(driver: Box<dyn DaliDriver>) -> MonitoredDriver {
+        let (request_tx, request_rx) = mpsc::channel(1);
+        let (events, _) = broadcast::channel(EVENT_QUEUE_LEN);
+        let monitor = BusMonitor {
+            events,
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+        let own_events = monitor.receiver();
+        let clock = Arc::new(Mutex::new(Clock {
+            driver: driver.current_timestamp(),
+            local: Instant::now(),
+        }));
+        tokio::spawn(driver_task(
+            driver,
+            request_rx,
+            monitor.clone(),
+            clock.clone(),
+        ));
+        MonitoredDriver {
+            requests: request_tx,
+            monitor,
+            events: own_events,
+            clock,
+        }
+    }
+
+    /// Handle for subscribing to bus events
+    pub fn monitor(&self) -> BusMonitor {
+        self.monitor.clone()
+    }
+
+    // Another handle to the same driver task, with its own event stream.
+    // The task stops when all handles are dropped.
+    pub(crate) fn share(&self) -> MonitoredDriver {
+        MonitoredDriver {
+            requests: self.requests.clone(),
+            monitor: self.monitor.clone(),
+            events: self.monitor.receiver(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl DaliDriver for MonitoredDriver {
+    fn send_frame(&mut self, frame: DaliFrame, flags: Flags) -> DynFuture<'_, DaliSendResult> {
+        Box::pin(async move {
+            let (reply, reply_rx) = oneshot::channel();
+            if self
+                .requests
+                .send(Request::Send {
+                    frame,
+                    flags,
+                    reply,
+                })
+                .await
+                .is_err()
+            {
+                return DaliSendResult::DriverError("Driver task stopped".into());
+            }
+            reply_rx
+                .await
+                .unwrap_or_else(|e| DaliSendResult::DriverError(Box::new(e)))
+        })
+    }
+
+    fn next_bus_event(&mut self) -> DynFuture<'_, DaliBusEventResult> {
+        Box::pin(async {
+            self.events
+                .recv()
+                .await
+                .unwrap_or_else(|| Err("Driver task stopped".into()))
+        })
+    }
+
+    /// Estimated from the time last read from the driver. The driver is
+    /// busy in another task so it can't be read directly.
+    fn current_timestamp(&self) -> Instant {
+        match self.clock.lock() {
+            Ok(clock) => clock.driver + clock.local.elapsed(),
+            Err(_) => Instant::now(),
+        }
+    }
+
+    fn wait_until(&self, end: Instant) -> DynFuture<'_, ()> {
+        Box::pin(async move {
+            let (reply, reply_rx) = oneshot::channel();
+            if self
+                .requests
+                .send(Request::WaitUntil { end, reply })
+                .await
+                .is_ok()
+            {
+                let _ = reply_rx.await;
+            }
+        })
+    }
 }
@@ -0,0 +1,382 @@
+//! A driver handle that can be shared between tasks.
+//!
+//! [`SharedDriver`] runs a driver in a separate task, like
+//! [`MonitoredDriver`]. Every clone of the handle is a driver of its own
+//! and the requests from all clones are scheduled by the priority in the
+//! send flags, 1 being the highest. Frames with the same priority are sent
+//! in the order they were requested.
+//!
+//! Sequences of frames that must not be interrupted by other clients are
+//! kept together:
+//! * After a DTR is set or a device type is enabled the same client keeps
+//!   the bus for the next frame.
+//! * A configuration command sent without [`SEND_TWICE`] keeps the bus
+//!   until it is repeated.
+//! * [`SharedDriver::transaction`] keeps the bus until the returned guard is
+//!   dropped.
+//!
+//! A client that doesn't send the next frame within [`TRANSACTION_TIMEOUT`]
+//! loses the bus, except for explicit transactions.
+//!
+//! [`SEND_TWICE`]: crate::drivers::send_flags::SEND_TWICE
+//! [`MonitoredDriver`]: crate::drivers::monitor::MonitoredDriver
+
+use crate::drivers::driver::{DaliBusEventResult, DaliDriver, DaliFrame, DaliSendResult};
+use crate::drivers::monitor::{BusMonitor, MonitoredDriver};
+use crate::drivers::send_flags::Flags;
+use crate::utils::dyn_future::DynFuture;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+/// Time a client keeps the bus between the frames of an implicit
+/// transaction
+pub const TRANSACTION_TIMEOUT: Duration = Duration::from_millis(100);
+
+enum RequestKind {
+    Send {
+        frame: DaliFrame,
+        flags: Flags,
+        reply: oneshot::Sender<DaliSendResult>,
+    },
+    Begin {
+        reply: oneshot::Sender<()>,
+    },
+    End,
+}
+
+struct Request {
+    client: u64,
+    priority: u16,
+    kind: RequestKind,
+}
+
+// Queued request, ordered so that the highest priority and then the
+// oldest request is the greatest
+struct Queued {
+    order: (Reverse<u16>, Reverse<u64>),
+    request: Request,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.order == other.order
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.order.cmp(&other.order)
+    }
+}
+
+// Client currently owning the bus
+struct Owner {
+    client: u64,
+    // None for explicit transactions
+    deadline: Option<tokio::time::Instant>,
+    // Last frame sent, to detect a repeated configuration command
+    last_frame: Option<DaliFrame>,
+}
+
+/// True if a frame must be followed by another frame from the same client
+fn starts_transaction(frame: &DaliFrame, flags: &Flags) -> bool {
+    match *frame {
+        // DTR0, DTR1, DTR2, ENABLE DEVICE TYPE
+        DaliFrame::Frame16([0xa3 | 0xc3 | 0xc5 | 0xc1, _]) => true,
+        // INITIALISE, RANDOMISE
+        DaliFrame::Frame16([0xa5 | 0xa7, _]) => !flags.send_twice(),
+        // Configuration commands to control gear
+        DaliFrame::Frame16([addr, opcode]) => {
+            addr & 1 == 1
+                && !(0xa0..=0xcb).contains(&addr)
+                && (0x20..=0x81).contains(&opcode)
+                && !flags.send_twice()
+        }
+        // DTR0, DTR1, DTR2, DTR1:DTR0, DTR2:DTR1 for control devices
+        DaliFrame::Frame24([0xc1, 0x30..=0x32, _]) | DaliFrame::Frame24([0xc7 | 0xc9, _, _]) => {
+            true
+        }
+        _ => false,
+    }
+}
+
+struct Scheduler {
+    driver: MonitoredDriver,
+    requests: mpsc::UnboundedReceiver<Request>,
+    queue: BinaryHeap<Queued>,
+    next_seq: u64,
+    owner: Option<Owner>,
+}
+
+enum Action {
+    Request(Request),
+    Timeout,
+    Stop,
+}
+
+impl Scheduler {
+    // Handle a new request. Some requests are handled immediately, the
+    // rest are queued.
+    fn add_request(&mut self, request: Request) {
+        match request.kind {
+            RequestKind::End => {
+                if self
+                    .owner
+                    .as_ref()
+                    .is_some_and(|owner| owner.client == request.client)
+                {
+                    self.owner = None;
+                }
+            }
+            _ => {
+                self.queue.push(Queued {
+                    order: (Reverse(request.priority), Reverse(self.next_seq)),
+                    request,
+                });
+                self.next_seq += 1;
+            }
+        }
+    }
+
+    // Next request that may run, only requests from the owner of the bus
+    // if there is one
+    fn next_runnable(&mut self) -> Option<Request> {
+        let owner = match &self.owner {
+            Some(owner) => owner.client,
+            None => return self.queue.pop().map(|q| q.request),
+        };
+        let mut queued = std::mem::take(&mut self.queue).into_sorted_vec();
+        let index = queued.iter().rposition(|q| q.request.client == owner);
+        let request = index.map(|i| queued.remove(i).request);
+        self.queue = queued.into();
+        request
+    }
+
+    async fn run_request(&mut self, request: Request) {
+        match request.kind {
+            RequestKind::Send {
+                frame,
+                flags,
+                reply,
+            } => {
+                let explicit = self
+                    .owner
+                    .as_ref()
+                    .is_some_and(|owner| owner.deadline.is_none());
+                let repeated = self
+                    .owner
+                    .as_ref()
+                    .is_some_and(|owner| owner.last_frame.as_ref() == Some(&frame));
+                let hold = !repeated && starts_transaction(&frame, &flags);
+                let res = self.driver.send_frame(frame.clone(), flags).await;
+                let _ = reply.send(res);
+                if explicit {
+                    if let Some(owner) = &mut self.owner {
+                        owner.last_frame = Some(frame);
+                    }
+                } else if hold {
+                    self.owner = Some(Owner {
+                        client: request.client,
+                        deadline: Some(tokio::time::Instant::now() + TRANSACTION_TIMEOUT),
+                        last_frame: Some(frame),
+                    });
+                } else {
+                    self.owner = None;
+                }
+            }
+            RequestKind::Begin { reply } => {
+                // The transaction is abandoned if nobody is waiting for it
+                if reply.send(()).is_ok() {
+                    self.owner = Some(Owner {
+                        client: request.client,
+                        deadline: None,
+                        last_frame: None,
+                    });
+                }
+            }
+            RequestKind::End => {}
+        }
+    }
+
+    async fn run(mut self) {
+        loop {
+            while let Ok(request) = self.requests.try_recv() {
+                self.add_request(request);
+            }
+            // The owner of an implicit transaction didn't continue in time
+            if self
+                .owner
+                .as_ref()
+                .and_then(|owner| owner.deadline)
+                .is_some_and(|deadline| deadline <= tokio::time::Instant::now())
+            {
+                self.owner = None;
+            }
+            if let Some(request) = self.next_runnable() {
+                self.run_request(request).await;
+                continue;
+            }
+            let deadline = self.owner.as_ref().and_then(|owner| owner.deadline);
+            let action = tokio::select! {
+                request = self.requests.recv() => match request {
+                    Some(request) => Action::Request(request),
+                    None => Action::Stop,
+                },
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)),
+                    if deadline.is_some() => Action::Timeout,
+            };
+            match action {
+                Action::Request(request) => self.add_request(request),
+                Action::Timeout => {}
+                Action::Stop => break,
+            }
+        }
+    }
+}
+
+/// Cloneable handle to a driver running in a separate task.
+///
+/// Each clone is a separate client of the scheduler and receives all bus
+/// events from [`next_bus_event`](DaliDriver::next_bus_event). The driver
+/// task stops when all clones are dropped. It must be created within a
+/// Tokio runtime.
+pub struct SharedDriver {
+    client: u64,
+    next_client: Arc<AtomicU64>,
+    requests: mpsc::UnboundedSender<Request>,
+    // Used for everything but sending
+    driver: MonitoredDriver,
+}
+
+impl SharedDriver {
+    pub fn new(driver: Box<dyn DaliDriver>) -> SharedDriver {
+        let (request_tx, request_rx) = mpsc::unbounded_channel();
+        let driver = MonitoredDriver::new(driver);
+        let scheduler = Scheduler {
+            driver: driver.share(),
+            requests: request_rx,
+            queue: BinaryHeap::new(),
+            next_seq: 0,
+            owner: None,
+        };
+        tokio::spawn(scheduler.run());
+        SharedDriver {
+            client: 0,
+            next_client: Arc::new(AtomicU64::new(1)),
+            requests: request_tx,
+            driver,
+        }
+    }
+
+    /// Handle for subscribing to bus events
+    pub fn monitor(&self) -> BusMonitor {
+        self.driver.monitor()
+    }
+
+    /// Wait until the bus is available and keep it until the returned
+    /// transaction is dropped. Frames sent through the transaction are
+    /// not interleaved with frames from other clients.
+    pub async fn transaction(&self, priority: Flags) -> Result<Transaction, DaliSendResult> {
+        let driver = self.clone();
+        let (reply, reply_rx) = oneshot::channel();
+        driver.request(priority.priority(), RequestKind::Begin { reply })?;
+        reply_rx
+            .await
+            .map_err(|e| DaliSendResult::DriverError(Box::new(e)))?;
+        Ok(Transaction { driver })
+    }
+
+    fn request(&self, priority: u16, kind: RequestKind) -> Result<(), DaliSendResult> {
+        self.requests
+            .send(Request {
+                client: self.client,
+                priority,
+                kind,
+            })
+            .map_err(|_| DaliSendResult::DriverError("Driver task stopped".into()))
+    }
+}
+
+impl Clone for SharedDriver {
+    fn clone(&self) -> Self {
+        SharedDriver {
+            client: self.next_client.fetch_add(1, Ordering::Relaxed),
+            next_client: self.next_client.clone(),
+            requests: self.requests.clone(),
+            driver: self.driver.share(),
+        }
+    }
+}
+
+impl DaliDriver for SharedDriver {
+    fn send_frame(&mut self, frame: DaliFrame, flags: Flags) -> DynFuture<'_, DaliSendResult> {
+        let (reply, reply_rx) = oneshot::channel();
+        let sent = self.request(
+            flags.priority(),
+            RequestKind::Send {
+                frame,
+                flags,
+                reply,
+            },
+        );
+        Box::pin(async move {
+            if let Err(e) = sent {
+                return e;
+            }
+            reply_rx
+                .await
+                .unwrap_or_else(|e| DaliSendResult::DriverError(Box::new(e)))
+        })
+    }
+
+    fn next_bus_event(&mut self) -> DynFuture<'_, DaliBusEventResult> {
+        self.driver.next_bus_event()
+    }
+
+    fn current_timestamp(&self) -> Instant {
+        self.driver.current_timestamp()
+    }
+
+    /// Other clients can send frames while waiting
+    fn wait_until(&self, end: Instant) -> DynFuture<'_, ()> {
+        self.driver.wait_until(end)
+    }
+}
+
+/// Exclusive use of a [`SharedDriver`], ends when dropped
+pub struct Transaction {
+    driver: SharedDriver,
+}
+
+impl Deref for Transaction {
+    type Target = SharedDriver;
+
+    fn deref(&self) -> &SharedDriver {
+        &self.driver
+    }
+}
+
+impl DerefMut for Transaction {
+    fn deref_mut(&mut self) -> &mut SharedDriver {
+        &mut self.driver
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        let _ = self.driver.request(0, RequestKind::End);
+    }
+}
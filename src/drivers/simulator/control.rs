@@ -0,0 +1,876 @@
+//! Simulated control device (IEC 62386-103) with input device
+//! instances (IEC 62386-301 and later parts).
+//!
+//! The device shares its state between all clones, so a clone can be
+//! added to a [`DaliBusSim`](super::simulator::DaliBusSim) while another
+//! one is used for scripting button presses and sensor values.
+
+use super::device::{DaliSimDevice, DaliSimEvent, DaliSimHost};
+use super::installation::{ControlDescription, DeviceDescription, InstanceDescription};
+use super::timing::{self, FRAME_24_DURATION, REPLY_DELAY, SEND_TWICE_DURATION};
+use crate::common::defs::MASK;
+use crate::drivers::driver::{DaliBusEventType, DaliFrame};
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Instance types
+pub mod instance_type {
+    pub const GENERIC: u8 = 0;
+    pub const PUSH_BUTTON: u8 = 1;
+    pub const ABSOLUTE_INPUT: u8 = 2;
+    pub const OCCUPANCY_SENSOR: u8 = 3;
+    pub const LIGHT_SENSOR: u8 = 4;
+}
+
+/// Event information for push buttons (IEC 62386-301)
+pub mod push_button {
+    pub const RELEASED: u16 = 0x00;
+    pub const PRESSED: u16 = 0x01;
+    pub const SHORT_PRESS: u16 = 0x02;
+    pub const DOUBLE_PRESS: u16 = 0x05;
+    pub const LONG_PRESS_START: u16 = 0x09;
+    pub const LONG_PRESS_REPEAT: u16 = 0x0b;
+    pub const LONG_PRESS_STOP: u16 = 0x0c;
+}
+
+/// Event schemes
+pub mod event_scheme {
+    pub const INSTANCE: u8 = 0;
+    pub const DEVICE: u8 = 1;
+    pub const DEVICE_INSTANCE: u8 = 2;
+    pub const DEVICE_GROUP: u8 = 3;
+    pub const INSTANCE_GROUP: u8 = 4;
+}
+
+mod opcode {
+    // Device commands
+    pub const IDENTIFY_DEVICE: u8 = 0x00;
+    pub const RESET_POWER_CYCLE_SEEN: u8 = 0x01;
+    pub const RESET: u8 = 0x10;
+    pub const SET_SHORT_ADDRESS: u8 = 0x14;
+    pub const ENABLE_APPLICATION_CONTROLLER: u8 = 0x16;
+    pub const DISABLE_APPLICATION_CONTROLLER: u8 = 0x17;
+    pub const SET_OPERATING_MODE: u8 = 0x18;
+    pub const ADD_TO_DEVICE_GROUPS_0_15: u8 = 0x19;
+    pub const ADD_TO_DEVICE_GROUPS_16_31: u8 = 0x1a;
+    pub const REMOVE_FROM_DEVICE_GROUPS_0_15: u8 = 0x1b;
+    pub const REMOVE_FROM_DEVICE_GROUPS_16_31: u8 = 0x1c;
+    pub const START_QUIESCENT_MODE: u8 = 0x1d;
+    pub const STOP_QUIESCENT_MODE: u8 = 0x1e;
+    pub const ENABLE_POWER_CYCLE_NOTIFICATION: u8 = 0x1f;
+    pub const DISABLE_POWER_CYCLE_NOTIFICATION: u8 = 0x20;
+    pub const QUERY_DEVICE_STATUS: u8 = 0x30;
+    pub const QUERY_APPLICATION_CONTROLLER_ERROR: u8 = 0x31;
+    pub const QUERY_INPUT_DEVICE_ERROR: u8 = 0x32;
+    pub const QUERY_MISSING_SHORT_ADDRESS: u8 = 0x33;
+    pub const QUERY_VERSION_NUMBER: u8 = 0x34;
+    pub const QUERY_NUMBER_OF_INSTANCES: u8 = 0x35;
+    pub const QUERY_CONTENT_DTR0: u8 = 0x36;
+    pub const QUERY_CONTENT_DTR1: u8 = 0x37;
+    pub const QUERY_CONTENT_DTR2: u8 = 0x38;
+    pub const QUERY_RANDOM_ADDRESS_H: u8 = 0x39;
+    pub const QUERY_RANDOM_ADDRESS_M: u8 = 0x3a;
+    pub const QUERY_RANDOM_ADDRESS_L: u8 = 0x3b;
+    pub const READ_MEMORY_LOCATION: u8 = 0x3c;
+    pub const QUERY_APPLICATION_CONTROL_ENABLED: u8 = 0x3d;
+    pub const QUERY_OPERATING_MODE: u8 = 0x3e;
+    pub const QUERY_QUIESCENT_MODE: u8 = 0x40;
+    pub const QUERY_DEVICE_GROUPS_0_7: u8 = 0x41;
+    pub const QUERY_DEVICE_GROUPS_8_15: u8 = 0x42;
+    pub const QUERY_DEVICE_GROUPS_16_23: u8 = 0x43;
+    pub const QUERY_DEVICE_GROUPS_24_31: u8 = 0x44;
+    pub const QUERY_POWER_CYCLE_NOTIFICATION: u8 = 0x45;
+    pub const QUERY_DEVICE_CAPABILITIES: u8 = 0x46;
+    pub const QUERY_RESET_STATE: u8 = 0x48;
+
+    // Instance commands
+    pub const SET_EVENT_PRIORITY: u8 = 0x61;
+    pub const ENABLE_INSTANCE: u8 = 0x62;
+    pub const DISABLE_INSTANCE: u8 = 0x63;
+    pub const SET_PRIMARY_INSTANCE_GROUP: u8 = 0x64;
+    pub const SET_INSTANCE_GROUP_1: u8 = 0x65;
+    pub const SET_INSTANCE_GROUP_2: u8 = 0x66;
+    pub const SET_EVENT_SCHEME: u8 = 0x67;
+    pub const SET_EVENT_FILTER: u8 = 0x68;
+    pub const QUERY_INSTANCE_TYPE: u8 = 0x80;
+    pub const QUERY_RESOLUTION: u8 = 0x81;
+    pub const QUERY_INSTANCE_ERROR: u8 = 0x82;
+    pub const QUERY_INSTANCE_STATUS: u8 = 0x83;
+    pub const QUERY_EVENT_PRIORITY: u8 = 0x84;
+    pub const QUERY_INSTANCE_ENABLED: u8 = 0x86;
+    pub const QUERY_PRIMARY_INSTANCE_GROUP: u8 = 0x88;
+    pub const QUERY_INSTANCE_GROUP_1: u8 = 0x89;
+    pub const QUERY_INSTANCE_GROUP_2: u8 = 0x8a;
+    pub const QUERY_EVENT_SCHEME: u8 = 0x8b;
+    pub const QUERY_INPUT_VALUE: u8 = 0x8c;
+    pub const QUERY_INPUT_VALUE_LATCH: u8 = 0x8d;
+    pub const QUERY_FEATURE_TYPE: u8 = 0x8e;
+    pub const QUERY_EVENT_FILTER_0_7: u8 = 0x90;
+    pub const QUERY_EVENT_FILTER_8_15: u8 = 0x91;
+    pub const QUERY_EVENT_FILTER_16_23: u8 = 0x92;
+
+    // Special commands, second byte when the first is 0xc1
+    pub const TERMINATE: u8 = 0x00;
+    pub const INITIALISE: u8 = 0x01;
+    pub const RANDOMISE: u8 = 0x02;
+    pub const COMPARE: u8 = 0x03;
+    pub const WITHDRAW: u8 = 0x04;
+    pub const SEARCHADDRH: u8 = 0x05;
+    pub const SEARCHADDRM: u8 = 0x06;
+    pub const SEARCHADDRL: u8 = 0x07;
+    pub const PROGRAM_SHORT_ADDRESS: u8 = 0x08;
+    pub const VERIFY_SHORT_ADDRESS: u8 = 0x09;
+    pub const QUERY_SHORT_ADDRESS: u8 = 0x0a;
+    pub const DTR0: u8 = 0x30;
+    pub const DTR1: u8 = 0x31;
+    pub const DTR2: u8 = 0x32;
+}
+
+// Device status bits
+const STATUS_INPUT_DEVICE_ERROR: u8 = 0x01;
+const STATUS_QUIESCENT_MODE: u8 = 0x02;
+const STATUS_NO_ADDRESS: u8 = 0x04;
+const STATUS_APPLICATION_ACTIVE: u8 = 0x08;
+const STATUS_POWER_CYCLE_SEEN: u8 = 0x20;
+const STATUS_RESET_STATE: u8 = 0x40;
+
+const YES: u8 = MASK;
+
+#[derive(PartialEq, Clone, Copy)]
+enum InitialisationState {
+    Enabled,
+    Disabled,
+    Withdrawn,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimInstance {
+    pub instance_type: u8,
+    /// Number of bits in the input value
+    pub resolution: u8,
+    pub enabled: bool,
+    pub error: u8,
+    pub event_priority: u8,
+    pub event_scheme: u8,
+    pub event_filter: u32,
+    pub primary_group: u8,
+    pub group_1: u8,
+    pub group_2: u8,
+    pub input_value: u32,
+}
+
+impl SimInstance {
+    /// Create an enabled instance with all events enabled
+    pub fn new(instance_type: u8) -> SimInstance {
+        SimInstance {
+            instance_type,
+            resolution: match instance_type {
+                instance_type::PUSH_BUTTON => 1,
+                instance_type::OCCUPANCY_SENSOR => 8,
+                _ => 10,
+            },
+            enabled: true,
+            error: 0,
+            event_priority: 4,
+            event_scheme: event_scheme::INSTANCE,
+            event_filter: 0xffffff,
+            primary_group: MASK,
+            group_1: MASK,
+            group_2: MASK,
+            input_value: 0,
+        }
+    }
+
+    /// The input value as big endian bytes, left aligned.
+    fn value_bytes(&self) -> Vec<u8> {
+        let n_bytes = (self.resolution as u32).div_ceil(8).clamp(1, 4);
+        let aligned = self.input_value << (n_bytes * 8 - self.resolution as u32);
+        aligned.to_be_bytes()[(4 - n_bytes as usize)..].to_vec()
+    }
+}
+
+struct ControlState {
+    short_address: u8,
+    random_address: u32,
+    search_address: u32,
+    initialisation_state: InitialisationState,
+    device_groups: u32,
+    dtr0: u8,
+    dtr1: u8,
+    dtr2: u8,
+    operating_mode: u8,
+    application_controller: bool,
+    application_active: bool,
+    quiescent: bool,
+    power_cycle_seen: bool,
+    power_cycle_notification: bool,
+    reset_state: bool,
+    memory_bank_0: Vec<u8>,
+    instances: Vec<SimInstance>,
+    // Remaining bytes of the value latched by QUERY INPUT VALUE
+    latched: Vec<u8>,
+    last_event: Option<DaliSimEvent>,
+    source_id: u32,
+    host: Option<Box<dyn DaliSimHost>>,
+    // Used for RANDOMISE
+    rng: StdRng,
+}
+
+impl ControlState {
+    fn device_status(&self) -> u8 {
+        let mut status = 0;
+        if self.instances.iter().any(|i| i.error != 0) {
+            status |= STATUS_INPUT_DEVICE_ERROR;
+        }
+        if self.quiescent {
+            status |= STATUS_QUIESCENT_MODE;
+        }
+        if self.short_address == MASK {
+            status |= STATUS_NO_ADDRESS;
+        }
+        if self.application_active {
+            status |= STATUS_APPLICATION_ACTIVE;
+        }
+        if self.power_cycle_seen {
+            status |= STATUS_POWER_CYCLE_SEEN;
+        }
+        if self.reset_state {
+            status |= STATUS_RESET_STATE;
+        }
+        status
+    }
+
+    fn addressed(&self, addr: u8) -> bool {
+        match addr {
+            0x00..=0x7f => (addr >> 1) == self.short_address,
+            0x80..=0xbf => self.device_groups & (1 << ((addr >> 1) & 0x1f)) != 0,
+            0xfd => self.short_address == MASK,
+            0xff => true,
+            _ => false,
+        }
+    }
+
+    /// Index of the instances selected by the instance byte
+    fn selected_instances(&self, inst: u8) -> Vec<usize> {
+        let selected = |i: &SimInstance, n: usize| match inst {
+            0x00..=0x1f => n == inst as usize,
+            0x80..=0x9f => {
+                let g = inst & 0x1f;
+                i.primary_group == g || i.group_1 == g || i.group_2 == g
+            }
+            0xc0..=0xdf => i.instance_type == inst & 0x1f,
+            0xff => true,
+            _ => false,
+        };
+        self.instances
+            .iter()
+            .enumerate()
+            .filter(|(n, i)| selected(i, *n))
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    fn device_cmd(&mut self, op: u8, twice: bool) -> Option<u8> {
+        use opcode::*;
+        let group_bits = (self.dtr2 as u32) << 8 | self.dtr1 as u32;
+        match op {
+            IDENTIFY_DEVICE => None,
+            RESET_POWER_CYCLE_SEEN if twice => {
+                self.power_cycle_seen = false;
+                None
+            }
+            RESET if twice => {
+                self.reset();
+                None
+            }
+            SET_SHORT_ADDRESS if twice => {
+                if self.dtr0 < 64 || self.dtr0 == MASK {
+                    self.short_address = self.dtr0;
+                }
+                None
+            }
+            ENABLE_APPLICATION_CONTROLLER if twice => {
+                self.application_active = self.application_controller;
+                None
+            }
+            DISABLE_APPLICATION_CONTROLLER if twice => {
+                self.application_active = false;
+                None
+            }
+            SET_OPERATING_MODE if twice => {
+                self.operating_mode = self.dtr0;
+                None
+            }
+            ADD_TO_DEVICE_GROUPS_0_15 if twice => {
+                self.device_groups |= group_bits;
+                None
+            }
+            ADD_TO_DEVICE_GROUPS_16_31 if twice => {
+                self.device_groups |= group_bits << 16;
+                None
+            }
+            REMOVE_FROM_DEVICE_GROUPS_0_15 if twice => {
+                self.device_groups &= !group_bits;
+                None
+            }
+            REMOVE_FROM_DEVICE_GROUPS_16_31 if twice => {
+                self.device_groups &= !(group_bits << 16);
+                None
+            }
+            START_QUIESCENT_MODE if twice => {
+                self.quiescent = true;
+                None
+            }
+            STOP_QUIESCENT_MODE if twice => {
+                self.quiescent = false;
+                None
+            }
+            ENABLE_POWER_CYCLE_NOTIFICATION if twice => {
+                self.power_cycle_notification = true;
+                None
+            }
+            DISABLE_POWER_CYCLE_NOTIFICATION if twice => {
+                self.power_cycle_notification = false;
+                None
+            }
+            QUERY_DEVICE_STATUS => Some(self.device_status()),
+            QUERY_APPLICATION_CONTROLLER_ERROR => self.application_controller.then_some(0),
+            QUERY_INPUT_DEVICE_ERROR => self.instances.iter().any(|i| i.error != 0).then_some(MASK),
+            QUERY_MISSING_SHORT_ADDRESS => (self.short_address == MASK).then_some(YES),
+            QUERY_VERSION_NUMBER => Some(2 << 2), // 2.0
+            QUERY_NUMBER_OF_INSTANCES => Some(self.instances.len() as u8),
+            QUERY_CONTENT_DTR0 => Some(self.dtr0),
+            QUERY_CONTENT_DTR1 => Some(self.dtr1),
+            QUERY_CONTENT_DTR2 => Some(self.dtr2),
+            QUERY_RANDOM_ADDRESS_H => Some((self.random_address >> 16) as u8),
+            QUERY_RANDOM_ADDRESS_M => Some((self.random_address >> 8) as u8),
+            QUERY_RANDOM_ADDRESS_L => Some(self.random_address as u8),
+            READ_MEMORY_LOCATION => self.read_memory(),
+            QUERY_APPLICATION_CONTROL_ENABLED => self.application_active.then_some(YES),
+            QUERY_OPERATING_MODE => Some(self.operating_mode),
+            QUERY_QUIESCENT_MODE => self.quiescent.then_some(YES),
+            QUERY_DEVICE_GROUPS_0_7 => Some(self.device_groups as u8),
+            QUERY_DEVICE_GROUPS_8_15 => Some((self.device_groups >> 8) as u8),
+            QUERY_DEVICE_GROUPS_16_23 => Some((self.device_groups >> 16) as u8),
+            QUERY_DEVICE_GROUPS_24_31 => Some((self.device_groups >> 24) as u8),
+            QUERY_POWER_CYCLE_NOTIFICATION => self.power_cycle_notification.then_some(YES),
+            QUERY_DEVICE_CAPABILITIES => {
+                let mut caps = 0;
+                if self.application_controller {
+                    caps |= 0x01;
+                }
+                if !self.instances.is_empty() {
+                    caps |= 0x02;
+                }
+                Some(caps)
+            }
+            QUERY_RESET_STATE => self.reset_state.then_some(YES),
+            _ => None,
+        }
+    }
+
+    fn instance_cmd(&mut self, index: usize, op: u8, twice: bool) -> Option<u8> {
+        use opcode::*;
+        let dtr0 = self.dtr0;
+        let filter = (self.dtr2 as u32) << 16 | (self.dtr1 as u32) << 8 | self.dtr0 as u32;
+        let inst = &mut self.instances[index];
+        let group = |g: u8| if g < 32 || g == MASK { Some(g) } else { None };
+        match op {
+            SET_EVENT_PRIORITY if twice => {
+                if (2..=5).contains(&dtr0) {
+                    inst.event_priority = dtr0;
+                }
+                None
+            }
+            ENABLE_INSTANCE if twice => {
+                inst.enabled = true;
+                None
+            }
+            DISABLE_INSTANCE if twice => {
+                inst.enabled = false;
+                None
+            }
+            SET_PRIMARY_INSTANCE_GROUP if twice => {
+                inst.primary_group = group(dtr0).unwrap_or(inst.primary_group);
+                None
+            }
+            SET_INSTANCE_GROUP_1 if twice => {
+                inst.group_1 = group(dtr0).unwrap_or(inst.group_1);
+                None
+            }
+            SET_INSTANCE_GROUP_2 if twice => {
+                inst.group_2 = group(dtr0).unwrap_or(inst.group_2);
+                None
+            }
+            SET_EVENT_SCHEME if twice => {
+                if dtr0 <= event_scheme::INSTANCE_GROUP {
+                    inst.event_scheme = dtr0;
+                }
+                None
+            }
+            SET_EVENT_FILTER if twice => {
+                inst.event_filter = filter;
+                None
+            }
+            QUERY_INSTANCE_TYPE => Some(inst.instance_type),
+            QUERY_RESOLUTION => Some(inst.resolution),
+            QUERY_INSTANCE_ERROR => Some(inst.error),
+            QUERY_INSTANCE_STATUS => {
+                Some(if inst.error != 0 { 0x01 } else { 0 } | if inst.enabled { 0x02 } else { 0 })
+            }
+            QUERY_EVENT_PRIORITY => Some(inst.event_priority),
+            QUERY_INSTANCE_ENABLED => inst.enabled.then_some(YES),
+            QUERY_PRIMARY_INSTANCE_GROUP => Some(inst.primary_group),
+            QUERY_INSTANCE_GROUP_1 => Some(inst.group_1),
+            QUERY_INSTANCE_GROUP_2 => Some(inst.group_2),
+            QUERY_EVENT_SCHEME => Some(inst.event_scheme),
+            QUERY_INPUT_VALUE => {
+                let mut bytes = inst.value_bytes();
+                let first = bytes.remove(0);
+                self.latched = bytes;
+                Some(first)
+            }
+            QUERY_INPUT_VALUE_LATCH => {
+                if self.latched.is_empty() {
+                    None
+                } else {
+                    Some(self.latched.remove(0))
+                }
+            }
+            // No features implemented
+            QUERY_FEATURE_TYPE => Some(254),
+            QUERY_EVENT_FILTER_0_7 => Some(inst.event_filter as u8),
+            QUERY_EVENT_FILTER_8_15 => Some((inst.event_filter >> 8) as u8),
+            QUERY_EVENT_FILTER_16_23 => Some((inst.event_filter >> 16) as u8),
+            _ => None,
+        }
+    }
+
+    fn special_cmd(&mut self, op: u8, data: u8, twice: bool) -> Option<u8> {
+        use opcode::*;
+        let enabled = self.initialisation_state != InitialisationState::Disabled;
+        let selected = enabled && self.search_address == self.random_address;
+        match op {
+            TERMINATE => {
+                self.initialisation_state = InitialisationState::Disabled;
+                None
+            }
+            INITIALISE if twice => {
+                if data == MASK
+                    || (data == 0x7f && self.short_address == MASK)
+                    || data == self.short_address
+                {
+                    self.initialisation_state = InitialisationState::Enabled;
+                }
+                None
+            }
+            RANDOMISE if twice => {
+                if enabled {
+                    self.random_address = self.rng.gen_range(0..=0xffffff);
+                }
+                None
+            }
+            COMPARE => (self.initialisation_state == InitialisationState::Enabled
+                && self.random_address <= self.search_address)
+                .then_some(YES),
+            WITHDRAW => {
+                if self.initialisation_state == InitialisationState::Enabled
+                    && self.random_address == self.search_address
+                {
+                    self.initialisation_state = InitialisationState::Withdrawn;
+                }
+                None
+            }
+            SEARCHADDRH => {
+                self.search_address = (self.search_address & 0x00ffff) | (data as u32) << 16;
+                None
+            }
+            SEARCHADDRM => {
+                self.search_address = (self.search_address & 0xff00ff) | (data as u32) << 8;
+                None
+            }
+            SEARCHADDRL => {
+                self.search_address = (self.search_address & 0xffff00) | data as u32;
+                None
+            }
+            PROGRAM_SHORT_ADDRESS => {
+                if selected && (data < 64 || data == MASK) {
+                    self.short_address = data;
+                }
+                None
+            }
+            VERIFY_SHORT_ADDRESS => (enabled && data == self.short_address).then_some(YES),
+            QUERY_SHORT_ADDRESS => selected.then_some(self.short_address),
+            DTR0 => {
+                self.dtr0 = data;
+                None
+            }
+            DTR1 => {
+                self.dtr1 = data;
+                None
+            }
+            DTR2 => {
+                self.dtr2 = data;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn read_memory(&mut self) -> Option<u8> {
+        if self.dtr1 != 0 {
+            return None;
+        }
+        let value = self.memory_bank_0.get(self.dtr0 as usize).copied();
+        self.dtr0 = self.dtr0.wrapping_add(1);
+        value
+    }
+
+    fn reset(&mut self) {
+        self.device_groups = 0;
+        self.quiescent = false;
+        self.power_cycle_notification = false;
+        self.operating_mode = 0;
+        self.application_active = self.application_controller;
+        for inst in &mut self.instances {
+            let instance_type = inst.instance_type;
+            let resolution = inst.resolution;
+            let input_value = inst.input_value;
+            *inst = SimInstance {
+                resolution,
+                input_value,
+                ..SimInstance::new(instance_type)
+            };
+        }
+        self.reset_state = true;
+    }
+
+    fn frame24(&mut self, frame: &[u8; 3], twice: bool) -> Option<u8> {
+        match frame[0] {
+            0xc1 => self.special_cmd(frame[1], frame[2], twice),
+            // DTR1:DTR0
+            0xc7 => {
+                self.dtr1 = frame[1];
+                self.dtr0 = frame[2];
+                None
+            }
+            // DTR2:DTR1
+            0xc9 => {
+                self.dtr2 = frame[1];
+                self.dtr1 = frame[2];
+                None
+            }
+            addr if addr & 0x01 == 0x01 && self.addressed(addr) => {
+                // Any configuration command except RESET leaves the reset state
+                if twice && !(frame[1] == 0xfe && frame[2] == opcode::RESET) {
+                    self.reset_state = false;
+                }
+                if frame[1] == 0xfe {
+                    self.device_cmd(frame[2], twice)
+                } else {
+                    // Instance commands only answer for a single instance
+                    let selected = self.selected_instances(frame[1]);
+                    let mut answer = None;
+                    for index in selected {
+                        answer = self.instance_cmd(index, frame[2], twice);
+                    }
+                    answer
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Build an event frame for the instance
+    fn event_frame(&self, index: usize, info: u16) -> Option<[u8; 3]> {
+        let inst = &self.instances[index];
+        if self.quiescent || !inst.enabled {
+            return None;
+        }
+        let info = info & 0x3ff;
+        if info < 24 && inst.event_filter & (1 << info) == 0 {
+            return None;
+        }
+        let (addr, source) = match inst.event_scheme {
+            event_scheme::DEVICE if self.short_address < 64 => {
+                (self.short_address << 1, inst.instance_type & 0x1f)
+            }
+            event_scheme::DEVICE_INSTANCE if self.short_address < 64 => {
+                (self.short_address << 1, 0x20 | index as u8)
+            }
+            event_scheme::DEVICE_GROUP => {
+                let group = (0..32).find(|g| self.device_groups & (1 << g) != 0)?;
+                (0x80 | group << 1, inst.instance_type & 0x1f)
+            }
+            event_scheme::INSTANCE_GROUP if inst.primary_group < 32 => {
+                (0xc0 | inst.primary_group << 1, inst.instance_type & 0x1f)
+            }
+            _ => (0x80 | (inst.instance_type & 0x1f) << 1, 0x20 | index as u8),
+        };
+        Some([addr, source << 2 | (info >> 8) as u8, info as u8])
+    }
+}
+
+/// Simulated control device with a number of input instances.
+#[derive(Clone)]
+pub struct DaliSimControlDevice {
+    state: Arc<Mutex<ControlState>>,
+}
+
+impl DaliSimControlDevice {
+    pub fn new(instances: Vec<SimInstance>) -> DaliSimControlDevice {
+        Self::with_rng(instances, StdRng::from_entropy())
+    }
+
+    /// Create a device whose RANDOMISE command generates a reproducible
+    /// sequence of random addresses
+    pub fn with_seed(instances: Vec<SimInstance>, seed: u64) -> DaliSimControlDevice {
+        Self::with_rng(instances, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(instances: Vec<SimInstance>, rng: StdRng) -> DaliSimControlDevice {
+        let mut memory_bank_0 = vec![0u8; 0x1b];
+        memory_bank_0[0x00] = 0x1a; // Last addressable memory location
+        memory_bank_0[0x15] = 2 << 2; // IEC 62386-101 version
+        memory_bank_0[0x16] = MASK; // No control gear
+        memory_bank_0[0x17] = 2 << 2; // IEC 62386-103 version
+        memory_bank_0[0x18] = 1; // Number of logical control devices
+        let state = ControlState {
+            short_address: MASK,
+            random_address: 0xffffff,
+            search_address: 0xffffff,
+            initialisation_state: InitialisationState::Disabled,
+            device_groups: 0,
+            dtr0: 0,
+            dtr1: 0,
+            dtr2: 0,
+            operating_mode: 0,
+            application_controller: false,
+            application_active: false,
+            quiescent: false,
+            power_cycle_seen: false,
+            power_cycle_notification: false,
+            reset_state: false,
+            memory_bank_0,
+            instances,
+            latched: Vec::new(),
+            last_event: None,
+            source_id: 0,
+            host: None,
+            rng,
+        };
+        DaliSimControlDevice {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut ControlState) -> R) -> R {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state)
+    }
+
+    pub fn set_short_address(&self, addr: Option<u8>) {
+        self.with_state(|s| s.short_address = addr.unwrap_or(MASK))
+    }
+
+    pub fn short_address(&self) -> Option<u8> {
+        self.with_state(|s| (s.short_address < 64).then_some(s.short_address))
+    }
+
+    pub fn set_random_address(&self, addr: u32) {
+        self.with_state(|s| s.random_address = addr & 0xffffff)
+    }
+
+    pub fn random_address(&self) -> u32 {
+        self.with_state(|s| s.random_address)
+    }
+
+    pub fn set_device_groups(&self, groups: u32) {
+        self.with_state(|s| s.device_groups = groups)
+    }
+
+    pub fn device_groups(&self) -> u32 {
+        self.with_state(|s| s.device_groups)
+    }
+
+    /// Make the device an application controller
+    pub fn set_application_controller(&self, present: bool) {
+        self.with_state(|s| {
+            s.application_controller = present;
+            s.application_active = present;
+        })
+    }
+
+    /// Set the contents of memory bank 0, starting at offset 0
+    pub fn set_memory_bank_0(&self, bank: &[u8]) {
+        self.with_state(|s| s.memory_bank_0 = bank.to_vec())
+    }
+
+    pub fn instances(&self) -> Vec<SimInstance> {
+        self.with_state(|s| s.instances.clone())
+    }
+
+    /// Modify the configuration of an instance
+    pub fn update_instance(&self, index: usize, f: impl FnOnce(&mut SimInstance)) {
+        self.with_state(|s| {
+            if let Some(inst) = s.instances.get_mut(index) {
+                f(inst)
+            }
+        })
+    }
+
+    /// Send an event from the instance with the given event information.
+    /// Nothing is sent if the event is disabled for the instance.
+    pub async fn trigger_event(&self, index: usize, info: u16) -> DynResult<()> {
+        let (frame, priority, source_id, mut host) = {
+            let state = self
+                .state
+                .lock()
+                .map_err(|_| "Control device lock failed")?;
+            if index >= state.instances.len() {
+                return Err(format!("No instance {}", index).into());
+            }
+            let Some(host) = &state.host else {
+                return Err("Control device not connected to a bus".into());
+            };
+            (
+                state.event_frame(index, info),
+                state.instances[index].event_priority,
+                state.source_id,
+                host.clone_box(),
+            )
+        };
+        if let Some(frame) = frame {
+            // Wait for the settling time of the event priority
+            let event = DaliSimEvent {
+                source_id,
+                timestamp: host.current_time() + timing::send_delay(priority.into(), false),
+                event_type: DaliBusEventType::Frame24(frame),
+            };
+            host.send_event(event).await?;
+        }
+        Ok(())
+    }
+
+    /// Press a push button
+    pub async fn press(&self, index: usize) -> DynResult<()> {
+        self.with_state(|s| {
+            if let Some(inst) = s.instances.get_mut(index) {
+                inst.input_value = 1;
+            }
+        });
+        self.trigger_event(index, push_button::PRESSED).await
+    }
+
+    /// Release a push button
+    pub async fn release(&self, index: usize) -> DynResult<()> {
+        self.with_state(|s| {
+            if let Some(inst) = s.instances.get_mut(index) {
+                inst.input_value = 0;
+            }
+        });
+        self.trigger_event(index, push_button::RELEASED).await
+    }
+
+    /// Set the input value of a sensor and send an event containing the
+    /// ten most significant bits of the value.
+    pub async fn set_input_value(&self, index: usize, value: u32) -> DynResult<()> {
+        let info = self.with_state(|s| {
+            let inst = s.instances.get_mut(index)?;
+            inst.input_value = value;
+            let res = inst.resolution as u32;
+            Some(if res > 10 {
+                value >> (res - 10)
+            } else {
+                value << (10 - res)
+            } as u16)
+        });
+        match info {
+            Some(info) => self.trigger_event(index, info).await,
+            None => Err(format!("No instance {}", index).into()),
+        }
+    }
+}
+
+impl DaliSimDevice for DaliSimControlDevice {
+    fn start(
+        &mut self,
+        mut host: Box<dyn DaliSimHost>,
+    ) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        self.with_state(|s| {
+            s.source_id = host.next_source_id();
+            s.host = Some(host);
+        });
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn stop(&mut self) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        self.with_state(|s| s.host = None);
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn description(&self) -> Option<DeviceDescription> {
+        Some(DeviceDescription::Control(self.into()))
+    }
+
+    fn event(&mut self, event: &DaliSimEvent) -> Option<DaliSimEvent> {
+        let DaliBusEventType::Frame24(frame) = event.event_type else {
+            return None;
+        };
+        let mut state = self.state.lock().ok()?;
+        if event.source_id == state.source_id {
+            return None;
+        }
+        let twice = match &state.last_event {
+            Some(DaliSimEvent {
+                timestamp: last_ts,
+                event_type: DaliBusEventType::Frame24(last_frame),
+                ..
+            }) => {
+                event.timestamp.duration_since(*last_ts) < FRAME_24_DURATION + SEND_TWICE_DURATION
+                    && frame == *last_frame
+            }
+            _ => false,
+        };
+        // A third identical frame starts a new pair
+        state.last_event = if twice { None } else { Some(event.clone()) };
+        debug!(
+            "Control device {} received: {:02x} {:02x} {:02x}",
+            state.short_address, frame[0], frame[1], frame[2]
+        );
+        let answer = state.frame24(&frame, twice)?;
+        let forward = DaliFrame::Frame24(frame);
+        Some(DaliSimEvent {
+            source_id: state.source_id,
+            timestamp: event.timestamp + timing::frame_duration(&forward) + REPLY_DELAY,
+            event_type: DaliBusEventType::Frame8(answer),
+        })
+    }
+}
+
+impl From<&DaliSimControlDevice> for ControlDescription {
+    fn from(dev: &DaliSimControlDevice) -> Self {
+        dev.with_state(|s| ControlDescription {
+            short_address: (s.short_address < 64).then_some(s.short_address),
+            random_address: s.random_address,
+            groups: (0..32)
+                .filter(|g| s.device_groups & (1 << g) != 0)
+                .collect(),
+            application_controller: s.application_controller,
+            instances: s
+                .instances
+                .iter()
+                .map(|i| InstanceDescription {
+                    instance_type: i.instance_type,
+                    resolution: Some(i.resolution),
+                })
+                .collect(),
+        })
+    }
+}
@@ -1,3 +1,4 @@
+use super::installation::DeviceDescription;
 use crate::drivers::driver::DaliBusEventType;
 use std::future::Future;
 use std::pin::Pin;
@@ -19,6 +20,12 @@ pub trait DaliSimHost: Send {
     ) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>>;
     fn current_time(&self) -> Instant;
     fn real_time(&self) -> bool;
+    /// Wait until the simulated time has reached `end`. In virtual time
+    /// all events up to `end` are dispatched and the clock is advanced
+    /// without waiting.
+    fn wait_until(&self, end: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+    /// Timestamp of the next event waiting to be dispatched
+    fn next_event_time(&self) -> Option<Instant>;
     fn next_source_id(&mut self) -> u32;
     fn clone_box(&self) -> Box<dyn DaliSimHost>;
 }
@@ -33,4 +40,9 @@ pub trait DaliSimDevice: Send {
     fn stop(&mut self) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>>;
     /// A new event has been dispatched on the bus
     fn event(&mut self, event: &DaliSimEvent) -> Option<DaliSimEvent>;
+    /// Describe the current configuration of the device, if it can be
+    /// part of an installation
+    fn description(&self) -> Option<DeviceDescription> {
+        None
+    }
 }
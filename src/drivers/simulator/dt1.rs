@@ -0,0 +1,178 @@
+//! Self-contained emergency gear, device type 1 (IEC 62386-202).
+//!
+//! Function and duration tests run when started by a command. Automatic
+//! test scheduling and the emergency mode itself are not simulated.
+
+use super::gear::DaliSimGear;
+use crate::drivers::driver::DaliBusEventType;
+use crate::drivers::send_flags::Flags;
+use crate::gear::dt1::{EmergencyTest, emergency_mode, emergency_status, failure};
+use std::time::{Duration, Instant};
+
+mod cmd {
+    pub const START_FUNCTION_TEST: u8 = 0xe3;
+    pub const START_DURATION_TEST: u8 = 0xe4;
+    pub const STOP_TEST: u8 = 0xe5;
+    pub const RESET_FUNCTION_TEST_DONE_FLAG: u8 = 0xe6;
+    pub const RESET_DURATION_TEST_DONE_FLAG: u8 = 0xe7;
+    pub const STORE_DTR_AS_EMERGENCY_LEVEL: u8 = 0xe9;
+    pub const QUERY_BATTERY_CHARGE: u8 = 0xf1;
+    pub const QUERY_DURATION_TEST_RESULT: u8 = 0xf3;
+    pub const QUERY_EMERGENCY_LEVEL: u8 = 0xf6;
+    pub const QUERY_RATED_DURATION: u8 = 0xf9;
+    pub const QUERY_EMERGENCY_MODE: u8 = 0xfa;
+    pub const QUERY_FEATURES: u8 = 0xfb;
+    pub const QUERY_FAILURE_STATUS: u8 = 0xfc;
+    pub const QUERY_EMERGENCY_STATUS: u8 = 0xfd;
+}
+
+// Failures that are detected by a test
+const HARDWARE_FAILURES: u8 = failure::CIRCUIT | failure::BATTERY | failure::EMERGENCY_LAMP;
+const BATTERY_FULL: u8 = 254;
+
+pub struct DaliSimEmergency {
+    pub features: u8,
+    /// Rated duration in units of 2 minutes
+    pub rated_duration: u8,
+    pub emergency_level: u8,
+    pub battery_charge: u8,
+    pub function_test_time: Duration,
+    /// Hardware failures, [`failure`] bits, that are detected by the
+    /// next test. `failure::BATTERY_DURATION` makes a duration test end
+    /// after half the rated duration.
+    pub faults: u8,
+    failure_status: u8,
+    status: u8,
+    duration_result: u8,
+    test: Option<(EmergencyTest, Instant)>,
+}
+
+impl DaliSimEmergency {
+    pub fn new() -> DaliSimEmergency {
+        DaliSimEmergency {
+            features: 0x01,
+            rated_duration: 90,
+            emergency_level: 0xfe,
+            battery_charge: BATTERY_FULL,
+            function_test_time: Duration::from_secs(10),
+            faults: 0,
+            failure_status: 0,
+            status: 0,
+            duration_result: 0,
+            test: None,
+        }
+    }
+
+    fn test_duration(&self, test: EmergencyTest) -> Duration {
+        match test {
+            EmergencyTest::Function => self.function_test_time,
+            EmergencyTest::Duration => {
+                let rated = Duration::from_secs(120) * self.rated_duration as u32;
+                if self.faults & failure::BATTERY_DURATION != 0 {
+                    rated / 2
+                } else {
+                    rated
+                }
+            }
+        }
+    }
+
+    /// Finish a running test if it has run long enough
+    fn update(&mut self, now: Instant) {
+        let Some((test, start)) = self.test else {
+            return;
+        };
+        let duration = self.test_duration(test);
+        if now.saturating_duration_since(start) < duration {
+            return;
+        }
+        self.test = None;
+        let (done, failed) = match test {
+            EmergencyTest::Function => {
+                (emergency_status::FUNCTION_TEST_DONE, failure::FUNCTION_TEST)
+            }
+            EmergencyTest::Duration => {
+                self.duration_result = (duration.as_secs() / 120).min(255) as u8;
+                self.battery_charge = 0;
+                (emergency_status::DURATION_TEST_DONE, failure::DURATION_TEST)
+            }
+        };
+        self.status |= done;
+        let faults = if test == EmergencyTest::Duration {
+            self.faults & (HARDWARE_FAILURES | failure::BATTERY_DURATION)
+        } else {
+            self.faults & HARDWARE_FAILURES
+        };
+        if faults != 0 {
+            self.failure_status |= faults | failed;
+        } else {
+            self.failure_status &= !failed;
+        }
+    }
+
+    fn start_test(&mut self, test: EmergencyTest, now: Instant) {
+        self.test = Some((test, now));
+    }
+
+    fn emergency_mode(&self) -> u8 {
+        match self.test {
+            Some((EmergencyTest::Function, _)) => emergency_mode::FUNCTION_TEST,
+            Some((EmergencyTest::Duration, _)) => emergency_mode::DURATION_TEST,
+            None => emergency_mode::NORMAL,
+        }
+    }
+
+    fn emergency_status(&self) -> u8 {
+        if self.battery_charge == BATTERY_FULL {
+            self.status | emergency_status::BATTERY_CHARGED
+        } else {
+            self.status
+        }
+    }
+}
+
+impl Default for DaliSimEmergency {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn answer(value: u8) -> Option<DaliBusEventType> {
+    Some(DaliBusEventType::Frame8(value))
+}
+
+/// Application extended commands for device type 1
+pub(super) fn extended_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
+    if matches!(cmd, 0xe9..=0xef) && !flags.send_twice() {
+        return None;
+    }
+    let emergency = &mut dev.emergency;
+    emergency.update(now);
+    match cmd {
+        cmd::START_FUNCTION_TEST => emergency.start_test(EmergencyTest::Function, now),
+        cmd::START_DURATION_TEST => emergency.start_test(EmergencyTest::Duration, now),
+        cmd::STOP_TEST => emergency.test = None,
+        cmd::RESET_FUNCTION_TEST_DONE_FLAG => {
+            emergency.status &= !emergency_status::FUNCTION_TEST_DONE
+        }
+        cmd::RESET_DURATION_TEST_DONE_FLAG => {
+            emergency.status &= !emergency_status::DURATION_TEST_DONE
+        }
+        cmd::STORE_DTR_AS_EMERGENCY_LEVEL => emergency.emergency_level = dev.dtr0,
+        cmd::QUERY_BATTERY_CHARGE => return answer(emergency.battery_charge),
+        cmd::QUERY_DURATION_TEST_RESULT => return answer(emergency.duration_result),
+        cmd::QUERY_EMERGENCY_LEVEL => return answer(emergency.emergency_level),
+        cmd::QUERY_RATED_DURATION => return answer(emergency.rated_duration),
+        cmd::QUERY_EMERGENCY_MODE => return answer(emergency.emergency_mode()),
+        cmd::QUERY_FEATURES => return answer(emergency.features),
+        cmd::QUERY_FAILURE_STATUS => return answer(emergency.failure_status),
+        cmd::QUERY_EMERGENCY_STATUS => return answer(emergency.emergency_status()),
+        _ => {}
+    }
+    None
+}
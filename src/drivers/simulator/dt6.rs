@@ -0,0 +1,121 @@
+//! LED gear, device type 6 (IEC 62386-207).
+//!
+//! Only the configuration and query commands are implemented. The
+//! selected dimming curve doesn't change the simulated light output.
+
+use super::gear::DaliSimGear;
+use crate::drivers::driver::DaliBusEventType;
+use crate::drivers::send_flags::Flags;
+use crate::gear::dt6::dimming_curve;
+
+mod cmd {
+    pub const SELECT_DIMMING_CURVE: u8 = 0xe3;
+    pub const STORE_DTR_AS_FAST_FADE_TIME: u8 = 0xe4;
+    pub const QUERY_GEAR_TYPE: u8 = 0xed;
+    pub const QUERY_DIMMING_CURVE: u8 = 0xee;
+    pub const QUERY_POSSIBLE_OPERATING_MODES: u8 = 0xef;
+    pub const QUERY_FEATURES: u8 = 0xf0;
+    pub const QUERY_FAILURE_STATUS: u8 = 0xf1;
+    pub const QUERY_SHORT_CIRCUIT: u8 = 0xf2;
+    pub const QUERY_THERMAL_OVERLOAD: u8 = 0xf8;
+    pub const QUERY_OPERATING_MODE: u8 = 0xfc;
+    pub const QUERY_FAST_FADE_TIME: u8 = 0xfd;
+    pub const QUERY_MIN_FAST_FADE_TIME: u8 = 0xfe;
+}
+
+// Bit in the operating mode
+const NON_LOG_DIMMING_CURVE: u8 = 0x10;
+// Longest fast fade time in units of 25 ms
+const MAX_FAST_FADE_TIME: u8 = 27;
+
+pub struct DaliSimLed {
+    /// Bit 0: integrated power supply, 1: integrated LED module, 2: AC
+    /// supply, 3: DC supply
+    pub gear_type: u8,
+    /// Bit 0: PWM, 1: AM, 2: current controlled, 3: high current pulse
+    pub possible_operating_modes: u8,
+    pub features: u8,
+    /// Failure flags as reported by QUERY FAILURE STATUS
+    pub failure_status: u8,
+    pub dimming_curve: u8,
+    pub fast_fade_time: u8,
+    pub min_fast_fade_time: u8,
+}
+
+impl DaliSimLed {
+    pub fn new() -> DaliSimLed {
+        DaliSimLed {
+            gear_type: 0x05,
+            possible_operating_modes: 0x01,
+            features: 0x00,
+            failure_status: 0x00,
+            dimming_curve: dimming_curve::STANDARD,
+            fast_fade_time: 0,
+            min_fast_fade_time: 4,
+        }
+    }
+
+    fn operating_mode(&self) -> u8 {
+        let mode = self.possible_operating_modes & (!self.possible_operating_modes + 1);
+        if self.dimming_curve == dimming_curve::STANDARD {
+            mode
+        } else {
+            mode | NON_LOG_DIMMING_CURVE
+        }
+    }
+}
+
+impl Default for DaliSimLed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn answer(value: u8) -> Option<DaliBusEventType> {
+    Some(DaliBusEventType::Frame8(value))
+}
+
+fn yes_no(p: bool) -> Option<DaliBusEventType> {
+    if p { answer(0xff) } else { None }
+}
+
+/// Application extended commands for device type 6
+pub(super) fn extended_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    flags: Flags,
+) -> Option<DaliBusEventType> {
+    if matches!(cmd, 0xe0..=0xe4) && !flags.send_twice() {
+        return None;
+    }
+    let led = &mut dev.led;
+    match cmd {
+        cmd::SELECT_DIMMING_CURVE if dev.dtr0 <= dimming_curve::LINEAR => {
+            led.dimming_curve = dev.dtr0
+        }
+        cmd::STORE_DTR_AS_FAST_FADE_TIME => {
+            led.fast_fade_time = match dev.dtr0 {
+                0 => 0,
+                t => t.clamp(led.min_fast_fade_time, MAX_FAST_FADE_TIME),
+            }
+        }
+        cmd::QUERY_GEAR_TYPE => return answer(led.gear_type),
+        cmd::QUERY_DIMMING_CURVE => return answer(led.dimming_curve),
+        cmd::QUERY_POSSIBLE_OPERATING_MODES => return answer(led.possible_operating_modes),
+        cmd::QUERY_FEATURES => return answer(led.features),
+        cmd::QUERY_FAILURE_STATUS => return answer(led.failure_status),
+        cmd::QUERY_SHORT_CIRCUIT..=cmd::QUERY_THERMAL_OVERLOAD => {
+            return yes_no(led.failure_status & (1 << (cmd - cmd::QUERY_SHORT_CIRCUIT)) != 0);
+        }
+        cmd::QUERY_OPERATING_MODE => return answer(led.operating_mode()),
+        cmd::QUERY_FAST_FADE_TIME => return answer(led.fast_fade_time),
+        cmd::QUERY_MIN_FAST_FADE_TIME => return answer(led.min_fast_fade_time),
+        _ => {}
+    }
+    None
+}
+
+pub(super) fn reset(dev: &mut DaliSimGear) {
+    dev.led.dimming_curve = dimming_curve::STANDARD;
+    dev.led.fast_fade_time = 0;
+}
@@ -0,0 +1,427 @@
+//! Colour control gear, device type 8 (IEC 62386-209).
+//!
+//! The xy-coordinate and colour temperature Tc colour types are
+//! implemented. New colour values are written to temporary registers and
+//! applied by ACTIVATE, or by the next arc power command when automatic
+//! activation is enabled. The colour fades over the fade time of the gear.
+//! xy-coordinates outside the gamut of the gear are moved to its edge and
+//! flagged as out of range.
+
+use super::gear::{self, DaliSimGear};
+use crate::common::defs::MASK;
+use crate::drivers::driver::DaliBusEventType;
+use crate::drivers::send_flags::Flags;
+use crate::gear::dt8::{colour_type, colour_value as value};
+use std::time::{Duration, Instant};
+
+mod cmd {
+    pub const SET_TEMPORARY_X_COORDINATE: u8 = 0xe0;
+    pub const SET_TEMPORARY_Y_COORDINATE: u8 = 0xe1;
+    pub const ACTIVATE: u8 = 0xe2;
+    pub const X_COORDINATE_STEP_UP: u8 = 0xe3;
+    pub const X_COORDINATE_STEP_DOWN: u8 = 0xe4;
+    pub const Y_COORDINATE_STEP_UP: u8 = 0xe5;
+    pub const Y_COORDINATE_STEP_DOWN: u8 = 0xe6;
+    pub const SET_TEMPORARY_COLOUR_TEMPERATURE: u8 = 0xe7;
+    pub const COLOUR_TEMPERATURE_STEP_COOLER: u8 = 0xe8;
+    pub const COLOUR_TEMPERATURE_STEP_WARMER: u8 = 0xe9;
+    pub const COPY_REPORT_TO_TEMPORARY: u8 = 0xee;
+    pub const STORE_COLOUR_TEMPERATURE_LIMIT: u8 = 0xf2;
+    pub const STORE_GEAR_FEATURES_STATUS: u8 = 0xf3;
+    pub const QUERY_GEAR_FEATURES_STATUS: u8 = 0xf7;
+    pub const QUERY_COLOUR_STATUS: u8 = 0xf8;
+    pub const QUERY_COLOUR_TYPE_FEATURES: u8 = 0xf9;
+    pub const QUERY_COLOUR_VALUE: u8 = 0xfa;
+    pub const QUERY_RGBWAF_CONTROL: u8 = 0xfb;
+    pub const QUERY_ASSIGNED_COLOUR: u8 = 0xfc;
+}
+
+// QUERY COLOUR STATUS flags
+const XY_OUT_OF_RANGE: u8 = 0x01;
+const TC_OUT_OF_RANGE: u8 = 0x02;
+
+// Bit in gear features/status
+const AUTOMATIC_ACTIVATION: u8 = 0x01;
+
+const MASK16: u16 = 0xffff;
+const XY_STEP: u16 = 256;
+
+/// A colour setting. Values that are not set are 0xffff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Colour {
+    /// One of the [`colour_type`] values, or MASK if not set
+    pub colour_type: u8,
+    /// x-coordinate scaled by 65536
+    pub x: u16,
+    /// y-coordinate scaled by 65536
+    pub y: u16,
+    /// Colour temperature in mirek
+    pub tc: u16,
+}
+
+impl Colour {
+    const UNSET: Colour = Colour {
+        colour_type: MASK,
+        x: MASK16,
+        y: MASK16,
+        tc: MASK16,
+    };
+}
+
+pub struct DaliSimColour {
+    /// Supported colour types, as reported by QUERY COLOUR TYPE FEATURES
+    pub features: u8,
+    /// Bit 0: automatic activation
+    pub gear_features: u8,
+    pub tc_coolest: u16,
+    pub tc_warmest: u16,
+    pub tc_physical_coolest: u16,
+    pub tc_physical_warmest: u16,
+    /// Primaries spanning the xy-coordinates the gear can produce, scaled
+    /// by 65536
+    pub gamut: [(u16, u16); 3],
+    /// The colour the gear is at, or is fading to
+    pub report: Colour,
+    temporary: Colour,
+    status: u8,
+
+    fade_start: Colour,
+    fade_start_time: Instant,
+    fade_duration: Duration,
+}
+
+impl DaliSimColour {
+    pub fn new() -> DaliSimColour {
+        let report = Colour {
+            colour_type: colour_type::TC,
+            // D65 white point
+            x: 20493,
+            y: 21561,
+            // 4000 K
+            tc: 250,
+        };
+        DaliSimColour {
+            features: (colour_type::XY | colour_type::TC) >> 4,
+            gear_features: 0,
+            tc_coolest: 153,
+            tc_warmest: 370,
+            tc_physical_coolest: 153,
+            tc_physical_warmest: 370,
+            // sRGB primaries
+            gamut: [(41943, 21627), (19661, 39322), (9830, 3932)],
+            report,
+            temporary: Colour::UNSET,
+            status: 0,
+            fade_start: report,
+            fade_start_time: Instant::now(),
+            fade_duration: Duration::ZERO,
+        }
+    }
+
+    fn supports(&self, colour_type: u8) -> bool {
+        colour_type != MASK && (self.features << 4) & colour_type != 0
+    }
+
+    /// The colour at time `now`, taking a running fade into account
+    pub fn actual(&self, now: Instant) -> Colour {
+        let elapsed = now.saturating_duration_since(self.fade_start_time);
+        if elapsed >= self.fade_duration || self.fade_start.colour_type != self.report.colour_type {
+            return self.report;
+        }
+        let interpolate = |start: u16, end: u16| {
+            let elapsed = elapsed.as_millis() as i64;
+            let duration = self.fade_duration.as_millis() as i64;
+            (start as i64 + (end as i64 - start as i64) * elapsed / duration) as u16
+        };
+        Colour {
+            colour_type: self.report.colour_type,
+            x: interpolate(self.fade_start.x, self.report.x),
+            y: interpolate(self.fade_start.y, self.report.y),
+            tc: interpolate(self.fade_start.tc, self.report.tc),
+        }
+    }
+
+    /// Change colour without fading
+    fn set_colour(&mut self, colour: Colour, now: Instant) {
+        self.fade_to(colour, Duration::ZERO, now);
+    }
+
+    fn fade_to(&mut self, colour: Colour, duration: Duration, now: Instant) {
+        self.fade_start = self.actual(now);
+        self.fade_start_time = now;
+        self.fade_duration = duration;
+        self.report = colour;
+    }
+
+    /// Clamp a colour temperature to the configured limits and update the
+    /// out of range flag
+    fn limit_tc(&mut self, tc: u16) -> u16 {
+        let limited = tc.clamp(self.tc_coolest, self.tc_warmest);
+        if limited != tc {
+            self.status |= TC_OUT_OF_RANGE;
+        } else {
+            self.status &= !TC_OUT_OF_RANGE;
+        }
+        limited
+    }
+
+    /// Move xy-coordinates outside the gamut to the closest point on its
+    /// edge and update the out of range flag
+    fn limit_xy(&mut self, x: u16, y: u16) -> (u16, u16) {
+        let p = (x as f64, y as f64);
+        let [a, b, c] = self.gamut.map(|(x, y)| (x as f64, y as f64));
+        let side =
+            |s: (f64, f64), t: (f64, f64)| (t.0 - s.0) * (p.1 - s.1) - (t.1 - s.1) * (p.0 - s.0);
+        let sides = [side(a, b), side(b, c), side(c, a)];
+        if sides.iter().all(|&d| d >= 0.0) || sides.iter().all(|&d| d <= 0.0) {
+            self.status &= !XY_OUT_OF_RANGE;
+            return (x, y);
+        }
+        self.status |= XY_OUT_OF_RANGE;
+        let closest = |s: (f64, f64), t: (f64, f64)| {
+            let (dx, dy) = (t.0 - s.0, t.1 - s.1);
+            let u = (((p.0 - s.0) * dx + (p.1 - s.1) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+            (s.0 + u * dx, s.1 + u * dy)
+        };
+        let distance = |q: &(f64, f64)| (q.0 - p.0).powi(2) + (q.1 - p.1).powi(2);
+        let q = [closest(a, b), closest(b, c), closest(c, a)]
+            .into_iter()
+            .min_by(|q1, q2| distance(q1).total_cmp(&distance(q2)))
+            .unwrap();
+        (q.0.round() as u16, q.1.round() as u16)
+    }
+
+    /// Apply the temporary colour, fading over `duration`
+    fn activate(&mut self, duration: Duration, now: Instant) {
+        let temporary = std::mem::replace(&mut self.temporary, Colour::UNSET);
+        if !self.supports(temporary.colour_type) {
+            return;
+        }
+        let current = self.actual(now);
+        let mut colour = Colour {
+            colour_type: temporary.colour_type,
+            ..self.report
+        };
+        match temporary.colour_type {
+            colour_type::XY => {
+                if temporary.x != MASK16 {
+                    colour.x = temporary.x;
+                }
+                if temporary.y != MASK16 {
+                    colour.y = temporary.y;
+                }
+                (colour.x, colour.y) = self.limit_xy(colour.x, colour.y);
+            }
+            colour_type::TC if temporary.tc != MASK16 => {
+                colour.tc = self.limit_tc(temporary.tc);
+            }
+            _ => {}
+        }
+        if current.colour_type != colour.colour_type {
+            // Changing colour type doesn't fade
+            self.set_colour(colour, now);
+        } else {
+            self.fade_to(colour, duration, now);
+        }
+    }
+
+    fn reset(&mut self, now: Instant) {
+        self.gear_features &= !AUTOMATIC_ACTIVATION;
+        self.tc_coolest = self.tc_physical_coolest;
+        self.tc_warmest = self.tc_physical_warmest;
+        self.temporary = Colour::UNSET;
+        self.status = 0;
+        let colour = if self.supports(colour_type::TC) {
+            Colour {
+                colour_type: colour_type::TC,
+                ..self.report
+            }
+        } else {
+            Colour {
+                colour_type: colour_type::XY,
+                ..self.report
+            }
+        };
+        self.set_colour(colour, now);
+    }
+}
+
+impl Default for DaliSimColour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn answer(value: u8) -> Option<DaliBusEventType> {
+    Some(DaliBusEventType::Frame8(value))
+}
+
+const NO_REPLY: Option<DaliBusEventType> = None;
+
+/// Answer with the MSB of a 16-bit value and put the LSB in DTR0
+fn answer16(dev: &mut DaliSimGear, value: u16) -> Option<DaliBusEventType> {
+    dev.dtr0 = (value & 0xff) as u8;
+    answer((value >> 8) as u8)
+}
+
+fn dtr16(dev: &DaliSimGear) -> u16 {
+    (dev.dtr1 as u16) << 8 | dev.dtr0 as u16
+}
+
+fn query_colour_value(dev: &mut DaliSimGear, now: Instant) -> Option<DaliBusEventType> {
+    let colour = &dev.colour;
+    let actual = colour.actual(now);
+    let only_if = |colour: &Colour, colour_type, value| {
+        if colour.colour_type == colour_type {
+            value
+        } else {
+            MASK16
+        }
+    };
+    let value = match dev.dtr0 {
+        value::X_COORDINATE => only_if(&actual, colour_type::XY, actual.x),
+        value::Y_COORDINATE => only_if(&actual, colour_type::XY, actual.y),
+        value::COLOUR_TEMPERATURE => only_if(&actual, colour_type::TC, actual.tc),
+        value::NUMBER_OF_PRIMARIES => 0,
+        value::TC_COOLEST => colour.tc_coolest,
+        value::TC_PHYSICAL_COOLEST => colour.tc_physical_coolest,
+        value::TC_WARMEST => colour.tc_warmest,
+        value::TC_PHYSICAL_WARMEST => colour.tc_physical_warmest,
+        value::TEMPORARY_X_COORDINATE => colour.temporary.x,
+        value::TEMPORARY_Y_COORDINATE => colour.temporary.y,
+        value::TEMPORARY_COLOUR_TEMPERATURE => colour.temporary.tc,
+        value::TEMPORARY_COLOUR_TYPE => return answer(colour.temporary.colour_type),
+        value::REPORT_X_COORDINATE => only_if(&colour.report, colour_type::XY, colour.report.x),
+        value::REPORT_Y_COORDINATE => only_if(&colour.report, colour_type::XY, colour.report.y),
+        value::REPORT_COLOUR_TEMPERATURE => {
+            only_if(&colour.report, colour_type::TC, colour.report.tc)
+        }
+        value::REPORT_COLOUR_TYPE => return answer(colour.report.colour_type),
+        _ => MASK16,
+    };
+    answer16(dev, value)
+}
+
+fn store_tc_limit(dev: &mut DaliSimGear) {
+    let value = dtr16(dev);
+    if value == 0 || value == MASK16 {
+        return;
+    }
+    let colour = &mut dev.colour;
+    match dev.dtr2 {
+        0 => {
+            colour.tc_coolest = value.clamp(colour.tc_physical_coolest, colour.tc_physical_warmest);
+            colour.tc_warmest = colour.tc_warmest.max(colour.tc_coolest);
+        }
+        1 => {
+            colour.tc_warmest = value.clamp(colour.tc_physical_coolest, colour.tc_physical_warmest);
+            colour.tc_coolest = colour.tc_coolest.min(colour.tc_warmest);
+        }
+        2 => {
+            colour.tc_physical_coolest = value;
+            colour.tc_physical_warmest = colour.tc_physical_warmest.max(value);
+            colour.tc_coolest = colour.tc_coolest.max(value);
+            colour.tc_warmest = colour.tc_warmest.max(value);
+        }
+        3 => {
+            colour.tc_physical_warmest = value;
+            colour.tc_physical_coolest = colour.tc_physical_coolest.min(value);
+            colour.tc_coolest = colour.tc_coolest.min(value);
+            colour.tc_warmest = colour.tc_warmest.min(value);
+        }
+        _ => {}
+    }
+}
+
+/// Change the active colour one step without fading
+fn step(dev: &mut DaliSimGear, colour_type: u8, now: Instant, change: impl Fn(&mut Colour)) {
+    let colour = &mut dev.colour;
+    let mut actual = colour.actual(now);
+    if actual.colour_type == colour_type {
+        change(&mut actual);
+        colour.set_colour(actual, now);
+    }
+}
+
+/// Application extended commands for device type 8
+pub(super) fn extended_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
+    if matches!(cmd, 0xf0..=0xf6) && !flags.send_twice() {
+        return NO_REPLY;
+    }
+    match cmd {
+        cmd::SET_TEMPORARY_X_COORDINATE => {
+            dev.colour.temporary.x = dtr16(dev);
+            dev.colour.temporary.colour_type = colour_type::XY;
+        }
+        cmd::SET_TEMPORARY_Y_COORDINATE => {
+            dev.colour.temporary.y = dtr16(dev);
+            dev.colour.temporary.colour_type = colour_type::XY;
+        }
+        cmd::ACTIVATE => {
+            let duration = gear::fade_duration(dev);
+            dev.colour.activate(duration, now);
+        }
+        cmd::X_COORDINATE_STEP_UP => step(dev, colour_type::XY, now, |c| {
+            c.x = c.x.saturating_add(XY_STEP).min(MASK16 - 1)
+        }),
+        cmd::X_COORDINATE_STEP_DOWN => step(dev, colour_type::XY, now, |c| {
+            c.x = c.x.saturating_sub(XY_STEP)
+        }),
+        cmd::Y_COORDINATE_STEP_UP => step(dev, colour_type::XY, now, |c| {
+            c.y = c.y.saturating_add(XY_STEP).min(MASK16 - 1)
+        }),
+        cmd::Y_COORDINATE_STEP_DOWN => step(dev, colour_type::XY, now, |c| {
+            c.y = c.y.saturating_sub(XY_STEP)
+        }),
+        cmd::SET_TEMPORARY_COLOUR_TEMPERATURE => {
+            dev.colour.temporary.tc = dtr16(dev);
+            dev.colour.temporary.colour_type = colour_type::TC;
+        }
+        cmd::COLOUR_TEMPERATURE_STEP_COOLER => {
+            let coolest = dev.colour.tc_coolest;
+            step(dev, colour_type::TC, now, |c| {
+                c.tc = c.tc.saturating_sub(1).max(coolest)
+            })
+        }
+        cmd::COLOUR_TEMPERATURE_STEP_WARMER => {
+            let warmest = dev.colour.tc_warmest;
+            step(dev, colour_type::TC, now, |c| {
+                c.tc = c.tc.saturating_add(1).min(warmest)
+            })
+        }
+        cmd::COPY_REPORT_TO_TEMPORARY => dev.colour.temporary = dev.colour.report,
+        cmd::STORE_COLOUR_TEMPERATURE_LIMIT => store_tc_limit(dev),
+        cmd::STORE_GEAR_FEATURES_STATUS => {
+            dev.colour.gear_features = (dev.colour.gear_features & !AUTOMATIC_ACTIVATION)
+                | (dev.dtr0 & AUTOMATIC_ACTIVATION)
+        }
+        cmd::QUERY_GEAR_FEATURES_STATUS => return answer(dev.colour.gear_features),
+        cmd::QUERY_COLOUR_STATUS => {
+            let colour = &dev.colour;
+            return answer(colour.status | colour.actual(now).colour_type);
+        }
+        cmd::QUERY_COLOUR_TYPE_FEATURES => return answer(dev.colour.features),
+        cmd::QUERY_COLOUR_VALUE => return query_colour_value(dev, now),
+        // No RGBWAF channels
+        cmd::QUERY_RGBWAF_CONTROL | cmd::QUERY_ASSIGNED_COLOUR => return answer(MASK),
+        _ => {}
+    }
+    NO_REPLY
+}
+
+/// Called for arc power commands. Applies the temporary colour if
+/// automatic activation is enabled.
+pub(super) fn arc_power(dev: &mut DaliSimGear, duration: Duration, now: Instant) {
+    if dev.colour.gear_features & AUTOMATIC_ACTIVATION != 0 {
+        dev.colour.activate(duration, now);
+    }
+}
+
+pub(super) fn reset(dev: &mut DaliSimGear, now: Instant) {
+    dev.colour.reset(now);
+}
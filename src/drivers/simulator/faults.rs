@@ -0,0 +1,105 @@
+//! Fault injection for the simulated bus.
+//!
+//! Faults are applied to events when they are dispatched, so all devices
+//! on the bus, including drivers, see the same faulty bus.
+
+use super::device::DaliSimEvent;
+use crate::drivers::driver::DaliBusEventType;
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+
+/// Probabilities, 0.0 - 1.0, for random faults
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    /// A backward frame is received as a framing error
+    pub corrupt_reply: f64,
+    /// A backward frame is lost
+    pub drop_reply: f64,
+    /// A forward frame is lost
+    pub drop_frame: f64,
+}
+
+/// Identifies a device added to a
+/// [`DaliBusSim`](super::simulator::DaliBusSim)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DeviceId(pub(super) usize);
+
+pub(super) struct FaultInjector {
+    config: FaultConfig,
+    rng: StdRng,
+    powered: bool,
+    unresponsive: HashSet<DeviceId>,
+}
+
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        FaultInjector {
+            config: FaultConfig::default(),
+            rng: StdRng::from_entropy(),
+            powered: true,
+            unresponsive: HashSet::new(),
+        }
+    }
+
+    pub fn configure(&mut self, config: FaultConfig, seed: Option<u64>) {
+        self.config = config;
+        if let Some(seed) = seed {
+            self.rng = StdRng::seed_from_u64(seed);
+        }
+    }
+
+    pub fn set_responsive(&mut self, id: DeviceId, responsive: bool) {
+        if responsive {
+            self.unresponsive.remove(&id);
+        } else {
+            self.unresponsive.insert(id);
+        }
+    }
+
+    /// Returns true if events generated by the device should be discarded
+    pub fn is_unresponsive(&self, id: DeviceId) -> bool {
+        self.unresponsive.contains(&id)
+    }
+
+    fn happens(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.gen_bool(probability.min(1.0))
+    }
+
+    /// Apply faults to an event about to be dispatched. Returns None if
+    /// the event is lost.
+    pub fn apply(&mut self, event: DaliSimEvent) -> Option<DaliSimEvent> {
+        match event.event_type {
+            DaliBusEventType::BusPowerOff => self.powered = false,
+            DaliBusEventType::BusPowerOn => self.powered = true,
+            _ if !self.powered => {
+                debug!("Bus unpowered, dropping {:?}", event);
+                return None;
+            }
+            DaliBusEventType::Frame8(_) => {
+                if self.happens(self.config.drop_reply) {
+                    debug!("Dropping reply {:?}", event);
+                    return None;
+                }
+                if self.happens(self.config.corrupt_reply) {
+                    debug!("Corrupting reply {:?}", event);
+                    return Some(DaliSimEvent {
+                        event_type: DaliBusEventType::FramingError,
+                        ..event
+                    });
+                }
+            }
+            DaliBusEventType::Frame16(_)
+            | DaliBusEventType::Frame24(_)
+            | DaliBusEventType::Frame25(_)
+                if self.happens(self.config.drop_frame) =>
+            {
+                debug!("Dropping frame {:?}", event);
+                return None;
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+}
@@ -1,41 +1,196 @@
-use super::device::{DaliSimHost, DaliSimEvent, DaliSimDevice};
-use crate::defs::common::MASK;
-use crate::defs::gear:: {cmd,status,device_type, light_source};
-use crate::drivers::driver::{DaliBusEventType};
+use super::device::{DaliSimDevice, DaliSimEvent, DaliSimHost};
+use super::dt1::{self, DaliSimEmergency};
+use super::dt6::{self, DaliSimLed};
+use super::dt8::{self, DaliSimColour};
+use super::installation::DeviceDescription;
+use super::timing::{self, FRAME_16_DURATION, INIT_TIMEOUT, REPLY_DELAY, SEND_TWICE_DURATION};
+use crate::common::defs::MASK;
+use crate::drivers::driver::{DaliBusEventType, DaliFrame};
 use crate::drivers::send_flags::Flags;
-use std::time::Instant;
-use std::time::Duration;
+use crate::gear::device_type::types as device_type;
+use crate::gear::light_source;
+use crate::gear::status::flag as status;
+use crate::utils::memory_banks::MemoryBank0Info;
+use log::debug;
 use std::future;
 use std::future::Future;
 use std::pin::Pin;
-use super::timing::{FRAME_8_DURATION, FRAME_16_DURATION,
-		    REPLY_DELAY, SEND_TWICE_DURATION, INIT_TIMEOUT};
+use std::time::Duration;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Opcode bytes of the commands handled by the gear
+mod cmd {
+    pub const OFF: u8 = 0x00;
+
+    pub const UP: u8 = 0x01;
+    pub const DOWN: u8 = 0x02;
+
+    pub const STEP_UP: u8 = 0x03;
+    pub const STEP_DOWN: u8 = 0x04;
+
+    pub const RECALL_MAX_LEVEL: u8 = 0x05;
+    pub const RECALL_MIN_LEVEL: u8 = 0x06;
+
+    pub const STEP_DOWN_AND_OFF: u8 = 0x07;
+    pub const ON_AND_STEP_UP: u8 = 0x08;
+
+    pub const ENABLE_DAPC: u8 = 0x09;
+    pub const GO_TO_LAST_ACTIVE_LEVEL: u8 = 0x0a;
+
+    pub const GO_TO_SCENE_0: u8 = 0x10;
+    pub const GO_TO_SCENE_15: u8 = 0x1f;
+
+    pub const RESET: u8 = 0x20;
+    pub const STORE_ACTUAL_LEVEL_IN_DTR0: u8 = 0x21;
+    pub const SAVE_PERSISTENT_VARIABLES: u8 = 0x22;
+    pub const SET_OPERATING_MODE: u8 = 0x23;
+    pub const RESET_MEMORY_BANK: u8 = 0x24;
+
+    pub const IDENTIFY_DEVICE: u8 = 0x25;
+    pub const SET_MAX_LEVEL: u8 = 0x2a;
+    pub const SET_MIN_LEVEL: u8 = 0x2b;
+    pub const SET_SYSTEM_FAILURE_LEVEL: u8 = 0x2c;
+    pub const SET_POWER_ON_LEVEL: u8 = 0x2d;
+    pub const SET_FADE_TIME: u8 = 0x2e;
+    pub const SET_FADE_RATE: u8 = 0x2f;
+    pub const SET_EXTENDED_FADE_TIME: u8 = 0x30;
+
+    pub const SET_SCENE_0: u8 = 0x40;
+    pub const SET_SCENE_15: u8 = 0x4f;
+
+    pub const REMOVE_FROM_SCENE_0: u8 = 0x50;
+    pub const REMOVE_FROM_SCENE_15: u8 = 0x5f;
+
+    pub const ADD_TO_GROUP_0: u8 = 0x60;
+    pub const ADD_TO_GROUP_15: u8 = 0x6f;
+
+    pub const REMOVE_FROM_GROUP_0: u8 = 0x70;
+    pub const REMOVE_FROM_GROUP_15: u8 = 0x7f;
+
+    pub const SET_SHORT_ADDRESS: u8 = 0x80;
+    pub const ENABLE_WRITE_MEMORY: u8 = 0x81;
+
+    pub const QUERY_STATUS: u8 = 0x90;
+    pub const QUERY_CONTROL_GEAR_PRESENT: u8 = 0x91;
+    pub const QUERY_LAMP_FAILURE: u8 = 0x92;
+    pub const QUERY_LAMP_POWER_ON: u8 = 0x93;
+    pub const QUERY_LIMIT_ERROR: u8 = 0x94;
+    pub const QUERY_RESET_STATE: u8 = 0x95;
+    pub const QUERY_MISSING_SHORT_ADDRESS: u8 = 0x96;
+    pub const QUERY_VERSION_NUMBER: u8 = 0x97;
+    pub const QUERY_CONTENT_DTR0: u8 = 0x98;
+    pub const QUERY_DEVICE_TYPE: u8 = 0x99;
+    pub const QUERY_PHYSICAL_MINIMUM: u8 = 0x9a;
+    pub const QUERY_POWER_FAILURE: u8 = 0x9b;
+
+    pub const QUERY_CONTENT_DTR1: u8 = 0x9c;
+    pub const QUERY_CONTENT_DTR2: u8 = 0x9d;
+
+    pub const QUERY_OPERATING_MODE: u8 = 0x9e;
+    pub const QUERY_LIGHT_SOURCE_TYPE: u8 = 0x9f;
+
+    pub const QUERY_ACTUAL_LEVEL: u8 = 0xa0;
+    pub const QUERY_MAX_LEVEL: u8 = 0xa1;
+    pub const QUERY_MIN_LEVEL: u8 = 0xa2;
+    pub const QUERY_POWER_ON_LEVEL: u8 = 0xa3;
+    pub const QUERY_SYSTEM_FAILURE_LEVEL: u8 = 0xa4;
+    pub const QUERY_FADE: u8 = 0xa5;
+    pub const QUERY_MANUFACTURER_SPECIFIC_MODE: u8 = 0xa6;
+    pub const QUERY_NEXT_DEVICE_TYPE: u8 = 0xa7;
+    pub const QUERY_EXTENDED_FADE_TIME: u8 = 0xa8;
+    pub const QUERY_CONTROL_GEAR_FAILURE: u8 = 0xaa;
+
+    pub const QUERY_SCENE_LEVEL_0: u8 = 0xb0;
+    pub const QUERY_SCENE_LEVEL_15: u8 = 0xbf;
+
+    pub const QUERY_GROUPS_0_7: u8 = 0xc0;
+    pub const QUERY_GROUPS_8_15: u8 = 0xc1;
+    pub const QUERY_RANDOM_ADDRESS_H: u8 = 0xc2;
+    pub const QUERY_RANDOM_ADDRESS_M: u8 = 0xc3;
+    pub const QUERY_RANDOM_ADDRESS_L: u8 = 0xc4;
+    pub const READ_MEMORY_LOCATION: u8 = 0xc5;
+
+    pub const APP_EXT_CMDS_FIRST: u8 = 0xe0;
+
+    pub const QUERY_EXTENDED_VERSION_NUMBER: u8 = 0xff;
+
+    pub const TERMINATE: u8 = 0xa1;
+    pub const DTR0: u8 = 0xa3;
 
-extern crate rand;
-use rand::Rng;
+    pub const INITIALISE: u8 = 0xa5;
 
-type DynResult<T> =  Result<T, Box<dyn std::error::Error + Send + Sync>>;
+    pub const RANDOMISE: u8 = 0xa7;
+    pub const COMPARE: u8 = 0xa9;
+    pub const WITHDRAW: u8 = 0xab;
+
+    pub const SEARCHADDRH: u8 = 0xb1;
+    pub const SEARCHADDRM: u8 = 0xb3;
+    pub const SEARCHADDRL: u8 = 0xb5;
+
+    pub const PROGRAM_SHORT_ADDRESS: u8 = 0xb7;
+    pub const VERIFY_SHORT_ADDRESS: u8 = 0xb9;
+    pub const QUERY_SHORT_ADDRESS: u8 = 0xbb;
+
+    pub const ENABLE_DEVICE_TYPE: u8 = 0xc1;
+    pub const DTR1: u8 = 0xc3;
+    pub const DTR2: u8 = 0xc5;
+    pub const WRITE_MEMORY_LOCATION: u8 = 0xc7;
+    pub const WRITE_MEMORY_LOCATION_NO_REPLY: u8 = 0xc9;
+}
 
 #[derive(PartialEq)]
-pub enum InitialisationState
-{
+pub enum InitialisationState {
     ENABLED,
     DISABLED,
-    WITHDRAWN
+    WITHDRAWN,
 }
 
 #[derive(PartialEq)]
-pub enum WriteEnableState
-{
+pub enum WriteEnableState {
     ENABLED,
-    DISABLED
+    DISABLED,
+}
+
+// Memory bank locations
+const LAST_ACCESSIBLE_LOCATION: usize = 0x00;
+const LAST_MEMORY_BANK: usize = 0x02;
+const LOCK_BYTE: usize = 0x02;
+// Lockable bytes are only writable when the lock byte has this value
+const UNLOCKED: u8 = 0x55;
+
+/// Memory bank 0 of a gear implementing IEC 62386-101 and -102 version 2.0
+pub fn default_memory_bank_0() -> Vec<u8> {
+    let mut bank = vec![0x00; 0x1b];
+    bank[LAST_ACCESSIBLE_LOCATION] = 0x1a;
+    bank[0x01] = MASK; // Reserved
+    bank[LAST_MEMORY_BANK] = 0x01;
+    bank[0x15] = 2 << 2; // IEC 62386-101 version
+    bank[0x16] = 2 << 2; // IEC 62386-102 version
+    bank[0x17] = MASK; // No control device
+    bank[0x18] = 0; // Number of logical control devices
+    bank[0x19] = 1; // Number of logical control gears
+    bank[0x1a] = 0; // Index of this control gear
+    bank
+}
+
+/// Memory bank 1 with all luminaire information unknown and locked
+pub fn default_memory_bank_1() -> Vec<u8> {
+    let mut bank = vec![MASK; 0x78];
+    bank[LAST_ACCESSIBLE_LOCATION] = 0x77;
+    bank[0x11] = 0x00; // Content format ID
+    bank[0x12] = 0x03;
+    bank
 }
 
 #[allow(dead_code)]
-pub struct DaliSimGear
-{
+pub struct DaliSimGear {
     pub powered: bool,
-    
+
     pub actual_level: u8,
     pub target_level: u8,
     pub last_active_level: u8,
@@ -52,39 +207,66 @@ pub struct DaliSimGear
     pub operating_mode: u8,
     pub initialisation_state: InitialisationState,
     pub write_enable_state: WriteEnableState,
-    pub status: u8, 
+    pub status: u8,
     pub gear_groups: u16,
-    pub scene: [u8;16],
+    pub scene: [u8; 16],
     pub dtr0: u8,
     pub dtr1: u8,
     pub dtr2: u8,
     pub phm: u8,
+    pub device_types: Vec<u8>,
+    /// Content of memory bank 0, 1, ... Location 0 of each bank and
+    /// location 2 of bank 0 are derived from the size of the banks.
+    pub memory_banks: Vec<Vec<u8>>,
+    /// Emergency state, only used if the device types include 1
+    pub emergency: DaliSimEmergency,
+    /// LED state, only used if the device types include 6
+    pub led: DaliSimLed,
+    /// Colour state, only used if the device types include 8
+    pub colour: DaliSimColour,
 
     // Fade endpoints. Scaled for better precision.
     // Scaled by 128
     fade_start_level: i16,
     // Scaled by 128
     fade_end_level: i16,
-    
-    // Timers            
+
+    // Timers
     fade_start_time: Instant,
     fade_duration: Duration,
     init_start_time: Instant,
 
+    // Set by ENABLE DEVICE TYPE for the next command
+    enabled_device_type: Option<u8>,
+    // Device type enabled for the current command
+    extended_device_type: Option<u8>,
+    // Index of the device type reported by the next QUERY NEXT DEVICE TYPE
+    next_device_type: Option<usize>,
+
     last_event: DaliSimEvent, // Previous event, used for detecting send twice
     source_id: u32,
     host: Option<Box<dyn DaliSimHost>>,
+    // Used for RANDOMISE
+    rng: StdRng,
 }
 
-impl DaliSimGear
-{
-    pub fn new() -> DaliSimGear
-    {
+impl DaliSimGear {
+    pub fn new() -> DaliSimGear {
+        Self::with_rng(StdRng::from_entropy())
+    }
+
+    /// Create a gear whose RANDOMISE command generates a reproducible
+    /// sequence of random addresses
+    pub fn with_seed(seed: u64) -> DaliSimGear {
+        Self::with_rng(StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(rng: StdRng) -> DaliSimGear {
         let phm = 0x01;
-	let now = Instant::now();
-        DaliSimGear{
+        let now = Instant::now();
+        DaliSimGear {
             powered: true,
-            
+
             actual_level: 0xfe,
             target_level: 0xfe,
             last_active_level: 0xfe,
@@ -103,63 +285,115 @@ impl DaliSimGear
             write_enable_state: WriteEnableState::DISABLED,
             status: 0x00,
             gear_groups: 0x0000,
-            scene:  [MASK;16],
+            scene: [MASK; 16],
             dtr0: 0,
             dtr1: 0,
             dtr2: 0,
-            phm: 0,
+            phm,
+            device_types: vec![device_type::LED],
+            memory_banks: vec![default_memory_bank_0(), default_memory_bank_1()],
+            emergency: DaliSimEmergency::new(),
+            led: DaliSimLed::new(),
+            colour: DaliSimColour::new(),
 
             fade_start_level: 0,
             // Scaled by 128
             fade_end_level: 0,
-            
+
             fade_start_time: now,
-            fade_duration: Duration::new(0,0),
+            fade_duration: Duration::new(0, 0),
             init_start_time: now,
-            last_event: DaliSimEvent{
-		source_id: 0,
-		timestamp: now,
-		event_type: DaliBusEventType::BusPowerOff},
-	    source_id: 0,
-	    host: None,
+
+            enabled_device_type: None,
+            extended_device_type: None,
+            next_device_type: None,
+
+            last_event: DaliSimEvent {
+                source_id: 0,
+                timestamp: now,
+                event_type: DaliBusEventType::BusPowerOff,
+            },
+            source_id: 0,
+            host: None,
+            rng,
         }
     }
+
+    /// Set the identification part of memory bank 0
+    pub fn set_memory_bank_0(&mut self, info: &MemoryBank0Info) {
+        let bank = &mut self.memory_banks[0];
+        bank[0x03..=0x08].copy_from_slice(&info.gtin.to_be_bytes()[2..8]);
+        bank[0x09..=0x0a].copy_from_slice(&info.firmware_version.to_be_bytes());
+        bank[0x0b..=0x12].copy_from_slice(&info.id_number.to_be_bytes());
+        bank[0x13..=0x14].copy_from_slice(&info.hardware_version.to_be_bytes());
+        bank[0x15] = info.version_101;
+        bank[0x16] = info.version_102;
+        bank[0x17] = info.version_103;
+        bank[0x18] = info.n_control_devices;
+        bank[0x19] = info.n_control_gears;
+        bank[0x1a] = info.control_gear_index;
+    }
+
+    /// Apply mains power to the gear. The gear goes to the power on
+    /// level and reports a power cycle.
+    pub fn power_cycle(&mut self, now: Instant) {
+        self.powered = true;
+        self.initialisation_state = InitialisationState::DISABLED;
+        self.write_enable_state = WriteEnableState::DISABLED;
+        self.enabled_device_type = None;
+        self.status |= status::POWER_CYCLE;
+        let level = if self.power_on_level == MASK {
+            self.last_light_level
+        } else {
+            self.power_on_level
+        };
+        let level = limit_level(self, level);
+        set_level(self, level, now);
+    }
 }
 
+impl Default for DaliSimGear {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-fn check_timers(dev: &mut DaliSimGear)
-{
-    if dev.initialisation_state != InitialisationState::DISABLED {
-        if dev.init_start_time.elapsed() >= INIT_TIMEOUT {
-            dev.initialisation_state = InitialisationState::DISABLED;
-        }
+/// Update the state that depends on time. `now` is the current
+/// simulated time.
+fn check_timers(dev: &mut DaliSimGear, now: Instant) {
+    if dev.initialisation_state != InitialisationState::DISABLED
+        && now.saturating_duration_since(dev.init_start_time) >= INIT_TIMEOUT
+    {
+        dev.initialisation_state = InitialisationState::DISABLED;
     }
-    
-    if  (dev.status & status::FADE_RUNNING) != 0 {
-        let elapsed = dev.fade_start_time.elapsed();
-        if elapsed >=  dev.fade_duration {
+
+    if (dev.status & status::FADE_RUNNING) != 0 {
+        let elapsed = now.saturating_duration_since(dev.fade_start_time);
+        if elapsed >= dev.fade_duration {
             dev.actual_level = dev.target_level;
             dev.status &= !status::FADE_RUNNING;
         } else {
             let elapsed_millis = elapsed.as_millis() as i128;
             let duration_millis = dev.fade_duration.as_millis() as i128;
-            dev.actual_level = 
-                ((dev.fade_start_level 
-                 + (((dev.fade_end_level - dev.fade_start_level) as i128 
-                     * elapsed_millis + duration_millis/2) 
-                                   / duration_millis) as i16) >> 7) as u8;
+            dev.actual_level = ((dev.fade_start_level
+                + (((dev.fade_end_level - dev.fade_start_level) as i128 * elapsed_millis
+                    + duration_millis / 2)
+                    / duration_millis) as i16)
+                >> 7) as u8;
+        }
+        if dev.actual_level > 0 {
+            dev.last_light_level = dev.actual_level;
         }
     }
 }
 
-const fn fade_time(n: u8) -> Duration
-{
+const fn fade_time(n: u8) -> Duration {
     let n = n as u64;
-    let millis = (1u64<<(n/2)) * ((n&1) * 707 + (1-(n&1)) * 500);
+    let millis = (1u64 << (n / 2)) * ((n & 1) * 707 + (1 - (n & 1)) * 500);
     Duration::from_millis(millis)
 }
 
-const FADE_TIMES: [Duration;16] = [
+const FADE_TIMES: [Duration; 16] = [
     Duration::from_millis(0),
     fade_time(1),
     fade_time(2),
@@ -175,343 +409,732 @@ const FADE_TIMES: [Duration;16] = [
     fade_time(12),
     fade_time(13),
     fade_time(14),
-    fade_time(15)
+    fade_time(15),
 ];
 
-const FADE_MULTIPLIER : [Duration;5] = [
+const FADE_MULTIPLIER: [Duration; 5] = [
     Duration::from_millis(0),
     Duration::from_millis(100),
     Duration::from_secs(1),
     Duration::from_secs(10),
-    Duration::from_secs(60)
+    Duration::from_secs(60),
 ];
-    
-fn start_fade_time(dev: &mut DaliSimGear)
-{
-    if (dev.fade & 0xf0) == 0x00 && (dev.extended_fade_time & 0x70) == 0x00 {
-        // No fade, change instantly
-        dev.actual_level = dev.target_level;
-        return;
+
+// Steps per 200 ms for each fade rate, used by UP and DOWN
+const FADE_RATE_STEPS: [u8; 16] = [72, 72, 51, 36, 25, 18, 13, 9, 6, 4, 3, 2, 2, 1, 1, 1];
+const UP_DOWN_DURATION: Duration = Duration::from_millis(200);
+
+/// Fade duration selected by the fade time or extended fade time
+pub(super) fn fade_duration(dev: &DaliSimGear) -> Duration {
+    if (dev.fade & 0xf0) != 0x00 {
+        // Basic fadetime
+        FADE_TIMES[dev.fade as usize >> 4]
+    } else if dev.extended_fade_time == 0 || dev.extended_fade_time > 0x4f {
+        Duration::ZERO
     } else {
-        if (dev.fade & 0xf0) == 0x0 {
-            // Use extended fade times
-            if dev.extended_fade_time == 0 || dev.extended_fade_time > 0x4f {
-                // Extended fade is zero
-                dev.actual_level = dev.target_level;
-                return;
-            } else {
-                // Extended fade time
-                dev.fade_duration = 
-                    FADE_MULTIPLIER[dev.extended_fade_time as usize>>4] 
-                    * ((dev.extended_fade_time & 0x0f) +1) as u32;
-            }
-        } else {
-            // Basic fadetime
-            dev.fade_duration = FADE_TIMES[dev.fade as usize >> 4];
+        // Extended fade time
+        FADE_MULTIPLIER[dev.extended_fade_time as usize >> 4]
+            * ((dev.extended_fade_time & 0x0f) + 1) as u32
+    }
+}
+
+/// Clamp a requested level to the configured range and update the limit
+/// error flag
+fn limit_level(dev: &mut DaliSimGear, level: u8) -> u8 {
+    let limited = if level == 0 {
+        0
+    } else {
+        level.clamp(dev.min_level, dev.max_level)
+    };
+    if limited != level {
+        dev.status |= status::LIMIT_ERROR;
+    } else {
+        dev.status &= !status::LIMIT_ERROR;
+    }
+    limited
+}
+
+/// Change level without fading
+fn set_level(dev: &mut DaliSimGear, level: u8, now: Instant) {
+    fade_to(dev, level, Duration::ZERO, now);
+}
+
+/// Fade from the actual level to `level`
+fn fade_to(dev: &mut DaliSimGear, level: u8, duration: Duration, now: Instant) {
+    dev.target_level = level;
+    if level > 0 {
+        dev.last_active_level = level;
+    }
+    if duration.is_zero() {
+        dev.actual_level = level;
+        if level > 0 {
+            dev.last_light_level = level;
         }
+        dev.status &= !status::FADE_RUNNING;
+        return;
     }
-    dev.fade_start_time = Instant::now();
+    dev.fade_duration = duration;
+    dev.fade_start_time = now;
     dev.fade_start_level = (dev.actual_level as i16) << 7;
     dev.fade_end_level = (dev.target_level as i16) << 7;
+    dev.status |= status::FADE_RUNNING;
 }
 
-fn query_status_flag(dev: &DaliSimGear, flag: u8)
-                     -> Option<DaliBusEventType>
-{
+fn query_status_flag(dev: &DaliSimGear, flag: u8) -> Option<DaliBusEventType> {
     if (dev.status & flag) != 0 {
         YES_REPLY
     } else {
         NO_REPLY
-    } 
+    }
 }
 
 // Status flags that are not dependant on any other state
-pub const STORED_STATUS_FLAGS : u8 = 
-    status::GEAR_FAILURE 
+pub const STORED_STATUS_FLAGS: u8 = status::GEAR_FAILURE
     | status::LAMP_FAILURE
     | status::LIMIT_ERROR
     | status::FADE_RUNNING
     | status::RESET_STATE
     | status::POWER_CYCLE;
 
-fn update_status(dev: &mut DaliSimGear) 
-{
-    dev.status = (dev.status & STORED_STATUS_FLAGS) 
-        | if dev.actual_level > 0 {status::LAMP_ON} else {0}
-        | if dev.short_address == MASK {status::NO_ADDRESS} else {0};
+fn update_status(dev: &mut DaliSimGear) {
+    dev.status = (dev.status & STORED_STATUS_FLAGS)
+        | if dev.actual_level > 0 {
+            status::LAMP_ON
+        } else {
+            0
+        }
+        | if dev.short_address == MASK {
+            status::NO_ADDRESS
+        } else {
+            0
+        };
+}
+
+fn yes_no(p: bool) -> Option<DaliBusEventType> {
+    if p { YES_REPLY } else { NO_REPLY }
 }
 
-fn yes_no(p: bool) -> Option<DaliBusEventType>
-{
-    if p {YES_REPLY} else {NO_REPLY}
+fn answer(value: u8) -> Option<DaliBusEventType> {
+    Some(DaliBusEventType::Frame8(value))
 }
 
-const YES_REPLY: Option<DaliBusEventType>= Some(DaliBusEventType::Frame8(MASK));
+const YES_REPLY: Option<DaliBusEventType> = Some(DaliBusEventType::Frame8(MASK));
 const NO_REPLY: Option<DaliBusEventType> = None;
 
-fn device_cmd(dev: &mut DaliSimGear, _addr: u8, cmd: u8, _flags: Flags) 
-              -> Option<DaliBusEventType>
-{
+fn direct_arc_power(dev: &mut DaliSimGear, level: u8, now: Instant) -> Option<DaliBusEventType> {
+    if level == MASK {
+        // Stop fading
+        dev.target_level = dev.actual_level;
+        dev.status &= !status::FADE_RUNNING;
+        return NO_REPLY;
+    }
+    let level = limit_level(dev, level);
+    let duration = fade_duration(dev);
+    fade_to(dev, level, duration, now);
+    if dev.device_types.contains(&device_type::COLOUR) {
+        dt8::arc_power(dev, duration, now);
+    }
+    NO_REPLY
+}
+
+/// Variables that are set by RESET. The reset state flag tells if they
+/// all have their reset value.
+type ResetVariables = ([u8; 9], u32, u32, u16, [u8; 16]);
+
+fn reset_values(dev: &DaliSimGear) -> ResetVariables {
+    (
+        [0xfe, 0xfe, 0xfe, 0xfe, dev.phm, 0xfe, 0x07, 0x00, 0x00],
+        0xffffff,
+        0xffffff,
+        0,
+        [MASK; 16],
+    )
+}
+
+fn reset_variables(dev: &DaliSimGear) -> ResetVariables {
+    (
+        [
+            dev.actual_level,
+            dev.last_active_level,
+            dev.power_on_level,
+            dev.system_failure_level,
+            dev.min_level,
+            dev.max_level,
+            dev.fade,
+            dev.extended_fade_time,
+            dev.operating_mode,
+        ],
+        dev.search_address,
+        dev.random_address,
+        dev.gear_groups,
+        dev.scene,
+    )
+}
+
+fn reset(dev: &mut DaliSimGear, now: Instant) {
+    set_level(dev, 0xfe, now);
+    dev.last_light_level = 0xfe;
+    dev.power_on_level = 0xfe;
+    dev.system_failure_level = 0xfe;
+    dev.min_level = dev.phm;
+    dev.max_level = 0xfe;
+    dev.fade = 0x07;
+    dev.extended_fade_time = 0x00;
+    dev.search_address = 0xffffff;
+    dev.random_address = 0xffffff;
+    dev.operating_mode = 0;
+    dev.gear_groups = 0;
+    dev.scene = [MASK; 16];
+    dev.status = (dev.status & (status::GEAR_FAILURE | status::LAMP_FAILURE)) | status::RESET_STATE;
+    if dev.device_types.contains(&device_type::LED) {
+        dt6::reset(dev);
+    }
+    if dev.device_types.contains(&device_type::COLOUR) {
+        dt8::reset(dev, now);
+    }
+}
+
+/// Get the content of a memory location. Returns None if the location
+/// isn't implemented.
+fn memory_value(dev: &DaliSimGear, bank: u8, location: u8) -> Option<u8> {
+    let data = dev.memory_banks.get(bank as usize)?;
+    let location = location as usize;
+    match location {
+        _ if location >= data.len() => None,
+        LAST_ACCESSIBLE_LOCATION => Some((data.len() - 1) as u8),
+        LAST_MEMORY_BANK if bank == 0 => Some((dev.memory_banks.len() - 1) as u8),
+        _ => Some(data[location]),
+    }
+}
+
+fn read_memory(dev: &mut DaliSimGear) -> Option<DaliBusEventType> {
+    if dev.memory_banks.get(dev.dtr1 as usize).is_none() {
+        return NO_REPLY;
+    }
+    let value = memory_value(dev, dev.dtr1, dev.dtr0);
+    dev.dtr0 = dev.dtr0.saturating_add(1);
+    value.map(DaliBusEventType::Frame8)
+}
+
+/// Write to the memory location selected by DTR1 and DTR0. Returns true
+/// if the location was written.
+fn write_memory(dev: &mut DaliSimGear, data: u8) -> bool {
+    if dev.write_enable_state != WriteEnableState::ENABLED {
+        return false;
+    }
+    let bank_index = dev.dtr1 as usize;
+    let location = dev.dtr0 as usize;
+    let Some(bank) = dev.memory_banks.get_mut(bank_index) else {
+        return false;
+    };
+    dev.dtr0 = dev.dtr0.saturating_add(1);
+    let writable = bank_index > 0
+        && location < bank.len()
+        && (location == LOCK_BYTE || (location > LOCK_BYTE && bank[LOCK_BYTE] == UNLOCKED));
+    if writable {
+        bank[location] = data;
+    }
+    writable
+}
+
+/// Reset the memory bank selected by DTR0, or all banks if DTR0 is 0
+fn reset_memory_bank(dev: &mut DaliSimGear) {
+    let banks = if dev.dtr0 == 0 {
+        1..dev.memory_banks.len()
+    } else {
+        dev.dtr0 as usize..(dev.dtr0 as usize + 1).min(dev.memory_banks.len())
+    };
+    for index in banks {
+        if index == 1 {
+            dev.memory_banks[1] = default_memory_bank_1();
+        } else {
+            dev.memory_banks[index][LOCK_BYTE..].fill(MASK);
+        }
+    }
+}
+
+/// Configuration commands are only executed when received twice
+fn requires_twice(cmd: u8) -> bool {
+    matches!(cmd, cmd::RESET..=cmd::ENABLE_WRITE_MEMORY)
+}
+
+fn device_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
+    if requires_twice(cmd) && !flags.send_twice() {
+        return NO_REPLY;
+    }
     match cmd {
+        cmd::OFF => set_level(dev, 0, now),
+        cmd::UP | cmd::DOWN if dev.actual_level > 0 => {
+            let steps = FADE_RATE_STEPS[(dev.fade & 0x0f) as usize];
+            let level = if cmd == cmd::UP {
+                dev.actual_level.saturating_add(steps).min(dev.max_level)
+            } else {
+                dev.actual_level.saturating_sub(steps).max(dev.min_level)
+            };
+            fade_to(dev, level, UP_DOWN_DURATION, now);
+        }
+        cmd::STEP_UP if dev.actual_level > 0 && dev.actual_level < dev.max_level => {
+            set_level(dev, dev.actual_level + 1, now);
+        }
+        cmd::STEP_DOWN if dev.actual_level > dev.min_level => {
+            set_level(dev, dev.actual_level - 1, now);
+        }
+        cmd::RECALL_MAX_LEVEL => set_level(dev, dev.max_level, now),
+        cmd::RECALL_MIN_LEVEL => set_level(dev, dev.min_level, now),
+        cmd::STEP_DOWN_AND_OFF => {
+            if dev.actual_level <= dev.min_level {
+                set_level(dev, 0, now);
+            } else {
+                set_level(dev, dev.actual_level - 1, now);
+            }
+        }
+        cmd::ON_AND_STEP_UP => {
+            if dev.actual_level == 0 {
+                set_level(dev, dev.min_level, now);
+            } else if dev.actual_level < dev.max_level {
+                set_level(dev, dev.actual_level + 1, now);
+            }
+        }
+        cmd::ENABLE_DAPC => {}
+        cmd::GO_TO_LAST_ACTIVE_LEVEL => return direct_arc_power(dev, dev.last_active_level, now),
+        cmd::GO_TO_SCENE_0..=cmd::GO_TO_SCENE_15 => {
+            let level = dev.scene[(cmd - cmd::GO_TO_SCENE_0) as usize];
+            if level != MASK {
+                return direct_arc_power(dev, level, now);
+            }
+        }
+        cmd::RESET => reset(dev, now),
+        cmd::STORE_ACTUAL_LEVEL_IN_DTR0 => dev.dtr0 = dev.actual_level,
+        cmd::SAVE_PERSISTENT_VARIABLES => {
+            // Variables are never lost so there's nothing to save
+        }
+        // Only the standard operating mode is implemented
+        cmd::SET_OPERATING_MODE if dev.dtr0 == 0 => dev.operating_mode = 0,
+        cmd::RESET_MEMORY_BANK => reset_memory_bank(dev),
+        cmd::IDENTIFY_DEVICE => {}
+        cmd::SET_MAX_LEVEL => {
+            dev.max_level = dev.dtr0.clamp(dev.min_level, 0xfe);
+            if dev.actual_level > dev.max_level {
+                set_level(dev, dev.max_level, now);
+            }
+        }
+        cmd::SET_MIN_LEVEL => {
+            dev.min_level = dev.dtr0.clamp(dev.phm, dev.max_level);
+            if dev.actual_level > 0 && dev.actual_level < dev.min_level {
+                set_level(dev, dev.min_level, now);
+            }
+        }
+        cmd::SET_SYSTEM_FAILURE_LEVEL => dev.system_failure_level = dev.dtr0,
+        cmd::SET_POWER_ON_LEVEL => dev.power_on_level = dev.dtr0,
+        cmd::SET_FADE_TIME => {
+            dev.fade = (dev.dtr0.min(15) << 4) | (dev.fade & 0x0f);
+            dev.extended_fade_time = 0;
+        }
+        cmd::SET_FADE_RATE => dev.fade = (dev.fade & 0xf0) | dev.dtr0.clamp(1, 15),
+        cmd::SET_EXTENDED_FADE_TIME => {
+            dev.extended_fade_time = if dev.dtr0 > 0x4f { 0 } else { dev.dtr0 };
+            dev.fade &= 0x0f;
+        }
+        cmd::SET_SCENE_0..=cmd::SET_SCENE_15 => {
+            dev.scene[(cmd - cmd::SET_SCENE_0) as usize] = dev.dtr0;
+        }
+        cmd::REMOVE_FROM_SCENE_0..=cmd::REMOVE_FROM_SCENE_15 => {
+            dev.scene[(cmd - cmd::REMOVE_FROM_SCENE_0) as usize] = MASK;
+        }
+        cmd::ADD_TO_GROUP_0..=cmd::ADD_TO_GROUP_15 => {
+            dev.gear_groups |= 1 << (cmd - cmd::ADD_TO_GROUP_0);
+        }
+        cmd::REMOVE_FROM_GROUP_0..=cmd::REMOVE_FROM_GROUP_15 => {
+            dev.gear_groups &= !(1 << (cmd - cmd::REMOVE_FROM_GROUP_0));
+        }
+        cmd::SET_SHORT_ADDRESS => {
+            if dev.dtr0 == MASK {
+                dev.short_address = MASK;
+            } else if (dev.dtr0 & 0x81) == 0x01 {
+                dev.short_address = dev.dtr0 >> 1;
+            }
+        }
+        cmd::ENABLE_WRITE_MEMORY => dev.write_enable_state = WriteEnableState::ENABLED,
         cmd::QUERY_STATUS => {
             update_status(dev);
-            return Some(DaliBusEventType::Frame8(dev.status))
-        },
-        cmd::QUERY_CONTROL_GEAR_PRESENT => 
-            return YES_REPLY,
-        cmd::QUERY_CONTROL_GEAR_FAILURE =>
-            return query_status_flag(&dev, status::GEAR_FAILURE),
-        cmd::QUERY_LAMP_FAILURE =>
-            return query_status_flag(&dev, status::LAMP_FAILURE),
-        cmd::QUERY_LAMP_POWER_ON =>
-            return yes_no(dev.actual_level > 0),
-        cmd::QUERY_LIMIT_ERROR =>
-            return query_status_flag(&dev, status::LIMIT_ERROR),
-        cmd::QUERY_RESET_STATE =>
-            return query_status_flag(&dev, status::RESET_STATE),
-        cmd::QUERY_MISSING_SHORT_ADDRESS => {
-            return yes_no(dev.short_address == MASK)
-        },
-        cmd::QUERY_VERSION_NUMBER => {
-            return Some(DaliBusEventType::Frame8(2<<2 + 0)) // 2.0
-        },
-        cmd::QUERY_DEVICE_TYPE =>
-            return Some(DaliBusEventType::Frame8(device_type::LED)),
-        cmd::QUERY_NEXT_DEVICE_TYPE =>
-            return NO_REPLY,
-        cmd::QUERY_PHYSICAL_MINIMUM =>
-            return Some(DaliBusEventType::Frame8(dev.phm)),
-        cmd::QUERY_POWER_FAILURE =>
-            return query_status_flag(&dev, status::POWER_CYCLE),
-        cmd::QUERY_CONTENT_DTR0 =>
-            return Some(DaliBusEventType::Frame8(dev.dtr0)),
-        cmd::QUERY_CONTENT_DTR1 =>
-            return Some(DaliBusEventType::Frame8(dev.dtr1)),
-        cmd::QUERY_CONTENT_DTR2 =>
-            return Some(DaliBusEventType::Frame8(dev.dtr2)),
-        cmd::QUERY_OPERATING_MODE =>
-            return Some(DaliBusEventType::Frame8(0x00)),
-        cmd::QUERY_LIGHT_SOURCE_TYPE =>
-            return Some(DaliBusEventType::Frame8(light_source::LED)),
-        cmd::QUERY_ACTUAL_LEVEL =>
-            return Some(DaliBusEventType::Frame8(dev.actual_level)),
-        cmd::QUERY_MAX_LEVEL =>
-            return Some(DaliBusEventType::Frame8(dev.max_level)),
-        cmd::QUERY_MIN_LEVEL =>
-            return Some(DaliBusEventType::Frame8(dev.min_level)),
-        cmd::QUERY_POWER_ON_LEVEL =>
-            return Some(DaliBusEventType::Frame8(dev.power_on_level)),
-        cmd::QUERY_SYSTEM_FAILURE_LEVEL =>
-            return Some(DaliBusEventType::Frame8(dev.system_failure_level)),
-        cmd::QUERY_FADE =>
-            return Some(DaliBusEventType::Frame8(dev.fade)),
-        cmd::QUERY_SCENE_LEVEL_0..= cmd::QUERY_SCENE_LEVEL_15 => {
-	    let level = dev.scene[(cmd - cmd::QUERY_SCENE_LEVEL_0) as usize];
-            return Some(DaliBusEventType::Frame8(level))
-	},
-        cmd::QUERY_GROUPS_0_7 => {
-	    let groups = (dev.gear_groups & 0xff) as u8;
-            return Some(DaliBusEventType::Frame8(groups))
-	},
-        cmd::QUERY_GROUPS_8_15 => {
-	    let groups = (dev.gear_groups >> 8) as u8;
-            return Some(DaliBusEventType::Frame8(groups))
-	},
-        cmd::QUERY_RANDOM_ADDRESS_H => {
-	    let addr = (dev.random_address >> 16) as u8;
-            return Some(DaliBusEventType::Frame8(addr))
-	},
-        cmd::QUERY_RANDOM_ADDRESS_M => {
-	    let addr = ((dev.random_address >> 8) & 0xff) as u8;
-            return Some(DaliBusEventType::Frame8(addr));
-	},
-        cmd::QUERY_RANDOM_ADDRESS_L => {
-	    let addr = (dev.random_address & 0xff) as u8; 
-            return Some(DaliBusEventType::Frame8(addr))
-	},
+            return answer(dev.status);
+        }
+        cmd::QUERY_CONTROL_GEAR_PRESENT => return YES_REPLY,
+        cmd::QUERY_CONTROL_GEAR_FAILURE => return query_status_flag(dev, status::GEAR_FAILURE),
+        cmd::QUERY_LAMP_FAILURE => return query_status_flag(dev, status::LAMP_FAILURE),
+        cmd::QUERY_LAMP_POWER_ON => return yes_no(dev.actual_level > 0),
+        cmd::QUERY_LIMIT_ERROR => return query_status_flag(dev, status::LIMIT_ERROR),
+        cmd::QUERY_RESET_STATE => return query_status_flag(dev, status::RESET_STATE),
+        cmd::QUERY_MISSING_SHORT_ADDRESS => return yes_no(dev.short_address == MASK),
+        cmd::QUERY_VERSION_NUMBER => return answer(2 << 2), // 2.0
+        cmd::QUERY_DEVICE_TYPE => {
+            return match dev.device_types.as_slice() {
+                [] => answer(device_type::UNIMPLEMENTED),
+                [dt] => answer(*dt),
+                _ => {
+                    dev.next_device_type = Some(0);
+                    answer(MASK)
+                }
+            };
+        }
+        cmd::QUERY_NEXT_DEVICE_TYPE => {
+            let Some(index) = dev.next_device_type.take() else {
+                return answer(MASK);
+            };
+            let mut types = dev.device_types.clone();
+            types.sort_unstable();
+            // Report each device type in ascending order followed by 254
+            return match types.get(index) {
+                Some(&dt) => {
+                    dev.next_device_type = Some(index + 1);
+                    answer(dt)
+                }
+                None => answer(device_type::UNIMPLEMENTED),
+            };
+        }
+        cmd::QUERY_PHYSICAL_MINIMUM => return answer(dev.phm),
+        cmd::QUERY_POWER_FAILURE => return query_status_flag(dev, status::POWER_CYCLE),
+        cmd::QUERY_CONTENT_DTR0 => return answer(dev.dtr0),
+        cmd::QUERY_CONTENT_DTR1 => return answer(dev.dtr1),
+        cmd::QUERY_CONTENT_DTR2 => return answer(dev.dtr2),
+        cmd::QUERY_OPERATING_MODE => return answer(dev.operating_mode),
+        cmd::QUERY_LIGHT_SOURCE_TYPE => return answer(light_source::LED),
+        cmd::QUERY_ACTUAL_LEVEL => return answer(dev.actual_level),
+        cmd::QUERY_MAX_LEVEL => return answer(dev.max_level),
+        cmd::QUERY_MIN_LEVEL => return answer(dev.min_level),
+        cmd::QUERY_POWER_ON_LEVEL => return answer(dev.power_on_level),
+        cmd::QUERY_SYSTEM_FAILURE_LEVEL => return answer(dev.system_failure_level),
+        cmd::QUERY_FADE => return answer(dev.fade),
+        cmd::QUERY_MANUFACTURER_SPECIFIC_MODE => return yes_no(dev.operating_mode >= 0x80),
+        cmd::QUERY_EXTENDED_FADE_TIME => return answer(dev.extended_fade_time),
+        cmd::QUERY_SCENE_LEVEL_0..=cmd::QUERY_SCENE_LEVEL_15 => {
+            return answer(dev.scene[(cmd - cmd::QUERY_SCENE_LEVEL_0) as usize]);
+        }
+        cmd::QUERY_GROUPS_0_7 => return answer((dev.gear_groups & 0xff) as u8),
+        cmd::QUERY_GROUPS_8_15 => return answer((dev.gear_groups >> 8) as u8),
+        cmd::QUERY_RANDOM_ADDRESS_H => return answer((dev.random_address >> 16) as u8),
+        cmd::QUERY_RANDOM_ADDRESS_M => return answer(((dev.random_address >> 8) & 0xff) as u8),
+        cmd::QUERY_RANDOM_ADDRESS_L => return answer((dev.random_address & 0xff) as u8),
+        cmd::READ_MEMORY_LOCATION => return read_memory(dev),
+        cmd::APP_EXT_CMDS_FIRST..=cmd::QUERY_EXTENDED_VERSION_NUMBER => {
+            return extended_cmd(dev, cmd, flags, now);
+        }
         _ => {}
     }
-    None
+    NO_REPLY
+}
+
+/// Application extended commands for the device type enabled by
+/// ENABLE DEVICE TYPE
+fn extended_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
+    let Some(dt) = dev.extended_device_type else {
+        return NO_REPLY;
+    };
+    if !dev.device_types.contains(&dt) {
+        return NO_REPLY;
+    }
+    match cmd {
+        cmd::QUERY_EXTENDED_VERSION_NUMBER => answer(2 << 2), // 2.0
+        _ if dt == device_type::EMERGENCY => dt1::extended_cmd(dev, cmd, flags, now),
+        _ if dt == device_type::LED => dt6::extended_cmd(dev, cmd, flags),
+        _ if dt == device_type::COLOUR => dt8::extended_cmd(dev, cmd, flags, now),
+        _ => NO_REPLY,
+    }
 }
 
-fn special_cmd(dev: &mut DaliSimGear, cmd: u8, data: u8, flags: Flags) 
-              -> Option<DaliBusEventType>
-{
-    //eprintln!("Special cmd: {:02x}", cmd);
+fn special_cmd(
+    dev: &mut DaliSimGear,
+    cmd: u8,
+    data: u8,
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
     match cmd {
         cmd::TERMINATE => {
             dev.initialisation_state = InitialisationState::DISABLED;
-            // TODO stop identification
             NO_REPLY
-        },
+        }
         cmd::INITIALISE if flags.send_twice() => {
-            if (((data & 0x81) == 0x01) 
-                && (data >> 1) == dev.short_address)
+            if (((data & 0x81) == 0x01) && (data >> 1) == dev.short_address)
                 || (data == 0xff && dev.short_address == MASK)
                 || data == 0x00
             {
-		println!("Initialised"); 
-                // TODO restart initialisation timer 
+                debug!("Initialised");
+                dev.init_start_time = now;
                 dev.initialisation_state = InitialisationState::ENABLED;
             }
-            
+
             NO_REPLY
-        },
+        }
         cmd::RANDOMISE if flags.send_twice() => {
             if dev.initialisation_state != InitialisationState::DISABLED {
-                dev.random_address = rand::thread_rng().gen_range(0..=0xffffff);
+                dev.random_address = dev.rng.gen_range(0..=0xffffff);
             }
             NO_REPLY
-        },
+        }
         cmd::COMPARE => {
-	    println!("Comparing: 0x{:06x} <=  0x{:06x}", 
-		    dev.random_address,  dev.search_address);
+            debug!(
+                "Comparing: 0x{:06x} <=  0x{:06x}",
+                dev.random_address, dev.search_address
+            );
             if dev.initialisation_state == InitialisationState::ENABLED
-                && dev.random_address <= dev.search_address {
-                    YES_REPLY
-                } else {
-                    NO_REPLY
-                }
-        },
+                && dev.random_address <= dev.search_address
+            {
+                YES_REPLY
+            } else {
+                NO_REPLY
+            }
+        }
         cmd::WITHDRAW => {
-             if dev.initialisation_state == InitialisationState::ENABLED
-                && dev.random_address == dev.search_address {
-                    dev.initialisation_state = InitialisationState::WITHDRAWN; 
-                }
+            if dev.initialisation_state == InitialisationState::ENABLED
+                && dev.random_address == dev.search_address
+            {
+                dev.initialisation_state = InitialisationState::WITHDRAWN;
+            }
             NO_REPLY
-        },
+        }
         cmd::SEARCHADDRH => {
             if dev.initialisation_state != InitialisationState::DISABLED {
-                dev.search_address =
-                    (dev.search_address & 0x00ffff) | ((data as u32) << 16);
+                dev.search_address = (dev.search_address & 0x00ffff) | ((data as u32) << 16);
             }
             NO_REPLY
-        },
+        }
         cmd::SEARCHADDRM => {
             if dev.initialisation_state != InitialisationState::DISABLED {
-                dev.search_address =
-                    (dev.search_address & 0xff00ff) | ((data as u32) << 8);
+                dev.search_address = (dev.search_address & 0xff00ff) | ((data as u32) << 8);
             }
             NO_REPLY
-        },
+        }
         cmd::SEARCHADDRL => {
             if dev.initialisation_state != InitialisationState::DISABLED {
-                dev.search_address =
-                    (dev.search_address & 0xffff00) | (data as u32);
+                dev.search_address = (dev.search_address & 0xffff00) | (data as u32);
             }
             NO_REPLY
-        },
-        
+        }
+
         cmd::PROGRAM_SHORT_ADDRESS => {
-            if dev.initialisation_state != InitialisationState::DISABLED {
+            if dev.initialisation_state != InitialisationState::DISABLED
+                && dev.search_address == dev.random_address
+            {
                 if (data & 0x81) == 0x01 {
-                    dev.short_address = data>>1;
+                    dev.short_address = data >> 1;
                 } else if data == MASK {
                     dev.short_address = MASK;
                 }
             }
-            NO_REPLY         
-        },
+            NO_REPLY
+        }
+        cmd::VERIFY_SHORT_ADDRESS => yes_no(
+            dev.initialisation_state != InitialisationState::DISABLED
+                && (data & 0x81) == 0x01
+                && data >> 1 == dev.short_address,
+        ),
         cmd::QUERY_SHORT_ADDRESS => {
             if dev.initialisation_state != InitialisationState::DISABLED
-                && dev.search_address == dev.random_address {
-                    eprintln!("Query_Short_Address: {}", dev.short_address);
-                    Some(DaliBusEventType::Frame8((dev.short_address<<1) |0x01))
+                && dev.search_address == dev.random_address
+            {
+                debug!("Query_Short_Address: {}", dev.short_address);
+                if dev.short_address == MASK {
+                    Some(DaliBusEventType::Frame8(MASK))
                 } else {
-                    NO_REPLY
+                    Some(DaliBusEventType::Frame8((dev.short_address << 1) | 0x01))
                 }
-        },
+            } else {
+                NO_REPLY
+            }
+        }
+        cmd::ENABLE_DEVICE_TYPE => {
+            dev.enabled_device_type = Some(data);
+            NO_REPLY
+        }
         cmd::DTR0 => {
             dev.dtr0 = data;
             NO_REPLY
-        },
+        }
         cmd::DTR1 => {
             dev.dtr1 = data;
             NO_REPLY
-        },
+        }
         cmd::DTR2 => {
             dev.dtr2 = data;
             NO_REPLY
-        },
-        
-        _ => {
+        }
+        cmd::WRITE_MEMORY_LOCATION => {
+            if write_memory(dev, data) {
+                answer(data)
+            } else {
+                NO_REPLY
+            }
+        }
+        cmd::WRITE_MEMORY_LOCATION_NO_REPLY => {
+            write_memory(dev, data);
             NO_REPLY
         }
+
+        _ => NO_REPLY,
     }
 }
 
+/// Check if the frame is addressed to this gear
+fn addressed(dev: &DaliSimGear, addr: u8) -> bool {
+    match addr >> 1 {
+        a @ 0x00..=0x3f => a == dev.short_address,
+        a @ 0x40..=0x4f => dev.gear_groups & (1 << (a & 0x0f)) != 0,
+        0x7e => dev.short_address == MASK,
+        0x7f => true,
+        _ => false,
+    }
+}
 
+/// Commands that may follow ENABLE WRITE MEMORY without disabling writes
+fn keeps_write_enabled(cmd: [u8; 2]) -> bool {
+    match cmd[0] {
+        cmd::DTR0
+        | cmd::DTR1
+        | cmd::DTR2
+        | cmd::WRITE_MEMORY_LOCATION
+        | cmd::WRITE_MEMORY_LOCATION_NO_REPLY => true,
+        0xa0..=0xcb => false,
+        _ => {
+            (cmd[0] & 0x01) == 0x01
+                && matches!(
+                    cmd[1],
+                    cmd::ENABLE_WRITE_MEMORY
+                        | cmd::QUERY_CONTENT_DTR0
+                        | cmd::QUERY_CONTENT_DTR1
+                        | cmd::QUERY_CONTENT_DTR2
+                )
+        }
+    }
+}
 
-impl DaliSimDevice for DaliSimGear
-{
-    fn start(&mut self, mut host: Box<dyn DaliSimHost>)
-	     -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>>
-    {
-	self.source_id = host.next_source_id();
-	self.host = Some(host);
-	Box::pin(future::ready(Ok(())))
+fn frame16(
+    dev: &mut DaliSimGear,
+    cmd: [u8; 2],
+    flags: Flags,
+    now: Instant,
+) -> Option<DaliBusEventType> {
+    // ENABLE DEVICE TYPE applies to the next command and its repetition
+    if !flags.send_twice() {
+        dev.extended_device_type = dev.enabled_device_type.take();
     }
-    
-    fn stop(&mut self) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>>
-    {
-	Box::pin(future::ready(Ok(())))
+    let next_device_type = dev.next_device_type.take();
+    if !keeps_write_enabled(cmd) {
+        dev.write_enable_state = WriteEnableState::DISABLED;
     }
-    
-    fn event(&mut self,event: &DaliSimEvent) 
-             -> Option<DaliSimEvent>
-    {
-	let mut flags = Flags::Empty;
-	match (event, &self.last_event) {
-	    (DaliSimEvent{timestamp: ts, 
-			  event_type: DaliBusEventType::Frame16(cmd), ..},
-	     DaliSimEvent{timestamp: last_ts, 
-			  event_type: DaliBusEventType::Frame16(last_cmd),
-			  ..}) => {
-		if ts.duration_since(*last_ts) 
-		    < FRAME_16_DURATION + SEND_TWICE_DURATION 
-		    && cmd == last_cmd
-		{
-		    flags = flags | Flags::SendTwice(true);
-		}
-	    },
-	    _ => {}
-	}
-	self.last_event = event.clone();
-	let event_type = match event.event_type {
-	    DaliBusEventType::Frame16(cmd) => {
-		eprintln!("Gear {} received: {:02x} {:02x}", 
-			   self.short_address,
-			   cmd[0], cmd[1]);
-		match cmd[0] >> 1 {
-		    addr @ 0x00..=0x3f if addr == self.short_address => {
-			device_cmd(self, cmd[0], cmd[1], flags)
-		    },
-		    addr @ 0x40..=0x4f 
-			if self.gear_groups & (1<<(addr & 0x0f)) != 0 => 
-		    {
-			device_cmd(self, cmd[0], cmd[1], flags)
-		    },
-		    0x7e if self.short_address == MASK => {
-			device_cmd(self, cmd[0], cmd[1], flags)
-		    },
-		    0x7f => {
-			device_cmd(self, cmd[0], cmd[1], flags)
-		    },
-		    _ => {
-			special_cmd(self, cmd[0], cmd[1], flags)
-		    }
-		}
-	    },
-	    _ => None
-	};
-	if let Some(event_type) = event_type {
-	    Some(DaliSimEvent{
-		source_id: self.source_id,
-		timestamp: Instant::now() +FRAME_8_DURATION+REPLY_DELAY,
-		event_type})
-	} else {
-	    None
-	}
+    if (0xa0..=0xcb).contains(&cmd[0]) {
+        special_cmd(dev, cmd[0], cmd[1], flags, now)
+    } else if addressed(dev, cmd[0]) {
+        if (cmd[0] & 0x01) == 0 {
+            direct_arc_power(dev, cmd[1], now)
+        } else {
+            if cmd[1] == cmd::QUERY_NEXT_DEVICE_TYPE {
+                dev.next_device_type = next_device_type;
+            }
+            device_cmd(dev, cmd[1], flags, now)
+        }
+    } else {
+        None
     }
 }
 
+impl DaliSimDevice for DaliSimGear {
+    fn start(
+        &mut self,
+        mut host: Box<dyn DaliSimHost>,
+    ) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        self.source_id = host.next_source_id();
+        self.host = Some(host);
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn stop(&mut self) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        Box::pin(future::ready(Ok(())))
+    }
+
+    fn description(&self) -> Option<DeviceDescription> {
+        Some(DeviceDescription::Gear(self.into()))
+    }
+
+    fn event(&mut self, event: &DaliSimEvent) -> Option<DaliSimEvent> {
+        check_timers(self, event.timestamp);
+        let mut flags = Flags::Empty;
+        if let (
+            DaliSimEvent {
+                timestamp: ts,
+                event_type: DaliBusEventType::Frame16(cmd),
+                ..
+            },
+            DaliSimEvent {
+                timestamp: last_ts,
+                event_type: DaliBusEventType::Frame16(last_cmd),
+                ..
+            },
+        ) = (event, &self.last_event)
+            && ts.duration_since(*last_ts) < FRAME_16_DURATION + SEND_TWICE_DURATION
+            && cmd == last_cmd
+        {
+            flags |= Flags::SendTwice(true);
+        }
+        self.last_event = if flags.send_twice() {
+            // A third identical frame starts a new pair
+            DaliSimEvent {
+                event_type: DaliBusEventType::BusPowerOn,
+                ..event.clone()
+            }
+        } else {
+            event.clone()
+        };
+        let before = reset_variables(self);
+        let event_type = match event.event_type {
+            DaliBusEventType::BusPowerOff => {
+                // System failure
+                self.powered = false;
+                if self.system_failure_level != MASK {
+                    let level = limit_level(self, self.system_failure_level);
+                    set_level(self, level, event.timestamp);
+                }
+                None
+            }
+            DaliBusEventType::BusPowerOn => {
+                self.powered = true;
+                None
+            }
+            DaliBusEventType::Frame16(_) if !self.powered => None,
+            DaliBusEventType::Frame16(cmd) => {
+                debug!(
+                    "Gear {} received: {:02x} {:02x}",
+                    self.short_address, cmd[0], cmd[1]
+                );
+                frame16(self, cmd, flags, event.timestamp)
+            }
+            _ => None,
+        };
+        let after = reset_variables(self);
+        if after != before {
+            if after == reset_values(self) {
+                self.status |= status::RESET_STATE;
+            } else {
+                self.status &= !status::RESET_STATE;
+            }
+        }
+        event_type.map(|event_type| {
+            let forward = DaliFrame::try_from(&event.event_type).unwrap();
+            DaliSimEvent {
+                source_id: self.source_id,
+                timestamp: event.timestamp + timing::frame_duration(&forward) + REPLY_DELAY,
+                event_type,
+            }
+        })
+    }
+}
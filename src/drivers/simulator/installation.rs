@@ -0,0 +1,570 @@
+//! Declarative description of a simulated DALI installation.
+//!
+//! An installation lists the control gears and control devices connected
+//! to a simulated bus. It can be loaded into a
+//! [`DaliBusSim`](super::simulator::DaliBusSim) and extracted from the
+//! current state of a running simulation. With the `serde` feature it can
+//! also be read from or written to JSON or TOML files.
+//!
+//! Example in TOML:
+//! ```toml
+//! [[gears]]
+//! short_address = 0
+//! random_address = 0x123456
+//! groups = [0, 3]
+//! max_level = 200
+//! device_types = [6]
+//! ```
+
+use super::control::{DaliSimControlDevice, SimInstance};
+use super::gear::DaliSimGear;
+use crate::common::defs::MASK;
+use crate::gear::device_type::types as device_type;
+use crate::gear::dt8::colour_type;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug)]
+pub enum InstallationError {
+    InvalidShortAddress(u8),
+    InvalidRandomAddress(u32),
+    InvalidGroup(u8),
+    /// Levels that don't satisfy physical minimum <= min level <= max
+    /// level <= 254
+    InvalidLevels {
+        physical_minimum: u8,
+        min_level: u8,
+        max_level: u8,
+    },
+}
+
+impl Error for InstallationError {}
+
+impl fmt::Display for InstallationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallationError::InvalidShortAddress(a) => {
+                write!(f, "Invalid short address {}", a)
+            }
+            InstallationError::InvalidRandomAddress(a) => {
+                write!(f, "Invalid random address 0x{:06x}", a)
+            }
+            InstallationError::InvalidGroup(g) => write!(f, "Invalid group {}", g),
+            InstallationError::InvalidLevels {
+                physical_minimum,
+                min_level,
+                max_level,
+            } => write!(
+                f,
+                "Invalid levels: physical minimum {}, min level {}, max level {}",
+                physical_minimum, min_level, max_level
+            ),
+        }
+    }
+}
+
+fn default_level() -> u8 {
+    0xfe
+}
+
+fn default_min_level() -> u8 {
+    0x01
+}
+
+fn default_random_address() -> u32 {
+    0xffffff
+}
+
+fn default_scenes() -> [u8; 16] {
+    [MASK; 16]
+}
+
+fn default_fade() -> u8 {
+    0x07
+}
+
+// Same as a gear created by DaliSimGear::new
+fn default_device_types() -> Vec<u8> {
+    vec![device_type::LED]
+}
+
+#[cfg(feature = "serde")]
+fn default_true() -> bool {
+    true
+}
+
+fn default_tc_coolest() -> u16 {
+    153
+}
+
+fn default_tc_warmest() -> u16 {
+    370
+}
+
+/// Colour capabilities of a device type 8 gear
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ColourDescription {
+    /// Supports the xy-coordinate colour type
+    #[cfg_attr(feature = "serde", serde(default = "default_true"))]
+    pub xy: bool,
+    /// Supports the colour temperature colour type
+    #[cfg_attr(feature = "serde", serde(default = "default_true"))]
+    pub tc: bool,
+    /// Physical colour temperature limits in mirek
+    #[cfg_attr(feature = "serde", serde(default = "default_tc_coolest"))]
+    pub tc_physical_coolest: u16,
+    #[cfg_attr(feature = "serde", serde(default = "default_tc_warmest"))]
+    pub tc_physical_warmest: u16,
+}
+
+impl Default for ColourDescription {
+    fn default() -> Self {
+        ColourDescription {
+            xy: true,
+            tc: true,
+            tc_physical_coolest: default_tc_coolest(),
+            tc_physical_warmest: default_tc_warmest(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GearDescription {
+    /// Short address 0-63, no address if missing
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub short_address: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(default = "default_random_address"))]
+    pub random_address: u32,
+    /// Groups 0-15 the gear is a member of
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub groups: Vec<u8>,
+    /// Level for each scene, 255 means that the gear is not part of the scene
+    #[cfg_attr(feature = "serde", serde(default = "default_scenes"))]
+    pub scenes: [u8; 16],
+    #[cfg_attr(feature = "serde", serde(default = "default_min_level"))]
+    pub physical_minimum: u8,
+    #[cfg_attr(feature = "serde", serde(default = "default_min_level"))]
+    pub min_level: u8,
+    #[cfg_attr(feature = "serde", serde(default = "default_level"))]
+    pub max_level: u8,
+    #[cfg_attr(feature = "serde", serde(default = "default_level"))]
+    pub power_on_level: u8,
+    #[cfg_attr(feature = "serde", serde(default = "default_level"))]
+    pub system_failure_level: u8,
+    /// Fade time in bit 4-7 and fade rate in bit 0-3
+    #[cfg_attr(feature = "serde", serde(default = "default_fade"))]
+    pub fade: u8,
+    /// LED (6) if missing
+    #[cfg_attr(feature = "serde", serde(default = "default_device_types"))]
+    pub device_types: Vec<u8>,
+    /// Colour capabilities if device type 8 is included, all colour types
+    /// supported if missing
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub colour: Option<ColourDescription>,
+}
+
+impl Default for GearDescription {
+    fn default() -> Self {
+        GearDescription {
+            short_address: None,
+            random_address: default_random_address(),
+            groups: Vec::new(),
+            scenes: default_scenes(),
+            physical_minimum: default_min_level(),
+            min_level: default_min_level(),
+            max_level: default_level(),
+            power_on_level: default_level(),
+            system_failure_level: default_level(),
+            fade: default_fade(),
+            device_types: default_device_types(),
+            colour: None,
+        }
+    }
+}
+
+impl GearDescription {
+    fn validate(&self) -> Result<(), InstallationError> {
+        if let Some(addr) = self.short_address
+            && addr >= 64
+        {
+            return Err(InstallationError::InvalidShortAddress(addr));
+        }
+        if self.random_address > 0xffffff {
+            return Err(InstallationError::InvalidRandomAddress(self.random_address));
+        }
+        if let Some(&g) = self.groups.iter().find(|&&g| g >= 16) {
+            return Err(InstallationError::InvalidGroup(g));
+        }
+        if self.physical_minimum == 0
+            || self.physical_minimum > self.min_level
+            || self.min_level > self.max_level
+            || self.max_level == MASK
+        {
+            return Err(InstallationError::InvalidLevels {
+                physical_minimum: self.physical_minimum,
+                min_level: self.min_level,
+                max_level: self.max_level,
+            });
+        }
+        Ok(())
+    }
+
+    /// Create a simulated gear with the described configuration
+    pub fn build(&self) -> Result<DaliSimGear, InstallationError> {
+        self.configure(DaliSimGear::new())
+    }
+
+    /// Like [`GearDescription::build`], with a reproducible sequence of
+    /// random addresses for RANDOMISE
+    pub fn build_with_seed(&self, seed: u64) -> Result<DaliSimGear, InstallationError> {
+        self.configure(DaliSimGear::with_seed(seed))
+    }
+
+    fn configure(&self, mut gear: DaliSimGear) -> Result<DaliSimGear, InstallationError> {
+        self.validate()?;
+        gear.short_address = self.short_address.unwrap_or(MASK);
+        gear.random_address = self.random_address;
+        gear.gear_groups = self.groups.iter().fold(0, |g, &n| g | (1 << n));
+        gear.scene = self.scenes;
+        gear.phm = self.physical_minimum;
+        gear.min_level = self.min_level;
+        gear.max_level = self.max_level;
+        gear.power_on_level = self.power_on_level;
+        gear.system_failure_level = self.system_failure_level;
+        gear.fade = self.fade;
+        gear.device_types = self.device_types.clone();
+        if let Some(colour) = &self.colour {
+            let features = if colour.xy { colour_type::XY } else { 0 }
+                | if colour.tc { colour_type::TC } else { 0 };
+            gear.colour.features = features >> 4;
+            gear.colour.tc_physical_coolest = colour.tc_physical_coolest;
+            gear.colour.tc_physical_warmest = colour.tc_physical_warmest;
+            gear.colour.tc_coolest = colour.tc_physical_coolest;
+            gear.colour.tc_warmest = colour.tc_physical_warmest;
+            if !colour.tc {
+                gear.colour.report.colour_type = colour_type::XY;
+            }
+        }
+        Ok(gear)
+    }
+}
+
+impl From<&DaliSimGear> for GearDescription {
+    fn from(gear: &DaliSimGear) -> Self {
+        GearDescription {
+            short_address: if gear.short_address < 64 {
+                Some(gear.short_address)
+            } else {
+                None
+            },
+            random_address: gear.random_address,
+            groups: (0..16)
+                .filter(|g| gear.gear_groups & (1 << g) != 0)
+                .collect(),
+            scenes: gear.scene,
+            physical_minimum: gear.phm,
+            min_level: gear.min_level,
+            max_level: gear.max_level,
+            power_on_level: gear.power_on_level,
+            system_failure_level: gear.system_failure_level,
+            fade: gear.fade,
+            device_types: gear.device_types.clone(),
+            colour: if gear.device_types.contains(&device_type::COLOUR) {
+                let features = gear.colour.features << 4;
+                Some(ColourDescription {
+                    xy: features & colour_type::XY != 0,
+                    tc: features & colour_type::TC != 0,
+                    tc_physical_coolest: gear.colour.tc_physical_coolest,
+                    tc_physical_warmest: gear.colour.tc_physical_warmest,
+                })
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InstanceDescription {
+    pub instance_type: u8,
+    /// Number of bits in the input value, default depends on the type
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub resolution: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ControlDescription {
+    /// Short address 0-63, no address if missing
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub short_address: Option<u8>,
+    #[cfg_attr(feature = "serde", serde(default = "default_random_address"))]
+    pub random_address: u32,
+    /// Device groups 0-31 the device is a member of
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub groups: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub application_controller: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub instances: Vec<InstanceDescription>,
+}
+
+impl ControlDescription {
+    fn validate(&self) -> Result<(), InstallationError> {
+        if let Some(addr) = self.short_address
+            && addr >= 64
+        {
+            return Err(InstallationError::InvalidShortAddress(addr));
+        }
+        if self.random_address > 0xffffff {
+            return Err(InstallationError::InvalidRandomAddress(self.random_address));
+        }
+        if let Some(&g) = self.groups.iter().find(|&&g| g >= 32) {
+            return Err(InstallationError::InvalidGroup(g));
+        }
+        Ok(())
+    }
+
+    /// Create a simulated control device with the described configuration
+    pub fn build(&self) -> Result<DaliSimControlDevice, InstallationError> {
+        self.configure(DaliSimControlDevice::new(self.instances()))
+    }
+
+    /// Like [`ControlDescription::build`], with a reproducible sequence of
+    /// random addresses for RANDOMISE
+    pub fn build_with_seed(&self, seed: u64) -> Result<DaliSimControlDevice, InstallationError> {
+        self.configure(DaliSimControlDevice::with_seed(self.instances(), seed))
+    }
+
+    fn instances(&self) -> Vec<SimInstance> {
+        self.instances
+            .iter()
+            .map(|desc| {
+                let mut inst = SimInstance::new(desc.instance_type);
+                if let Some(resolution) = desc.resolution {
+                    inst.resolution = resolution;
+                }
+                inst
+            })
+            .collect()
+    }
+
+    fn configure(
+        &self,
+        dev: DaliSimControlDevice,
+    ) -> Result<DaliSimControlDevice, InstallationError> {
+        self.validate()?;
+        dev.set_short_address(self.short_address);
+        dev.set_random_address(self.random_address);
+        dev.set_device_groups(self.groups.iter().fold(0, |g, &n| g | (1 << n)));
+        dev.set_application_controller(self.application_controller);
+        Ok(dev)
+    }
+}
+
+/// Description of a single simulated device
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceDescription {
+    Gear(GearDescription),
+    Control(ControlDescription),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Installation {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub gears: Vec<GearDescription>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub control_devices: Vec<ControlDescription>,
+    /// Seed for the random addresses generated by RANDOMISE, different
+    /// each time the installation is loaded if missing
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub seed: Option<u64>,
+}
+
+impl Installation {
+    pub fn new() -> Installation {
+        Installation::default()
+    }
+
+    /// Add the description of a device to the installation
+    pub fn add(&mut self, desc: DeviceDescription) {
+        match desc {
+            DeviceDescription::Gear(gear) => self.gears.push(gear),
+            DeviceDescription::Control(ctrl) => self.control_devices.push(ctrl),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Installation {
+    pub fn from_json(s: &str) -> DynResult<Installation> {
+        Ok(serde_json::from_str(s)?)
+    }
+
+    pub fn to_json(&self) -> DynResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_toml(s: &str) -> DynResult<Installation> {
+        Ok(toml::from_str(s)?)
+    }
+
+    pub fn to_toml(&self) -> DynResult<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    fn is_toml(path: &Path) -> bool {
+        path.extension().is_some_and(|ext| ext == "toml")
+    }
+
+    /// Read an installation from a file. Files with the extension
+    /// `.toml` are parsed as TOML, everything else as JSON.
+    pub fn read(path: &Path) -> DynResult<Installation> {
+        let s = std::fs::read_to_string(path)?;
+        if Self::is_toml(path) {
+            Self::from_toml(&s)
+        } else {
+            Self::from_json(&s)
+        }
+    }
+
+    /// Write an installation to a file. The format is selected in the
+    /// same way as for [`Installation::read`].
+    pub fn write(&self, path: &Path) -> DynResult<()> {
+        let s = if Self::is_toml(path) {
+            self.to_toml()?
+        } else {
+            self.to_json()?
+        };
+        std::fs::write(path, s)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "serde")]
+    const TOML_INSTALLATION: &str = r#"
+[[gears]]
+short_address = 3
+random_address = 0x123456
+groups = [0, 15]
+max_level = 200
+device_types = [6]
+
+[[gears]]
+random_address = 0x654321
+
+[[control_devices]]
+short_address = 1
+random_address = 0x111111
+groups = [31]
+instances = [{instance_type = 1}, {instance_type = 4, resolution = 12}]
+"#;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parse_toml() {
+        let inst = Installation::from_toml(TOML_INSTALLATION).unwrap();
+        assert_eq!(inst.gears.len(), 2);
+        let gear = inst.gears[0].build().unwrap();
+        assert_eq!(gear.short_address, 3);
+        assert_eq!(gear.random_address, 0x123456);
+        assert_eq!(gear.gear_groups, 0x8001);
+        assert_eq!(gear.max_level, 200);
+        assert_eq!(gear.min_level, 1);
+        assert_eq!(gear.device_types, vec![6]);
+        let gear = inst.gears[1].build().unwrap();
+        assert_eq!(gear.short_address, MASK);
+        assert_eq!(gear.scene, [MASK; 16]);
+        assert_eq!(gear.device_types, DaliSimGear::new().device_types);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trip() {
+        let inst = Installation::from_toml(TOML_INSTALLATION).unwrap();
+        let json = inst.to_json().unwrap();
+        assert_eq!(Installation::from_json(&json).unwrap(), inst);
+        let toml = inst.to_toml().unwrap();
+        assert_eq!(Installation::from_toml(&toml).unwrap(), inst);
+        let gear = inst.gears[0].build().unwrap();
+        assert_eq!(GearDescription::from(&gear), inst.gears[0]);
+        let ctrl = inst.control_devices[0].build().unwrap();
+        let mut desc = ControlDescription::from(&ctrl);
+        assert_eq!(desc.instances[0].resolution, Some(1));
+        desc.instances[0].resolution = None;
+        assert_eq!(desc, inst.control_devices[0]);
+    }
+
+    #[test]
+    fn invalid_address() {
+        let desc = GearDescription {
+            short_address: Some(64),
+            ..GearDescription::default()
+        };
+        assert!(desc.build().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn invalid_levels() {
+        let inst = Installation::from_toml(
+            r#"
+[[gears]]
+short_address = 1
+min_level = 100
+max_level = 50
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            inst.gears[0].build(),
+            Err(InstallationError::InvalidLevels {
+                physical_minimum: 1,
+                min_level: 100,
+                max_level: 50
+            })
+        ));
+        for (physical_minimum, min_level, max_level) in [(0, 0, 254), (10, 5, 254), (1, 1, 255)] {
+            let desc = GearDescription {
+                physical_minimum,
+                min_level,
+                max_level,
+                ..GearDescription::default()
+            };
+            assert!(desc.build().is_err());
+        }
+    }
+}
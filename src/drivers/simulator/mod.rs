@@ -1,8 +1,14 @@
+pub mod control;
 pub mod device;
+pub mod dt1;
+pub mod dt6;
+pub mod dt8;
+pub mod faults;
+pub mod gear;
+pub mod installation;
+#[allow(clippy::module_inception)]
 pub mod simulator;
-pub mod simulator_bus;
 pub mod simulator_driver;
-pub mod timing;
-//pub mod gear;
 #[cfg(test)]
 mod test;
+pub mod timing;
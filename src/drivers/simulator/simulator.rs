@@ -1,9 +1,14 @@
+use super::control::DaliSimControlDevice;
 use super::device::{DaliSimDevice, DaliSimEvent, DaliSimHost};
+use super::faults::{DeviceId, FaultConfig, FaultInjector};
+use super::installation::Installation;
 use super::timing;
 use crate::drivers::driver::{DaliBusEventType, DaliFrame};
+use log::debug;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::BinaryHeap;
-use std::convert::TryFrom;
-use std::future::Future;
+use std::future::{self, Future};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -49,6 +54,16 @@ impl DaliSimHost for DaliSimDeviceHost {
         &mut self,
         event: DaliSimEvent,
     ) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        if !self.real_time() {
+            // Queue directly, the event is dispatched when time is advanced
+            return Box::pin(future::ready(match self.engine.lock() {
+                Ok(mut engine) => {
+                    push_event(&mut engine.events, event);
+                    Ok(())
+                }
+                Err(_) => Err("Simulator engine lock failed".into()),
+            }));
+        }
         let send_event = self.send_event.clone();
         Box::pin(async move {
             match send_event.send(event).await {
@@ -63,7 +78,7 @@ impl DaliSimHost for DaliSimDeviceHost {
             if engine.real_time {
                 Instant::now()
             } else {
-                engine.last_event_time
+                engine.virtual_time
             }
         } else {
             Instant::now()
@@ -78,6 +93,23 @@ impl DaliSimHost for DaliSimDeviceHost {
         }
     }
 
+    fn wait_until(&self, end: Instant) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        if self.real_time() {
+            Box::pin(tokio::time::sleep_until(end.into()))
+        } else {
+            let engine = self.engine.clone();
+            Box::pin(async move { run_until(&engine, end) })
+        }
+    }
+
+    fn next_event_time(&self) -> Option<Instant> {
+        let engine = self.engine.lock().ok()?;
+        engine
+            .events
+            .peek()
+            .map(|TimeOrderedEvent(DaliSimEvent { timestamp, .. })| *timestamp)
+    }
+
     fn next_source_id(&mut self) -> u32 {
         get_next_source_id()
     }
@@ -96,7 +128,10 @@ struct DaliBusSimEngine {
     /* If true then dispatch events at the system time indicated by
     the timestamp, otherwise dispatch events as soon as possible. */
     real_time: bool,
-    last_event_time: Instant,
+    // Time of the last dispatched event. Used as the current time when
+    // not running in real time.
+    virtual_time: Instant,
+    faults: FaultInjector,
 }
 /// Add an event to the queue of events waiting for dispatch
 fn push_event(events: &mut BinaryHeap<TimeOrderedEvent>, event: DaliSimEvent) {
@@ -104,97 +139,104 @@ fn push_event(events: &mut BinaryHeap<TimeOrderedEvent>, event: DaliSimEvent) {
 }
 
 fn get_next_event(events: &mut BinaryHeap<TimeOrderedEvent>) -> Option<DaliSimEvent> {
-    match events.pop() {
-        Some(TimeOrderedEvent(event)) => {
-            let DaliSimEvent {
-                event_type,
-                timestamp,
-                source_id,
-            } = &event;
-            if let Ok(frame) = DaliFrame::try_from(event_type) {
-                // Check if frame overlaps next
-                match events.peek() {
-                    Some(TimeOrderedEvent(DaliSimEvent {
-                        timestamp: next_timestamp,
-                        ..
-                    })) => {
-                        let frame_dur = timing::frame_duration(&frame);
-                        if *timestamp + frame_dur >= *next_timestamp {
-                            Some(DaliSimEvent {
-                                event_type: DaliBusEventType::FramingError,
-                                timestamp: timestamp.clone(),
-                                source_id: *source_id,
-                            })
-                        } else {
-                            Some(event)
-                        }
-                    }
-                    None => Some(event),
-                }
-            } else {
-                Some(event)
+    let TimeOrderedEvent(event) = events.pop()?;
+    let Ok(frame) = DaliFrame::try_from(&event.event_type) else {
+        return Some(event);
+    };
+    // Any frame starting before this one has ended collides with it
+    let frame_end = event.timestamp + timing::frame_duration(&frame);
+    let mut collision = false;
+    while let Some(TimeOrderedEvent(next)) = events.peek() {
+        if next.timestamp >= frame_end || DaliFrame::try_from(&next.event_type).is_err() {
+            break;
+        }
+        collision = true;
+        events.pop();
+    }
+    if collision {
+        Some(DaliSimEvent {
+            event_type: DaliBusEventType::FramingError,
+            ..event
+        })
+    } else {
+        Some(event)
+    }
+}
+
+/// Send an event to all devices and queue any replies
+fn dispatch_next_event(engine: &mut DaliBusSimEngine) {
+    let DaliBusSimEngine {
+        events,
+        devices,
+        virtual_time,
+        faults,
+        ..
+    } = engine;
+    if let Some(event) = get_next_event(events) {
+        if event.timestamp > *virtual_time {
+            *virtual_time = event.timestamp;
+        }
+        let Some(event) = faults.apply(event) else {
+            return;
+        };
+        debug!("Dispatching event: {:?}", event);
+        for (index, dev) in devices.iter_mut().enumerate() {
+            if faults.is_unresponsive(DeviceId(index)) {
+                continue;
+            }
+            if let Some(new_event) = dev.event(&event) {
+                push_event(events, new_event)
             }
         }
-        None => None,
     }
 }
 
+/// Dispatch all events up to `end` and advance the virtual time
+fn run_until(engine: &Mutex<DaliBusSimEngine>, end: Instant) {
+    let Ok(mut engine) = engine.lock() else {
+        return;
+    };
+    while let Some(TimeOrderedEvent(event)) = engine.events.peek()
+        && event.timestamp <= end
+    {
+        dispatch_next_event(&mut engine);
+    }
+    if end > engine.virtual_time {
+        engine.virtual_time = end;
+    }
+}
+
+/// Dispatch events in real time
 async fn dispatch_event(
     bus_arc: Arc<Mutex<DaliBusSimEngine>>,
     mut event_recv: mpsc::Receiver<DaliSimEvent>,
 ) {
-    let real_time = match bus_arc.lock() {
-        Ok(bus) => bus.real_time,
-        Err(_) => return,
-    };
     loop {
         // Get the time of the next pending event
         let next_timeout = match bus_arc.lock() {
-            Ok(bus) => match bus.events.peek() {
-                Some(TimeOrderedEvent(DaliSimEvent { timestamp, .. })) => Some(timestamp.clone()),
-                None => None,
-            },
+            Ok(bus) => bus
+                .events
+                .peek()
+                .map(|TimeOrderedEvent(DaliSimEvent { timestamp, .. })| *timestamp),
             Err(_) => return,
         };
         // Dispatch event immediately if the time stamp is in the past
-        if let Some(timeout) = next_timeout {
-            if real_time || timeout <= Instant::now() {
-                match bus_arc.lock() {
-                    Ok(mut bus) => {
-                        if let Some(event) = get_next_event(&mut bus.events) {
-                            let DaliBusSimEngine {
-                                events, devices, ..
-                            } = &mut *bus;
-                            eprintln!("Dispatching event: {:?}", event);
-                            for dev in devices.iter_mut() {
-                                if let Some(new_event) = dev.event(&event) {
-                                    push_event(events, new_event)
-                                }
-                            }
-                        }
-                        bus.last_event_time = timeout;
-                    }
-                    Err(_) => return,
-                };
-                continue;
-            }
+        if let Some(timeout) = next_timeout
+            && timeout <= Instant::now()
+        {
+            match bus_arc.lock() {
+                Ok(mut bus) => dispatch_next_event(&mut bus),
+                Err(_) => return,
+            };
+            continue;
         }
 
         // Wait for a new event or until it's time to dispatch a queued event
         let event = if let Some(timeout) = next_timeout {
-            if real_time {
-                match timeout_at(tokio::time::Instant::from_std(timeout), event_recv.recv()).await {
-                    Ok(res) => match res {
-                        Some(event) => Some(event),
-                        None => return,
-                    },
-                    Err(_) => None,
-                }
-            } else {
-                match event_recv.recv().await {
-                    Some(event) => Some(event),
-                    None => return,
-                }
+            match timeout_at(tokio::time::Instant::from_std(timeout), event_recv.recv()).await {
+                Ok(Some(event)) => Some(event),
+                Ok(None) => return,
+                Err(_) => None,
             }
         } else {
             match event_recv.recv().await {
@@ -205,9 +247,7 @@ async fn dispatch_event(
         // If there was a new event then add it to the queue
         if let Some(event) = event {
             match bus_arc.lock() {
-                Ok(mut bus) => {
-                    bus.events.push(TimeOrderedEvent(event));
-                }
+                Ok(mut bus) => push_event(&mut bus.events, event),
                 Err(_) => return,
             }
         }
@@ -220,43 +260,145 @@ pub struct DaliBusSim {
 }
 
 impl DaliBusSim {
+    /// Create a simulated bus running in real time
     pub async fn new() -> Result<DaliBusSim, Box<dyn std::error::Error + Send + Sync>> {
+        Self::with_real_time(true).await
+    }
+
+    /// Create a simulated bus. If `real_time` is false then the bus runs
+    /// on a virtual clock that is only advanced by
+    /// [`DaliSimHost::wait_until`], making the simulation deterministic
+    /// and independent of the system clock.
+    pub async fn with_real_time(
+        real_time: bool,
+    ) -> Result<DaliBusSim, Box<dyn std::error::Error + Send + Sync>> {
         let (send_event, recv_event) = mpsc::channel(10);
         let bus = DaliBusSimEngine {
             devices: Vec::new(),
             events: BinaryHeap::new(),
-            real_time: true,
-            last_event_time: Instant::now(),
+            real_time,
+            virtual_time: Instant::now(),
+            faults: FaultInjector::new(),
         };
         let bus_arc = Arc::new(Mutex::new(bus));
-        let dispatch_bus = bus_arc.clone();
-        tokio::spawn(dispatch_event(dispatch_bus, recv_event));
+        if real_time {
+            let dispatch_bus = bus_arc.clone();
+            tokio::spawn(dispatch_event(dispatch_bus, recv_event));
+        }
         Ok(DaliBusSim {
             bus_arc,
             send_event,
         })
     }
 
+    fn host(&self) -> DaliSimDeviceHost {
+        DaliSimDeviceHost {
+            engine: self.bus_arc.clone(),
+            send_event: self.send_event.clone(),
+        }
+    }
+
+    /// Add a device to the bus. The returned id can be used for
+    /// injecting faults for this device.
     pub async fn add_device(
         &self,
         mut device: Box<dyn DaliSimDevice + Send>,
+    ) -> Result<DeviceId, Box<dyn std::error::Error + Send + Sync>> {
+        device.start(Box::new(self.host())).await?;
+        let mut bus = self
+            .bus_arc
+            .lock()
+            .map_err(|_| "Simulator engine lock failed")?;
+        bus.devices.push(device);
+        Ok(DeviceId(bus.devices.len() - 1))
+    }
+
+    pub async fn add_event(
+        &self,
+        event: DaliSimEvent,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let dev_host = DaliSimDeviceHost {
-            engine: self.bus_arc.clone(),
-            send_event: self.send_event.clone(),
-        };
-        device.start(Box::new(dev_host)).await?;
+        self.host().send_event(event).await
+    }
+
+    /// Current time of the simulation
+    pub fn current_time(&self) -> Instant {
+        self.host().current_time()
+    }
+
+    /// Wait until the simulation has reached `end`
+    pub async fn wait_until(&self, end: Instant) {
+        self.host().wait_until(end).await
+    }
+
+    /// Set probabilities for random faults. Use `seed` to make the
+    /// faults reproducible.
+    pub fn set_faults(&self, config: FaultConfig, seed: Option<u64>) {
         if let Ok(mut bus) = self.bus_arc.lock() {
-            bus.devices.push(device);
+            bus.faults.configure(config, seed);
         }
-        Ok(())
     }
 
-    pub async fn add_event(
+    /// An unresponsive device neither receives nor answers any frames,
+    /// as if it was disconnected from the bus.
+    pub fn set_responsive(&self, id: DeviceId, responsive: bool) {
+        if let Ok(mut bus) = self.bus_arc.lock() {
+            bus.faults.set_responsive(id, responsive);
+        }
+    }
+
+    /// Turn the bus power off or on. All frames are lost while the
+    /// power is off. The devices receive a `BusPowerOff` or `BusPowerOn`
+    /// event.
+    pub async fn set_bus_power(
         &self,
-        event: DaliSimEvent,
+        on: bool,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.send_event.send(event).await?;
-        Ok(())
+        let event_type = if on {
+            DaliBusEventType::BusPowerOn
+        } else {
+            DaliBusEventType::BusPowerOff
+        };
+        self.add_event(DaliSimEvent {
+            source_id: 0,
+            timestamp: self.current_time(),
+            event_type,
+        })
+        .await
+    }
+
+    /// Add all devices described by the installation to the bus.
+    /// Returns the control devices so that they can be used for
+    /// generating input events. Each device gets its own seed for
+    /// RANDOMISE, derived from the seed of the installation if set.
+    pub async fn load_installation(
+        &self,
+        installation: &Installation,
+    ) -> Result<Vec<DaliSimControlDevice>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut rng = match installation.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for desc in &installation.gears {
+            let gear = desc.build_with_seed(rng.r#gen())?;
+            self.add_device(Box::new(gear)).await?;
+        }
+        let mut control_devices = Vec::new();
+        for desc in &installation.control_devices {
+            let dev = desc.build_with_seed(rng.r#gen())?;
+            self.add_device(Box::new(dev.clone())).await?;
+            control_devices.push(dev);
+        }
+        Ok(control_devices)
+    }
+
+    /// Describe the current state of all devices on the bus
+    pub fn installation(&self) -> Installation {
+        let mut installation = Installation::new();
+        if let Ok(bus) = self.bus_arc.lock() {
+            for desc in bus.devices.iter().filter_map(|dev| dev.description()) {
+                installation.add(desc);
+            }
+        }
+        installation
     }
 }
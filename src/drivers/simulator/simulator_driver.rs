@@ -1,20 +1,31 @@
 use crate::drivers::driver::{
     DaliBusEvent, DaliBusEventResult, DaliBusEventType, DaliDriver, DaliFrame, DaliSendResult,
+    DriverInfo, OpenError,
 };
 use crate::drivers::send_flags::Flags;
 use crate::drivers::simulator::device::{DaliSimDevice, DaliSimEvent, DaliSimHost};
+use crate::drivers::simulator::faults::FaultConfig;
+use crate::drivers::simulator::gear::DaliSimGear;
+use crate::drivers::simulator::installation::Installation;
+use crate::drivers::simulator::simulator::DaliBusSim;
 use crate::drivers::simulator::timing;
 use crate::utils::dyn_future::DynFuture;
+use log::error;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::future::{self, Future};
+#[cfg(feature = "serde")]
+use std::path::Path;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
 type DynResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
-use std::convert::TryFrom;
-use tokio::sync::oneshot;
 
 #[derive(Debug, Clone)]
 pub enum SimDriverError {
@@ -38,11 +49,9 @@ impl fmt::Display for SimDriverError {
 }
 
 struct PendingResult {
-    expect_answer: bool,
     // Send reply back to user
     reply: oneshot::Sender<DaliSendResult>,
-    // For request with answers this is when the request times
-    // out. For other requests this is when it's done and returns OK
+    // Answers arriving after this are not accepted
     request_end: Instant,
 }
 
@@ -53,9 +62,10 @@ struct DaliSimDriverCtxt {
     last_transition: Instant,
     // Request waiting for an answer
     pending_result: Option<PendingResult>,
-    monitor_reply: Option<oneshot::Sender<DaliBusEvent>>,
+    monitor: mpsc::Sender<DaliBusEvent>,
+    // Events were dropped since the monitor queue was full
+    overrun: bool,
     source_id: u32,
-    queued_event: Option<DaliBusEvent>,
 }
 
 pub struct DaliSimDriverDevice {
@@ -75,6 +85,9 @@ impl DaliSimDevice for DaliSimDriverDevice {
     }
 
     fn stop(&mut self) -> Pin<Box<dyn Future<Output = DynResult<()>> + Send>> {
+        if let Ok(mut ctxt) = self.ctxt.lock() {
+            ctxt.host = None;
+        }
         Box::pin(future::ready(Ok(())))
     }
 
@@ -86,197 +99,382 @@ impl DaliSimDevice for DaliSimDriverDevice {
 
         // Calculate the last transition of the frame
         if let Ok(frame) = DaliFrame::try_from(&event.event_type) {
-            ctxt.last_transition = event.timestamp + timing::frame_duration(&frame);
-        }
-        // End the request if the end time is past
-        if let Some(pending_result) = ctxt.pending_result.take() {
-            if pending_result.request_end <= event.timestamp {
-                pending_result
-                    .reply
-                    .send(if pending_result.expect_answer {
-                        DaliSendResult::Timeout
-                    } else {
-                        DaliSendResult::Ok
-                    })
-                    .unwrap_or(());
+            let end = event.timestamp + timing::frame_duration(&frame);
+            if end > ctxt.last_transition {
+                ctxt.last_transition = end;
             }
         }
         // Ignore events sent by this driver
         if event.source_id == ctxt.source_id {
             return None;
         }
-        let mut sent_answer = false;
-        match event {
-            DaliSimEvent {
-                event_type: DaliBusEventType::Frame8(answer),
-                ..
-            } => {
-                if let Some(pending_result) = ctxt.pending_result.take() {
-                    pending_result
-                        .reply
-                        .send(DaliSendResult::Answer(*answer))
-                        .unwrap_or(());
-                    sent_answer = true;
-                }
-            }
-            DaliSimEvent {
-                event_type: DaliBusEventType::FramingError,
-                ..
-            } => {
-                if let Some(pending_result) = ctxt.pending_result.take() {
-                    pending_result
-                        .reply
-                        .send(DaliSendResult::Framing)
-                        .unwrap_or(());
-                    sent_answer = true;
-                }
-            }
-            _ => {}
+        let answer = match event.event_type {
+            DaliBusEventType::Frame8(answer) => Some(DaliSendResult::Answer(answer)),
+            DaliBusEventType::FramingError => Some(DaliSendResult::Framing),
+            _ => None,
         };
-
-        /* We expected an answer but there was some other event instead.
-        Treat this as a timeout since no acceptable answer was received. */
-        if let Some(pending_result) = ctxt.pending_result.take() {
-            pending_result
-                .reply
-                .send(DaliSendResult::Timeout)
-                .unwrap_or(());
+        if let Some(answer) = answer
+            && let Some(pending_result) = ctxt.pending_result.take()
+        {
+            if event.timestamp <= pending_result.request_end {
+                pending_result.reply.send(answer).unwrap_or(());
+                return None;
+            }
+            ctxt.pending_result = Some(pending_result);
         }
 
-        // If no answer was sent then this is an unrelated frame
-        if !sent_answer {
-            let DaliSimEvent {
-                timestamp,
-                event_type,
-                ..
-            } = event;
-            let bus_event = DaliBusEvent {
-                timestamp: *timestamp,
-                event_type: event_type.clone(),
+        // This is an unrelated event
+        let bus_event = DaliBusEvent {
+            timestamp: event.timestamp,
+            event_type: event.event_type.clone(),
+        };
+        // Tell the reader that events were lost once there's room again
+        if ctxt.overrun {
+            let overrun = DaliBusEvent {
+                timestamp: event.timestamp,
+                event_type: DaliBusEventType::Overrun,
             };
-            if let Some(monitor_reply) = ctxt.monitor_reply.take() {
-                monitor_reply.send(bus_event).unwrap_or(());
-            } else {
-                if let Some(old_event) = &ctxt.queued_event {
-                    ctxt.queued_event = Some(DaliBusEvent {
-                        timestamp: old_event.timestamp,
-                        event_type: DaliBusEventType::Overrun,
-                    });
-                } else {
-                    ctxt.queued_event = Some(bus_event);
-                }
-            }
+            ctxt.overrun = ctxt.monitor.try_send(overrun).is_err();
+        }
+        if !ctxt.overrun {
+            ctxt.overrun = ctxt.monitor.try_send(bus_event).is_err();
         }
-
         None
     }
 }
 
+/// Number of bus events queued for the driver before
+/// [`DaliBusEventType::Overrun`] is reported
+pub const MONITOR_QUEUE_LEN: usize = 64;
+
+type SetupFuture = Pin<Box<dyn Future<Output = DynResult<DaliBusSim>> + Send>>;
+
 pub struct DaliSimDriver {
     ctxt: Arc<Mutex<DaliSimDriverCtxt>>,
+    monitor: mpsc::Receiver<DaliBusEvent>,
+    // Builds the bus the driver is connected to on first use. Held
+    // across the await, so that a cancelled setup is resumed by the next
+    // caller.
+    setup: tokio::sync::Mutex<Option<SetupFuture>>,
 }
 
 impl DaliSimDriver {
     pub fn new() -> (DaliSimDriver, Box<DaliSimDriverDevice>) {
         let now = Instant::now();
+        let (monitor_tx, monitor_rx) = mpsc::channel(MONITOR_QUEUE_LEN);
         let ctxt = DaliSimDriverCtxt {
             // Queue for events to the simulated  bus
             host: None,
             last_transition: now,
             // Request waiting for an answer
             pending_result: None,
-            monitor_reply: None,
-            queued_event: None,
+            monitor: monitor_tx,
+            overrun: false,
             source_id: 0,
         };
         let ctxt1 = Arc::new(Mutex::new(ctxt));
         let ctxt2 = ctxt1.clone();
 
         (
-            DaliSimDriver { ctxt: ctxt1 },
+            DaliSimDriver {
+                ctxt: ctxt1,
+                monitor: monitor_rx,
+                setup: tokio::sync::Mutex::new(None),
+            },
             Box::new(DaliSimDriverDevice { ctxt: ctxt2 }),
         )
     }
-}
 
-impl DaliDriver for DaliSimDriver {
-    fn send_frame(
-        &mut self,
-        cmd: DaliFrame,
-        flags: Flags,
-    ) -> Pin<Box<dyn Future<Output = DaliSendResult> + Send>> {
-        let sim_event;
-        let sim_event2;
-        let answer_recv;
-        let mut host: Box<dyn DaliSimHost>;
-        if let Ok(ref mut ctxt) = &mut self.ctxt.lock() {
-            if let Some(h) = &ctxt.host {
-                host = h.clone_box();
-            } else {
-                return Box::pin(future::ready(DaliSendResult::DriverError(
-                    "No host for device".into(),
-                )));
-            }
+    // The host must not be used while the context is locked since the
+    // simulator locks them in the reverse order when dispatching events.
+    fn host(&self) -> Option<Box<dyn DaliSimHost>> {
+        let ctxt = self.ctxt.lock().ok()?;
+        ctxt.host.as_ref().map(|h| h.clone_box())
+    }
+
+    fn connected(&self) -> bool {
+        self.setup.try_lock().is_ok_and(|setup| setup.is_none())
+    }
+
+    // Run the pending bus setup, if any
+    async fn connect(&self) -> DynResult<()> {
+        let mut setup = self.setup.lock().await;
+        if let Some(future) = setup.as_mut() {
+            let res = future.await;
+            *setup = None;
+            // The bus keeps running as long as the devices connected to it
+            let _sim = res?;
+        }
+        Ok(())
+    }
+
+    fn send_connected(&mut self, cmd: DaliFrame, flags: Flags) -> DynFuture<'_, DaliSendResult> {
+        let Some(mut host) = self.host() else {
+            return Box::pin(future::ready(DaliSendResult::DriverError(
+                "No host for device".into(),
+            )));
+        };
+        let now = host.current_time();
+        let mut sim_events = Vec::new();
+        let frame_end;
+        let request_end;
+        let mut answer_recv = None;
+        if let Ok(mut ctxt) = self.ctxt.lock() {
             let frame_dur = timing::frame_duration(&cmd);
-            sim_event = DaliSimEvent {
+            let start = now.max(ctxt.last_transition);
+            let sim_event = DaliSimEvent {
                 source_id: ctxt.source_id,
-                timestamp: host.current_time(),
+                timestamp: start,
                 event_type: DaliBusEventType::from(cmd),
             };
-            sim_event2 = if flags.send_twice() {
-                Some({
-                    let mut ev = sim_event.clone();
-                    ev.timestamp = sim_event.timestamp + frame_dur + Duration::from_micros(13500);
-                    ev
-                })
+            if flags.send_twice() {
+                let mut ev = sim_event.clone();
+                ev.timestamp = start + frame_dur + Duration::from_micros(13500);
+                sim_events.push(sim_event);
+                sim_events.push(ev);
             } else {
-                None
-            };
+                sim_events.push(sim_event);
+            }
+            frame_end = sim_events.last().unwrap().timestamp + frame_dur;
+            request_end = frame_end + timing::ANSWER_TIMEOUT;
 
-            let (send, recv) = oneshot::channel();
-            ctxt.pending_result = Some(PendingResult {
-                reply: send,
-                expect_answer: true,
-                request_end: host.current_time() + frame_dur,
-            });
-            answer_recv = Some(recv);
+            if flags.expect_answer() {
+                let (send, recv) = oneshot::channel();
+                ctxt.pending_result = Some(PendingResult {
+                    reply: send,
+                    request_end,
+                });
+                answer_recv = Some(recv);
+            } else {
+                ctxt.pending_result = None;
+            }
         } else {
             return Box::pin(future::ready(DaliSendResult::DriverError(
                 "Context lock failed".into(),
             )));
         }
 
+        let ctxt = self.ctxt.clone();
         Box::pin(async move {
-            if host.send_event(sim_event).await.is_err() {
-                return DaliSendResult::DriverError("Sending to queue failed".into());
-            }
-            if let Some(sim_event) = sim_event2 {
-                tokio::time::sleep_until(tokio::time::Instant::from_std(sim_event.timestamp)).await;
+            for sim_event in sim_events {
                 if host.send_event(sim_event).await.is_err() {
                     return DaliSendResult::DriverError("Sending to queue failed".into());
                 }
             }
-            if let Some(answer_recv) = answer_recv {
-                match tokio::time::timeout(Duration::from_millis(50), answer_recv).await {
-                    Ok(Ok(res)) => res,
-                    Ok(Err(_)) => DaliSendResult::DriverError("No answer was queued".into()),
-                    Err(_) => DaliSendResult::Timeout,
-                }
+            if let Some(mut answer_recv) = answer_recv {
+                let res = if host.real_time() {
+                    let deadline = tokio::time::Instant::from_std(request_end);
+                    match tokio::time::timeout_at(deadline, answer_recv).await {
+                        Ok(Ok(res)) => Some(res),
+                        _ => None,
+                    }
+                } else {
+                    host.wait_until(request_end).await;
+                    answer_recv.try_recv().ok()
+                };
+                res.unwrap_or_else(|| {
+                    if let Ok(mut ctxt) = ctxt.lock() {
+                        ctxt.pending_result = None;
+                    }
+                    DaliSendResult::Timeout
+                })
             } else {
+                host.wait_until(frame_end).await;
                 DaliSendResult::Ok
             }
         })
     }
+}
+
+impl DaliDriver for DaliSimDriver {
+    fn send_frame(&mut self, cmd: DaliFrame, flags: Flags) -> DynFuture<'_, DaliSendResult> {
+        if self.connected() {
+            return self.send_connected(cmd, flags);
+        }
+        Box::pin(async move {
+            if let Err(e) = self.connect().await {
+                return DaliSendResult::DriverError(e);
+            }
+            self.send_connected(cmd, flags).await
+        })
+    }
 
-    fn next_bus_event(&mut self) -> DynFuture<DaliBusEventResult> {
-        Box::pin(future::ready(Err("Not implemented".into())))
+    fn next_bus_event(&mut self) -> DynFuture<'_, DaliBusEventResult> {
+        Box::pin(async {
+            self.connect().await?;
+            // In virtual time, advance the clock until an event arrives
+            if let Some(host) = self.host()
+                && !host.real_time()
+            {
+                loop {
+                    if let Ok(event) = self.monitor.try_recv() {
+                        return Ok(event);
+                    }
+                    match host.next_event_time() {
+                        Some(next) => host.wait_until(next).await,
+                        None => break,
+                    }
+                }
+            }
+            self.monitor
+                .recv()
+                .await
+                .ok_or_else(|| "Simulated bus closed".into())
+        })
     }
 
     fn current_timestamp(&self) -> Instant {
-        Instant::now()
+        match self.host() {
+            Some(host) => host.current_time(),
+            None => Instant::now(),
+        }
+    }
+
+    fn wait_until(&self, end: Instant) -> DynFuture<'_, ()> {
+        if !self.connected() {
+            return Box::pin(async move {
+                if let Err(e) = self.connect().await {
+                    error!("Failed to set up simulated bus: {}", e);
+                }
+                self.wait_until(end).await
+            });
+        }
+        match self.host() {
+            Some(host) => host.wait_until(end),
+            None => Box::pin(tokio::time::sleep_until(end.into())),
+        }
+    }
+}
+
+fn parse_param<T>(params: &HashMap<String, String>, name: &str) -> Result<Option<T>, OpenError>
+where
+    T: FromStr,
+{
+    match params.get(name) {
+        Some(s) => match T::from_str(s) {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => Err(OpenError::ParameterError(format!(
+                "{} has invalid value",
+                name
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+async fn build_bus(
+    real_time: bool,
+    installation: Option<Installation>,
+    gears: usize,
+    addressed: usize,
+    faults: FaultConfig,
+    mut rng: StdRng,
+    driver_dev: Box<DaliSimDriverDevice>,
+) -> DynResult<DaliBusSim> {
+    let sim = DaliBusSim::with_real_time(real_time).await?;
+    if let Some(mut installation) = installation {
+        // Devices from the installation are reproducible with the seed
+        // parameter as well
+        installation.seed.get_or_insert(rng.r#gen());
+        sim.load_installation(&installation).await?;
+    }
+    for index in 0..gears {
+        let mut gear = DaliSimGear::with_seed(rng.r#gen());
+        gear.random_address = rng.gen_range(0..=0xffffff);
+        if index < addressed.min(64) {
+            gear.short_address = index as u8;
+        }
+        sim.add_device(Box::new(gear)).await?;
+    }
+    sim.set_faults(faults, Some(rng.r#gen()));
+    sim.add_device(driver_dev).await?;
+    Ok(sim)
+}
+
+#[cfg(feature = "serde")]
+fn read_installation(path: &str) -> Result<Installation, OpenError> {
+    Installation::read(Path::new(path)).map_err(OpenError::DriverError)
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_installation(_path: &str) -> Result<Installation, OpenError> {
+    Err(OpenError::ParameterError(String::from(
+        "Installation files require the serde feature",
+    )))
+}
+
+fn driver_open(params: HashMap<String, String>) -> Result<Box<dyn DaliDriver>, OpenError> {
+    let installation = match params.get("installation") {
+        Some(path) => Some(read_installation(path)?),
+        None => None,
+    };
+    // Only add generated gears to an installation if explicitly requested
+    let default_gears = if installation.is_some() { 0 } else { 4 };
+    let gears = parse_param::<usize>(&params, "gears")?.unwrap_or(default_gears);
+    let addressed = parse_param::<usize>(&params, "addressed")?.unwrap_or(gears);
+    let rng = match parse_param::<u64>(&params, "seed")? {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let virtual_time = parse_param::<bool>(&params, "virtual_time")?.unwrap_or(false);
+    let faults = FaultConfig {
+        corrupt_reply: parse_param(&params, "corrupt_reply")?.unwrap_or(0.0),
+        drop_reply: parse_param(&params, "drop_reply")?.unwrap_or(0.0),
+        drop_frame: parse_param(&params, "drop_frame")?.unwrap_or(0.0),
+    };
+    // The bus is built when the driver is first used, since opening may
+    // happen outside of a Tokio runtime
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    *driver.setup.get_mut() = Some(Box::pin(build_bus(
+        !virtual_time,
+        installation,
+        gears,
+        addressed,
+        faults,
+        rng,
+        driver_dev,
+    )));
+    Ok(Box::new(driver))
+}
+
+pub fn driver_info() -> DriverInfo {
+    DriverInfo {
+        name: "simulator".to_string(),
+        description: "Simulated DALI bus with control gears. \
+                      Parameters: gears=<count>, seed=<random address seed>, \
+                      addressed=<number of gears with short address>, \
+                      installation=<JSON or TOML installation file>, \
+                      virtual_time=<true to run on a simulated clock>, \
+                      corrupt_reply=<probability>, drop_reply=<probability>, \
+                      drop_frame=<probability>"
+            .to_string(),
+        open: driver_open,
     }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::address::Short;
+    use crate::common::commands::Commands;
+    use crate::gear::commands_102::Commands102;
+    use futures::FutureExt;
 
-    fn wait_until(&self, _end: Instant) -> DynFuture<()> {
-        Box::pin(future::ready(()))
+    // A setup interrupted by a cancelled call is finished by the next one
+    #[tokio::test]
+    async fn cancelled_setup() {
+        let (mut driver, driver_dev) = DaliSimDriver::new();
+        *driver.setup.get_mut() = Some(Box::pin(async move {
+            // Not ready on the first poll
+            tokio::task::yield_now().await;
+            let rng = StdRng::seed_from_u64(1);
+            build_bus(false, None, 1, 1, FaultConfig::default(), rng, driver_dev).await
+        }));
+        // Polled once and then dropped, like the losing branch of a select
+        assert!(driver.next_bus_event().now_or_never().is_none());
+        assert!(!driver.connected());
+        let mut commands = Commands102::new(&mut driver);
+        commands.query_random_address(Short::new(0)).await.unwrap();
+        assert!(driver.connected());
     }
 }
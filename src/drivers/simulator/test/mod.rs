@@ -1,18 +1,19 @@
 use crate as dali;
-use dali::defs::gear::cmd;
+use dali::common::address::Short;
+use dali::common::commands::{Commands, YesNo};
 use dali::drivers::driver::{DaliDriver, DaliSendResult};
-//use dali::drivers::simulator::gear;
+use dali::drivers::driver_utils::DaliDriverExt;
+use dali::drivers::send_flags::Flags;
+use dali::drivers::simulator::gear;
 use dali::drivers::simulator::simulator;
 use dali::drivers::simulator::simulator_driver::DaliSimDriver;
+use dali::drivers::simulator::timing;
+use dali::gear::cmd_defs as cmd;
+use dali::gear::commands_102::Commands102;
+use dali::utils::discover;
 use dali::utils::long_address;
-//use dali::defs::gear::status;
-use dali::drivers::send_flags::Flags;
-use dali::utils::discover::{self, DiscoverItem};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio_stream::StreamExt;
+use std::time::{Duration, Instant};
 
-/*
 #[tokio::test]
 async fn add_sim_device() {
     let sim = simulator::DaliBusSim::new().await.unwrap();
@@ -25,63 +26,40 @@ async fn add_sim_device() {
     sim.add_device(Box::new(dev)).await.unwrap();
     let (mut driver, driver_dev) = DaliSimDriver::new();
     sim.add_device(driver_dev).await.unwrap();
+    let mut commands = Commands102::new(&mut driver);
 
-    match driver
-        .send_frame16(&[cmd::INITIALISE, 0x00], Flags::SendTwice(true))
-        .await
-    {
-        DaliSendResult::OK => {}
-        res => panic!("INITIALISE failed: {}", res),
-    };
-
-    match long_address::set_search_addr(&mut driver, 0x123456).await {
-        Err(err) => panic!("set_search_addr failed: {}", err),
-        _ => {}
-    };
+    commands.initialise_all().await.unwrap();
 
-    match driver
-        .send_frame16(&[cmd::COMPARE, 0x00], Flags::ExpectAnswer(true))
+    long_address::set_search_addr(&mut commands, 0x123456)
         .await
-    {
-        DaliSendResult::Answer(0xff) => {}
-        r => panic!("Compare failed: {:?}", r),
+        .unwrap();
+    match commands.compare().await {
+        Ok(YesNo::Yes) => {}
+        _ => panic!("Compare failed, expected single answer"),
     }
 
-    match long_address::set_search_addr(&mut driver, 0x123455).await {
-        Err(err) => panic!("set_search_addr failed: {}", err),
-        _ => {}
-    };
-
-    match driver
-        .send_frame16(&[cmd::COMPARE, 0x00], Flags::ExpectAnswer(true))
+    long_address::set_search_addr(&mut commands, 0x123455)
         .await
-    {
-        DaliSendResult::Timeout => {}
-        r => panic!("Compare failed: {:?}", r),
+        .unwrap();
+    match commands.compare().await {
+        Ok(YesNo::No) => {}
+        _ => panic!("Compare failed, expected no answer"),
     }
 
-    match long_address::set_search_addr(&mut driver, 0x123457).await {
-        Err(err) => panic!("set_search_addr failed: {}", err),
-        _ => {}
-    };
-
-    match driver
-        .send_frame16(&[cmd::COMPARE, 0x00], Flags::ExpectAnswer(true))
+    long_address::set_search_addr(&mut commands, 0x123457)
         .await
-    {
-        DaliSendResult::Framing => {}
-        r => panic!("Compare failed: {:?}", r),
+        .unwrap();
+    match commands.compare().await {
+        Ok(YesNo::Multiple) => {}
+        _ => panic!("Compare failed, expected multiple answers"),
     }
 
-    let res = driver
-        .send_frame16(&[cmd::TERMINATE, 00], Flags::Empty)
-        .await;
-    println!("Sent: {:?}", res);
+    commands.terminate().await.unwrap();
 }
 
 #[tokio::test]
 async fn discover() {
-    let sim = simulator::DaliBusSim::new().await.unwrap();
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
 
     let addrs = [0, 1, 0x123456, 0x123457, 0xfffffd, 0xfffffe, 0xffffff];
 
@@ -95,31 +73,24 @@ async fn discover() {
     dev.random_address = 0x123457;
     sim.add_device(Box::new(dev)).await.unwrap();
 
-    let (driver, driver_dev) = DaliSimDriver::new();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
     sim.add_device(driver_dev).await.unwrap();
 
-    let sim = Arc::new(Mutex::new(
-        Box::new(driver) as Box<dyn dali::drivers::driver::DaliDriver>
-    ));
-    let v = discover::find_quick(sim)
-        .collect::<Vec<DiscoverItem>>()
-        .await;
-    for i in 0..v.len() {
-        match &v[i] {
-            Ok(d) => {
-                if d.long != Some(addrs[i]) {
-                    panic!(
-                        "Address {:02x} found, expected {:06x}",
-                        d.long.unwrap(),
-                        addrs[i]
-                    );
-                }
-            }
-            Err(e) => panic!("Discovery failed: {}", e),
+    let mut v = Vec::new();
+    let mut commands = Commands102::new(&mut driver);
+    discover::find_quick(&mut commands, &mut async |d: discover::Discovered| {
+        v.push(d)
+    })
+    .await
+    .unwrap();
+    assert_eq!(v.len(), addrs.len());
+    for (d, a) in v.iter().zip(&addrs) {
+        if d.long != Some(*a) {
+            panic!("Address {:06x?} found, expected {:06x}", d.long, a);
         }
     }
 
-    //assert_eq!(v[3].as_ref().unwrap().long_conflict, true);
+    assert!(v[3].long_conflict);
 }
 
 #[tokio::test]
@@ -138,7 +109,10 @@ async fn test_queries() {
 
     assert_eq!(
         driver
-            .send_frame16(&[3 << 1, cmd::QUERY_STATUS], Flags::ExpectAnswer(true))
+            .send_frame16(
+                &cmd::QUERY_STATUS(Short::new(3)).0,
+                Flags::ExpectAnswer(true)
+            )
             .await
             .check_answer()
             .unwrap(),
@@ -148,21 +122,24 @@ async fn test_queries() {
     let mut dev = gear::DaliSimGear::new();
     dev.random_address = 0x123446;
     dev.short_address = 4;
-    dev.status = 0x38;
+    dev.status = 0x28;
     sim.add_device(Box::new(dev)).await.unwrap();
 
     assert_eq!(
         driver
-            .send_frame16(&[4 << 1, cmd::QUERY_STATUS], Flags::ExpectAnswer(true))
+            .send_frame16(
+                &cmd::QUERY_STATUS(Short::new(4)).0,
+                Flags::ExpectAnswer(true)
+            )
             .await
             .check_answer()
             .unwrap(),
-        0x3cu8
+        0x2cu8
     );
 
     match driver
         .send_frame16(
-            &[4 << 1, cmd::QUERY_CONTROL_GEAR_PRESENT],
+            &cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(4)).0,
             Flags::ExpectAnswer(true),
         )
         .await
@@ -173,7 +150,7 @@ async fn test_queries() {
 
     match driver
         .send_frame16(
-            &[5 << 1, cmd::QUERY_CONTROL_GEAR_PRESENT],
+            &cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(5)).0,
             Flags::ExpectAnswer(true),
         )
         .await
@@ -183,4 +160,1645 @@ async fn test_queries() {
         r => panic!("Invalid answer: {:?}", r),
     }
 }
-*/
+
+#[tokio::test]
+async fn open_simulator() {
+    let first;
+    {
+        let mut driver = dali::drivers::open("simulator: gears=3, seed=42, addressed=2").unwrap();
+        let mut commands = Commands102::new(driver.as_mut());
+        first = commands.query_random_address(Short::new(0)).await.unwrap();
+        let second = commands.query_random_address(Short::new(1)).await.unwrap();
+        assert_ne!(first, second);
+        match commands.query_random_address(Short::new(2)).await {
+            Err(DaliSendResult::Timeout) => {}
+            r => panic!("Unexpected reply from unaddressed gear: {:?}", r),
+        }
+    }
+
+    // Same seed gives the same installation
+    let mut driver = dali::drivers::open("simulator: gears=3, seed=42, addressed=2").unwrap();
+    let mut commands = Commands102::new(driver.as_mut());
+    assert_eq!(
+        commands.query_random_address(Short::new(0)).await.unwrap(),
+        first
+    );
+}
+
+// The bus is built on first use, so opening doesn't need a runtime
+#[test]
+fn open_simulator_outside_runtime() {
+    let mut driver = dali::drivers::open("simulator: gears=1, virtual_time=true").unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut commands = Commands102::new(driver.as_mut());
+        commands.query_random_address(Short::new(0)).await.unwrap();
+    });
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn load_installation() {
+    use dali::drivers::simulator::installation::Installation;
+    let installation = Installation::from_json(
+        r#"{"gears": [
+             {"short_address": 5, "random_address": 4660, "groups": [1], "max_level": 180},
+             {"random_address": 4661}
+           ]}"#,
+    )
+    .unwrap();
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    sim.load_installation(&installation).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_MAX_LEVEL(Short::new(5)))
+            .await
+            .unwrap(),
+        180
+    );
+    assert_eq!(
+        commands.query_random_address(Short::new(5)).await.unwrap(),
+        4660
+    );
+
+    // Address the second gear and check that it's reflected in the saved installation
+    commands.initialise_no_addr().await.unwrap();
+    long_address::set_search_addr(&mut commands, 4661)
+        .await
+        .unwrap();
+    commands
+        .program_short_address(Some(Short::new(6)))
+        .await
+        .unwrap();
+    commands.terminate().await.unwrap();
+
+    let saved = sim.installation();
+    assert_eq!(saved.gears.len(), 2);
+    assert_eq!(saved.gears[0], installation.gears[0]);
+    assert_eq!(saved.gears[1].short_address, Some(6));
+    assert_eq!(saved.gears[1].random_address, 4661);
+}
+
+#[tokio::test]
+async fn virtual_init_timeout() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.random_address = 0x123456;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let real_start = Instant::now();
+    let start = driver.current_timestamp();
+    let mut commands = Commands102::new(&mut driver);
+    commands.initialise_all().await.unwrap();
+    long_address::set_search_addr(&mut commands, 0xffffff)
+        .await
+        .unwrap();
+    assert!(matches!(commands.compare().await, Ok(YesNo::Yes)));
+
+    driver
+        .wait_until(start + timing::INIT_TIMEOUT + Duration::from_secs(1))
+        .await;
+    let mut commands = Commands102::new(&mut driver);
+    assert!(matches!(commands.compare().await, Ok(YesNo::No)));
+    assert!(driver.current_timestamp() > start + timing::INIT_TIMEOUT);
+    assert!(real_start.elapsed() < timing::INIT_TIMEOUT);
+}
+
+#[tokio::test]
+async fn virtual_fade() {
+    use dali::drivers::simulator::installation::{GearDescription, Installation};
+    let mut installation = Installation::new();
+    installation.gears.push(GearDescription {
+        short_address: Some(0),
+        fade: 0x70, // 5.6 s
+        ..GearDescription::default()
+    });
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    sim.load_installation(&installation).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let start = driver.current_timestamp();
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::DAPC(Short::new(0), 100)).await.unwrap();
+    driver.wait_until(start + Duration::from_secs(2)).await;
+    let mut commands = Commands102::new(&mut driver);
+    let level = commands
+        .query(cmd::QUERY_ACTUAL_LEVEL(Short::new(0)))
+        .await
+        .unwrap();
+    assert!(level > 100 && level < 0xfe, "level = {}", level);
+
+    driver.wait_until(start + Duration::from_secs(6)).await;
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_ACTUAL_LEVEL(Short::new(0)))
+            .await
+            .unwrap(),
+        100
+    );
+}
+
+#[tokio::test]
+async fn virtual_discover_64() {
+    let mut driver =
+        dali::drivers::open("simulator: gears=64, addressed=0, seed=7, virtual_time=true").unwrap();
+    let mut commands = Commands102::new(driver.as_mut());
+    let mut found = Vec::new();
+    discover::find_quick(&mut commands, &mut async |d: discover::Discovered| {
+        found.push(d)
+    })
+    .await
+    .unwrap();
+    assert_eq!(found.len(), 64);
+    assert!(found.iter().all(|d| !d.long_conflict && d.short.is_none()));
+}
+
+#[tokio::test]
+async fn control_device() {
+    use dali::control::commands_103::Commands103;
+    use dali::drivers::driver::DaliBusEventType;
+    use dali::drivers::simulator::control::{DaliSimControlDevice, SimInstance, instance_type};
+    use dali::utils::device_info::read_control_info;
+
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let dev = DaliSimControlDevice::new(vec![
+        SimInstance::new(instance_type::PUSH_BUTTON),
+        SimInstance::new(instance_type::LIGHT_SENSOR),
+    ]);
+    dev.set_random_address(0x345678);
+    dev.set_short_address(Some(7));
+    dev.set_device_groups(0x80000001);
+    sim.add_device(Box::new(dev.clone())).await.unwrap();
+    let mut gear = gear::DaliSimGear::new();
+    gear.short_address = 7;
+    sim.add_device(Box::new(gear)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let mut found = Vec::new();
+    let mut commands = Commands103::new(&mut driver);
+    discover::find_quick(&mut commands, &mut async |d: discover::Discovered| {
+        found.push(d)
+    })
+    .await
+    .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].long, Some(0x345678));
+    assert_eq!(found[0].short.map(|s| s.value()), Some(7));
+
+    let info = read_control_info(&mut driver, Short::new(7)).await.unwrap();
+    assert_eq!(info.device_groups, Some(0x80000001));
+    assert_eq!(info.device_capabilities, Some(0x02));
+
+    // Input value of the light sensor
+    dev.set_input_value(1, 0x2aa).await.unwrap();
+    // Let the event be sent before querying
+    driver
+        .wait_until(driver.current_timestamp() + Duration::from_millis(100))
+        .await;
+    let value = driver
+        .send_frame24(
+            &dali::control::cmd_defs::QUERY_INPUT_VALUE(Short::new(7), 1).0,
+            Flags::ExpectAnswer(true),
+        )
+        .await
+        .check_answer()
+        .unwrap();
+    assert_eq!(value, 0xaa);
+    let latch = driver
+        .send_frame24(
+            &dali::control::cmd_defs::QUERY_INPUT_VALUE_LATCH(Short::new(7), 1).0,
+            Flags::ExpectAnswer(true),
+        )
+        .await
+        .check_answer()
+        .unwrap();
+    assert_eq!(latch, 0x80);
+
+    // Event frames from the push button
+    dev.press(0).await.unwrap();
+    let mut events = Vec::new();
+    while events.len() < 2 {
+        if let DaliBusEventType::Frame24(frame) = driver.next_bus_event().await.unwrap().event_type
+        {
+            events.push(frame);
+        }
+    }
+    // The light sensor event is followed by a button press, both in the
+    // instance scheme
+    assert_eq!(events[0], [0x88, 0x84 | 0x02, 0xaa]);
+    assert_eq!(events[1], [0x82, 0x80, 0x01]);
+}
+
+// Devices loaded from an installation with a seed randomise reproducibly
+#[tokio::test]
+async fn randomise_seeded() {
+    use dali::control::commands_103::Commands103;
+    use dali::drivers::simulator::installation::{
+        ControlDescription, GearDescription, Installation,
+    };
+    let mut installation = Installation::new();
+    installation.gears.push(GearDescription {
+        short_address: Some(1),
+        ..GearDescription::default()
+    });
+    installation.control_devices.push(ControlDescription {
+        short_address: Some(2),
+        random_address: 0xffffff,
+        groups: Vec::new(),
+        application_controller: false,
+        instances: Vec::new(),
+    });
+    installation.seed = Some(17);
+    let mut randomised = Vec::new();
+    for _ in 0..2 {
+        let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+        sim.load_installation(&installation).await.unwrap();
+        let (mut driver, driver_dev) = DaliSimDriver::new();
+        sim.add_device(driver_dev).await.unwrap();
+        let mut gear = Commands102::new(&mut driver);
+        gear.initialise_all().await.unwrap();
+        gear.randomize().await.unwrap();
+        gear.terminate().await.unwrap();
+        let gear_random = gear.query_random_address(Short::new(1)).await.unwrap();
+        let mut control = Commands103::new(&mut driver);
+        control.initialise_addr(Short::new(2)).await.unwrap();
+        control.randomize().await.unwrap();
+        control.terminate().await.unwrap();
+        let control_random = control.query_random_address(Short::new(2)).await.unwrap();
+        assert_ne!(control_random, 0xffffff);
+        randomised.push((gear_random, control_random));
+    }
+    assert_eq!(randomised[0], randomised[1]);
+}
+
+#[tokio::test]
+async fn discover_conflicts() {
+    use dali::drivers::simulator::installation::{GearDescription, Installation};
+    let mut installation = Installation::new();
+    for (short_address, random_address) in [
+        (Some(2), 0x010000),
+        (Some(2), 0x020000),
+        (None, 0x300000),
+        (None, 0x300000),
+        (None, 0x400000),
+    ] {
+        installation.gears.push(GearDescription {
+            short_address,
+            random_address,
+            ..GearDescription::default()
+        });
+    }
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    sim.load_installation(&installation).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let mut found = Vec::new();
+    let mut commands = Commands102::new(&mut driver);
+    discover::find_quick(&mut commands, &mut async |d: discover::Discovered| {
+        found.push(d)
+    })
+    .await
+    .unwrap();
+    // Colliding replies to QUERY RANDOM ADDRESS
+    assert!(
+        found
+            .iter()
+            .any(|d| d.short.map(|s| s.value()) == Some(2) && d.short_conflict)
+    );
+    // Colliding replies to COMPARE
+    assert!(
+        found
+            .iter()
+            .any(|d| d.long == Some(0x300000) && d.long_conflict)
+    );
+    assert!(
+        found
+            .iter()
+            .any(|d| d.long == Some(0x400000) && !d.long_conflict)
+    );
+
+    // Gears with identical random addresses can't be addressed individually
+    let mut commands = Commands102::new(&mut driver);
+    commands.initialise_all().await.unwrap();
+    match dali::utils::address_assignment::program_short_address(
+        &mut commands,
+        0x300000,
+        Short::new(10),
+    )
+    .await
+    {
+        Err(dali::utils::address_assignment::Error::Send(DaliSendResult::Framing)) => {}
+        r => panic!("Unexpected result: {:?}", r),
+    }
+}
+
+#[tokio::test]
+async fn faults() {
+    use dali::drivers::simulator::faults::FaultConfig;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    let gear_id = sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let query = cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(1)).0;
+    assert!(matches!(
+        driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Answer(0xff)
+    ));
+
+    sim.set_faults(
+        FaultConfig {
+            corrupt_reply: 1.0,
+            ..FaultConfig::default()
+        },
+        None,
+    );
+    assert!(matches!(
+        driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Framing
+    ));
+
+    for config in [
+        FaultConfig {
+            drop_reply: 1.0,
+            ..FaultConfig::default()
+        },
+        FaultConfig {
+            drop_frame: 1.0,
+            ..FaultConfig::default()
+        },
+    ] {
+        sim.set_faults(config, None);
+        assert!(matches!(
+            driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+            DaliSendResult::Timeout
+        ));
+    }
+    sim.set_faults(FaultConfig::default(), None);
+
+    sim.set_responsive(gear_id, false);
+    let mut commands = Commands102::new(&mut driver);
+    assert!(matches!(
+        commands.query_random_address(Short::new(1)).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    match dali::utils::address_assignment::program_short_addresses(
+        &mut commands,
+        &[(Short::new(1), Short::new(2))],
+    )
+    .await
+    {
+        Err(dali::utils::address_assignment::Error::Send(DaliSendResult::Timeout)) => {}
+        r => panic!("Unexpected result: {:?}", r),
+    }
+    sim.set_responsive(gear_id, true);
+    assert!(matches!(
+        driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Answer(0xff)
+    ));
+}
+
+#[tokio::test]
+async fn bus_power() {
+    use dali::drivers::driver::DaliBusEventType;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let query = cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(1)).0;
+
+    sim.set_bus_power(false).await.unwrap();
+    assert!(matches!(
+        driver.next_bus_event().await.unwrap().event_type,
+        DaliBusEventType::BusPowerOff
+    ));
+    assert!(matches!(
+        driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Timeout
+    ));
+
+    sim.set_bus_power(true).await.unwrap();
+    assert!(matches!(
+        driver.next_bus_event().await.unwrap().event_type,
+        DaliBusEventType::BusPowerOn
+    ));
+    assert!(matches!(
+        driver.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Answer(0xff)
+    ));
+}
+
+#[tokio::test]
+async fn monitor_overrun() {
+    use dali::drivers::driver::DaliBusEventType;
+    use dali::drivers::send_flags::NO_FLAG;
+    use dali::drivers::simulator::simulator_driver::MONITOR_QUEUE_LEN;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let (mut sender, sender_dev) = DaliSimDriver::new();
+    sim.add_device(sender_dev).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    for data in 0..MONITOR_QUEUE_LEN + 2 {
+        sender
+            .send_frame16(&cmd::DTR0(data as u8).0, NO_FLAG)
+            .await
+            .check_send()
+            .unwrap();
+    }
+    for data in 0..MONITOR_QUEUE_LEN {
+        assert!(matches!(
+            driver.next_bus_event().await.unwrap().event_type,
+            DaliBusEventType::Frame16(frame) if frame == cmd::DTR0(data as u8).0
+        ));
+    }
+    // The lost events are reported before the next one
+    sender
+        .send_frame16(&cmd::DTR0(0).0, NO_FLAG)
+        .await
+        .check_send()
+        .unwrap();
+    assert!(matches!(
+        driver.next_bus_event().await.unwrap().event_type,
+        DaliBusEventType::Overrun
+    ));
+    assert!(matches!(
+        driver.next_bus_event().await.unwrap().event_type,
+        DaliBusEventType::Frame16([0xa3, 0x00])
+    ));
+}
+
+#[tokio::test]
+async fn discover_with_faults() {
+    // Discovery should complete despite lost and corrupted replies
+    let mut driver = dali::drivers::open(
+        "simulator: gears=8, addressed=4, seed=3, virtual_time=true, \
+         corrupt_reply=0.05, drop_reply=0.05",
+    )
+    .unwrap();
+    let mut commands = Commands102::new(driver.as_mut());
+    let mut found = Vec::new();
+    discover::find_quick(&mut commands, &mut async |d: discover::Discovered| {
+        found.push(d)
+    })
+    .await
+    .unwrap();
+    // With this seed no fault makes discovery miss a gear. The addressed
+    // gears are found by short address and the rest by random address.
+    assert!(found.iter().all(|d| !d.long_conflict && !d.short_conflict));
+    let mut shorts: Vec<_> = found.iter().filter_map(|d| d.short).collect();
+    shorts.sort();
+    assert_eq!(shorts, (0..4).map(Short::new).collect::<Vec<_>>());
+    let unaddressed: Vec<_> = found.iter().filter(|d| d.short.is_none()).collect();
+    assert_eq!(unaddressed.len(), 4);
+    assert!(unaddressed.iter().all(|d| d.long.is_some()));
+    let mut longs: Vec<_> = found.iter().filter_map(|d| d.long).collect();
+    longs.sort();
+    longs.dedup();
+    assert_eq!(longs.len(), 8);
+}
+
+#[tokio::test]
+async fn gear_configuration() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 2;
+    dev.fade = 0x00;
+    dev.device_types = vec![8, 6];
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addr = Short::new(2);
+    let mut commands = Commands102::new(&mut driver);
+
+    commands.cmd(cmd::DTR0(100)).await.unwrap();
+    commands.cmd(cmd::SET_MAX_LEVEL(addr)).await.unwrap();
+    commands.cmd(cmd::DTR0(50)).await.unwrap();
+    commands.cmd(cmd::SET_SCENE(addr, 3)).await.unwrap();
+    commands.cmd(cmd::ADD_TO_GROUP(addr, 5)).await.unwrap();
+    assert_eq!(
+        commands.query(cmd::QUERY_MAX_LEVEL(addr)).await.unwrap(),
+        100
+    );
+    assert_eq!(
+        commands.query(cmd::QUERY_ACTUAL_LEVEL(addr)).await.unwrap(),
+        100
+    );
+    assert_eq!(
+        commands.query(cmd::QUERY_GROUPS_0_7(addr)).await.unwrap(),
+        0x20
+    );
+    commands.cmd(cmd::GOTO_SCENE(addr, 3)).await.unwrap();
+    assert_eq!(
+        commands.query(cmd::QUERY_ACTUAL_LEVEL(addr)).await.unwrap(),
+        50
+    );
+    commands.cmd(cmd::DAPC(addr, 200)).await.unwrap();
+    assert_eq!(
+        commands.query(cmd::QUERY_ACTUAL_LEVEL(addr)).await.unwrap(),
+        100
+    );
+    assert_eq!(
+        commands.query(cmd::QUERY_LIMIT_ERROR(addr)).await.unwrap(),
+        0xff
+    );
+
+    // Device types are reported in ascending order
+    assert_eq!(
+        commands.query(cmd::QUERY_DEVICE_TYPE(addr)).await.unwrap(),
+        0xff
+    );
+    for dt in [6, 8, 254] {
+        assert_eq!(
+            commands
+                .query(cmd::QUERY_NEXT_DEVICE_TYPE(addr))
+                .await
+                .unwrap(),
+            dt
+        );
+    }
+    commands.cmd(cmd::ENABLE_DEVICE_TYPE(8)).await.unwrap();
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_EXTENDED_VERSION_NUMBER(addr))
+            .await
+            .unwrap(),
+        2 << 2
+    );
+    assert!(matches!(
+        commands
+            .query(cmd::QUERY_EXTENDED_VERSION_NUMBER(addr))
+            .await,
+        Err(DaliSendResult::Timeout)
+    ));
+    commands.cmd(cmd::ENABLE_DEVICE_TYPE(7)).await.unwrap();
+    assert!(matches!(
+        commands
+            .query(cmd::QUERY_EXTENDED_VERSION_NUMBER(addr))
+            .await,
+        Err(DaliSendResult::Timeout)
+    ));
+
+    // Configuration commands must be sent twice
+    driver
+        .send_frame16(&cmd::RESET(addr).0, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands.query(cmd::QUERY_MAX_LEVEL(addr)).await.unwrap(),
+        100
+    );
+    assert!(matches!(
+        commands.query(cmd::QUERY_RESET_STATE(addr)).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    commands.cmd(cmd::RESET(addr)).await.unwrap();
+    assert_eq!(
+        commands.query(cmd::QUERY_MAX_LEVEL(addr)).await.unwrap(),
+        254
+    );
+    assert_eq!(
+        commands.query(cmd::QUERY_GROUPS_0_7(addr)).await.unwrap(),
+        0
+    );
+    assert_eq!(
+        commands.query(cmd::QUERY_RESET_STATE(addr)).await.unwrap(),
+        0xff
+    );
+    commands.cmd(cmd::DTR0(10)).await.unwrap();
+    commands.cmd(cmd::SET_POWER_ON_LEVEL(addr)).await.unwrap();
+    assert!(matches!(
+        commands.query(cmd::QUERY_RESET_STATE(addr)).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_POWER_ON_LEVEL(addr))
+            .await
+            .unwrap(),
+        10
+    );
+}
+
+#[tokio::test]
+async fn gear_memory_banks() {
+    use dali::common::commands::Commands;
+    use dali::utils::memory_banks::{self, MemoryBank0Info};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 4;
+    dev.set_memory_bank_0(&MemoryBank0Info {
+        gtin: 0x123456789abc,
+        firmware_version: 0x0102,
+        id_number: 42,
+        ..MemoryBank0Info::default()
+    });
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addr = Short::new(4);
+
+    let info = memory_banks::read_bank_0(&mut driver, addr, 0, 0, 0)
+        .await
+        .unwrap();
+    assert_eq!(info.gtin, 0x123456789abc);
+    assert_eq!(info.firmware_version, 0x0102);
+    assert_eq!(info.id_number, 42);
+    let bank1 = memory_banks::read_range(&mut driver, addr, 1, 0, 3)
+        .await
+        .unwrap();
+    assert_eq!(bank1, vec![0x77, 0xff, 0xff]);
+
+    // Locked bytes can't be written
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::ENABLE_WRITE_MEMORY(addr)).await.unwrap();
+    commands.dtr1(1).await.unwrap();
+    commands.dtr0(0x13).await.unwrap();
+    assert!(matches!(
+        commands.write_memory_location(24).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    commands.dtr0(0x02).await.unwrap();
+    assert_eq!(commands.write_memory_location(0x55).await.unwrap(), 0x55);
+    assert_eq!(commands.write_memory_location(0x20).await.unwrap(), 0x20);
+    // Writing is disabled by other commands
+    commands.query(cmd::QUERY_STATUS(addr)).await.unwrap();
+    assert!(matches!(
+        commands.write_memory_location(0x01).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    let bank1 = memory_banks::read_range(&mut driver, addr, 1, 2, 3)
+        .await
+        .unwrap();
+    assert_eq!(bank1, vec![0x55, 0x20, 0xff]);
+
+    // Bank 0 is read only and bank 2 isn't implemented
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::ENABLE_WRITE_MEMORY(addr)).await.unwrap();
+    commands.dtr1(0).await.unwrap();
+    commands.dtr0(0x03).await.unwrap();
+    assert!(matches!(
+        commands.write_memory_location(0x00).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    commands.dtr1(2).await.unwrap();
+    commands.dtr0(0x00).await.unwrap();
+    assert!(matches!(
+        commands.read_memory_location(addr).await,
+        Err(DaliSendResult::Timeout)
+    ));
+    assert_eq!(
+        commands.query(cmd::QUERY_CONTENT_DTR0(addr)).await.unwrap(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn gear_memory_bank_writes() {
+    use dali::utils::memory_banks::{self, MemoryBank1Info, MemoryError};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 7;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addr = Short::new(7);
+
+    assert_eq!(
+        memory_banks::last_memory_bank(&mut driver, addr)
+            .await
+            .unwrap(),
+        1
+    );
+    let bank0 = memory_banks::read_bank(&mut driver, addr, 0).await.unwrap();
+    assert_eq!(bank0.len(), 0x1b);
+    assert!(memory_banks::read_bank(&mut driver, addr, 2).await.is_err());
+    let info = memory_banks::read_bank_1(&mut driver, addr)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        info,
+        MemoryBank1Info {
+            content_format: 0x0003,
+            ..MemoryBank1Info::default()
+        }
+    );
+
+    // Writing a locked bank restores the lock byte afterwards
+    memory_banks::write_luminaire_id(&mut driver, addr, "Hall 2, east")
+        .await
+        .unwrap();
+    memory_banks::write_range(&mut driver, addr, 1, 0x13, &[24, 12])
+        .await
+        .unwrap();
+    let info = memory_banks::read_bank_1(&mut driver, addr)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.luminaire_id.as_deref(), Some("Hall 2, east"));
+    assert_eq!((info.year, info.week), (Some(24), Some(12)));
+    assert_eq!(
+        memory_banks::read_range(&mut driver, addr, 1, 2, 1)
+            .await
+            .unwrap(),
+        vec![0xff]
+    );
+
+    // Unlock explicitly
+    memory_banks::write_range(&mut driver, addr, 1, 2, &[0x55, 100])
+        .await
+        .unwrap();
+    assert_eq!(
+        memory_banks::read_range(&mut driver, addr, 1, 2, 2)
+            .await
+            .unwrap(),
+        vec![0x55, 100]
+    );
+
+    let err = memory_banks::write_range(&mut driver, addr, 0, 3, &[1])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MemoryError>(),
+        Some(MemoryError::ReadOnly)
+    ));
+    let err = memory_banks::write_range(&mut driver, addr, 1, 0x77, &[1, 2])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<MemoryError>(),
+        Some(MemoryError::InvalidMemoryArea)
+    ));
+}
+
+#[tokio::test]
+async fn gear_info() {
+    use dali::utils::device_info::read_gear_info;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    dev.device_types = vec![1, 6, 8];
+    dev.max_level = 200;
+    dev.actual_level = 200;
+    dev.scene[2] = 30;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let info = read_gear_info(&mut driver, Short::new(1))
+        .await
+        .unwrap()
+        .to_string();
+    assert!(info.contains("(0x6) "), "{}", info);
+    assert!(info.contains("(0x8)\n"), "{}", info);
+    assert!(info.contains("Scenes: 2: 30\n"), "{}", info);
+    assert!(info.contains("Maximum level: 200\n"), "{}", info);
+    assert!(info.contains("Version: 2.0\n"), "{}", info);
+    assert!(info.contains("Dimming curve: Standard\n"), "{}", info);
+    assert!(info.contains("DT6 version: 2.0\n"), "{}", info);
+    assert!(info.contains("Emergency mode: Normal\n"), "{}", info);
+}
+
+#[tokio::test]
+async fn dt6_commands() {
+    use dali::gear::address::Address;
+    use dali::gear::dt6::{self, dimming_curve};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 7;
+    dev.led.failure_status = 0x02;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addr = Address::Short(Short::new(7));
+
+    dt6::select_dimming_curve(&mut driver, addr, dimming_curve::LINEAR)
+        .await
+        .unwrap();
+    dt6::set_fast_fade_time(&mut driver, addr, 2).await.unwrap();
+    let info = dt6::read_info(&mut driver, Short::new(7)).await.unwrap();
+    assert_eq!(info.dimming_curve, Some(dimming_curve::LINEAR));
+    assert_eq!(info.fast_fade_time, Some(4));
+    assert_eq!(info.operating_mode, Some(0x11));
+    let text = info.to_string();
+    assert!(
+        text.contains("LED failure status: Open circuit\n"),
+        "{}",
+        text
+    );
+    assert!(
+        text.contains("LED operating mode: PWM, Non-logarithmic dimming curve\n"),
+        "{}",
+        text
+    );
+
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::RESET(addr)).await.unwrap();
+    let info = dt6::read_info(&mut driver, Short::new(7)).await.unwrap();
+    assert_eq!(info.dimming_curve, Some(dimming_curve::STANDARD));
+    assert_eq!(info.fast_fade_time, Some(0));
+}
+
+#[tokio::test]
+async fn dt1_tests() {
+    use dali::gear::dt1::{self, EmergencyTest, TestOutcome, failure};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    for (addr, faults) in [
+        (1, 0),
+        (2, failure::BATTERY_DURATION),
+        (3, failure::EMERGENCY_LAMP),
+    ] {
+        let mut dev = gear::DaliSimGear::new();
+        dev.short_address = addr;
+        dev.device_types = vec![1];
+        dev.emergency.rated_duration = 5;
+        dev.emergency.faults = faults;
+        sim.add_device(Box::new(dev)).await.unwrap();
+    }
+    // Not an emergency gear
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 4;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addrs: Vec<Short> = (1..=5).map(Short::new).collect();
+
+    let results = dt1::run_tests(
+        &mut driver,
+        &addrs,
+        EmergencyTest::Function,
+        Duration::from_secs(5),
+        Duration::from_secs(60),
+    )
+    .await
+    .unwrap();
+    let outcomes: Vec<TestOutcome> = results.iter().map(|r| r.outcome.clone()).collect();
+    assert_eq!(
+        outcomes,
+        [
+            TestOutcome::Passed,
+            TestOutcome::Passed,
+            TestOutcome::Failed(failure::EMERGENCY_LAMP | failure::FUNCTION_TEST),
+            TestOutcome::NoResponse,
+            TestOutcome::NoResponse
+        ]
+    );
+    assert_eq!(results[0].battery_charge, Some(254));
+
+    let start = driver.current_timestamp();
+    let results = dt1::run_tests(
+        &mut driver,
+        &addrs[0..2],
+        EmergencyTest::Duration,
+        Duration::from_secs(60),
+        Duration::from_secs(3600),
+    )
+    .await
+    .unwrap();
+    assert_eq!(results[0].outcome, TestOutcome::Passed);
+    assert_eq!(results[0].duration, Some(5));
+    assert_eq!(
+        results[1].outcome,
+        TestOutcome::Failed(failure::BATTERY_DURATION | failure::DURATION_TEST)
+    );
+    assert_eq!(results[1].duration, Some(2));
+    assert!(driver.current_timestamp() >= start + Duration::from_secs(600));
+
+    // Stopped on timeout
+    let results = dt1::run_tests(
+        &mut driver,
+        &addrs[0..1],
+        EmergencyTest::Duration,
+        Duration::from_secs(60),
+        Duration::from_secs(120),
+    )
+    .await
+    .unwrap();
+    assert_eq!(results[0].outcome, TestOutcome::TimedOut);
+    let info = dt1::read_info(&mut driver, Short::new(1)).await.unwrap();
+    let text = info.to_string();
+    assert!(text.contains("Emergency mode: Normal\n"), "{}", text);
+}
+
+#[tokio::test]
+async fn gear_power() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    dev.system_failure_level = 30;
+    dev.power_on_level = 40;
+    dev.power_cycle(sim.current_time());
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let addr = Short::new(1);
+
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands.query(cmd::QUERY_ACTUAL_LEVEL(addr)).await.unwrap(),
+        40
+    );
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_POWER_FAILURE(addr))
+            .await
+            .unwrap(),
+        0xff
+    );
+    sim.set_bus_power(false).await.unwrap();
+    sim.set_bus_power(true).await.unwrap();
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands.query(cmd::QUERY_ACTUAL_LEVEL(addr)).await.unwrap(),
+        30
+    );
+}
+
+// Send an application extended command for device type 8
+async fn dt8_send(
+    driver: &mut DaliSimDriver,
+    addr: u8,
+    opcode: u8,
+    flags: Flags,
+) -> DaliSendResult {
+    let mut commands = Commands102::new(driver);
+    commands.cmd(cmd::ENABLE_DEVICE_TYPE(8)).await.unwrap();
+    driver.send_frame16(&[(addr << 1) | 1, opcode], flags).await
+}
+
+async fn dt8_query(driver: &mut DaliSimDriver, addr: u8, opcode: u8) -> u8 {
+    match dt8_send(driver, addr, opcode, Flags::ExpectAnswer(true)).await {
+        DaliSendResult::Answer(value) => value,
+        res => panic!("Unexpected result for DT8 query {:02x}: {:?}", opcode, res),
+    }
+}
+
+async fn dt8_colour_value(driver: &mut DaliSimDriver, addr: u8, selector: u8) -> u16 {
+    let mut commands = Commands102::new(driver);
+    commands.cmd(cmd::DTR0(selector)).await.unwrap();
+    let msb = dt8_query(driver, addr, 0xfa).await;
+    let mut commands = Commands102::new(driver);
+    let lsb = commands
+        .query(cmd::QUERY_CONTENT_DTR0(Short::new(addr)))
+        .await
+        .unwrap();
+    (msb as u16) << 8 | lsb as u16
+}
+
+async fn dt8_set_dtr16(driver: &mut DaliSimDriver, value: u16) {
+    let mut commands = Commands102::new(driver);
+    commands.cmd(cmd::DTR0((value & 0xff) as u8)).await.unwrap();
+    commands.cmd(cmd::DTR1((value >> 8) as u8)).await.unwrap();
+}
+
+#[tokio::test]
+async fn dt8_colour_temperature() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 3;
+    dev.fade = 0x00;
+    dev.device_types = vec![8];
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    assert_eq!(dt8_query(&mut driver, 3, 0xf9).await, 0x03);
+    assert_eq!(dt8_query(&mut driver, 3, 0xf8).await, 0x20);
+
+    // The temporary value isn't used until ACTIVATE
+    dt8_set_dtr16(&mut driver, 300).await;
+    dt8_send(&mut driver, 3, 0xe7, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 194).await, 300);
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 250);
+    dt8_send(&mut driver, 3, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 300);
+    assert_eq!(dt8_colour_value(&mut driver, 3, 194).await, 0xffff);
+
+    dt8_send(&mut driver, 3, 0xe8, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 299);
+
+    // Limits
+    dt8_set_dtr16(&mut driver, 200).await;
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::DTR2(0)).await.unwrap();
+    dt8_send(&mut driver, 3, 0xf2, Flags::SendTwice(true))
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 128).await, 200);
+    assert_eq!(dt8_colour_value(&mut driver, 3, 129).await, 153);
+    dt8_set_dtr16(&mut driver, 160).await;
+    dt8_send(&mut driver, 3, 0xe7, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_send(&mut driver, 3, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 200);
+    assert_eq!(dt8_query(&mut driver, 3, 0xf8).await, 0x22);
+
+    // Automatic activation applies the colour with the next arc power command
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::DTR0(0x01)).await.unwrap();
+    dt8_send(&mut driver, 3, 0xf3, Flags::SendTwice(true))
+        .await
+        .check_send()
+        .unwrap();
+    dt8_set_dtr16(&mut driver, 350).await;
+    dt8_send(&mut driver, 3, 0xe7, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 200);
+    let mut commands = Commands102::new(&mut driver);
+    commands.cmd(cmd::DAPC(Short::new(3), 100)).await.unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 3, 2).await, 350);
+}
+
+#[tokio::test]
+async fn dt8_xy_fade() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 4;
+    dev.fade = 0x40; // 2 s
+    dev.device_types = vec![8];
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    // Changing colour type is immediate
+    dt8_set_dtr16(&mut driver, 0x4000).await;
+    dt8_send(&mut driver, 4, 0xe0, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_set_dtr16(&mut driver, 0x5000).await;
+    dt8_send(&mut driver, 4, 0xe1, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_send(&mut driver, 4, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 4, 0).await, 0x4000);
+    assert_eq!(dt8_colour_value(&mut driver, 4, 1).await, 0x5000);
+    assert_eq!(dt8_colour_value(&mut driver, 4, 2).await, 0xffff);
+    assert_eq!(dt8_colour_value(&mut driver, 4, 240).await >> 8, 0x10);
+
+    dt8_set_dtr16(&mut driver, 0x6000).await;
+    dt8_send(&mut driver, 4, 0xe0, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    let start = driver.current_timestamp();
+    dt8_send(&mut driver, 4, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 4, 224).await, 0x6000);
+    driver.wait_until(start + Duration::from_secs(1)).await;
+    let x = dt8_colour_value(&mut driver, 4, 0).await;
+    assert!(x > 0x4000 && x < 0x6000, "x = {:04x}", x);
+    assert_eq!(dt8_colour_value(&mut driver, 4, 1).await, 0x5000);
+    driver.wait_until(start + Duration::from_secs(3)).await;
+    assert_eq!(dt8_colour_value(&mut driver, 4, 0).await, 0x6000);
+
+    dt8_send(&mut driver, 4, 0xe4, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_colour_value(&mut driver, 4, 0).await, 0x5f00);
+}
+
+#[tokio::test]
+async fn dt8_xy_gamut() {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 4;
+    dev.fade = 0x00;
+    dev.device_types = vec![8];
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    // Outside the gamut, moved to the edge between the green and blue
+    // primaries
+    dt8_set_dtr16(&mut driver, 0x1000).await;
+    dt8_send(&mut driver, 4, 0xe0, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_set_dtr16(&mut driver, 0x5000).await;
+    dt8_send(&mut driver, 4, 0xe1, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_send(&mut driver, 4, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_query(&mut driver, 4, 0xf8).await, 0x11);
+    let x = dt8_colour_value(&mut driver, 4, 0).await;
+    let y = dt8_colour_value(&mut driver, 4, 1).await;
+    assert!(x > 0x3000 && x < 0x3800, "x = {:04x}", x);
+    assert!(y > 0x4000 && y < 0x4800, "y = {:04x}", y);
+
+    // Back inside
+    dt8_set_dtr16(&mut driver, 0x5000).await;
+    dt8_send(&mut driver, 4, 0xe0, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    dt8_send(&mut driver, 4, 0xe2, Flags::Empty)
+        .await
+        .check_send()
+        .unwrap();
+    assert_eq!(dt8_query(&mut driver, 4, 0xf8).await, 0x10);
+    assert_eq!(dt8_colour_value(&mut driver, 4, 0).await, 0x5000);
+}
+
+#[tokio::test]
+async fn dt8_commands() {
+    use dali::gear::address::Address;
+    use dali::gear::dt8::{self, Commands209, colour_type, colour_value};
+    use dali::light_control::colored_light::{ColoredLight, LightValue};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 5;
+    dev.fade = 0x00;
+    dev.device_types = vec![6, 8];
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let short = Short::new(5);
+    let addr = Address::Short(short);
+
+    let mut commands = Commands209::new(&mut driver);
+    assert_eq!(
+        commands.query_colour_type_features(short).await.unwrap() << 4,
+        colour_type::XY | colour_type::TC
+    );
+    commands
+        .set_temporary_colour_temperature_kelvin(addr, 5000)
+        .await
+        .unwrap();
+    assert_eq!(
+        commands
+            .query_colour_value(short, colour_value::TEMPORARY_COLOUR_TEMPERATURE)
+            .await
+            .unwrap(),
+        200
+    );
+    commands.activate(addr).await.unwrap();
+    assert_eq!(
+        commands
+            .query_colour_value(short, colour_value::COLOUR_TEMPERATURE)
+            .await
+            .unwrap(),
+        200
+    );
+
+    dt8::set_light_value(
+        &mut driver,
+        addr,
+        &LightValue {
+            power: 10.0,
+            color: ColoredLight::Coordinate {
+                x: 0.3125,
+                y: 0.375,
+            },
+        },
+    )
+    .await
+    .unwrap();
+    let mut commands = Commands209::new(&mut driver);
+    assert_eq!(
+        commands.query_colour_status(short).await.unwrap(),
+        colour_type::XY
+    );
+    assert_eq!(
+        commands
+            .query_colour_value(short, colour_value::X_COORDINATE)
+            .await
+            .unwrap(),
+        0x5000
+    );
+    assert_eq!(
+        commands
+            .query_colour_value(short, colour_value::Y_COORDINATE)
+            .await
+            .unwrap(),
+        0x6000
+    );
+    let mut commands = Commands102::new(&mut driver);
+    assert_eq!(
+        commands
+            .query(cmd::QUERY_ACTUAL_LEVEL(short))
+            .await
+            .unwrap(),
+        dt8::power_to_level(10.0)
+    );
+}
+
+#[tokio::test]
+async fn monitored_driver() {
+    use dali::drivers::driver::DaliBusEventType;
+    use dali::drivers::monitor::MonitoredDriver;
+    use futures::StreamExt;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let mut driver = MonitoredDriver::new(Box::new(driver));
+    let monitor = driver.monitor();
+    let mut first = monitor.subscribe();
+    let mut second = monitor.subscribe();
+
+    // Traffic from another control device is seen by all subscribers
+    let (mut other, other_dev) = DaliSimDriver::new();
+    sim.add_device(other_dev).await.unwrap();
+    let query = cmd::QUERY_ACTUAL_LEVEL(Short::new(1)).0;
+    assert!(matches!(
+        other.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Answer(_)
+    ));
+    for events in [&mut first, &mut second] {
+        let event = events.next().await.unwrap().unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame16(f) if f == query));
+        let event = events.next().await.unwrap().unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame8(_)));
+    }
+    let event = driver.next_bus_event().await.unwrap();
+    assert!(matches!(event.event_type, DaliBusEventType::Frame16(f) if f == query));
+
+    // The wrapped driver can still be used for sending
+    let mut commands = Commands102::new(&mut driver);
+    commands
+        .query(cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(1)))
+        .await
+        .unwrap();
+
+    drop(driver);
+    let mut remaining = 0;
+    while let Some(event) = first.next().await {
+        event.unwrap();
+        remaining += 1;
+    }
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn shared_driver_events() {
+    use dali::drivers::driver::DaliBusEventType;
+    use dali::drivers::shared::SharedDriver;
+    use futures::StreamExt;
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let mut driver = SharedDriver::new(Box::new(driver));
+    let mut clone = driver.clone();
+    let monitor = driver.monitor();
+    let mut first = monitor.subscribe();
+    let mut second = monitor.subscribe();
+
+    // Traffic from another control device is seen by all subscribers
+    let (mut other, other_dev) = DaliSimDriver::new();
+    sim.add_device(other_dev).await.unwrap();
+    let query = cmd::QUERY_ACTUAL_LEVEL(Short::new(1)).0;
+    assert!(matches!(
+        other.send_frame16(&query, Flags::ExpectAnswer(true)).await,
+        DaliSendResult::Answer(_)
+    ));
+    for events in [&mut first, &mut second] {
+        let event = events.next().await.unwrap().unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame16(f) if f == query));
+        let event = events.next().await.unwrap().unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame8(_)));
+    }
+    for driver in [&mut driver, &mut clone] {
+        let event = driver.next_bus_event().await.unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame16(f) if f == query));
+        let event = driver.next_bus_event().await.unwrap();
+        assert!(matches!(event.event_type, DaliBusEventType::Frame8(_)));
+    }
+
+    // The shared driver can still be used for sending
+    let mut commands = Commands102::new(&mut driver);
+    commands
+        .query(cmd::QUERY_CONTROL_GEAR_PRESENT(Short::new(1)))
+        .await
+        .unwrap();
+
+    // The events end when all clones are gone
+    drop(driver);
+    drop(clone);
+    let mut remaining = 0;
+    while let Some(event) = first.next().await {
+        event.unwrap();
+        remaining += 1;
+    }
+    assert_eq!(remaining, 0);
+}
+
+async fn shared_sim() -> dali::drivers::shared::SharedDriver {
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    dali::drivers::shared::SharedDriver::new(Box::new(driver))
+}
+
+#[tokio::test]
+async fn shared_driver_transactions() {
+    use dali::drivers::send_flags::{EXPECT_ANSWER, NO_FLAG, SEND_TWICE};
+    let mut driver = shared_sim().await;
+    let mut max_driver = driver.clone();
+    let mut min_driver = driver.clone();
+
+    // DTR0 and the store command from each client are kept together
+    let set_max = async {
+        max_driver.send_frame16(&[0xa3, 200], NO_FLAG).await;
+        max_driver.send_frame16(&[0x03, 0x2a], SEND_TWICE).await
+    };
+    let set_min = async {
+        min_driver.send_frame16(&[0xa3, 50], NO_FLAG).await;
+        min_driver.send_frame16(&[0x03, 0x2b], SEND_TWICE).await
+    };
+    let (max_res, min_res) = futures::join!(set_max, set_min);
+    assert!(matches!(max_res, DaliSendResult::Ok));
+    assert!(matches!(min_res, DaliSendResult::Ok));
+    assert!(matches!(
+        driver.send_frame16(&[0x03, 0xa1], EXPECT_ANSWER).await,
+        DaliSendResult::Answer(200)
+    ));
+    assert!(matches!(
+        driver.send_frame16(&[0x03, 0xa2], EXPECT_ANSWER).await,
+        DaliSendResult::Answer(50)
+    ));
+
+    // No other client gets the bus during an explicit transaction
+    let mut transaction = driver.transaction(NO_FLAG).await.unwrap();
+    let mut other = driver.clone();
+    transaction.send_frame16(&[0xa3, 1], NO_FLAG).await;
+    let other_res = other.send_frame16(&[0xa3, 2], NO_FLAG);
+    assert!(matches!(
+        transaction.send_frame16(&[0x03, 0x98], EXPECT_ANSWER).await,
+        DaliSendResult::Answer(1)
+    ));
+    drop(transaction);
+    assert!(matches!(other_res.await, DaliSendResult::Ok));
+    assert!(matches!(
+        driver.send_frame16(&[0x03, 0x98], EXPECT_ANSWER).await,
+        DaliSendResult::Answer(2)
+    ));
+}
+
+#[tokio::test]
+async fn shared_driver_priority() {
+    use dali::drivers::send_flags::{EXPECT_ANSWER, NO_FLAG, PRIORITY_1, PRIORITY_5};
+    let mut driver = shared_sim().await;
+    let mut low = driver.clone();
+    let mut high = driver.clone();
+
+    // Queue both while the bus is taken, the high priority frame is sent
+    // first
+    let transaction = driver.transaction(NO_FLAG).await.unwrap();
+    let low_res = low.send_frame16(&[0xa3, 5], PRIORITY_5);
+    let high_res = high.send_frame16(&[0xa3, 1], PRIORITY_1);
+    drop(transaction);
+    let (low_res, high_res) = futures::join!(low_res, high_res);
+    assert!(matches!(low_res, DaliSendResult::Ok));
+    assert!(matches!(high_res, DaliSendResult::Ok));
+    assert!(matches!(
+        driver.send_frame16(&[0x03, 0x98], EXPECT_ANSWER).await,
+        DaliSendResult::Answer(5)
+    ));
+}
+
+#[tokio::test]
+async fn snapshot_restore() {
+    use dali::gear::address::Address;
+    use dali::gear::dt6;
+    use dali::utils::memory_banks::MemoryBank0Info;
+    use dali::utils::snapshot::{self, GearDiff, Setting};
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    dev.random_address = 0x123456;
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 2;
+    dev.random_address = 0x654321;
+    dev.set_memory_bank_0(&MemoryBank0Info {
+        gtin: 7350000000017,
+        id_number: 4711,
+        ..MemoryBank0Info::default()
+    });
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let mut commands = Commands102::new(&mut driver);
+    commands
+        .cmd(cmd::ADD_TO_GROUP(Short::new(1), 3))
+        .await
+        .unwrap();
+    commands.dtr0(100).await.unwrap();
+    commands
+        .cmd(cmd::SET_SCENE(Short::new(2), 4))
+        .await
+        .unwrap();
+    commands.dtr0(200).await.unwrap();
+    commands
+        .cmd(cmd::SET_MAX_LEVEL(Short::new(2)))
+        .await
+        .unwrap();
+
+    let mut saved = snapshot::read_snapshot(&mut driver, "Office")
+        .await
+        .unwrap();
+    assert_eq!(saved.gear.len(), 2);
+    assert_eq!(saved.gear[0].random_address, Some(0x123456));
+    assert_eq!(saved.gear[0].groups, Some(1 << 3));
+    assert_eq!(saved.gear[1].gtin, Some(7350000000017));
+    assert_eq!(saved.gear[1].id_number, Some(4711));
+    assert_eq!(saved.gear[1].scenes.unwrap()[4], 100);
+    assert!(saved.gear[0].status.is_some());
+    assert_eq!(
+        saved.gear[0].dt6.as_ref().unwrap().dimming_curve,
+        Some(dt6::dimming_curve::STANDARD)
+    );
+    #[cfg(feature = "serde")]
+    {
+        let mut file = Vec::new();
+        saved.save(&mut file).unwrap();
+        assert_eq!(snapshot::BusSnapshot::load(file.as_slice()).unwrap(), saved);
+    }
+    assert!(
+        snapshot::diff(
+            &saved,
+            &snapshot::read_snapshot(&mut driver, "").await.unwrap()
+        )
+        .is_empty()
+    );
+
+    // Change the bus and match the second gear by GTIN and serial number
+    saved.gear[1].random_address = Some(0xabcdef);
+    let mut commands = Commands102::new(&mut driver);
+    commands
+        .cmd(cmd::REMOVE_FROM_GROUP(Short::new(1), 3))
+        .await
+        .unwrap();
+    commands
+        .cmd(cmd::REMOVE_FROM_SCENE(Short::new(2), 4))
+        .await
+        .unwrap();
+    commands.dtr0(50).await.unwrap();
+    commands
+        .cmd(cmd::SET_MIN_LEVEL(Short::new(2)))
+        .await
+        .unwrap();
+    commands.dtr0(60).await.unwrap();
+    commands
+        .cmd(cmd::SET_MAX_LEVEL(Short::new(2)))
+        .await
+        .unwrap();
+    dt6::select_dimming_curve(
+        &mut driver,
+        Address::Short(Short::new(1)),
+        dt6::dimming_curve::LINEAR,
+    )
+    .await
+    .unwrap();
+    let live = snapshot::read_snapshot(&mut driver, "").await.unwrap();
+    let diffs = snapshot::diff(&saved, &live);
+    assert_eq!(diffs.len(), 2);
+    let changed = |diff: &GearDiff| match diff {
+        GearDiff::Changed { settings, .. } => {
+            settings.iter().map(|s| s.setting).collect::<Vec<_>>()
+        }
+        _ => panic!("Expected a changed gear"),
+    };
+    assert_eq!(changed(&diffs[0]), [Setting::Groups, Setting::DimmingCurve]);
+    assert_eq!(
+        changed(&diffs[1]),
+        [Setting::Scene(4), Setting::MinLevel, Setting::MaxLevel]
+    );
+
+    let restored = snapshot::restore(&mut driver, &saved).await.unwrap();
+    assert_eq!(restored, diffs);
+    let live = snapshot::read_snapshot(&mut driver, "").await.unwrap();
+    assert!(snapshot::diff(&saved, &live).is_empty());
+    assert_eq!(live.gear[1].max_level, Some(200));
+}
+
+#[tokio::test]
+async fn replace_gear() {
+    use dali::utils::memory_banks::MemoryBank0Info;
+    use dali::utils::replace;
+    use dali::utils::snapshot;
+    let bank0 = |gtin, id_number| MemoryBank0Info {
+        gtin,
+        id_number,
+        ..MemoryBank0Info::default()
+    };
+
+    // Original installation
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    dev.random_address = 0x100000;
+    dev.set_memory_bank_0(&bank0(1001, 1));
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 5;
+    dev.random_address = 0x200000;
+    dev.gear_groups = 0x0006;
+    dev.scene[2] = 120;
+    dev.max_level = 180;
+    dev.set_memory_bank_0(&bank0(2002, 2));
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+    let saved = snapshot::read_snapshot(&mut driver, "").await.unwrap();
+
+    // The gear at 5 is replaced and an unrelated gear is added
+    let sim = simulator::DaliBusSim::with_real_time(false).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.short_address = 1;
+    dev.random_address = 0x100000;
+    dev.set_memory_bank_0(&bank0(1001, 1));
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.random_address = 0x300000;
+    dev.set_memory_bank_0(&bank0(3003, 3));
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let mut dev = gear::DaliSimGear::new();
+    dev.random_address = 0x400000;
+    dev.set_memory_bank_0(&bank0(2002, 4));
+    sim.add_device(Box::new(dev)).await.unwrap();
+    let (mut driver, driver_dev) = DaliSimDriver::new();
+    sim.add_device(driver_dev).await.unwrap();
+
+    let report = replace::replace(&mut driver, &saved).await.unwrap();
+    assert_eq!(report.replaced.len(), 1);
+    assert!(report.missing.is_empty());
+    assert_eq!(report.unmatched, [0x300000]);
+    let replacement = &report.replaced[0];
+    assert_eq!(replacement.old.short_address, 5);
+    assert_eq!(replacement.new.random_address, Some(0x400000));
+    assert_eq!(replacement.new.id_number, Some(4));
+
+    let new = snapshot::read_gear(&mut driver, Short::new(5))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(new.random_address, Some(0x400000));
+    assert_eq!(new.groups, Some(0x0006));
+    assert_eq!(new.scenes.unwrap()[2], 120);
+    assert_eq!(new.max_level, Some(180));
+    // The unrelated gear is left unaddressed
+    assert_eq!(
+        replace::find_unaddressed(&mut driver).await.unwrap(),
+        [0x300000]
+    );
+}
@@ -0,0 +1,37 @@
+//! Timing of frames on the bus (IEC 62386-101)
+
+use crate::drivers::driver::DaliFrame;
+use std::time::Duration;
+
+pub const HALF_BIT_MICROS: u64 = 417;
+pub const BIT_MICROS: u64 = 833;
+// Includes start bit
+pub const FRAME_8_DURATION: Duration = Duration::from_micros(BIT_MICROS * 9);
+pub const FRAME_16_DURATION: Duration = Duration::from_micros(BIT_MICROS * 17);
+pub const FRAME_24_DURATION: Duration = Duration::from_micros(BIT_MICROS * 25);
+pub const FRAME_25_DURATION: Duration = Duration::from_micros(BIT_MICROS * 26);
+/// Maximum time from the end of a forward frame to the start of the
+/// backward frame answering it
+pub const ANSWER_TIMEOUT: Duration = Duration::from_millis(22);
+
+pub fn frame_duration(frame: &DaliFrame) -> Duration {
+    use DaliFrame::*;
+    Duration::from_micros(match frame {
+        Frame8(f) => {
+            // If the frame ends with a 1 then the last transition is
+            // in the middle of the last bit.
+
+            9 * BIT_MICROS - if f & 1 == 1 { HALF_BIT_MICROS } else { 0 }
+        }
+        Frame16(f) => 17 * BIT_MICROS - if f[1] & 1 == 1 { HALF_BIT_MICROS } else { 0 },
+        Frame24(f) => 25 * BIT_MICROS - if f[2] & 1 == 1 { HALF_BIT_MICROS } else { 0 },
+        Frame25(f) => {
+            26 * BIT_MICROS
+                - if f[3] & 0x80 == 0x80 {
+                    HALF_BIT_MICROS
+                } else {
+                    0
+                }
+        }
+    })
+}
@@ -42,4 +42,22 @@ mod test {
         let a = Group::try_from(b).unwrap();
         assert_eq!(a, Group::new(15));
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let json = |a: Address| serde_json::to_string(&a).unwrap();
+        let parse = |s: &str| serde_json::from_str::<Address>(s);
+        assert_eq!(json(Short::new(0).into()), r#"{"short":0}"#);
+        assert_eq!(json(Group::new(15).into()), r#"{"group":15}"#);
+        assert_eq!(json(Address::Broadcast), r#""broadcast""#);
+        assert_eq!(parse(r#"{"short":63}"#).unwrap(), Short::new(63));
+        assert_eq!(
+            parse(r#""broadcast_unaddressed""#).unwrap(),
+            Address::BroadcastUnaddressed
+        );
+        assert!(parse(r#"{"short":64}"#).is_err());
+        assert!(parse(r#"{"group":16}"#).is_err());
+        assert_eq!(serde_json::from_str::<Short>("5").unwrap(), Short::new(5));
+    }
 }
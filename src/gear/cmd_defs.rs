@@ -54,6 +54,9 @@ macro_rules! special_data_cmd_def {
     };
 }
 
+// Used for device type specific commands
+pub(crate) use {cmd_type, dev_cmd_def};
+
 #[allow(non_snake_case)]
 #[inline(always)]
 pub fn DAPC<A>(addr: A, level: u8) -> Command<false, true>
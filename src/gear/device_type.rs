@@ -1,5 +1,10 @@
 use std::fmt;
 
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Device type number, serialized as a number
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct DeviceType(u8);
 pub mod types {
     pub const FLORESCENT: u8 = 0;
@@ -0,0 +1,360 @@
+//! Self-contained emergency lighting, device type 1 (IEC 62386-202)
+//!
+//! The commands are application extended commands and must be preceded
+//! by ENABLE DEVICE TYPE 1.
+
+use crate::common::address::Short;
+use crate::common::cmd_defs::AddressByte;
+use crate::drivers::command_utils::send16;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::drivers::send_flags::NO_FLAG;
+use crate::gear::cmd_defs::{Command, cmd_type, dev_cmd_def};
+use crate::gear::device_type::types as device_type;
+use crate::utils::device_info::fmt_bitflags;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+dev_cmd_def!(REST, 0xe0);
+dev_cmd_def!(INHIBIT, 0xe1);
+dev_cmd_def!(RE_LIGHT_RESET_INHIBIT, 0xe2);
+dev_cmd_def!(START_FUNCTION_TEST, 0xe3);
+dev_cmd_def!(START_DURATION_TEST, 0xe4);
+dev_cmd_def!(STOP_TEST, 0xe5);
+dev_cmd_def!(RESET_FUNCTION_TEST_DONE_FLAG, 0xe6);
+dev_cmd_def!(RESET_DURATION_TEST_DONE_FLAG, 0xe7);
+dev_cmd_def!(RESET_LAMP_TIME, 0xe8);
+dev_cmd_def!(STORE_DTR_AS_EMERGENCY_LEVEL, 0xe9, Twice);
+dev_cmd_def!(STORE_TEST_DELAY_TIME_HIGH_BYTE, 0xea, Twice);
+dev_cmd_def!(STORE_TEST_DELAY_TIME_LOW_BYTE, 0xeb, Twice);
+dev_cmd_def!(STORE_FUNCTION_TEST_INTERVAL, 0xec, Twice);
+dev_cmd_def!(STORE_DURATION_TEST_INTERVAL, 0xed, Twice);
+dev_cmd_def!(STORE_TEST_EXECUTION_TIMEOUT, 0xee, Twice);
+dev_cmd_def!(STORE_PROLONG_TIME, 0xef, Twice);
+dev_cmd_def!(START_IDENTIFICATION, 0xf0);
+
+dev_cmd_def!(QUERY_BATTERY_CHARGE, 0xf1, Answer);
+dev_cmd_def!(QUERY_TEST_TIMING, 0xf2, Answer);
+dev_cmd_def!(QUERY_DURATION_TEST_RESULT, 0xf3, Answer);
+dev_cmd_def!(QUERY_LAMP_EMERGENCY_TIME, 0xf4, Answer);
+dev_cmd_def!(QUERY_LAMP_TOTAL_OPERATION_TIME, 0xf5, Answer);
+dev_cmd_def!(QUERY_EMERGENCY_LEVEL, 0xf6, Answer);
+dev_cmd_def!(QUERY_EMERGENCY_MIN_LEVEL, 0xf7, Answer);
+dev_cmd_def!(QUERY_EMERGENCY_MAX_LEVEL, 0xf8, Answer);
+dev_cmd_def!(QUERY_RATED_DURATION, 0xf9, Answer);
+dev_cmd_def!(QUERY_EMERGENCY_MODE, 0xfa, Answer);
+dev_cmd_def!(QUERY_FEATURES, 0xfb, Answer);
+dev_cmd_def!(QUERY_FAILURE_STATUS, 0xfc, Answer);
+dev_cmd_def!(QUERY_EMERGENCY_STATUS, 0xfd, Answer);
+dev_cmd_def!(PERFORM_DTR_SELECTED_FUNCTION, 0xfe);
+pub use crate::gear::cmd_defs::QUERY_EXTENDED_VERSION_NUMBER;
+
+/// Bits returned by QUERY EMERGENCY MODE
+pub mod emergency_mode {
+    pub const REST: u8 = 0x01;
+    pub const NORMAL: u8 = 0x02;
+    pub const EMERGENCY: u8 = 0x04;
+    pub const EXTENDED_EMERGENCY: u8 = 0x08;
+    pub const FUNCTION_TEST: u8 = 0x10;
+    pub const DURATION_TEST: u8 = 0x20;
+    pub const HARDWIRED_INHIBIT: u8 = 0x40;
+    pub const HARDWIRED_SWITCH: u8 = 0x80;
+}
+
+/// Bits returned by QUERY FAILURE STATUS
+pub mod failure {
+    pub const CIRCUIT: u8 = 0x01;
+    pub const BATTERY_DURATION: u8 = 0x02;
+    pub const BATTERY: u8 = 0x04;
+    pub const EMERGENCY_LAMP: u8 = 0x08;
+    pub const FUNCTION_TEST_DELAYED: u8 = 0x10;
+    pub const DURATION_TEST_DELAYED: u8 = 0x20;
+    pub const FUNCTION_TEST: u8 = 0x40;
+    pub const DURATION_TEST: u8 = 0x80;
+}
+
+/// Bits returned by QUERY EMERGENCY STATUS
+pub mod emergency_status {
+    pub const INHIBIT: u8 = 0x01;
+    pub const FUNCTION_TEST_DONE: u8 = 0x02;
+    pub const DURATION_TEST_DONE: u8 = 0x04;
+    pub const BATTERY_CHARGED: u8 = 0x08;
+    pub const FUNCTION_TEST_PENDING: u8 = 0x10;
+    pub const DURATION_TEST_PENDING: u8 = 0x20;
+    pub const IDENTIFICATION: u8 = 0x40;
+    pub const PHYSICALLY_SELECTED: u8 = 0x80;
+}
+
+pub(crate) const EMERGENCY_MODE_NAMES: [&str; 8] = [
+    "Rest",
+    "Normal",
+    "Emergency",
+    "Extended emergency",
+    "Function test",
+    "Duration test",
+    "Hardwired inhibit",
+    "Hardwired switch on",
+];
+
+pub(crate) const FAILURE_NAMES: [&str; 8] = [
+    "Circuit failure",
+    "Battery duration failure",
+    "Battery failure",
+    "Emergency lamp failure",
+    "Function test delayed",
+    "Duration test delayed",
+    "Function test failed",
+    "Duration test failed",
+];
+
+pub(crate) const EMERGENCY_STATUS_NAMES: [&str; 8] = [
+    "Inhibit",
+    "Function test done",
+    "Duration test done",
+    "Battery fully charged",
+    "Function test pending",
+    "Duration test pending",
+    "Identification active",
+    "Physically selected",
+];
+
+/// Device type 1 specific state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dt1Info {
+    pub emergency_mode: Option<u8>,
+    pub emergency_status: Option<u8>,
+    pub failure_status: Option<u8>,
+    /// 0-254, 255 if unknown
+    pub battery_charge: Option<u8>,
+    /// In units of 2 minutes
+    pub rated_duration: Option<u8>,
+    /// In units of 2 minutes
+    pub duration_test_result: Option<u8>,
+    pub emergency_level: Option<u8>,
+    pub features: Option<u8>,
+}
+
+impl fmt::Display for Dt1Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v) = self.emergency_mode {
+            writeln!(
+                f,
+                "Emergency mode: {}",
+                fmt_bitflags(v, &EMERGENCY_MODE_NAMES)
+            )?;
+        }
+        if let Some(v) = self.emergency_status {
+            writeln!(
+                f,
+                "Emergency status: {}",
+                fmt_bitflags(v, &EMERGENCY_STATUS_NAMES)
+            )?;
+        }
+        if let Some(v) = self.failure_status {
+            writeln!(f, "Emergency failure: {}", fmt_bitflags(v, &FAILURE_NAMES))?;
+        }
+        match self.battery_charge {
+            Some(255) => writeln!(f, "Battery charge: Unknown")?,
+            Some(v) => writeln!(f, "Battery charge: {}%", v as u32 * 100 / 254)?,
+            None => {}
+        }
+        if let Some(v) = self.rated_duration {
+            writeln!(f, "Rated duration: {} min", v as u32 * 2)?;
+        }
+        if let Some(v) = self.duration_test_result {
+            writeln!(f, "Duration test result: {} min", v as u32 * 2)?;
+        }
+        if let Some(v) = self.emergency_level {
+            writeln!(f, "Emergency level: {}", v)?;
+        }
+        Ok(())
+    }
+}
+
+async fn query(
+    d: &mut dyn DaliDriver,
+    cmd: Command<true, false>,
+) -> Result<Option<u8>, DaliSendResult> {
+    match send16::device_type_query(d, device_type::EMERGENCY, cmd, NO_FLAG).await {
+        DaliSendResult::Answer(v) => Ok(Some(v)),
+        DaliSendResult::Timeout => Ok(None),
+        e => Err(e),
+    }
+}
+
+async fn send<const TWICE: bool>(
+    d: &mut dyn DaliDriver,
+    cmd: Command<false, TWICE>,
+) -> Result<(), DaliSendResult> {
+    send16::device_type_cmd(d, device_type::EMERGENCY, cmd, NO_FLAG)
+        .await
+        .check_send()
+}
+
+/// Read the device type 1 specific state of a gear
+pub async fn read_info(d: &mut dyn DaliDriver, addr: Short) -> Result<Dt1Info, DaliSendResult> {
+    Ok(Dt1Info {
+        emergency_mode: query(d, QUERY_EMERGENCY_MODE(addr)).await?,
+        emergency_status: query(d, QUERY_EMERGENCY_STATUS(addr)).await?,
+        failure_status: query(d, QUERY_FAILURE_STATUS(addr)).await?,
+        battery_charge: query(d, QUERY_BATTERY_CHARGE(addr)).await?,
+        rated_duration: query(d, QUERY_RATED_DURATION(addr)).await?,
+        duration_test_result: query(d, QUERY_DURATION_TEST_RESULT(addr)).await?,
+        emergency_level: query(d, QUERY_EMERGENCY_LEVEL(addr)).await?,
+        features: query(d, QUERY_FEATURES(addr)).await?,
+    })
+}
+
+/// Set the level used in emergency mode
+pub async fn set_emergency_level(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    level: u8,
+) -> Result<(), DaliSendResult> {
+    send16::set_dtr0(d, level, NO_FLAG).await.check_send()?;
+    send(d, STORE_DTR_AS_EMERGENCY_LEVEL(addr)).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmergencyTest {
+    /// Short test of the lamp and the circuit
+    Function,
+    /// Runs on battery for the rated duration
+    Duration,
+}
+
+impl EmergencyTest {
+    fn start_cmd(self, addr: Short) -> Command<false, false> {
+        match self {
+            EmergencyTest::Function => START_FUNCTION_TEST(addr),
+            EmergencyTest::Duration => START_DURATION_TEST(addr),
+        }
+    }
+
+    fn reset_done_cmd(self, addr: Short) -> Command<false, false> {
+        match self {
+            EmergencyTest::Function => RESET_FUNCTION_TEST_DONE_FLAG(addr),
+            EmergencyTest::Duration => RESET_DURATION_TEST_DONE_FLAG(addr),
+        }
+    }
+
+    fn done_flag(self) -> u8 {
+        match self {
+            EmergencyTest::Function => emergency_status::FUNCTION_TEST_DONE,
+            EmergencyTest::Duration => emergency_status::DURATION_TEST_DONE,
+        }
+    }
+
+    fn failed_flag(self) -> u8 {
+        match self {
+            EmergencyTest::Function => failure::FUNCTION_TEST,
+            EmergencyTest::Duration => failure::DURATION_TEST,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    /// Failure status bits
+    Failed(u8),
+    /// The test didn't finish before the timeout
+    TimedOut,
+    /// The gear doesn't answer DT1 queries
+    NoResponse,
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TestOutcome::Passed => write!(f, "Passed"),
+            TestOutcome::Failed(bits) => {
+                write!(f, "Failed ({})", fmt_bitflags(*bits, &FAILURE_NAMES))
+            }
+            TestOutcome::TimedOut => write!(f, "Timed out"),
+            TestOutcome::NoResponse => write!(f, "No response"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub addr: Short,
+    pub outcome: TestOutcome,
+    /// Battery charge after the test, 0-254
+    pub battery_charge: Option<u8>,
+    /// Duration test result in units of 2 minutes
+    pub duration: Option<u8>,
+}
+
+/// Start a test on each gear and poll until all of them are done.
+///
+/// The test done flags are cleared before the tests are started. The gears
+/// are polled every `poll_interval` until the test done flag is set or
+/// `timeout` has passed since the tests were started. A gear may delay
+/// the test, e.g. while the battery is charging, so the timeout for a
+/// duration test should allow for that.
+pub async fn run_tests(
+    d: &mut dyn DaliDriver,
+    addrs: &[Short],
+    test: EmergencyTest,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> Result<Vec<TestResult>, DaliSendResult> {
+    let mut results: Vec<TestResult> = addrs
+        .iter()
+        .map(|&addr| TestResult {
+            addr,
+            outcome: TestOutcome::TimedOut,
+            battery_charge: None,
+            duration: None,
+        })
+        .collect();
+    let mut pending = Vec::new();
+    for (index, &addr) in addrs.iter().enumerate() {
+        if query(d, QUERY_EMERGENCY_MODE(addr)).await?.is_none() {
+            results[index].outcome = TestOutcome::NoResponse;
+            continue;
+        }
+        send(d, test.reset_done_cmd(addr)).await?;
+        send(d, test.start_cmd(addr)).await?;
+        pending.push(index);
+    }
+    let start = d.current_timestamp();
+    let mut next_poll = start;
+    while !pending.is_empty() {
+        next_poll += poll_interval;
+        d.wait_until(next_poll).await;
+        let mut still_pending = Vec::new();
+        for index in pending {
+            let addr = addrs[index];
+            let status = query(d, QUERY_EMERGENCY_STATUS(addr)).await?;
+            if status.is_none_or(|s| s & test.done_flag() == 0) {
+                still_pending.push(index);
+                continue;
+            }
+            let result = &mut results[index];
+            let failure_status = query(d, QUERY_FAILURE_STATUS(addr)).await?.unwrap_or(0);
+            result.outcome = if failure_status & test.failed_flag() != 0 {
+                TestOutcome::Failed(failure_status)
+            } else {
+                TestOutcome::Passed
+            };
+            result.battery_charge = query(d, QUERY_BATTERY_CHARGE(addr)).await?;
+            if test == EmergencyTest::Duration {
+                result.duration = query(d, QUERY_DURATION_TEST_RESULT(addr)).await?;
+            }
+        }
+        pending = still_pending;
+        if d.current_timestamp().saturating_duration_since(start) >= timeout {
+            for &index in &pending {
+                // Don't leave the gear running on battery
+                send(d, STOP_TEST(addrs[index])).await?;
+            }
+            break;
+        }
+    }
+    Ok(results)
+}
@@ -0,0 +1,201 @@
+//! LED modules, device type 6 (IEC 62386-207)
+//!
+//! The commands are application extended commands and must be preceded
+//! by ENABLE DEVICE TYPE 6.
+
+use crate::common::cmd_defs::AddressByte;
+use crate::drivers::command_utils::send16;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::drivers::send_flags::NO_FLAG;
+use crate::gear::address::{Address, Short};
+use crate::gear::cmd_defs::{Command, cmd_type, dev_cmd_def};
+use crate::gear::device_type::types as device_type;
+use crate::utils::device_info::fmt_bitflags;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+dev_cmd_def!(REFERENCE_SYSTEM_POWER, 0xe0, Twice);
+dev_cmd_def!(ENABLE_CURRENT_PROTECTOR, 0xe1, Twice);
+dev_cmd_def!(DISABLE_CURRENT_PROTECTOR, 0xe2, Twice);
+dev_cmd_def!(SELECT_DIMMING_CURVE, 0xe3, Twice);
+dev_cmd_def!(STORE_DTR_AS_FAST_FADE_TIME, 0xe4, Twice);
+
+dev_cmd_def!(QUERY_GEAR_TYPE, 0xed, Answer);
+dev_cmd_def!(QUERY_DIMMING_CURVE, 0xee, Answer);
+dev_cmd_def!(QUERY_POSSIBLE_OPERATING_MODES, 0xef, Answer);
+dev_cmd_def!(QUERY_FEATURES, 0xf0, Answer);
+dev_cmd_def!(QUERY_FAILURE_STATUS, 0xf1, Answer);
+dev_cmd_def!(QUERY_SHORT_CIRCUIT, 0xf2, Answer);
+dev_cmd_def!(QUERY_OPEN_CIRCUIT, 0xf3, Answer);
+dev_cmd_def!(QUERY_LOAD_DECREASE, 0xf4, Answer);
+dev_cmd_def!(QUERY_LOAD_INCREASE, 0xf5, Answer);
+dev_cmd_def!(QUERY_CURRENT_PROTECTOR_ACTIVE, 0xf6, Answer);
+dev_cmd_def!(QUERY_THERMAL_SHUT_DOWN, 0xf7, Answer);
+dev_cmd_def!(QUERY_THERMAL_OVERLOAD, 0xf8, Answer);
+dev_cmd_def!(QUERY_REFERENCE_RUNNING, 0xf9, Answer);
+dev_cmd_def!(QUERY_REFERENCE_MEASUREMENT_FAILED, 0xfa, Answer);
+dev_cmd_def!(QUERY_CURRENT_PROTECTOR_ENABLED, 0xfb, Answer);
+dev_cmd_def!(QUERY_OPERATING_MODE, 0xfc, Answer);
+dev_cmd_def!(QUERY_FAST_FADE_TIME, 0xfd, Answer);
+dev_cmd_def!(QUERY_MIN_FAST_FADE_TIME, 0xfe, Answer);
+pub use crate::gear::cmd_defs::QUERY_EXTENDED_VERSION_NUMBER;
+
+/// Dimming curves selected by SELECT DIMMING CURVE
+pub mod dimming_curve {
+    pub const STANDARD: u8 = 0;
+    pub const LINEAR: u8 = 1;
+}
+
+const GEAR_TYPE_NAMES: [&str; 4] = [
+    "Integrated power supply",
+    "Integrated LED module",
+    "AC supply",
+    "DC supply",
+];
+
+const OPERATING_MODE_NAMES: [&str; 5] = [
+    "PWM",
+    "AM",
+    "Current controlled",
+    "High current pulse",
+    "Non-logarithmic dimming curve",
+];
+
+const FEATURE_NAMES: [&str; 8] = [
+    "Short circuit detection",
+    "Open circuit detection",
+    "Load decrease detection",
+    "Load increase detection",
+    "Current protector",
+    "Thermal shut down",
+    "Thermal overload reduction",
+    "Physical selection",
+];
+
+const FAILURE_NAMES: [&str; 8] = [
+    "Short circuit",
+    "Open circuit",
+    "Load decrease",
+    "Load increase",
+    "Current protector active",
+    "Thermal shut down",
+    "Thermal overload",
+    "Reference measurement failed",
+];
+
+/// Device type 6 specific information
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dt6Info {
+    pub gear_type: Option<u8>,
+    pub possible_operating_modes: Option<u8>,
+    pub features: Option<u8>,
+    pub failure_status: Option<u8>,
+    pub operating_mode: Option<u8>,
+    pub dimming_curve: Option<u8>,
+    pub fast_fade_time: Option<u8>,
+    pub min_fast_fade_time: Option<u8>,
+    pub extended_version: Option<u8>,
+}
+
+impl fmt::Display for Dt6Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v) = self.gear_type {
+            writeln!(f, "LED gear type: {}", fmt_bitflags(v, &GEAR_TYPE_NAMES))?;
+        }
+        if let Some(v) = self.possible_operating_modes {
+            writeln!(
+                f,
+                "Possible operating modes: {}",
+                fmt_bitflags(v, &OPERATING_MODE_NAMES)
+            )?;
+        }
+        if let Some(v) = self.operating_mode {
+            writeln!(
+                f,
+                "LED operating mode: {}",
+                fmt_bitflags(v, &OPERATING_MODE_NAMES)
+            )?;
+        }
+        if let Some(v) = self.features {
+            writeln!(f, "LED features: {}", fmt_bitflags(v, &FEATURE_NAMES))?;
+        }
+        if let Some(v) = self.failure_status {
+            writeln!(f, "LED failure status: {}", fmt_bitflags(v, &FAILURE_NAMES))?;
+        }
+        if let Some(v) = self.dimming_curve {
+            let curve = match v {
+                dimming_curve::STANDARD => "Standard",
+                dimming_curve::LINEAR => "Linear",
+                _ => "Unknown",
+            };
+            writeln!(f, "Dimming curve: {}", curve)?;
+        }
+        if let Some(v) = self.fast_fade_time {
+            writeln!(f, "Fast fade time: {} ms", v as u32 * 25)?;
+        }
+        if let Some(v) = self.min_fast_fade_time {
+            writeln!(f, "Minimum fast fade time: {} ms", v as u32 * 25)?;
+        }
+        if let Some(v) = self.extended_version {
+            writeln!(f, "DT6 version: {}.{}", v >> 2, v & 3)?;
+        }
+        Ok(())
+    }
+}
+
+async fn query(
+    d: &mut dyn DaliDriver,
+    cmd: Command<true, false>,
+) -> Result<Option<u8>, DaliSendResult> {
+    match send16::device_type_query(d, device_type::LED, cmd, NO_FLAG).await {
+        DaliSendResult::Answer(v) => Ok(Some(v)),
+        DaliSendResult::Timeout => Ok(None),
+        e => Err(e),
+    }
+}
+
+/// Read the device type 6 specific state of a gear
+pub async fn read_info(d: &mut dyn DaliDriver, addr: Short) -> Result<Dt6Info, DaliSendResult> {
+    Ok(Dt6Info {
+        gear_type: query(d, QUERY_GEAR_TYPE(addr)).await?,
+        possible_operating_modes: query(d, QUERY_POSSIBLE_OPERATING_MODES(addr)).await?,
+        features: query(d, QUERY_FEATURES(addr)).await?,
+        failure_status: query(d, QUERY_FAILURE_STATUS(addr)).await?,
+        operating_mode: query(d, QUERY_OPERATING_MODE(addr)).await?,
+        dimming_curve: query(d, QUERY_DIMMING_CURVE(addr)).await?,
+        fast_fade_time: query(d, QUERY_FAST_FADE_TIME(addr)).await?,
+        min_fast_fade_time: query(d, QUERY_MIN_FAST_FADE_TIME(addr)).await?,
+        extended_version: query(d, QUERY_EXTENDED_VERSION_NUMBER(addr)).await?,
+    })
+}
+
+/// Select one of the [`dimming_curve`]s
+pub async fn select_dimming_curve(
+    d: &mut dyn DaliDriver,
+    addr: Address,
+    curve: u8,
+) -> Result<(), DaliSendResult> {
+    send16::set_dtr0(d, curve, NO_FLAG).await.check_send()?;
+    send16::device_type_cmd(d, device_type::LED, SELECT_DIMMING_CURVE(addr), NO_FLAG)
+        .await
+        .check_send()
+}
+
+/// Set the fast fade time in units of 25 ms. 0 disables fast fading.
+pub async fn set_fast_fade_time(
+    d: &mut dyn DaliDriver,
+    addr: Address,
+    time: u8,
+) -> Result<(), DaliSendResult> {
+    send16::set_dtr0(d, time, NO_FLAG).await.check_send()?;
+    send16::device_type_cmd(
+        d,
+        device_type::LED,
+        STORE_DTR_AS_FAST_FADE_TIME(addr),
+        NO_FLAG,
+    )
+    .await
+    .check_send()
+}
@@ -0,0 +1,146 @@
+//! Switching function, device type 7 (IEC 62386-208)
+//!
+//! The commands are application extended commands and must be preceded
+//! by ENABLE DEVICE TYPE 7. The thresholds are arc power levels where the
+//! switch changes state when the level is going up or down.
+
+use crate::common::cmd_defs::AddressByte;
+use crate::common::defs::MASK;
+use crate::drivers::command_utils::send16;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::drivers::send_flags::NO_FLAG;
+use crate::gear::address::{Address, Short};
+use crate::gear::cmd_defs::{Command, cmd_type, dev_cmd_def};
+use crate::gear::device_type::types as device_type;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+dev_cmd_def!(REFERENCE_SYSTEM_POWER, 0xe0, Twice);
+dev_cmd_def!(STORE_DTR_AS_UP_SWITCH_ON_THRESHOLD, 0xe1, Twice);
+dev_cmd_def!(STORE_DTR_AS_UP_SWITCH_OFF_THRESHOLD, 0xe2, Twice);
+dev_cmd_def!(STORE_DTR_AS_DOWN_SWITCH_ON_THRESHOLD, 0xe3, Twice);
+dev_cmd_def!(STORE_DTR_AS_DOWN_SWITCH_OFF_THRESHOLD, 0xe4, Twice);
+dev_cmd_def!(STORE_DTR_AS_ERROR_HOLD_OFF_TIME, 0xe5, Twice);
+
+dev_cmd_def!(QUERY_FEATURES, 0xf0, Answer);
+dev_cmd_def!(QUERY_SWITCH_STATUS, 0xf1, Answer);
+dev_cmd_def!(QUERY_UP_SWITCH_ON_THRESHOLD, 0xf2, Answer);
+dev_cmd_def!(QUERY_UP_SWITCH_OFF_THRESHOLD, 0xf3, Answer);
+dev_cmd_def!(QUERY_DOWN_SWITCH_ON_THRESHOLD, 0xf4, Answer);
+dev_cmd_def!(QUERY_DOWN_SWITCH_OFF_THRESHOLD, 0xf5, Answer);
+dev_cmd_def!(QUERY_ERROR_HOLD_OFF_TIME, 0xf6, Answer);
+dev_cmd_def!(QUERY_GEAR_TYPE, 0xf7, Answer);
+dev_cmd_def!(QUERY_REFERENCE_RUNNING, 0xf9, Answer);
+dev_cmd_def!(QUERY_REFERENCE_MEASUREMENT_FAILED, 0xfa, Answer);
+pub use crate::gear::cmd_defs::QUERY_EXTENDED_VERSION_NUMBER;
+
+/// Device type 7 specific information
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Dt7Info {
+    pub features: Option<u8>,
+    pub switch_status: Option<u8>,
+    pub gear_type: Option<u8>,
+    pub up_switch_on_threshold: Option<u8>,
+    pub up_switch_off_threshold: Option<u8>,
+    pub down_switch_on_threshold: Option<u8>,
+    pub down_switch_off_threshold: Option<u8>,
+    /// In seconds
+    pub error_hold_off_time: Option<u8>,
+    pub extended_version: Option<u8>,
+}
+
+fn fmt_threshold(f: &mut fmt::Formatter<'_>, name: &str, value: Option<u8>) -> fmt::Result {
+    match value {
+        Some(MASK) => writeln!(f, "{}: Disabled", name),
+        Some(v) => writeln!(f, "{}: {}", name, v),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for Dt7Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(v) = self.features {
+            writeln!(f, "Switch features: 0x{:02x}", v)?;
+        }
+        if let Some(v) = self.switch_status {
+            writeln!(f, "Switch status: 0x{:02x}", v)?;
+        }
+        if let Some(v) = self.gear_type {
+            writeln!(f, "Switch gear type: 0x{:02x}", v)?;
+        }
+        fmt_threshold(f, "Up switch-on threshold", self.up_switch_on_threshold)?;
+        fmt_threshold(f, "Up switch-off threshold", self.up_switch_off_threshold)?;
+        fmt_threshold(f, "Down switch-on threshold", self.down_switch_on_threshold)?;
+        fmt_threshold(
+            f,
+            "Down switch-off threshold",
+            self.down_switch_off_threshold,
+        )?;
+        if let Some(v) = self.error_hold_off_time {
+            writeln!(f, "Error hold-off time: {} s", v)?;
+        }
+        if let Some(v) = self.extended_version {
+            writeln!(f, "DT7 version: {}.{}", v >> 2, v & 3)?;
+        }
+        Ok(())
+    }
+}
+
+async fn query(
+    d: &mut dyn DaliDriver,
+    cmd: Command<true, false>,
+) -> Result<Option<u8>, DaliSendResult> {
+    match send16::device_type_query(d, device_type::SWITCHING, cmd, NO_FLAG).await {
+        DaliSendResult::Answer(v) => Ok(Some(v)),
+        DaliSendResult::Timeout => Ok(None),
+        e => Err(e),
+    }
+}
+
+/// Read the device type 7 specific state of a gear
+pub async fn read_info(d: &mut dyn DaliDriver, addr: Short) -> Result<Dt7Info, DaliSendResult> {
+    Ok(Dt7Info {
+        features: query(d, QUERY_FEATURES(addr)).await?,
+        switch_status: query(d, QUERY_SWITCH_STATUS(addr)).await?,
+        gear_type: query(d, QUERY_GEAR_TYPE(addr)).await?,
+        up_switch_on_threshold: query(d, QUERY_UP_SWITCH_ON_THRESHOLD(addr)).await?,
+        up_switch_off_threshold: query(d, QUERY_UP_SWITCH_OFF_THRESHOLD(addr)).await?,
+        down_switch_on_threshold: query(d, QUERY_DOWN_SWITCH_ON_THRESHOLD(addr)).await?,
+        down_switch_off_threshold: query(d, QUERY_DOWN_SWITCH_OFF_THRESHOLD(addr)).await?,
+        error_hold_off_time: query(d, QUERY_ERROR_HOLD_OFF_TIME(addr)).await?,
+        extended_version: query(d, QUERY_EXTENDED_VERSION_NUMBER(addr)).await?,
+    })
+}
+
+/// Store a threshold level. `cmd` is one of the STORE_DTR_AS_*_THRESHOLD
+/// commands. MASK disables the threshold.
+pub async fn store_threshold(
+    d: &mut dyn DaliDriver,
+    cmd: fn(Address) -> Command<false, true>,
+    addr: Address,
+    level: u8,
+) -> Result<(), DaliSendResult> {
+    send16::set_dtr0(d, level, NO_FLAG).await.check_send()?;
+    send16::device_type_cmd(d, device_type::SWITCHING, cmd(addr), NO_FLAG)
+        .await
+        .check_send()
+}
+
+/// Set the error hold-off time in seconds
+pub async fn set_error_hold_off_time(
+    d: &mut dyn DaliDriver,
+    addr: Address,
+    time: u8,
+) -> Result<(), DaliSendResult> {
+    send16::set_dtr0(d, time, NO_FLAG).await.check_send()?;
+    send16::device_type_cmd(
+        d,
+        device_type::SWITCHING,
+        STORE_DTR_AS_ERROR_HOLD_OFF_TIME(addr),
+        NO_FLAG,
+    )
+    .await
+    .check_send()
+}
@@ -0,0 +1,267 @@
+//! Colour control gear, device type 8 (IEC 62386-209)
+//!
+//! The commands are application extended commands and must be preceded
+//! by ENABLE DEVICE TYPE 8. [`Commands209`] does this for every command it
+//! sends.
+
+use crate::common::cmd_defs::AddressByte;
+use crate::drivers::command_utils::send16;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::drivers::send_flags::{Flags, PRIORITY_DEFAULT};
+use crate::gear::address::{Address, Short};
+use crate::gear::cmd_defs::{self, Command, cmd_type, dev_cmd_def};
+use crate::gear::device_type::types as device_type;
+use crate::light_control::colored_light::{ColoredLight, LightValue};
+
+dev_cmd_def!(SET_TEMPORARY_X_COORDINATE, 0xe0);
+dev_cmd_def!(SET_TEMPORARY_Y_COORDINATE, 0xe1);
+dev_cmd_def!(ACTIVATE, 0xe2);
+dev_cmd_def!(X_COORDINATE_STEP_UP, 0xe3);
+dev_cmd_def!(X_COORDINATE_STEP_DOWN, 0xe4);
+dev_cmd_def!(Y_COORDINATE_STEP_UP, 0xe5);
+dev_cmd_def!(Y_COORDINATE_STEP_DOWN, 0xe6);
+dev_cmd_def!(SET_TEMPORARY_COLOUR_TEMPERATURE, 0xe7);
+dev_cmd_def!(COLOUR_TEMPERATURE_STEP_COOLER, 0xe8);
+dev_cmd_def!(COLOUR_TEMPERATURE_STEP_WARMER, 0xe9);
+dev_cmd_def!(SET_TEMPORARY_PRIMARY_N_DIMLEVEL, 0xea);
+dev_cmd_def!(SET_TEMPORARY_RGB_DIMLEVEL, 0xeb);
+dev_cmd_def!(SET_TEMPORARY_WAF_DIMLEVEL, 0xec);
+dev_cmd_def!(SET_TEMPORARY_RGBWAF_CONTROL, 0xed);
+dev_cmd_def!(COPY_REPORT_TO_TEMPORARY, 0xee);
+
+dev_cmd_def!(STORE_TY_PRIMARY_N, 0xf0, Twice);
+dev_cmd_def!(STORE_XY_COORDINATE_PRIMARY_N, 0xf1, Twice);
+dev_cmd_def!(STORE_COLOUR_TEMPERATURE_LIMIT, 0xf2, Twice);
+dev_cmd_def!(STORE_GEAR_FEATURES_STATUS, 0xf3, Twice);
+dev_cmd_def!(ASSIGN_COLOUR_TO_LINKED_CHANNEL, 0xf5, Twice);
+dev_cmd_def!(START_AUTO_CALIBRATION, 0xf6, Twice);
+
+dev_cmd_def!(QUERY_GEAR_FEATURES_STATUS, 0xf7, Answer);
+dev_cmd_def!(QUERY_COLOUR_STATUS, 0xf8, Answer);
+dev_cmd_def!(QUERY_COLOUR_TYPE_FEATURES, 0xf9, Answer);
+dev_cmd_def!(QUERY_COLOUR_VALUE, 0xfa, Answer);
+dev_cmd_def!(QUERY_RGBWAF_CONTROL, 0xfb, Answer);
+dev_cmd_def!(QUERY_ASSIGNED_COLOUR, 0xfc, Answer);
+pub use cmd_defs::QUERY_EXTENDED_VERSION_NUMBER;
+
+/// Colour types as used by QUERY COLOUR STATUS and QUERY COLOUR TYPE
+/// FEATURES. The features are reported shifted 4 bits to the right.
+pub mod colour_type {
+    pub const XY: u8 = 0x10;
+    pub const TC: u8 = 0x20;
+    pub const PRIMARY_N: u8 = 0x40;
+    pub const RGBWAF: u8 = 0x80;
+}
+
+/// Values selected by DTR0 for QUERY COLOUR VALUE
+pub mod colour_value {
+    pub const X_COORDINATE: u8 = 0;
+    pub const Y_COORDINATE: u8 = 1;
+    pub const COLOUR_TEMPERATURE: u8 = 2;
+    pub const NUMBER_OF_PRIMARIES: u8 = 82;
+    pub const TC_COOLEST: u8 = 128;
+    pub const TC_PHYSICAL_COOLEST: u8 = 129;
+    pub const TC_WARMEST: u8 = 130;
+    pub const TC_PHYSICAL_WARMEST: u8 = 131;
+    pub const TEMPORARY_X_COORDINATE: u8 = 192;
+    pub const TEMPORARY_Y_COORDINATE: u8 = 193;
+    pub const TEMPORARY_COLOUR_TEMPERATURE: u8 = 194;
+    pub const TEMPORARY_COLOUR_TYPE: u8 = 208;
+    pub const REPORT_X_COORDINATE: u8 = 224;
+    pub const REPORT_Y_COORDINATE: u8 = 225;
+    pub const REPORT_COLOUR_TEMPERATURE: u8 = 226;
+    pub const REPORT_COLOUR_TYPE: u8 = 240;
+}
+
+/// Convert a colour temperature in Kelvin to mirek
+pub fn kelvin_to_mirek(kelvin: u32) -> u16 {
+    if kelvin == 0 {
+        return 0xfffe;
+    }
+    (1_000_000 / kelvin).clamp(1, 0xfffe) as u16
+}
+
+/// Convert a CIE 1931 coordinate, 0.0 - 1.0, to the 16-bit DALI
+/// representation
+pub fn coordinate_to_u16(c: f32) -> u16 {
+    (c * 65536.0).round().clamp(0.0, 65534.0) as u16
+}
+
+/// Convert a power in percent to an arc power level using the standard
+/// logarithmic dimming curve
+pub fn power_to_level(power: f32) -> u8 {
+    if power <= 0.0 {
+        return 0;
+    }
+    (1.0 + (power.log10() + 1.0) * 253.0 / 3.0)
+        .round()
+        .clamp(1.0, 254.0) as u8
+}
+
+pub struct Commands209<'a> {
+    driver: &'a mut dyn DaliDriver,
+    flags: Flags,
+}
+
+impl<'a> Commands209<'a> {
+    pub fn new(driver: &'a mut dyn DaliDriver) -> Self {
+        Commands209 {
+            driver,
+            flags: PRIORITY_DEFAULT,
+        }
+    }
+
+    /// Send a DT8 command
+    pub async fn cmd<const TWICE: bool>(
+        &mut self,
+        cmd: Command<false, TWICE>,
+    ) -> Result<(), DaliSendResult> {
+        send16::device_type_cmd(self.driver, device_type::COLOUR, cmd, self.flags.clone())
+            .await
+            .check_send()
+    }
+
+    /// Send a DT8 query
+    pub async fn query(&mut self, cmd: Command<true, false>) -> Result<u8, DaliSendResult> {
+        send16::device_type_query(self.driver, device_type::COLOUR, cmd, self.flags.clone())
+            .await
+            .check_answer()
+    }
+
+    async fn set_dtr16(&mut self, value: u16) -> Result<(), DaliSendResult> {
+        send16::set_dtr0(self.driver, (value & 0xff) as u8, self.flags.clone())
+            .await
+            .check_send()?;
+        send16::set_dtr1(self.driver, (value >> 8) as u8, self.flags.clone())
+            .await
+            .check_send()
+    }
+
+    /// Set the temporary colour temperature in mirek
+    pub async fn set_temporary_colour_temperature(
+        &mut self,
+        addr: Address,
+        mirek: u16,
+    ) -> Result<(), DaliSendResult> {
+        self.set_dtr16(mirek).await?;
+        self.cmd(SET_TEMPORARY_COLOUR_TEMPERATURE(addr)).await
+    }
+
+    /// Set the temporary colour temperature in Kelvin
+    pub async fn set_temporary_colour_temperature_kelvin(
+        &mut self,
+        addr: Address,
+        kelvin: u32,
+    ) -> Result<(), DaliSendResult> {
+        self.set_temporary_colour_temperature(addr, kelvin_to_mirek(kelvin))
+            .await
+    }
+
+    /// Set the temporary CIE 1931 xy-coordinate
+    pub async fn set_temporary_xy(
+        &mut self,
+        addr: Address,
+        x: f32,
+        y: f32,
+    ) -> Result<(), DaliSendResult> {
+        self.set_dtr16(coordinate_to_u16(x)).await?;
+        self.cmd(SET_TEMPORARY_X_COORDINATE(addr)).await?;
+        self.set_dtr16(coordinate_to_u16(y)).await?;
+        self.cmd(SET_TEMPORARY_Y_COORDINATE(addr)).await
+    }
+
+    /// Start a transition to the temporary colour
+    pub async fn activate(&mut self, addr: Address) -> Result<(), DaliSendResult> {
+        self.cmd(ACTIVATE(addr)).await
+    }
+
+    /// Query a 16-bit colour value selected by one of the [`colour_value`]
+    /// constants. 0xffff means that the value is unknown or not
+    /// applicable.
+    pub async fn query_colour_value(
+        &mut self,
+        addr: Short,
+        selector: u8,
+    ) -> Result<u16, DaliSendResult> {
+        send16::set_dtr0(self.driver, selector, self.flags.clone())
+            .await
+            .check_send()?;
+        let msb = self.query(QUERY_COLOUR_VALUE(addr)).await?;
+        // The LSB is put in DTR0
+        let lsb = send16::query(
+            self.driver,
+            cmd_defs::QUERY_CONTENT_DTR0(addr),
+            self.flags.clone(),
+        )
+        .await
+        .check_answer()?;
+        Ok(u16::from(msb) << 8 | u16::from(lsb))
+    }
+
+    /// Supported colour types, see [`colour_type`]
+    pub async fn query_colour_type_features(&mut self, addr: Short) -> Result<u8, DaliSendResult> {
+        self.query(QUERY_COLOUR_TYPE_FEATURES(addr)).await
+    }
+
+    /// Active colour type in bit 4-7 and out of range flags in bit 0-1
+    pub async fn query_colour_status(&mut self, addr: Short) -> Result<u8, DaliSendResult> {
+        self.query(QUERY_COLOUR_STATUS(addr)).await
+    }
+}
+
+/// Set intensity and colour of a gear.
+///
+/// The colour is set in the temporary registers first. The arc power
+/// command and ACTIVATE then follow each other, so that both fades start
+/// together. The sequence isn't atomic on the bus: other bus masters, or
+/// other clients of a [`SharedDriver`], may send frames in between. Run it
+/// in a [`SharedDriver::transaction`] to keep other clients out.
+///
+/// [`SharedDriver`]: crate::drivers::shared::SharedDriver
+/// [`SharedDriver::transaction`]: crate::drivers::shared::SharedDriver::transaction
+pub async fn set_light_value(
+    driver: &mut dyn DaliDriver,
+    addr: Address,
+    value: &LightValue,
+) -> Result<(), DaliSendResult> {
+    let mut commands = Commands209::new(driver);
+    match value.color {
+        ColoredLight::None => {}
+        ColoredLight::ColorTemp { kelvin } => {
+            commands
+                .set_temporary_colour_temperature_kelvin(addr, kelvin)
+                .await?
+        }
+        ColoredLight::Coordinate { x, y } => commands.set_temporary_xy(addr, x, y).await?,
+    }
+    send16::device_level(
+        commands.driver,
+        addr,
+        power_to_level(value.power),
+        commands.flags.clone(),
+    )
+    .await
+    .check_send()?;
+    if value.color != ColoredLight::None {
+        commands.activate(addr).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(kelvin_to_mirek(4000), 250);
+        assert_eq!(kelvin_to_mirek(0), 0xfffe);
+        assert_eq!(coordinate_to_u16(0.5), 0x8000);
+        assert_eq!(coordinate_to_u16(1.0), 0xfffe);
+        assert_eq!(power_to_level(0.0), 0);
+        assert_eq!(power_to_level(0.1), 1);
+        assert_eq!(power_to_level(100.0), 254);
+        assert_eq!(power_to_level(1.0), 85);
+        assert_eq!(ACTIVATE(Short::new(3)).0, [0x07, 0xe2], "Command encoding");
+    }
+}
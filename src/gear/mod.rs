@@ -2,5 +2,9 @@ pub mod address;
 pub mod cmd_defs;
 pub mod commands_102;
 pub mod device_type;
+pub mod dt1;
+pub mod dt6;
+pub mod dt7;
+pub mod dt8;
 pub mod light_source;
 pub mod status;
@@ -1,5 +1,10 @@
 use std::fmt;
 
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+
+/// Answer to QUERY STATUS, serialized as the raw status byte
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
 pub struct GearStatus(u8);
 
 pub mod flag {
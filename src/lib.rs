@@ -6,13 +6,24 @@ pub mod error;
 pub mod utils {
     pub mod address_assignment;
     pub mod address_set;
+    pub mod bus_stats;
+    #[cfg(feature = "serde")]
+    pub mod capture;
     pub mod decode;
     pub mod device_info;
     pub mod discover;
     pub mod dyn_future;
     pub mod filtered_vec;
+    pub mod frame_filter;
+    #[cfg(feature = "serde")]
+    pub mod frame_printer;
     pub mod long_address;
     pub mod memory_banks;
+    #[cfg(feature = "serde")]
+    pub mod output;
+    pub mod pcapng;
+    pub mod replace;
+    pub mod snapshot;
 }
 
 pub mod drivers;
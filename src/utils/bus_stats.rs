@@ -0,0 +1,434 @@
+//! Statistics of bus traffic over a sliding window.
+//!
+//! Feed every event from [`DaliDriver::next_bus_event`] to
+//! [`BusStats::update`] and call [`BusStats::summary`] whenever a report
+//! is needed. Only events within the window are included in the summary.
+//!
+//! [`DaliDriver::next_bus_event`]: crate::drivers::driver::DaliDriver::next_bus_event
+
+use crate::drivers::driver::{DaliBusEvent, DaliBusEventType, DaliFrame};
+use crate::drivers::timing;
+use crate::utils::decode::{CMD_DESCR_16, DecodedFrame, DecoderState, FrameAddress};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Type of a forward frame, without parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommandKey {
+    ArcPower,
+    Gear { opcode: u8, device_type: Option<u8> },
+    GearSpecial { opcode: u8 },
+    Device { opcode: u8 },
+    Instance { opcode: u8 },
+    DeviceSpecial { opcode: u8 },
+    Event,
+    Frame25,
+    Unknown,
+}
+
+impl CommandKey {
+    fn from_frame(frame: &DecodedFrame) -> (Option<FrameAddress>, CommandKey) {
+        match frame {
+            DecodedFrame::ArcPower { address, .. } => (Some(*address), CommandKey::ArcPower),
+            DecodedFrame::GearCommand {
+                address,
+                opcode,
+                device_type,
+                ..
+            } => (
+                Some(*address),
+                CommandKey::Gear {
+                    opcode: *opcode,
+                    device_type: *device_type,
+                },
+            ),
+            DecodedFrame::GearSpecial { opcode, .. } => {
+                (None, CommandKey::GearSpecial { opcode: *opcode })
+            }
+            DecodedFrame::DeviceCommand {
+                address, opcode, ..
+            } => (Some(*address), CommandKey::Device { opcode: *opcode }),
+            DecodedFrame::InstanceCommand {
+                address, opcode, ..
+            } => (Some(*address), CommandKey::Instance { opcode: *opcode }),
+            DecodedFrame::DeviceSpecial { data, .. } => {
+                (None, CommandKey::DeviceSpecial { opcode: data[1] })
+            }
+            DecodedFrame::Event { .. } => (None, CommandKey::Event),
+            DecodedFrame::Frame25 { .. } => (None, CommandKey::Frame25),
+            _ => (None, CommandKey::Unknown),
+        }
+    }
+}
+
+impl fmt::Display for CommandKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandKey::ArcPower => write!(f, "Direct arc power"),
+            CommandKey::Gear {
+                opcode,
+                device_type: Some(dt),
+            } => write!(
+                f,
+                "Application extended command 0x{:02x} (device type {})",
+                opcode, dt
+            ),
+            CommandKey::Gear { opcode, .. } => match CMD_DESCR_16[usize::from(*opcode)] {
+                "" => write!(f, "Gear command 0x{:02x}", opcode),
+                name => write!(f, "{}", name),
+            },
+            CommandKey::GearSpecial { opcode } => {
+                write!(f, "Gear special command 0x{:02x}", opcode)
+            }
+            CommandKey::Device { opcode } => write!(f, "Device command 0x{:02x}", opcode),
+            CommandKey::Instance { opcode } => write!(f, "Instance command 0x{:02x}", opcode),
+            CommandKey::DeviceSpecial { opcode } => {
+                write!(f, "Device special command 0x{:02x}", opcode)
+            }
+            CommandKey::Event => write!(f, "Input event"),
+            CommandKey::Frame25 => write!(f, "25-bit frame"),
+            CommandKey::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QueryOutcome {
+    Answered,
+    Unanswered,
+    Collision,
+}
+
+#[derive(Debug, Clone)]
+enum Record {
+    Forward {
+        busy: Duration,
+        address: Option<FrameAddress>,
+        command: CommandKey,
+    },
+    Backward {
+        busy: Duration,
+    },
+    FramingError,
+    Query {
+        address: Option<FrameAddress>,
+        outcome: QueryOutcome,
+    },
+    BusPowerOff,
+    BusPowerOn,
+    Overrun,
+}
+
+/// Outcome of queries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    pub queries: u32,
+    pub unanswered: u32,
+    /// Answers with framing errors, usually several gears answering
+    pub collisions: u32,
+}
+
+impl QueryStats {
+    fn add(&mut self, outcome: QueryOutcome) {
+        self.queries += 1;
+        match outcome {
+            QueryOutcome::Answered => {}
+            QueryOutcome::Unanswered => self.unanswered += 1,
+            QueryOutcome::Collision => self.collisions += 1,
+        }
+    }
+
+    /// Fraction of the queries that weren't answered
+    pub fn unanswered_rate(&self) -> f32 {
+        rate(self.unanswered, self.queries)
+    }
+
+    /// Fraction of the queries answered by colliding frames
+    pub fn collision_rate(&self) -> f32 {
+        rate(self.collisions, self.queries)
+    }
+}
+
+fn rate(count: u32, total: u32) -> f32 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f32 / total as f32
+    }
+}
+
+/// Statistics for a window of bus traffic
+#[derive(Debug, Clone, Default)]
+pub struct BusSummary {
+    /// Time covered by the summary. Shorter than the window until enough
+    /// traffic has been seen.
+    pub period: Duration,
+    /// Fraction of the time the bus was busy sending frames
+    pub utilisation: f32,
+    pub forward_frames: u32,
+    pub backward_frames: u32,
+    pub framing_errors: u32,
+    /// Events lost by the driver
+    pub overruns: u32,
+    pub bus_power_off: u32,
+    pub bus_power_on: u32,
+    /// Number of forward frames sent to each address
+    pub addresses: BTreeMap<FrameAddress, u32>,
+    /// Number of forward frames of each type
+    pub commands: BTreeMap<CommandKey, u32>,
+    /// All queries
+    pub queries: QueryStats,
+    /// Queries sent to a short address, indexed by address
+    pub gear_queries: BTreeMap<u8, QueryStats>,
+}
+
+impl BusSummary {
+    /// Fraction of all received frames that had framing errors
+    pub fn framing_error_rate(&self) -> f32 {
+        rate(
+            self.framing_errors,
+            self.forward_frames + self.backward_frames + self.framing_errors,
+        )
+    }
+}
+
+impl fmt::Display for BusSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Last {:.0} s: utilisation {:.1}%, {} forward frames, {} backward frames",
+            self.period.as_secs_f32(),
+            self.utilisation * 100.0,
+            self.forward_frames,
+            self.backward_frames
+        )?;
+        writeln!(
+            f,
+            "Framing errors: {} ({:.1}%), overruns: {}, bus power off: {}, bus power on: {}",
+            self.framing_errors,
+            self.framing_error_rate() * 100.0,
+            self.overruns,
+            self.bus_power_off,
+            self.bus_power_on
+        )?;
+        writeln!(
+            f,
+            "Queries: {}, unanswered: {} ({:.1}%), collisions: {} ({:.1}%)",
+            self.queries.queries,
+            self.queries.unanswered,
+            self.queries.unanswered_rate() * 100.0,
+            self.queries.collisions,
+            self.queries.collision_rate() * 100.0
+        )?;
+        for (addr, stats) in &self.gear_queries {
+            if stats.unanswered > 0 || stats.collisions > 0 {
+                writeln!(
+                    f,
+                    "  Gear {}: {} queries, {} unanswered, {} collisions",
+                    addr, stats.queries, stats.unanswered, stats.collisions
+                )?;
+            }
+        }
+        writeln!(f, "Addresses:")?;
+        for (addr, count) in &self.addresses {
+            writeln!(f, "  {:>10}: {}", addr.to_string(), count)?;
+        }
+        writeln!(f, "Commands:")?;
+        for (cmd, count) in &self.commands {
+            writeln!(f, "  {}: {}", cmd, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects statistics from bus events
+pub struct BusStats {
+    window: Duration,
+    decoder: DecoderState,
+    records: VecDeque<(Instant, Record)>,
+    first_event: Option<Instant>,
+}
+
+impl BusStats {
+    /// Create statistics for the last `window` of traffic
+    pub fn new(window: Duration) -> BusStats {
+        BusStats {
+            window,
+            decoder: DecoderState::new(),
+            records: VecDeque::new(),
+            first_event: None,
+        }
+    }
+
+    fn add_decoded(&mut self, timestamp: Instant, frame: &DecodedFrame) {
+        let (query, outcome) = match frame {
+            DecodedFrame::Answer { query, .. } => (query, QueryOutcome::Answered),
+            DecodedFrame::NoAnswer { query } => (query, QueryOutcome::Unanswered),
+            DecodedFrame::AnswerCollision { query } => (query, QueryOutcome::Collision),
+            _ => return,
+        };
+        let (address, _) = CommandKey::from_frame(query);
+        self.records
+            .push_back((timestamp, Record::Query { address, outcome }));
+    }
+
+    /// Add an event. Events must be added in time order.
+    pub fn update(&mut self, event: &DaliBusEvent) {
+        let timestamp = event.timestamp;
+        self.first_event.get_or_insert(timestamp);
+        let decoded = self.decoder.decode_event(event);
+        for frame in &decoded {
+            self.add_decoded(timestamp, frame);
+        }
+        // Time the bus is busy sending the frame
+        let busy = DaliFrame::try_from(&event.event_type)
+            .map(|frame| timing::frame_duration(&frame))
+            .unwrap_or_default();
+        let record = match &event.event_type {
+            DaliBusEventType::Frame8(_) => Record::Backward { busy },
+            DaliBusEventType::Frame16(_)
+            | DaliBusEventType::Frame24(_)
+            | DaliBusEventType::Frame25(_) => {
+                let (address, command) = decoded
+                    .last()
+                    .map(CommandKey::from_frame)
+                    .unwrap_or((None, CommandKey::Unknown));
+                Record::Forward {
+                    busy,
+                    address,
+                    command,
+                }
+            }
+            DaliBusEventType::FramingError => Record::FramingError,
+            DaliBusEventType::BusPowerOff => Record::BusPowerOff,
+            DaliBusEventType::BusPowerOn => Record::BusPowerOn,
+            DaliBusEventType::Overrun => Record::Overrun,
+        };
+        self.records.push_back((timestamp, record));
+        self.prune(timestamp);
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((timestamp, _)) = self.records.front() {
+            if now.saturating_duration_since(*timestamp) <= self.window {
+                break;
+            }
+            self.records.pop_front();
+        }
+    }
+
+    /// Summarize the traffic of the window ending at `now`
+    pub fn summary(&mut self, now: Instant) -> BusSummary {
+        if let Some(frame) = self.decoder.expire(now) {
+            self.add_decoded(now, &frame);
+        }
+        self.prune(now);
+        let mut summary = BusSummary {
+            period: self
+                .first_event
+                .map(|first| now.saturating_duration_since(first).min(self.window))
+                .unwrap_or_default(),
+            ..BusSummary::default()
+        };
+        let mut busy = Duration::ZERO;
+        for (_, record) in &self.records {
+            match record {
+                Record::Forward {
+                    busy: b,
+                    address,
+                    command,
+                } => {
+                    busy += *b;
+                    summary.forward_frames += 1;
+                    if let Some(address) = address {
+                        *summary.addresses.entry(*address).or_default() += 1;
+                    }
+                    *summary.commands.entry(*command).or_default() += 1;
+                }
+                Record::Backward { busy: b } => {
+                    busy += *b;
+                    summary.backward_frames += 1;
+                }
+                Record::FramingError => summary.framing_errors += 1,
+                Record::Query { address, outcome } => {
+                    summary.queries.add(*outcome);
+                    if let Some(FrameAddress::Short(addr)) = address {
+                        summary.gear_queries.entry(*addr).or_default().add(*outcome);
+                    }
+                }
+                Record::BusPowerOff => summary.bus_power_off += 1,
+                Record::BusPowerOn => summary.bus_power_on += 1,
+                Record::Overrun => summary.overruns += 1,
+            }
+        }
+        if !summary.period.is_zero() {
+            summary.utilisation = (busy.as_secs_f32() / summary.period.as_secs_f32()).min(1.0);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let start = Instant::now();
+        let mut stats = BusStats::new(Duration::from_secs(10));
+        let mut add = |ms: u64, event_type: DaliBusEventType| {
+            stats.update(&DaliBusEvent {
+                timestamp: start + Duration::from_millis(ms),
+                event_type,
+            })
+        };
+        // Broadcast off, outside the window at the end
+        add(0, DaliBusEventType::Frame16([0xff, 0x00]));
+        // Query status of 1, answered
+        add(5_000, DaliBusEventType::Frame16([0x03, 0x90]));
+        add(5_010, DaliBusEventType::Frame8(0x05));
+        // Query status of 2, not answered
+        add(5_100, DaliBusEventType::Frame16([0x05, 0x90]));
+        // Query status of 2, collision
+        add(5_200, DaliBusEventType::Frame16([0x05, 0x90]));
+        add(5_210, DaliBusEventType::FramingError);
+        add(6_000, DaliBusEventType::BusPowerOff);
+        add(6_500, DaliBusEventType::BusPowerOn);
+        // Query status of 1, not answered when the summary is made
+        add(11_000, DaliBusEventType::Frame16([0x03, 0x90]));
+
+        let summary = stats.summary(start + Duration::from_secs(12));
+        assert_eq!(summary.period, Duration::from_secs(10));
+        assert_eq!(summary.forward_frames, 4);
+        assert_eq!(summary.backward_frames, 1);
+        assert_eq!(summary.framing_errors, 1);
+        assert_eq!(summary.bus_power_off, 1);
+        assert_eq!(summary.bus_power_on, 1);
+        assert_eq!(summary.addresses.get(&FrameAddress::Broadcast), None);
+        assert_eq!(summary.addresses.get(&FrameAddress::Short(1)), Some(&2));
+        assert_eq!(summary.addresses.get(&FrameAddress::Short(2)), Some(&2));
+        let query_status = CommandKey::Gear {
+            opcode: 0x90,
+            device_type: None,
+        };
+        assert_eq!(summary.commands.get(&query_status), Some(&4));
+        assert_eq!(query_status.to_string(), "Query status");
+        assert_eq!(
+            summary.queries,
+            QueryStats {
+                queries: 4,
+                unanswered: 2,
+                collisions: 1
+            }
+        );
+        assert_eq!(summary.gear_queries[&1].unanswered_rate(), 0.5);
+        assert_eq!(summary.gear_queries[&2].collision_rate(), 0.5);
+        assert_eq!(summary.framing_error_rate(), 1.0 / 6.0);
+        // 4 forward and 1 backward frame in 10 s. The backward frame ends
+        // with a 1 and so half a bit early.
+        let busy = 4 * timing::FRAME_16_DURATION + timing::FRAME_8_DURATION
+            - Duration::from_micros(timing::HALF_BIT_MICROS);
+        assert!((summary.utilisation - busy.as_secs_f32() / 10.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,343 @@
+//! Capture files with recorded bus traffic.
+//!
+//! A capture is a text file with one JSON object per line. The first line
+//! is a header with the [`CaptureInfo`]. Each following line is a bus
+//! event with the time since the start of the capture in microseconds.
+//!
+//! ```text
+//! {"format":"dali-capture","version":1,"driver":"dali_rpi","site":"Office"}
+//! {"t":0,"type":"frame16","data":[3,160]}
+//! {"t":14200,"type":"frame8","data":254}
+//! {"t":30000,"type":"bus_power_off"}
+//! ```
+
+use crate::drivers::driver::{DaliBusEvent, DaliBusEventType};
+use serde_derive::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+const FORMAT_NAME: &str = "dali-capture";
+const FORMAT_VERSION: u32 = 1;
+
+/// Metadata describing a capture
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CaptureInfo {
+    /// Name of the driver used for capturing
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub driver: String,
+    /// Free text describing the site or installation
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub site: String,
+    /// Wall clock time when the capture started, in milliseconds since
+    /// the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time_ms: Option<u64>,
+}
+
+impl CaptureInfo {
+    /// Create metadata with the start time set to the current time
+    pub fn new(driver: &str, site: &str) -> CaptureInfo {
+        CaptureInfo {
+            driver: driver.to_string(),
+            site: site.to_string(),
+            start_time_ms: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .ok()
+                .map(|t| t.as_millis() as u64),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    info: CaptureInfo,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum RecordEvent {
+    Frame8(u8),
+    Frame16([u8; 2]),
+    Frame24([u8; 3]),
+    Frame25([u8; 4]),
+    FramingError,
+    BusPowerOff,
+    BusPowerOn,
+    Overrun,
+}
+
+impl From<&DaliBusEventType> for RecordEvent {
+    fn from(event_type: &DaliBusEventType) -> Self {
+        match *event_type {
+            DaliBusEventType::Frame8(f) => RecordEvent::Frame8(f),
+            DaliBusEventType::Frame16(f) => RecordEvent::Frame16(f),
+            DaliBusEventType::Frame24(f) => RecordEvent::Frame24(f),
+            DaliBusEventType::Frame25(f) => RecordEvent::Frame25(f),
+            DaliBusEventType::FramingError => RecordEvent::FramingError,
+            DaliBusEventType::BusPowerOff => RecordEvent::BusPowerOff,
+            DaliBusEventType::BusPowerOn => RecordEvent::BusPowerOn,
+            DaliBusEventType::Overrun => RecordEvent::Overrun,
+        }
+    }
+}
+
+impl From<RecordEvent> for DaliBusEventType {
+    fn from(record: RecordEvent) -> Self {
+        match record {
+            RecordEvent::Frame8(f) => DaliBusEventType::Frame8(f),
+            RecordEvent::Frame16(f) => DaliBusEventType::Frame16(f),
+            RecordEvent::Frame24(f) => DaliBusEventType::Frame24(f),
+            RecordEvent::Frame25(f) => DaliBusEventType::Frame25(f),
+            RecordEvent::FramingError => DaliBusEventType::FramingError,
+            RecordEvent::BusPowerOff => DaliBusEventType::BusPowerOff,
+            RecordEvent::BusPowerOn => DaliBusEventType::BusPowerOn,
+            RecordEvent::Overrun => DaliBusEventType::Overrun,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// Microseconds since the start of the capture
+    t: u64,
+    #[serde(flatten)]
+    event: RecordEvent,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    /// The file doesn't start with a capture header
+    NotACapture,
+    UnsupportedVersion(u32),
+    /// A line couldn't be parsed
+    Syntax {
+        line: usize,
+        error: serde_json::Error,
+    },
+}
+
+impl Error for CaptureError {}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Io(e) => write!(f, "I/O error: {}", e),
+            CaptureError::NotACapture => write!(f, "Not a capture file"),
+            CaptureError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported capture format version {}", v)
+            }
+            CaptureError::Syntax { line, error } => write!(f, "Line {}: {}", line, error),
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(e: io::Error) -> Self {
+        CaptureError::Io(e)
+    }
+}
+
+/// Writes bus events to a capture.
+///
+/// ```no_run
+/// # use dali_tools::drivers::driver::DaliDriver;
+/// # use dali_tools::utils::capture::{CaptureInfo, CaptureWriter};
+/// # async fn record(driver: &mut dyn DaliDriver) -> std::io::Result<()> {
+/// let file = std::fs::File::create("bus.capture")?;
+/// let info = CaptureInfo::new("default", "Office");
+/// let mut capture = CaptureWriter::new(file, &info, driver.current_timestamp())?;
+/// while let Ok(event) = driver.next_bus_event().await {
+///     capture.write_event(&event)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    start: Instant,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Write the header. The times of the events are relative to `start`.
+    pub fn new(mut writer: W, info: &CaptureInfo, start: Instant) -> io::Result<Self> {
+        let header = Header {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            info: info.clone(),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        Ok(CaptureWriter { writer, start })
+    }
+
+    /// Append an event. Events before the start are recorded at time 0.
+    pub fn write_event(&mut self, event: &DaliBusEvent) -> io::Result<()> {
+        let record = Record {
+            t: event
+                .timestamp
+                .saturating_duration_since(self.start)
+                .as_micros() as u64,
+            event: RecordEvent::from(&event.event_type),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// An event read from a capture
+#[derive(Debug, Clone)]
+pub struct CapturedEvent {
+    /// Time since the start of the capture
+    pub offset: Duration,
+    pub event_type: DaliBusEventType,
+}
+
+impl CapturedEvent {
+    /// Convert to a bus event for a capture starting at `start`
+    pub fn to_bus_event(&self, start: Instant) -> DaliBusEvent {
+        DaliBusEvent {
+            timestamp: start + self.offset,
+            event_type: self.event_type.clone(),
+        }
+    }
+}
+
+/// Reads a capture. The events are returned by iterating over the reader.
+pub struct CaptureReader<R: BufRead> {
+    lines: io::Lines<R>,
+    line: usize,
+    info: CaptureInfo,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    /// Read the header of the capture
+    pub fn new(reader: R) -> Result<Self, CaptureError> {
+        let mut lines = reader.lines();
+        let first = lines.next().ok_or(CaptureError::NotACapture)??;
+        let header: Header = serde_json::from_str(&first).map_err(|_| CaptureError::NotACapture)?;
+        if header.format != FORMAT_NAME {
+            return Err(CaptureError::NotACapture);
+        }
+        if header.version != FORMAT_VERSION {
+            return Err(CaptureError::UnsupportedVersion(header.version));
+        }
+        Ok(CaptureReader {
+            lines,
+            line: 1,
+            info: header.info,
+        })
+    }
+
+    pub fn info(&self) -> &CaptureInfo {
+        &self.info
+    }
+}
+
+impl<R: BufRead> Iterator for CaptureReader<R> {
+    type Item = Result<CapturedEvent, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.line += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                serde_json::from_str::<Record>(&line)
+                    .map(|record| CapturedEvent {
+                        offset: Duration::from_micros(record.t),
+                        event_type: record.event.into(),
+                    })
+                    .map_err(|error| CaptureError::Syntax {
+                        line: self.line,
+                        error,
+                    }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let start = Instant::now();
+        let info = CaptureInfo {
+            driver: "simulator".to_string(),
+            site: "Lab".to_string(),
+            start_time_ms: Some(1_700_000_000_000),
+        };
+        let mut writer = CaptureWriter::new(Vec::new(), &info, start).unwrap();
+        let events = [
+            (0, DaliBusEventType::Frame16([0x03, 0xa0])),
+            (14_200, DaliBusEventType::Frame8(0xfe)),
+            (20_000, DaliBusEventType::FramingError),
+            (30_000, DaliBusEventType::BusPowerOff),
+            (40_000, DaliBusEventType::Frame25([1, 2, 3, 0x80])),
+        ];
+        for (t, event_type) in &events {
+            writer
+                .write_event(&DaliBusEvent {
+                    timestamp: start + Duration::from_micros(*t),
+                    event_type: event_type.clone(),
+                })
+                .unwrap();
+        }
+        let data = writer.into_inner();
+        let text = String::from_utf8(data.clone()).unwrap();
+        assert!(
+            text.contains("\n{\"t\":14200,\"type\":\"frame8\",\"data\":254}\n"),
+            "{}",
+            text
+        );
+
+        let reader = CaptureReader::new(data.as_slice()).unwrap();
+        assert_eq!(reader.info(), &info);
+        let read: Vec<CapturedEvent> = reader.map(|e| e.unwrap()).collect();
+        assert_eq!(read.len(), events.len());
+        for (r, (t, event_type)) in read.iter().zip(events.iter()) {
+            assert_eq!(r.offset, Duration::from_micros(*t));
+            assert_eq!(format!("{:?}", r.event_type), format!("{:?}", event_type));
+        }
+    }
+
+    #[test]
+    fn bad_input() {
+        assert!(matches!(
+            CaptureReader::new(&b"{\"t\":0,\"type\":\"overrun\"}\n"[..]),
+            Err(CaptureError::NotACapture)
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"{\"format\":\"dali-capture\",\"version\":2}\n"[..]),
+            Err(CaptureError::UnsupportedVersion(2))
+        ));
+        let mut reader =
+            CaptureReader::new(&b"{\"format\":\"dali-capture\",\"version\":1}\n\n{\"t\":1}\n"[..])
+                .unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(CaptureError::Syntax { line: 3, .. }))
+        ));
+        assert!(reader.next().is_none());
+    }
+}
@@ -1,15 +1,193 @@
+use crate::drivers::driver::{DaliBusEvent, DaliBusEventType, DaliFrame};
+use crate::drivers::timing::{self, ANSWER_TIMEOUT};
+use crate::gear::device_type::{DeviceType, types as device_type};
+use crate::gear::status::GearStatus;
+use crate::utils::device_info::fmt_bitflags;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
 
-trait Decoder16 {
+/// Decodes application extended commands (0xe0-0xfe) for one device type.
+///
+/// A decoder is used for a command that follows ENABLE DEVICE TYPE with
+/// the device type it is registered for.
+pub trait Decoder16: Send {
+    /// Describe the command. `pkt` is the complete forward frame, with
+    /// the address in the first byte.
     fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String;
+
+    /// True if the command is answered by a backward frame
+    fn is_query(&self, _opcode: u8) -> bool {
+        false
+    }
+
+    /// Describe the answer to a query. Returns None if the value has no
+    /// special meaning.
+    fn decode_answer(&self, _opcode: u8, _answer: u8) -> Option<String> {
+        None
+    }
+}
+
+struct PendingQuery {
+    // End of the forward frame
+    end: Instant,
+    query: DecodedFrame,
 }
 
 pub struct DecoderState {
     dtr: [u8; 3],
-    extended: Option<Box<dyn Decoder16>>, // Use for next device type specific command
+    used_dtr: Cell<u8>,       // Bit mask of DTRs read while decoding a command
+    enabled_type: Option<u8>, // Use for next device type specific command
+    decoders: HashMap<u8, Box<dyn Decoder16>>,
+    pending: Option<PendingQuery>, // Query waiting for an answer
+}
+
+/// A decoded frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "frame", rename_all = "snake_case")
+)]
+pub enum DecodedFrame {
+    /// Direct arc power control (16-bit)
+    ArcPower { address: FrameAddress, level: u8 },
+    /// Control gear command (16-bit)
+    GearCommand {
+        address: FrameAddress,
+        opcode: u8,
+        /// Set for application extended commands following ENABLE
+        /// DEVICE TYPE
+        device_type: Option<u8>,
+        /// Value of DTR0, DTR1 and DTR2 if used by the command
+        dtr: [Option<u8>; 3],
+        description: String,
+    },
+    /// Control gear special command (16-bit)
+    GearSpecial {
+        opcode: u8,
+        data: u8,
+        description: String,
+    },
+    /// Control device command (24-bit)
+    DeviceCommand {
+        address: FrameAddress,
+        opcode: u8,
+        description: String,
+    },
+    /// Control device instance command (24-bit)
+    InstanceCommand {
+        address: FrameAddress,
+        instance: u8,
+        opcode: u8,
+        description: String,
+    },
+    /// Control device special command (24-bit)
+    DeviceSpecial { data: [u8; 3], description: String },
+    /// Input device event (24-bit)
+    Event { source: EventSource, value: u16 },
+    /// Backward frame answering the preceding query
+    Answer {
+        query: Box<DecodedFrame>,
+        value: u8,
+        description: String,
+    },
+    /// The query wasn't answered within [`ANSWER_TIMEOUT`] from the end of
+    /// the forward frame
+    NoAnswer { query: Box<DecodedFrame> },
+    /// A framing error during the answer, usually several gears answering
+    AnswerCollision { query: Box<DecodedFrame> },
+    /// Backward frame without a preceding query
+    Backward { value: u8 },
+    /// 25-bit frame. These are reserved and only used for proprietary
+    /// purposes.
+    Frame25 { value: u32 },
+    /// Frame of unsupported length
+    Unknown { data: Vec<u8> },
+}
+
+impl DecodedFrame {
+    /// All names returned by [`DecodedFrame::type_name`]
+    pub const TYPE_NAMES: [&'static str; 13] = [
+        "arc_power",
+        "gear_command",
+        "gear_special",
+        "device_command",
+        "instance_command",
+        "device_special",
+        "event",
+        "answer",
+        "no_answer",
+        "answer_collision",
+        "backward",
+        "frame25",
+        "unknown",
+    ];
+
+    /// Name of the frame type, the same as the `frame` tag of the
+    /// serialized frame
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DecodedFrame::ArcPower { .. } => "arc_power",
+            DecodedFrame::GearCommand { .. } => "gear_command",
+            DecodedFrame::GearSpecial { .. } => "gear_special",
+            DecodedFrame::DeviceCommand { .. } => "device_command",
+            DecodedFrame::InstanceCommand { .. } => "instance_command",
+            DecodedFrame::DeviceSpecial { .. } => "device_special",
+            DecodedFrame::Event { .. } => "event",
+            DecodedFrame::Answer { .. } => "answer",
+            DecodedFrame::NoAnswer { .. } => "no_answer",
+            DecodedFrame::AnswerCollision { .. } => "answer_collision",
+            DecodedFrame::Backward { .. } => "backward",
+            DecodedFrame::Frame25 { .. } => "frame25",
+            DecodedFrame::Unknown { .. } => "unknown",
+        }
+    }
 }
 
-const CMD_DESCR_16: [&str; 256] = [
+impl fmt::Display for DecodedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodedFrame::ArcPower { address, level } => {
+                write!(f, "{}: Set power = {}", address, level)
+            }
+            DecodedFrame::GearCommand {
+                address,
+                description,
+                ..
+            }
+            | DecodedFrame::DeviceCommand {
+                address,
+                description,
+                ..
+            }
+            | DecodedFrame::InstanceCommand {
+                address,
+                description,
+                ..
+            } => write!(f, "{}: {}", address, description),
+            DecodedFrame::GearSpecial { description, .. }
+            | DecodedFrame::DeviceSpecial { description, .. } => f.write_str(description),
+            DecodedFrame::Event { source, value } => {
+                write!(f, "({}): {} (0x{:03x})", source, value, value)
+            }
+            DecodedFrame::Answer {
+                query, description, ..
+            } => write!(f, "{} → {}", query, description),
+            DecodedFrame::NoAnswer { query } => write!(f, "{} → no answer", query),
+            DecodedFrame::AnswerCollision { query } => write!(f, "{} → collision", query),
+            DecodedFrame::Backward { value } => write!(f, "Answer {} (0x{:02x})", value, value),
+            DecodedFrame::Frame25 { value } => write!(f, "25-bit frame 0x{:07x}", value),
+            DecodedFrame::Unknown { .. } => write!(f, "Unhandled packet length"),
+        }
+    }
+}
+
+pub(crate) const CMD_DESCR_16: [&str; 256] = [
     "Off",
     "Up",
     "Down",
@@ -348,52 +526,64 @@ fn instance_cmd_descr_24(cmd: u8) -> &'static str {
     }
 }
 
-fn decode_addr(addr: u8) -> String {
+/// Address part of a command frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "value", rename_all = "snake_case")
+)]
+pub enum FrameAddress {
+    Short(u8),
+    Group(u8),
+    Broadcast,
+    Unaddressed,
+    /// Group address with reserved bits set
+    IllegalGroup,
+}
+
+impl fmt::Display for FrameAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameAddress::Short(a) => write!(f, "Addr: {}", a),
+            FrameAddress::Group(g) => write!(f, "Group: {}", g),
+            FrameAddress::Broadcast => write!(f, "Broadcast"),
+            FrameAddress::Unaddressed => write!(f, "Unaddressed"),
+            FrameAddress::IllegalGroup => write!(f, "Illegal group address"),
+        }
+    }
+}
+
+fn decode_addr(addr: u8) -> FrameAddress {
     if addr & 0xfe == 0xfe {
-        // Broadcast
-        "Broadcast".to_string()
+        FrameAddress::Broadcast
     } else if addr & 0xfe == 0xfc {
-        // Broadcast unaddressed
-        "Unaddressed".to_string()
+        FrameAddress::Unaddressed
     } else if addr & 0x80 != 0 {
-        // Group
         if addr & 0x60 != 0 {
-            "Illegal group address".to_string()
+            FrameAddress::IllegalGroup
         } else {
-            format!("Group: {}", (addr >> 1) & 0x0f)
+            FrameAddress::Group((addr >> 1) & 0x0f)
         }
     } else {
-        /* Address */
-        format!("Addr: {}", (addr >> 1) & 0x3f)
+        FrameAddress::Short((addr >> 1) & 0x3f)
     }
 }
 
-fn decode_cmd_addr_24bit(addr: u8) -> Option<String> {
+fn decode_cmd_addr_24bit(addr: u8) -> Option<FrameAddress> {
     if addr & 0xfe == 0xfe {
-        // Broadcast
-        Some("Broadcast".to_string())
+        Some(FrameAddress::Broadcast)
     } else if addr & 0xfe == 0xfc {
-        // Broadcast unaddressed
-        Some("Unaddressed".to_string())
+        Some(FrameAddress::Unaddressed)
     } else if addr & 0xc0 == 0x80 {
-        // Group
-        Some(format!("Group: {}", (addr >> 1) & 0x0f))
+        Some(FrameAddress::Group((addr >> 1) & 0x0f))
     } else if (addr & 0x80) == 0x00 {
-        // Address
-        Some(format!("Addr: {}", (addr >> 1) & 0x3f))
+        Some(FrameAddress::Short((addr >> 1) & 0x3f))
     } else {
         None
     }
 }
 
-fn decode_device_command(cmd: u8) -> String {
-    device_cmd_descr_24(cmd).to_string()
-}
-
-fn decode_instance_command(cmd: u8) -> String {
-    instance_cmd_descr_24(cmd).to_string()
-}
-
 fn decode_instance_type(instance_type: u8) -> &'static str {
     match instance_type {
         1 => "Push button",
@@ -403,45 +593,88 @@ fn decode_instance_type(instance_type: u8) -> &'static str {
     }
 }
 
-fn source_device_addr(addr: u8) -> String {
-    format!("Device addr: {}, ", addr)
+/// Source of an input device event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", rename_all = "snake_case")
+)]
+pub enum EventSource {
+    Device { address: u8, instance_type: u8 },
+    DeviceInstance { address: u8, instance: u8 },
+    DeviceGroup { group: u8, instance_type: u8 },
+    DeviceGroupInstance { group: u8, instance: u8 },
+    InstanceGroup { group: u8, instance_type: u8 },
+    Reserved,
 }
 
-fn source_device_group(group: u8) -> String {
-    format!("Device group: {}, ", group)
-}
-
-fn source_instance(instance: u8) -> String {
-    format!("Instance: {}, ", instance)
-}
-
-fn source_instance_group(group: u8) -> String {
-    format!("Instance group: {}, ", group)
-}
-
-fn source_instance_type(instance_type: u8) -> String {
-    format!("Instance type: {}", decode_instance_type(instance_type))
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            EventSource::Device {
+                address,
+                instance_type,
+            } => write!(
+                f,
+                "Device addr: {}, Instance type: {}",
+                address,
+                decode_instance_type(instance_type)
+            ),
+            EventSource::DeviceInstance { address, instance } => {
+                write!(f, "Device addr: {}, Instance: {}", address, instance)
+            }
+            EventSource::DeviceGroup {
+                group,
+                instance_type,
+            } => write!(
+                f,
+                "Device group: {}, Instance type: {}",
+                group,
+                decode_instance_type(instance_type)
+            ),
+            EventSource::DeviceGroupInstance { group, instance } => {
+                write!(f, "Device group: {}, Instance: {}", group, instance)
+            }
+            EventSource::InstanceGroup {
+                group,
+                instance_type,
+            } => write!(
+                f,
+                "Instance group: {}, Instance type: {}",
+                group,
+                decode_instance_type(instance_type)
+            ),
+            EventSource::Reserved => write!(f, "Reserved"),
+        }
+    }
 }
 
-fn decode_event_source(source: &[u8; 2]) -> String {
-    if (source[0] & 1) != 0 {
-        return "Illegal event".to_string();
-    }
+fn decode_event_source(source: &[u8; 2]) -> EventSource {
     let source1 = (source[0] >> 1) & 0x3f;
     let source2 = (source[1] >> 2) & 0x1f;
     match (source[0] & 0xc1, source[1] & 0x80) {
-        (0x00, 0x00) | (0x40, 0x00) => {
-            source_device_addr(source1) + ", " + &source_instance_type(source2)
-        }
-        (0x00, 0x80) | (0x40, 0x80) => {
-            source_device_addr(source1) + ", " + &source_instance(source2)
-        }
-        (0x80, 0x00) => source_device_group(source1) + ", " + &source_instance_type(source2),
-        (0x80, 0x80) => source_device_group(source1) + ", " + &source_instance(source2),
-        (0xc0, 0x00) => {
-            source_instance_group(source1 & 0x1f) + ", " + &source_instance_type(source2)
-        }
-        _ => "Reserved".to_string(),
+        (0x00, 0x00) | (0x40, 0x00) => EventSource::Device {
+            address: source1,
+            instance_type: source2,
+        },
+        (0x00, 0x80) | (0x40, 0x80) => EventSource::DeviceInstance {
+            address: source1,
+            instance: source2,
+        },
+        (0x80, 0x00) => EventSource::DeviceGroup {
+            group: source1,
+            instance_type: source2,
+        },
+        (0x80, 0x80) => EventSource::DeviceGroupInstance {
+            group: source1,
+            instance: source2,
+        },
+        (0xc0, 0x00) => EventSource::InstanceGroup {
+            group: source1 & 0x1f,
+            instance_type: source2,
+        },
+        _ => EventSource::Reserved,
     }
 }
 
@@ -520,16 +753,12 @@ fn decode_special_command(pkt: &[u8; 3]) -> String {
     }
 }
 
-struct Dt8Decoder;
-
-impl Dt8Decoder {
-    fn new() -> Dt8Decoder {
-        Dt8Decoder
-    }
+fn value16(state: &DecoderState) -> u16 {
+    u16::from(state.dtr(0)) | u16::from(state.dtr(1)) << 8
 }
 
-fn value16(state: &DecoderState) -> u16 {
-    u16::from(state.dtr[0]) | u16::from(state.dtr[1]) << 8
+fn kelvin(mirek: u16) -> u32 {
+    1_000_000 / max(1, mirek) as u32
 }
 
 fn linked_status(ctrl: u8, mask: u8) -> &'static str {
@@ -540,204 +769,897 @@ fn linked_status(ctrl: u8, mask: u8) -> &'static str {
     }
 }
 
+const PRIMARY_COLOUR_NAMES: [&str; 6] = ["red", "green", "blue", "white", "amber", "freecolour"];
+
+fn colour_value_selector(selector: u8) -> String {
+    // Temporary and report values use the same layout as the actual values
+    let (prefix, base) = match selector {
+        192..=208 => ("temporary ", selector - 192),
+        224..=240 => ("report ", selector - 224),
+        _ => ("", selector),
+    };
+    let name = match base {
+        0 => "x-coordinate".to_string(),
+        1 => "y-coordinate".to_string(),
+        2 => "colour temperature".to_string(),
+        3..=8 => format!("primary {} dimlevel", base - 3),
+        9..=14 => format!("{} dimlevel", PRIMARY_COLOUR_NAMES[usize::from(base - 9)]),
+        15 => "RGBWAF control".to_string(),
+        16 if !prefix.is_empty() => "colour type".to_string(),
+        64..=81 if prefix.is_empty() => format!(
+            "{} primary {}",
+            ["x-coordinate", "y-coordinate", "TY"][usize::from((base - 64) % 3)],
+            (base - 64) / 3
+        ),
+        82 if prefix.is_empty() => "number of primaries".to_string(),
+        128 if prefix.is_empty() => "colour temperature coolest".to_string(),
+        129 if prefix.is_empty() => "colour temperature physical coolest".to_string(),
+        130 if prefix.is_empty() => "colour temperature warmest".to_string(),
+        131 if prefix.is_empty() => "colour temperature physical warmest".to_string(),
+        _ => return format!("unknown ({})", selector),
+    };
+    prefix.to_string() + &name
+}
+
+fn answer_value(value: u8) -> String {
+    format!("{} (0x{:02x})", value, value)
+}
+
+fn answer_yes(value: u8) -> String {
+    if value == 0xff {
+        "Yes".to_string()
+    } else {
+        answer_value(value)
+    }
+}
+
+fn gear_answer(opcode: u8, value: u8) -> String {
+    match opcode {
+        0x90 if value == 0 => answer_value(value),
+        0x90 => format!("{} (0x{:02x})", GearStatus::new(value), value),
+        0x91..=0x96 | 0x9b | 0xaa => answer_yes(value),
+        0x99 | 0xa7 => DeviceType::new(value).to_string(),
+        0xa0..=0xa4 | 0xb0..=0xbf if value == 0xff => "MASK".to_string(),
+        _ => answer_value(value),
+    }
+}
+
+/// Decoder for colour control gear, device type 8 (IEC 62386-209)
+pub struct Dt8Decoder;
+
 impl Decoder16 for Dt8Decoder {
     fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String {
-        if (pkt[0] & 1) != 0 {
-            match pkt[1] {
-                0xe0 => format!(
-                    "Set temporary x-coordinate ({:.4})",
-                    (value16(state) as f32) / 65536.0
-                ),
-                0xe1 => format!(
-                    "Set temporary y-coordinate ({:.4})",
-                    (value16(state) as f32) / 65536.0
-                ),
-                0xe2 => "Activate".to_string(),
-                0xe3 => "x-coordinate step up".to_string(),
-                0xe4 => "x-coordinate step down".to_string(),
-                0xe5 => "y-coordinate step up".to_string(),
-                0xe6 => "y-coordinate step down".to_string(),
-                0xe7 => format!(
-                    "Set temporary colour temperature ({} K)",
-                    1_000_000 / max(1, value16(state)) as u32
-                ),
-                0xe8 => "Colour temperature step cooler".to_string(),
-                0xe9 => "Colour temperature step warmer".to_string(),
-                0xea => format!(
-                    "Set temporary primary N dimlevel (primary {}: {:.4})",
-                    state.dtr[2],
-                    (1.0 / 65536.0) * (value16(state) as f32),
-                ),
-                0xeb => format!(
-                    "Set temporary RGB dimlevel (R: {}, G: {}, B: {})",
-                    state.dtr[0], state.dtr[1], state.dtr[2]
-                ),
-                0xec => format!(
-                    "Set temporary WAF dimlevel (W: {}, A: {}, F: {})",
-                    state.dtr[0], state.dtr[1], state.dtr[2]
-                ),
-                0xed => {
-                    format!("Set temporary RGBWAF control (R: {}, G: {}, B: {}, W: {}, A: {}, F: {}, Type: {})",
-			   linked_status(state.dtr[0], 0x01),
-			   linked_status(state.dtr[0], 0x02),
-			   linked_status(state.dtr[0], 0x04),
-			   linked_status(state.dtr[0], 0x08),
-			   linked_status(state.dtr[0], 0x10),
-			   linked_status(state.dtr[0], 0x20),
-			   ["Channel", "Colour", "Normalized colour", "Reserved"][(state.dtr[0] >>6) as usize],
-			   )
+        match pkt[1] {
+            0xe0 => format!(
+                "Set temporary x-coordinate ({:.4})",
+                (value16(state) as f32) / 65536.0
+            ),
+            0xe1 => format!(
+                "Set temporary y-coordinate ({:.4})",
+                (value16(state) as f32) / 65536.0
+            ),
+            0xe2 => "Activate".to_string(),
+            0xe3 => "x-coordinate step up".to_string(),
+            0xe4 => "x-coordinate step down".to_string(),
+            0xe5 => "y-coordinate step up".to_string(),
+            0xe6 => "y-coordinate step down".to_string(),
+            0xe7 => format!(
+                "Set temporary colour temperature ({} K)",
+                kelvin(value16(state))
+            ),
+            0xe8 => "Colour temperature step cooler".to_string(),
+            0xe9 => "Colour temperature step warmer".to_string(),
+            0xea => format!(
+                "Set temporary primary N dimlevel (primary {}: {:.4})",
+                state.dtr(2),
+                (1.0 / 65536.0) * (value16(state) as f32),
+            ),
+            0xeb => format!(
+                "Set temporary RGB dimlevel (R: {}, G: {}, B: {})",
+                state.dtr(0),
+                state.dtr(1),
+                state.dtr(2)
+            ),
+            0xec => format!(
+                "Set temporary WAF dimlevel (W: {}, A: {}, F: {})",
+                state.dtr(0),
+                state.dtr(1),
+                state.dtr(2)
+            ),
+            0xed => format!(
+                "Set temporary RGBWAF control (R: {}, G: {}, B: {}, W: {}, A: {}, F: {}, Type: {})",
+                linked_status(state.dtr(0), 0x01),
+                linked_status(state.dtr(0), 0x02),
+                linked_status(state.dtr(0), 0x04),
+                linked_status(state.dtr(0), 0x08),
+                linked_status(state.dtr(0), 0x10),
+                linked_status(state.dtr(0), 0x20),
+                ["Channel", "Colour", "Normalized colour", "Reserved"]
+                    [(state.dtr(0) >> 6) as usize],
+            ),
+            0xee => "Copy report to temporary".to_string(),
+            0xf0 => format!(
+                "Store TY primary N (primary {}: {:.4} lumen)",
+                state.dtr(2),
+                0.5 * (value16(state) as f32),
+            ),
+            0xf1 => format!("Store xy-coordinate primary N (primary {})", state.dtr(2)),
+            0xf2 => format!(
+                "Store colour temperature limit ({} K, {})",
+                kelvin(value16(state)),
+                ["Coolest", "Warmest", "Physical coolest", "Physical warmest"]
+                    .get(state.dtr(2) as usize)
+                    .unwrap_or(&"Ignored")
+            ),
+            0xf3 => format!(
+                "Store gear features/status (automatic activation {})",
+                if state.dtr(0) & 0x01 != 0 {
+                    "on"
+                } else {
+                    "off"
                 }
-                0xee => "Copy report to temporary".to_string(),
-
-                0xf0 => format!(
-                    "Store TY primary N (primary {}: {:.4} lumen)",
-                    state.dtr[2],
-                    0.5 * (value16(state) as f32),
-                ),
-                0xf1 => format!("Store xy-coordinate primary N (primary {})", state.dtr[2]),
-                0xf2 => format!(
-                    "Store colour temperature limit ({} K, {})",
-                    1_000_000 / max(1, value16(state)) as u32,
-                    ["Coolest", "Warmest", "Physical coolest", "Physical warmest"]
-                        .get(state.dtr[2] as usize)
-                        .unwrap_or(&"Ignored")
-                ),
-                _ => "Unknown DT8 command".to_string(),
-            }
-        } else {
-            "Unknown DT8 command".to_string()
+            ),
+            0xf5 => format!(
+                "Assign colour to linked channel ({})",
+                match state.dtr(0) {
+                    0 => "none",
+                    c @ 1..=6 => PRIMARY_COLOUR_NAMES[usize::from(c - 1)],
+                    _ => "ignored",
+                }
+            ),
+            0xf6 => "Start auto calibration".to_string(),
+            0xf7 => "Query gear features/status".to_string(),
+            0xf8 => "Query colour status".to_string(),
+            0xf9 => "Query colour type features".to_string(),
+            0xfa => format!(
+                "Query colour value ({})",
+                colour_value_selector(state.dtr(0))
+            ),
+            0xfb => format!("Query RGBWAF control (channel {})", state.dtr(0)),
+            0xfc => format!("Query assigned colour (channel {})", state.dtr(0)),
+            _ => "Unknown DT8 command".to_string(),
+        }
+    }
+
+    fn is_query(&self, opcode: u8) -> bool {
+        matches!(opcode, 0xf7..=0xfc)
+    }
+}
+
+/// Decoder for self-contained emergency gear, device type 1 (IEC 62386-202)
+pub struct Dt1Decoder;
+
+impl Decoder16 for Dt1Decoder {
+    fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String {
+        match pkt[1] {
+            0xe0 => "Rest".to_string(),
+            0xe1 => "Inhibit".to_string(),
+            0xe2 => "Re-light/reset inhibit".to_string(),
+            0xe3 => "Start function test".to_string(),
+            0xe4 => "Start duration test".to_string(),
+            0xe5 => "Stop test".to_string(),
+            0xe6 => "Reset function test done flag".to_string(),
+            0xe7 => "Reset duration test done flag".to_string(),
+            0xe8 => "Reset lamp time".to_string(),
+            0xe9 => format!("Store DTR as emergency level ({})", state.dtr(0)),
+            0xea => format!("Store test delay time high byte ({})", state.dtr(0)),
+            0xeb => format!("Store test delay time low byte ({})", state.dtr(0)),
+            0xec => format!("Store function test interval ({} days)", state.dtr(0)),
+            0xed => format!("Store duration test interval ({} weeks)", state.dtr(0)),
+            0xee => format!("Store test execution timeout ({} days)", state.dtr(0)),
+            0xef => format!("Store prolong time ({} s)", u32::from(state.dtr(0)) * 30),
+            0xf0 => "Start identification".to_string(),
+            0xf1 => "Query battery charge".to_string(),
+            0xf2 => format!("Query test timing (selector {})", state.dtr(0)),
+            0xf3 => "Query duration test result".to_string(),
+            0xf4 => "Query lamp emergency time".to_string(),
+            0xf5 => "Query lamp total operation time".to_string(),
+            0xf6 => "Query emergency level".to_string(),
+            0xf7 => "Query emergency min level".to_string(),
+            0xf8 => "Query emergency max level".to_string(),
+            0xf9 => "Query rated duration".to_string(),
+            0xfa => "Query emergency mode".to_string(),
+            0xfb => "Query features".to_string(),
+            0xfc => "Query failure status".to_string(),
+            0xfd => "Query emergency status".to_string(),
+            0xfe => format!("Perform DTR selected function ({})", state.dtr(0)),
+            _ => "Unknown DT1 command".to_string(),
+        }
+    }
+
+    fn is_query(&self, opcode: u8) -> bool {
+        matches!(opcode, 0xf1..=0xfd)
+    }
+
+    fn decode_answer(&self, opcode: u8, answer: u8) -> Option<String> {
+        use crate::gear::dt1::{EMERGENCY_MODE_NAMES, EMERGENCY_STATUS_NAMES, FAILURE_NAMES};
+        match opcode {
+            0xf1 if answer == 0xff => Some("Unknown".to_string()),
+            0xf1 => Some(format!("{}%", u32::from(answer) * 100 / 254)),
+            0xf3 | 0xf9 => Some(format!("{} min", u32::from(answer) * 2)),
+            0xfa => Some(fmt_bitflags(answer, &EMERGENCY_MODE_NAMES)),
+            0xfc => Some(fmt_bitflags(answer, &FAILURE_NAMES)),
+            0xfd => Some(fmt_bitflags(answer, &EMERGENCY_STATUS_NAMES)),
+            _ => None,
+        }
+    }
+}
+
+/// Decoder for LED gear, device type 6 (IEC 62386-207)
+pub struct Dt6Decoder;
+
+impl Decoder16 for Dt6Decoder {
+    fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String {
+        match pkt[1] {
+            0xe0 => "Reference system power".to_string(),
+            0xe1 => "Enable current protector".to_string(),
+            0xe2 => "Disable current protector".to_string(),
+            0xe3 => format!(
+                "Select dimming curve ({})",
+                match state.dtr(0) {
+                    0 => "Standard",
+                    1 => "Linear",
+                    _ => "Ignored",
+                }
+            ),
+            0xe4 => format!(
+                "Store DTR as fast fade time ({} ms)",
+                u32::from(state.dtr(0)) * 25
+            ),
+            0xed => "Query gear type".to_string(),
+            0xee => "Query dimming curve".to_string(),
+            0xef => "Query possible operating modes".to_string(),
+            0xf0 => "Query features".to_string(),
+            0xf1 => "Query failure status".to_string(),
+            0xf2 => "Query short circuit".to_string(),
+            0xf3 => "Query open circuit".to_string(),
+            0xf4 => "Query load decrease".to_string(),
+            0xf5 => "Query load increase".to_string(),
+            0xf6 => "Query current protector active".to_string(),
+            0xf7 => "Query thermal shut down".to_string(),
+            0xf8 => "Query thermal overload".to_string(),
+            0xf9 => "Query reference running".to_string(),
+            0xfa => "Query reference measurement failed".to_string(),
+            0xfb => "Query current protector enabled".to_string(),
+            0xfc => "Query operating mode".to_string(),
+            0xfd => "Query fast fade time".to_string(),
+            0xfe => "Query min fast fade time".to_string(),
+            _ => "Unknown DT6 command".to_string(),
+        }
+    }
+
+    fn is_query(&self, opcode: u8) -> bool {
+        matches!(opcode, 0xed..=0xfe)
+    }
+
+    fn decode_answer(&self, opcode: u8, answer: u8) -> Option<String> {
+        match opcode {
+            0xee => Some(
+                match answer {
+                    0 => "Standard",
+                    1 => "Linear",
+                    _ => return None,
+                }
+                .to_string(),
+            ),
+            0xf2..=0xfb => Some(answer_yes(answer)),
+            0xfd | 0xfe => Some(format!("{} ms", u32::from(answer) * 25)),
+            _ => None,
+        }
+    }
+}
+
+fn threshold(level: u8) -> String {
+    if level == 0xff {
+        "disabled".to_string()
+    } else {
+        level.to_string()
+    }
+}
+
+/// Decoder for switching function gear, device type 7 (IEC 62386-208)
+pub struct Dt7Decoder;
+
+impl Decoder16 for Dt7Decoder {
+    fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String {
+        match pkt[1] {
+            0xe0 => "Reference system power".to_string(),
+            0xe1 => format!(
+                "Store DTR as up switch-on threshold ({})",
+                threshold(state.dtr(0))
+            ),
+            0xe2 => format!(
+                "Store DTR as up switch-off threshold ({})",
+                threshold(state.dtr(0))
+            ),
+            0xe3 => format!(
+                "Store DTR as down switch-on threshold ({})",
+                threshold(state.dtr(0))
+            ),
+            0xe4 => format!(
+                "Store DTR as down switch-off threshold ({})",
+                threshold(state.dtr(0))
+            ),
+            0xe5 => format!("Store DTR as error hold-off time ({} s)", state.dtr(0)),
+            0xf0 => "Query features".to_string(),
+            0xf1 => "Query switch status".to_string(),
+            0xf2 => "Query up switch-on threshold".to_string(),
+            0xf3 => "Query up switch-off threshold".to_string(),
+            0xf4 => "Query down switch-on threshold".to_string(),
+            0xf5 => "Query down switch-off threshold".to_string(),
+            0xf6 => "Query error hold-off time".to_string(),
+            0xf7 => "Query gear type".to_string(),
+            0xf9 => "Query reference running".to_string(),
+            0xfa => "Query reference measurement failed".to_string(),
+            _ => "Unknown DT7 command".to_string(),
+        }
+    }
+
+    fn is_query(&self, opcode: u8) -> bool {
+        matches!(opcode, 0xf0..=0xfa)
+    }
+
+    fn decode_answer(&self, opcode: u8, answer: u8) -> Option<String> {
+        match opcode {
+            0xf2..=0xf5 => Some(threshold(answer)),
+            0xf6 => Some(format!("{} s", answer)),
+            0xf9 | 0xfa => Some(answer_yes(answer)),
+            _ => None,
         }
     }
 }
 
 impl DecoderState {
+    /// Create a decoder with decoders for device type 1, 6, 7 and 8
+    /// registered.
     pub fn new() -> DecoderState {
-        DecoderState {
+        let mut state = DecoderState {
             dtr: [0, 0, 0],
-            extended: None,
-        }
+            used_dtr: Cell::new(0),
+            enabled_type: None,
+            decoders: HashMap::new(),
+            pending: None,
+        };
+        state.register(device_type::EMERGENCY, Box::new(Dt1Decoder));
+        state.register(device_type::LED, Box::new(Dt6Decoder));
+        state.register(device_type::SWITCHING, Box::new(Dt7Decoder));
+        state.register(device_type::COLOUR, Box::new(Dt8Decoder));
+        state
     }
 
-    fn decode_16bit(&mut self, pkt: &[u8; 2]) -> String {
-        let str;
-        assert!(pkt.len() >= 2);
-        if (pkt[0] & 1) != 0 {
-            // Command
-            if (pkt[0] & 0xe0) == 0xa0 || (pkt[0] & 0xe0) == 0xc0 {
-                // Special command
-                str = match pkt[0] {
-                    0xa1 => "Terminate".to_string(),
-                    0xa3 => {
-                        self.dtr[0] = pkt[1];
-                        format!("Set DTR = {} (0x{:02x})", pkt[1], pkt[1])
-                    }
-                    0xc3 => {
-                        self.dtr[1] = pkt[1];
-                        format!("Set DTR1 = {} (0x{:02x})", pkt[1], pkt[1])
-                    }
-                    0xc5 => {
-                        self.dtr[2] = pkt[1];
-                        format!("Set DTR2 = {} (0x{:02x})", pkt[1], pkt[1])
-                    }
-                    0xa5 => {
-                        format!("Initialise {} (0x{:02x})", pkt[1], pkt[1])
-                    }
-                    0xa7 => "Randomise".to_string(),
-                    0xa9 => "Compare".to_string(),
-                    0xab => "Withdraw".to_string(),
-                    0xb1 => format!("Search address high 0x{:02x}", pkt[1]),
-                    0xb3 => format!("Search address middle 0x{:02x}", pkt[1]),
-                    0xb5 => format!("Search address low 0x{:02x}", pkt[1]),
-                    0xb7 => format!("Program short address {}", (pkt[1] >> 1) & 0x3f),
-                    0xb9 => format!("Verify short address {}", (pkt[1] >> 1) & 0x3f),
-                    0xbb => "Query short address".to_string(),
-                    0xbd => "Physical selection".to_string(),
-                    0xc1 => {
-                        match pkt[1] {
-                            8 => {
-                                self.extended = Some(Box::new(Dt8Decoder::new()));
-                            }
-                            _ => self.extended = None,
-                        };
-                        format!("Enable device type {}", pkt[1])
-                    }
-                    0xc7 => format!("Write memory location: 0x{:02x}", pkt[1]),
-                    0xc9 => format!("Write memory location (no reply): 0x{:02x}", pkt[1]),
+    /// Use `decoder` for commands following ENABLE DEVICE TYPE
+    /// `device_type`. Replaces any decoder already registered for that
+    /// device type.
+    pub fn register(&mut self, device_type: u8, decoder: Box<dyn Decoder16>) {
+        self.decoders.insert(device_type, decoder);
+    }
 
-                    _ => "Unknown special command".to_string(),
-                };
-            } else {
-                /* Device command */
-                let cmd_descr = match pkt[1] {
-                    0xe0..0xfe => {
-                        if let Some(decoder) = self.extended.take() {
-                            decoder.decode_device_cmd(&self, pkt)
-                        } else {
-                            format!("Application extended command 0x{:02x}", pkt[0])
-                        }
-                    }
-                    0x2a => format!("Store DTR as max level ({})", self.dtr[0]),
-                    0x2b => format!("Store DTR as min level ({})", self.dtr[0]),
-                    0x2c => format!("Store DTR as system failure level ({})", self.dtr[0]),
-                    0x2d => format!("Store DTR as power on level ({})", self.dtr[0]),
-                    0x2e => {
-                        if self.dtr[0] == 0 {
-                            "Store DTR as fade time (Extended)".to_string()
-                        } else {
-                            format!(
-                                "Store DTR as fade time ({:.1})",
-                                0.5 * ((1u32 << min(15, self.dtr[0] as u32)) as f32).sqrt()
-                            )
-                        }
-                    }
-                    0x30 => format!(
-                        "Store DTR as extend fade time ({:.1} s)",
-                        (self.dtr[0] & 0x0f) as f32
-                            * [0.0, 0.1, 1.0, 10.0, 60.0]
-                                .get((self.dtr[0] >> 4) as usize)
-                                .unwrap_or(&0.0)
-                    ),
-                    _ => CMD_DESCR_16[usize::from(pkt[1])].to_string(),
-                };
-                str = decode_addr(pkt[0]) + ": " + &cmd_descr;
+    /// Stop decoding commands for `device_type`
+    pub fn unregister(&mut self, device_type: u8) -> Option<Box<dyn Decoder16>> {
+        self.decoders.remove(&device_type)
+    }
+
+    /// Current content of DTR0, DTR1 or DTR2 as seen on the bus. The
+    /// value is included in the decoded frame.
+    pub fn dtr(&self, index: usize) -> u8 {
+        self.used_dtr.set(self.used_dtr.get() | 1 << index);
+        self.dtr[index]
+    }
+
+    // DTR values read while decoding the last command
+    fn take_used_dtr(&self) -> [Option<u8>; 3] {
+        let used = self.used_dtr.replace(0);
+        std::array::from_fn(|i| (used & (1 << i) != 0).then_some(self.dtr[i]))
+    }
+
+    fn decode_special_16bit(&mut self, pkt: &[u8; 2]) -> String {
+        match pkt[0] {
+            0xa1 => "Terminate".to_string(),
+            0xa3 => {
+                self.dtr[0] = pkt[1];
+                format!("Set DTR = {} (0x{:02x})", pkt[1], pkt[1])
             }
-        } else {
-            str = decode_addr(pkt[0]) + ": " + &format!("Set power = {}", pkt[1]);
+            0xc3 => {
+                self.dtr[1] = pkt[1];
+                format!("Set DTR1 = {} (0x{:02x})", pkt[1], pkt[1])
+            }
+            0xc5 => {
+                self.dtr[2] = pkt[1];
+                format!("Set DTR2 = {} (0x{:02x})", pkt[1], pkt[1])
+            }
+            0xa5 => {
+                format!("Initialise {} (0x{:02x})", pkt[1], pkt[1])
+            }
+            0xa7 => "Randomise".to_string(),
+            0xa9 => "Compare".to_string(),
+            0xab => "Withdraw".to_string(),
+            0xb1 => format!("Search address high 0x{:02x}", pkt[1]),
+            0xb3 => format!("Search address middle 0x{:02x}", pkt[1]),
+            0xb5 => format!("Search address low 0x{:02x}", pkt[1]),
+            0xb7 => format!("Program short address {}", (pkt[1] >> 1) & 0x3f),
+            0xb9 => format!("Verify short address {}", (pkt[1] >> 1) & 0x3f),
+            0xbb => "Query short address".to_string(),
+            0xbd => "Physical selection".to_string(),
+            0xc1 => {
+                self.enabled_type = Some(pkt[1]);
+                format!("Enable device type {}", pkt[1])
+            }
+            0xc7 => format!("Write memory location: 0x{:02x}", pkt[1]),
+            0xc9 => format!("Write memory location (no reply): 0x{:02x}", pkt[1]),
+
+            _ => "Unknown special command".to_string(),
         }
-        str
-    }
-    fn decode_24bit(&mut self, pkt: &[u8; 3]) -> String {
-        let str;
-        if (pkt[0] & 1) == 1 {
-            // Command frame
-            let addr = decode_cmd_addr_24bit(pkt[0]);
-            if let Some(addr_str) = addr {
-                if pkt[1] == 0xfe {
-                    // Device command
-                    str = addr_str + ": " + &decode_device_command(pkt[2]);
+    }
+
+    fn decode_gear_cmd(&self, pkt: &[u8; 2], device_type: Option<u8>) -> String {
+        match pkt[1] {
+            0xe0..=0xfe => match device_type {
+                Some(dt) => match self.decoders.get(&dt) {
+                    Some(decoder) => decoder.decode_device_cmd(self, pkt),
+                    None => format!(
+                        "Application extended command 0x{:02x} (device type {})",
+                        pkt[1], dt
+                    ),
+                },
+                None => format!("Application extended command 0x{:02x}", pkt[1]),
+            },
+            0x2a => format!("Store DTR as max level ({})", self.dtr(0)),
+            0x2b => format!("Store DTR as min level ({})", self.dtr(0)),
+            0x2c => format!("Store DTR as system failure level ({})", self.dtr(0)),
+            0x2d => format!("Store DTR as power on level ({})", self.dtr(0)),
+            0x2e => {
+                let dtr = self.dtr(0);
+                if dtr == 0 {
+                    "Store DTR as fade time (Extended)".to_string()
                 } else {
-                    // Instance command
-                    str = addr_str + ": " + &decode_instance_command(pkt[2]);
+                    format!(
+                        "Store DTR as fade time ({:.1})",
+                        0.5 * ((1u32 << min(15, dtr as u32)) as f32).sqrt()
+                    )
                 }
-            } else {
-                str = decode_special_command(pkt);
             }
+            0x30 => {
+                let dtr = self.dtr(0);
+                format!(
+                    "Store DTR as extend fade time ({:.1} s)",
+                    (dtr & 0x0f) as f32
+                        * [0.0, 0.1, 1.0, 10.0, 60.0]
+                            .get((dtr >> 4) as usize)
+                            .unwrap_or(&0.0)
+                )
+            }
+            _ => CMD_DESCR_16[usize::from(pkt[1])].to_string(),
+        }
+    }
+
+    fn decode_16bit(&mut self, pkt: &[u8; 2]) -> DecodedFrame {
+        if (pkt[0] & 1) == 0 {
+            return DecodedFrame::ArcPower {
+                address: decode_addr(pkt[0]),
+                level: pkt[1],
+            };
+        }
+        if (pkt[0] & 0xe0) == 0xa0 || (pkt[0] & 0xe0) == 0xc0 {
+            return DecodedFrame::GearSpecial {
+                opcode: pkt[0],
+                data: pkt[1],
+                description: self.decode_special_16bit(pkt),
+            };
+        }
+        let device_type = if (0xe0..=0xfe).contains(&pkt[1]) {
+            self.enabled_type.take()
         } else {
+            None
+        };
+        let description = self.decode_gear_cmd(pkt, device_type);
+        DecodedFrame::GearCommand {
+            address: decode_addr(pkt[0]),
+            opcode: pkt[1],
+            device_type,
+            dtr: self.take_used_dtr(),
+            description,
+        }
+    }
+
+    fn decode_24bit(&mut self, pkt: &[u8; 3]) -> DecodedFrame {
+        if (pkt[0] & 1) == 0 {
             let value = ((u16::from(pkt[1]) & 0x03) << 8) | u16::from(pkt[2]);
-            str = "(".to_string()
-                + &decode_event_source(&pkt[0..1].try_into().unwrap())
-                + "): "
-                + &format!("{} (0x{:03x})", value, value);
+            return DecodedFrame::Event {
+                source: decode_event_source(&[pkt[0], pkt[1]]),
+                value,
+            };
+        }
+        match decode_cmd_addr_24bit(pkt[0]) {
+            Some(address) if pkt[1] == 0xfe => DecodedFrame::DeviceCommand {
+                address,
+                opcode: pkt[2],
+                description: device_cmd_descr_24(pkt[2]).to_string(),
+            },
+            Some(address) => DecodedFrame::InstanceCommand {
+                address,
+                instance: pkt[1],
+                opcode: pkt[2],
+                description: instance_cmd_descr_24(pkt[2]).to_string(),
+            },
+            None => DecodedFrame::DeviceSpecial {
+                data: *pkt,
+                description: decode_special_command(pkt),
+            },
+        }
+    }
+
+    /// Decode a frame. Backward frames are decoded without context, use
+    /// [`decode_event`](Self::decode_event) to pair them with the query.
+    pub fn decode_frame(&mut self, frame: &DaliFrame) -> DecodedFrame {
+        match frame {
+            DaliFrame::Frame8(value) => DecodedFrame::Backward { value: *value },
+            DaliFrame::Frame16(pkt) => self.decode_16bit(pkt),
+            DaliFrame::Frame24(pkt) => self.decode_24bit(pkt),
+            DaliFrame::Frame25(pkt) => DecodedFrame::Frame25 {
+                // The last bit is the MSB of the last byte
+                value: u32::from_be_bytes([0, pkt[0], pkt[1], pkt[2]]) << 1
+                    | u32::from(pkt[3] >> 7),
+            },
+        }
+    }
+
+    /// Decode a frame given as bytes. The length selects the frame
+    /// type: 1, 2, 3 or 4 bytes for 8, 16, 24 or 25 bit frames.
+    pub fn decode(&mut self, pkt: &[u8]) -> DecodedFrame {
+        let frame = match *pkt {
+            [value] => DaliFrame::Frame8(value),
+            [a, b] => DaliFrame::Frame16([a, b]),
+            [a, b, c] => DaliFrame::Frame24([a, b, c]),
+            [a, b, c, d] => DaliFrame::Frame25([a, b, c, d]),
+            _ => return DecodedFrame::Unknown { data: pkt.to_vec() },
+        };
+        self.decode_frame(&frame)
+    }
+
+    // True if the frame is answered by a backward frame
+    fn is_query(&self, frame: &DecodedFrame) -> bool {
+        match *frame {
+            DecodedFrame::GearCommand {
+                opcode,
+                device_type: Some(dt),
+                ..
+            } => self.decoders.get(&dt).is_some_and(|d| d.is_query(opcode)),
+            DecodedFrame::GearCommand { opcode, .. } => matches!(opcode, 0x90..=0xc5 | 0xff),
+            DecodedFrame::GearSpecial { opcode, .. } => {
+                matches!(opcode, 0xa9 | 0xb9 | 0xbb | 0xc7)
+            }
+            DecodedFrame::DeviceCommand { opcode, .. } => matches!(opcode, 0x30..=0x48),
+            DecodedFrame::InstanceCommand { opcode, .. } => matches!(opcode, 0x80..=0x92),
+            DecodedFrame::DeviceSpecial { data, .. } => {
+                data[0] == 0xc1 && matches!(data[1], 0x03 | 0x09 | 0x0a | 0x20)
+            }
+            _ => false,
+        }
+    }
+
+    fn describe_answer(&self, query: &DecodedFrame, value: u8) -> String {
+        match *query {
+            DecodedFrame::GearCommand {
+                opcode,
+                device_type: Some(dt),
+                ..
+            } => self
+                .decoders
+                .get(&dt)
+                .and_then(|d| d.decode_answer(opcode, value))
+                .unwrap_or_else(|| answer_value(value)),
+            DecodedFrame::GearCommand { opcode, .. } => gear_answer(opcode, value),
+            DecodedFrame::GearSpecial {
+                opcode: 0xa9 | 0xb9,
+                ..
+            } => answer_yes(value),
+            DecodedFrame::DeviceSpecial { data, .. } if matches!(data[1], 0x03 | 0x09) => {
+                answer_yes(value)
+            }
+            _ => answer_value(value),
+        }
+    }
+
+    /// Report the pending query as unanswered if the answer window has
+    /// passed at `now`.
+    pub fn expire(&mut self, now: Instant) -> Option<DecodedFrame> {
+        let pending = self
+            .pending
+            .take_if(|p| now.saturating_duration_since(p.end) > ANSWER_TIMEOUT)?;
+        Some(DecodedFrame::NoAnswer {
+            query: Box::new(pending.query),
+        })
+    }
+
+    fn decode_forward(&mut self, timestamp: Instant, frame: &DaliFrame) -> DecodedFrame {
+        let decoded = self.decode_frame(frame);
+        self.pending = self.is_query(&decoded).then(|| PendingQuery {
+            end: timestamp + timing::frame_duration(frame),
+            query: decoded.clone(),
+        });
+        decoded
+    }
+
+    /// Decode a bus event, pairing backward frames with the query they
+    /// answer.
+    ///
+    /// A query that isn't answered is reported as
+    /// [`DecodedFrame::NoAnswer`] before the next decoded frame, or by
+    /// [`expire`](Self::expire). Events other than frames are ignored,
+    /// except a framing error in the answer window.
+    pub fn decode_event(&mut self, event: &DaliBusEvent) -> Vec<DecodedFrame> {
+        let mut frames = Vec::new();
+        frames.extend(self.expire(event.timestamp));
+        match DaliFrame::try_from(&event.event_type) {
+            Ok(DaliFrame::Frame8(value)) => frames.push(match self.pending.take() {
+                Some(pending) => DecodedFrame::Answer {
+                    description: self.describe_answer(&pending.query, value),
+                    query: Box::new(pending.query),
+                    value,
+                },
+                None => DecodedFrame::Backward { value },
+            }),
+            Ok(frame) => frames.push(self.decode_forward(event.timestamp, &frame)),
+            Err(_) => {
+                if let DaliBusEventType::FramingError = event.event_type
+                    && let Some(pending) = self.pending.take()
+                {
+                    frames.push(DecodedFrame::AnswerCollision {
+                        query: Box::new(pending.query),
+                    })
+                }
+            }
         }
-        str
+        frames
     }
 
+    /// Decode a frame into a human readable description
     pub fn decode_packet(&mut self, pkt: &[u8]) -> String {
-        let len = pkt.len();
-        match len {
-            3 => self.decode_24bit(pkt.try_into().unwrap()),
-            2 => self.decode_16bit(pkt.try_into().unwrap()),
-            _ => "Unhandled packet length".to_string(),
+        self.decode(pkt).to_string()
+    }
+}
+
+impl Default for DecoderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    struct VendorDecoder;
+
+    impl Decoder16 for VendorDecoder {
+        fn decode_device_cmd(&self, state: &DecoderState, pkt: &[u8; 2]) -> String {
+            format!("Vendor command 0x{:02x} ({})", pkt[1], state.dtr(0))
         }
     }
+
+    #[test]
+    fn device_type_decoders() {
+        let mut state = DecoderState::new();
+        state.decode_packet(&[0xa3, 0xc2]);
+        state.decode_packet(&[0xc1, 8]);
+        assert_eq!(
+            state.decode_packet(&[0x03, 0xfa]),
+            "Addr: 1: Query colour value (temporary colour temperature)"
+        );
+        // Only the command directly after ENABLE DEVICE TYPE is decoded
+        assert_eq!(
+            state.decode_packet(&[0x03, 0xfa]),
+            "Addr: 1: Application extended command 0xfa"
+        );
+        state.decode_packet(&[0xa3, 3]);
+        state.decode_packet(&[0xc1, 1]);
+        assert_eq!(
+            state.decode_packet(&[0x03, 0xe9]),
+            "Addr: 1: Store DTR as emergency level (3)"
+        );
+
+        state.decode_packet(&[0xc1, 130]);
+        assert_eq!(
+            state.decode_packet(&[0x03, 0xe0]),
+            "Addr: 1: Application extended command 0xe0 (device type 130)"
+        );
+        state.register(130, Box::new(VendorDecoder));
+        state.decode_packet(&[0xc1, 130]);
+        assert_eq!(
+            state.decode_packet(&[0x03, 0xe0]),
+            "Addr: 1: Vendor command 0xe0 (3)"
+        );
+    }
+
+    #[test]
+    fn structured() {
+        let mut state = DecoderState::new();
+        state.decode(&[0xa3, 0x20]);
+        let frame = state.decode(&[0x85, 0x2a]);
+        assert_eq!(
+            frame,
+            DecodedFrame::GearCommand {
+                address: FrameAddress::Group(2),
+                opcode: 0x2a,
+                device_type: None,
+                dtr: [Some(0x20), None, None],
+                description: "Store DTR as max level (32)".to_string()
+            }
+        );
+        assert_eq!(frame.to_string(), "Group: 2: Store DTR as max level (32)");
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::to_string(&frame).unwrap(),
+            r#"{"frame":"gear_command","address":{"type":"group","value":2},"opcode":42,"device_type":null,"dtr":[32,null,null],"description":"Store DTR as max level (32)"}"#
+        );
+
+        let frame = state.decode(&[0xfe, 0x80]);
+        assert_eq!(frame.to_string(), "Broadcast: Set power = 128");
+
+        let frame = state.decode(&[0x02, 0x80, 0x10]);
+        assert_eq!(
+            frame,
+            DecodedFrame::Event {
+                source: EventSource::DeviceInstance {
+                    address: 1,
+                    instance: 0
+                },
+                value: 0x10
+            }
+        );
+        assert_eq!(
+            frame.to_string(),
+            "(Device addr: 1, Instance: 0): 16 (0x010)"
+        );
+        #[cfg(feature = "serde")]
+        assert_eq!(
+            serde_json::from_str::<DecodedFrame>(&serde_json::to_string(&frame).unwrap()).unwrap(),
+            frame
+        );
+    }
+
+    #[test]
+    fn type_names() {
+        let query = Box::new(DecodedFrame::Backward { value: 0 });
+        let frames = [
+            DecodedFrame::ArcPower {
+                address: FrameAddress::Broadcast,
+                level: 0,
+            },
+            DecodedFrame::GearCommand {
+                address: FrameAddress::Broadcast,
+                opcode: 0,
+                device_type: None,
+                dtr: [None; 3],
+                description: String::new(),
+            },
+            DecodedFrame::GearSpecial {
+                opcode: 0,
+                data: 0,
+                description: String::new(),
+            },
+            DecodedFrame::DeviceCommand {
+                address: FrameAddress::Broadcast,
+                opcode: 0,
+                description: String::new(),
+            },
+            DecodedFrame::InstanceCommand {
+                address: FrameAddress::Broadcast,
+                instance: 0,
+                opcode: 0,
+                description: String::new(),
+            },
+            DecodedFrame::DeviceSpecial {
+                data: [0; 3],
+                description: String::new(),
+            },
+            DecodedFrame::Event {
+                source: EventSource::Reserved,
+                value: 0,
+            },
+            DecodedFrame::Answer {
+                query: query.clone(),
+                value: 0,
+                description: String::new(),
+            },
+            DecodedFrame::NoAnswer {
+                query: query.clone(),
+            },
+            DecodedFrame::AnswerCollision { query },
+            DecodedFrame::Backward { value: 0 },
+            DecodedFrame::Frame25 { value: 0 },
+            DecodedFrame::Unknown { data: Vec::new() },
+        ];
+        for (frame, name) in frames.iter().zip(DecodedFrame::TYPE_NAMES) {
+            assert_eq!(frame.type_name(), name);
+            #[cfg(feature = "serde")]
+            assert_eq!(serde_json::to_value(frame).unwrap()["frame"], name);
+        }
+    }
+
+    #[test]
+    fn answers() {
+        let mut state = DecoderState::new();
+        let start = Instant::now();
+        let mut event = |ms: u64, event_type: DaliBusEventType| {
+            state
+                .decode_event(&DaliBusEvent {
+                    timestamp: start + Duration::from_millis(ms),
+                    event_type,
+                })
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            event(0, DaliBusEventType::Frame16([0x03, 0xa0])),
+            ["Addr: 1: Query actual level"]
+        );
+        assert_eq!(
+            event(15, DaliBusEventType::Frame8(128)),
+            ["Addr: 1: Query actual level → 128 (0x80)"]
+        );
+        event(100, DaliBusEventType::Frame16([0x03, 0x99]));
+        assert_eq!(
+            event(115, DaliBusEventType::Frame8(6)),
+            ["Addr: 1: Query device type → LED"]
+        );
+        event(200, DaliBusEventType::Frame16([0x05, 0x91]));
+        assert_eq!(
+            event(300, DaliBusEventType::Frame16([0xfe, 0x00])),
+            [
+                "Addr: 2: Query ballast → no answer",
+                "Broadcast: Set power = 0"
+            ]
+        );
+        // Answer after the window has passed
+        assert_eq!(event(310, DaliBusEventType::Frame8(1)), ["Answer 1 (0x01)"]);
+        event(400, DaliBusEventType::Frame16([0xc1, 1]));
+        event(420, DaliBusEventType::Frame16([0x03, 0xfa]));
+        assert_eq!(
+            event(435, DaliBusEventType::Frame8(0x12)),
+            ["Addr: 1: Query emergency mode → Normal, Function test"]
+        );
+        event(500, DaliBusEventType::Frame16([0xa9, 0x00]));
+        assert_eq!(
+            event(515, DaliBusEventType::FramingError),
+            ["Compare → collision"]
+        );
+        // The window starts at the end of the forward frame
+        event(600, DaliBusEventType::Frame24([0x03, 0xfe, 0x30]));
+        assert_eq!(
+            event(641, DaliBusEventType::Frame8(0)),
+            ["Addr: 1: Query device status → 0 (0x00)"]
+        );
+    }
+
+    #[test]
+    fn frame_lengths() {
+        let mut state = DecoderState::new();
+        assert_eq!(
+            state.decode(&[0x80]),
+            DecodedFrame::Backward { value: 0x80 }
+        );
+        assert_eq!(
+            state.decode_frame(&DaliFrame::Frame25([0x12, 0x34, 0x56, 0x80])),
+            DecodedFrame::Frame25 { value: 0x02468ad }
+        );
+        assert_eq!(
+            state.decode(&[0x12, 0x34, 0x56, 0x00]).to_string(),
+            "25-bit frame 0x02468ac"
+        );
+        assert_eq!(
+            state
+                .decode_frame(&DaliFrame::Frame16([0x01, 0x05]))
+                .to_string(),
+            "Addr: 0: Recall max level"
+        );
+        assert_eq!(
+            state.decode(&[1, 2, 3, 4, 5]),
+            DecodedFrame::Unknown {
+                data: vec![1, 2, 3, 4, 5]
+            }
+        );
+    }
 }
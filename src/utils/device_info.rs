@@ -9,9 +9,16 @@ use crate::drivers::send_flags::{EXPECT_ANSWER, NO_FLAG};
 use crate::gear::cmd_defs as cmd;
 use crate::gear::cmd_defs::Command as Command16;
 use crate::gear::device_type::DeviceType;
+use crate::gear::device_type::types as device_type;
+use crate::gear::dt1::{self, Dt1Info};
+use crate::gear::dt6::{self, Dt6Info};
+use crate::gear::dt7::{self, Dt7Info};
 use crate::gear::status::GearStatus;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct GearInfo {
     short_addr: Short,
     version: Option<u8>,
@@ -30,6 +37,10 @@ pub struct GearInfo {
     failure_level: Option<u8>,
     fade: Option<u8>,
     extended_fade_time: Option<u8>,
+
+    dt1: Option<Dt1Info>,
+    dt6: Option<Dt6Info>,
+    dt7: Option<Dt7Info>,
 }
 
 impl GearInfo {
@@ -52,8 +63,91 @@ impl GearInfo {
             failure_level: None,
             fade: None,
             extended_fade_time: None,
+
+            dt1: None,
+            dt6: None,
+            dt7: None,
         }
     }
+
+    pub fn short_addr(&self) -> Short {
+        self.short_addr
+    }
+
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    pub fn device_types(&self) -> &[DeviceType] {
+        &self.device_types
+    }
+
+    pub fn light_source_types(&self) -> &[u8] {
+        &self.light_source_types
+    }
+
+    pub fn operating_mode(&self) -> Option<u8> {
+        self.operating_mode
+    }
+
+    pub fn status(&self) -> Option<&GearStatus> {
+        self.status.as_ref()
+    }
+
+    /// Bit mask of the groups, bit 0 for group 0
+    pub fn groups(&self) -> Option<u16> {
+        self.groups
+    }
+
+    /// 255 for scenes the gear isn't part of
+    pub fn scenes(&self) -> Option<[u8; 16]> {
+        self.scenes
+    }
+
+    pub fn physical_min(&self) -> Option<u8> {
+        self.physical_min
+    }
+
+    pub fn actual_level(&self) -> Option<u8> {
+        self.actual_level
+    }
+
+    pub fn min_level(&self) -> Option<u8> {
+        self.min_level
+    }
+
+    pub fn max_level(&self) -> Option<u8> {
+        self.max_level
+    }
+
+    pub fn power_on_level(&self) -> Option<u8> {
+        self.power_on_level
+    }
+
+    pub fn failure_level(&self) -> Option<u8> {
+        self.failure_level
+    }
+
+    /// Fade time in the upper four bits, fade rate in the lower
+    pub fn fade(&self) -> Option<u8> {
+        self.fade
+    }
+
+    pub fn extended_fade_time(&self) -> Option<u8> {
+        self.extended_fade_time
+    }
+
+    pub fn dt1(&self) -> Option<&Dt1Info> {
+        self.dt1.as_ref()
+    }
+
+    pub fn dt6(&self) -> Option<&Dt6Info> {
+        self.dt6.as_ref()
+    }
+
+    pub fn dt7(&self) -> Option<&Dt7Info> {
+        self.dt7.as_ref()
+    }
 }
 pub fn fmt_groups(f: &mut fmt::Formatter<'_>, groups: u16) -> fmt::Result {
     let mut str = Vec::new();
@@ -206,6 +300,15 @@ impl fmt::Display for GearInfo {
             writeln!(f, "Fade time: {}", t)?;
             writeln!(f, "Fade rate: {} steps/s", r)?;
         }
+        if let Some(dt1) = &self.dt1 {
+            write!(f, "{}", dt1)?;
+        }
+        if let Some(dt6) = &self.dt6 {
+            write!(f, "{}", dt6)?;
+        }
+        if let Some(dt7) = &self.dt7 {
+            write!(f, "{}", dt7)?;
+        }
 
         Ok(())
     }
@@ -236,7 +339,7 @@ pub async fn read_gear_info(
     match send16::query(d, cmd::QUERY_DEVICE_TYPE(addr), NO_FLAG).await {
         DaliSendResult::Answer(MASK) => loop {
             match send16::query(d, cmd::QUERY_NEXT_DEVICE_TYPE(addr), NO_FLAG).await {
-                DaliSendResult::Answer(MASK) => break,
+                DaliSendResult::Answer(MASK | device_type::UNIMPLEMENTED) => break,
                 DaliSendResult::Answer(t) => info.device_types.push(DeviceType::new(t)),
                 DaliSendResult::Timeout => break,
                 e => return Err(e),
@@ -292,8 +395,20 @@ pub async fn read_gear_info(
         e => return Err(e),
     };
 
+    let has_type = |info: &GearInfo, t| info.device_types.iter().any(|dt| dt.value() == t);
+    if has_type(&info, device_type::EMERGENCY) {
+        info.dt1 = Some(dt1::read_info(d, addr).await?);
+    }
+    if has_type(&info, device_type::LED) {
+        info.dt6 = Some(dt6::read_info(d, addr).await?);
+    }
+    if has_type(&info, device_type::SWITCHING) {
+        info.dt7 = Some(dt7::read_info(d, addr).await?);
+    }
+
     Ok(info)
 }
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Instance {
     pub instance_type: Option<u8>,
     pub features_types: Vec<u8>,
@@ -308,6 +423,7 @@ pub struct Instance {
     pub event_filter: Option<u32>,
 }
 
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ControlInfo {
     pub short_addr: Short,
     pub version: Option<u8>,
@@ -336,7 +452,7 @@ impl ControlInfo {
     }
 }
 
-fn fmt_bitflags(mut bits: u8, flag_names: &[&str]) -> String {
+pub(crate) fn fmt_bitflags(mut bits: u8, flag_names: &[&str]) -> String {
     let mut i: usize = 0;
     let mut s = String::new();
     loop {
@@ -499,7 +615,7 @@ async fn query_device_groups(
         ccmd::QUERY_DEVICE_GROUPS_0_7(addr),
     ] {
         groups = match send_query24(d, cmd).await? {
-            Some(b) => u32::from(b) + groups << 8,
+            Some(b) => (groups << 8) | u32::from(b),
             None => return Ok(None),
         }
     }
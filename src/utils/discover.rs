@@ -4,6 +4,8 @@ use crate::common::commands::{Commands, ErrorInfo, YesNo};
 use crate::drivers::driver::DaliSendResult;
 use crate::utils::long_address;
 use log::debug;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
 
 // Clear all bits except for the highest one
 fn high_bit(mut bits: u32) -> u32 {
@@ -155,8 +157,9 @@ where
                     Ok(short) => {
                         let short_conflict = if let Some(short) = short {
                             let addr_mask = 1u64 << short.value();
+                            let conflict = (found_short & addr_mask) != 0;
                             found_short |= addr_mask;
-                            (found_short & addr_mask) != 0
+                            conflict
                         } else {
                             false
                         };
@@ -235,6 +238,7 @@ where
 
 /// Addresses of devices discovered on the bus.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Discovered {
     /// Random address for device. None if there's conflicting short addresses or the device doesn't report a random address.
     pub long: Option<Long>,
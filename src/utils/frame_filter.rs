@@ -0,0 +1,438 @@
+//! Filter expressions for decoded frames.
+//!
+//! An expression is built from terms combined with `and`, `or`, `not` and
+//! parentheses. `and` binds tighter than `or`. Numbers are decimal or
+//! hexadecimal with a `0x` prefix.
+//!
+//! | Term                | Matches                                                 |
+//! |---------------------|---------------------------------------------------------|
+//! | `addr N`            | Frames to short address N                               |
+//! | `group N`           | Frames to group N                                       |
+//! | `broadcast`         | Broadcast frames                                        |
+//! | `unaddressed`       | Broadcast frames to unaddressed gear                    |
+//! | `type NAME`         | Frame type, same names as in the JSON output, e.g. `gear_command`, `event` or `answer` |
+//! | `opcode N`          | Command opcode                                          |
+//! | `cmd TEXT`          | Decoded text contains TEXT, ignoring case. Use quotes for text with spaces. |
+//! | `instance N`        | Instance commands and input device events for instance N |
+//! | `instance_type N`   | Input device events from instances of type N            |
+//! | `source N`          | Input device events from device N                       |
+//! | `source_group N`    | Input device events from device or instance group N     |
+//! | `after S`, `before S` | Frames after or before S seconds from the start       |
+//!
+//! Answers match the address and opcode of the query they answer.
+//!
+//! ```
+//! # use dali_tools::utils::frame_filter::Filter;
+//! let filter: Filter = "group 3 or (type event and not source 5)".parse().unwrap();
+//! ```
+
+use crate::utils::decode::{DecodedFrame, EventSource, FrameAddress};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Address(FrameAddress),
+    Type(String),
+    Opcode(u8),
+    Command(String),
+    Instance(u8),
+    InstanceType(u8),
+    Source(u8),
+    SourceGroup(u8),
+    After(Duration),
+    Before(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Term(Term),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+// The command an answer belongs to, or the frame itself
+fn command_frame(frame: &DecodedFrame) -> &DecodedFrame {
+    match frame {
+        DecodedFrame::Answer { query, .. }
+        | DecodedFrame::NoAnswer { query }
+        | DecodedFrame::AnswerCollision { query } => query,
+        _ => frame,
+    }
+}
+
+impl Term {
+    fn matches(&self, frame: &DecodedFrame, elapsed: Duration) -> bool {
+        let command = command_frame(frame);
+        match self {
+            Term::Address(addr) => match command {
+                DecodedFrame::ArcPower { address, .. }
+                | DecodedFrame::GearCommand { address, .. }
+                | DecodedFrame::DeviceCommand { address, .. }
+                | DecodedFrame::InstanceCommand { address, .. } => address == addr,
+                _ => false,
+            },
+            Term::Type(name) => frame.type_name() == name,
+            Term::Opcode(op) => match command {
+                DecodedFrame::GearCommand { opcode, .. }
+                | DecodedFrame::GearSpecial { opcode, .. }
+                | DecodedFrame::DeviceCommand { opcode, .. }
+                | DecodedFrame::InstanceCommand { opcode, .. } => opcode == op,
+                _ => false,
+            },
+            Term::Command(text) => frame.to_string().to_lowercase().contains(text),
+            Term::Instance(i) => match command {
+                DecodedFrame::InstanceCommand { instance, .. } => instance == i,
+                DecodedFrame::Event {
+                    source:
+                        EventSource::DeviceInstance { instance, .. }
+                        | EventSource::DeviceGroupInstance { instance, .. },
+                    ..
+                } => instance == i,
+                _ => false,
+            },
+            Term::InstanceType(t) => matches!(command,
+                DecodedFrame::Event {
+                    source:
+                        EventSource::Device { instance_type, .. }
+                        | EventSource::DeviceGroup { instance_type, .. }
+                        | EventSource::InstanceGroup { instance_type, .. },
+                    ..
+                } if instance_type == t),
+            Term::Source(a) => matches!(command,
+                DecodedFrame::Event {
+                    source:
+                        EventSource::Device { address, .. }
+                        | EventSource::DeviceInstance { address, .. },
+                    ..
+                } if address == a),
+            Term::SourceGroup(g) => matches!(command,
+                DecodedFrame::Event {
+                    source:
+                        EventSource::DeviceGroup { group, .. }
+                        | EventSource::DeviceGroupInstance { group, .. }
+                        | EventSource::InstanceGroup { group, .. },
+                    ..
+                } if group == g),
+            Term::After(t) => elapsed >= *t,
+            Term::Before(t) => elapsed < *t,
+        }
+    }
+}
+
+impl Expr {
+    fn matches(&self, frame: &DecodedFrame, elapsed: Duration) -> bool {
+        match self {
+            Expr::Term(term) => term.matches(frame, elapsed),
+            Expr::Not(e) => !e.matches(frame, elapsed),
+            Expr::And(a, b) => a.matches(frame, elapsed) && b.matches(frame, elapsed),
+            Expr::Or(a, b) => a.matches(frame, elapsed) || b.matches(frame, elapsed),
+        }
+    }
+}
+
+/// Error when parsing a filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterError {
+    /// Position in the expression, in bytes
+    pub pos: usize,
+    pub message: String,
+}
+
+impl Error for FilterError {}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "At position {}: {}", self.pos, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '(' => tokens.push((pos, Token::Open)),
+            ')' => tokens.push((pos, Token::Close)),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) => text.push(c),
+                        None => {
+                            return Err(FilterError {
+                                pos,
+                                message: "Unterminated string".to_string(),
+                            });
+                        }
+                    }
+                }
+                tokens.push((pos, Token::Quoted(text)));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((pos, Token::Word(word)));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn error<T>(&self, pos: usize, message: impl Into<String>) -> Result<T, FilterError> {
+        Err(FilterError {
+            pos,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map(|(p, _)| *p)
+            .unwrap_or(self.end)
+    }
+
+    fn next_token(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(_, t)| t.clone());
+        self.next += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if let Some(Token::Word(w)) = self.peek()
+            && w.eq_ignore_ascii_case(keyword)
+        {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or_expr(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and_expr()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn and_expr(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.not_expr()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not_expr()?));
+        }
+        Ok(expr)
+    }
+
+    fn not_expr(&mut self) -> Result<Expr, FilterError> {
+        if self.keyword("not") {
+            Ok(Expr::Not(Box::new(self.not_expr()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, FilterError> {
+        let pos = self.pos();
+        match self.next_token() {
+            Some(Token::Open) => {
+                let expr = self.or_expr()?;
+                match self.next_token() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => self.error(pos, "Unmatched parenthesis"),
+                }
+            }
+            Some(Token::Word(word)) => Ok(Expr::Term(self.term(pos, &word.to_lowercase())?)),
+            Some(_) => self.error(pos, "Expected a filter term"),
+            None => self.error(pos, "Unexpected end of filter"),
+        }
+    }
+
+    fn argument(&mut self, name: &str) -> Result<(usize, String), FilterError> {
+        let pos = self.pos();
+        match self.next_token() {
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => Ok((pos, w)),
+            _ => self.error(pos, format!("Missing argument for '{}'", name)),
+        }
+    }
+
+    fn number(&mut self, name: &str, max: u8) -> Result<u8, FilterError> {
+        let (pos, arg) = self.argument(name)?;
+        let value = match arg.strip_prefix("0x") {
+            Some(hex) => u8::from_str_radix(hex, 16),
+            None => arg.parse::<u8>(),
+        };
+        match value {
+            Ok(v) if v <= max => Ok(v),
+            _ => self.error(pos, format!("Expected a number 0-{} for '{}'", max, name)),
+        }
+    }
+
+    fn seconds(&mut self, name: &str) -> Result<Duration, FilterError> {
+        let (pos, arg) = self.argument(name)?;
+        match arg
+            .parse::<f64>()
+            .ok()
+            .and_then(|s| Duration::try_from_secs_f64(s).ok())
+        {
+            Some(d) => Ok(d),
+            None => self.error(pos, format!("Expected seconds for '{}'", name)),
+        }
+    }
+
+    fn term(&mut self, pos: usize, name: &str) -> Result<Term, FilterError> {
+        Ok(match name {
+            "addr" => Term::Address(FrameAddress::Short(self.number(name, 63)?)),
+            "group" => Term::Address(FrameAddress::Group(self.number(name, 31)?)),
+            "broadcast" => Term::Address(FrameAddress::Broadcast),
+            "unaddressed" => Term::Address(FrameAddress::Unaddressed),
+            "type" => {
+                let (pos, arg) = self.argument(name)?;
+                let arg = arg.to_lowercase();
+                if !DecodedFrame::TYPE_NAMES.contains(&arg.as_str()) {
+                    return self.error(pos, format!("Unknown frame type '{}'", arg));
+                }
+                Term::Type(arg)
+            }
+            "opcode" => Term::Opcode(self.number(name, 255)?),
+            "cmd" => Term::Command(self.argument(name)?.1.to_lowercase()),
+            "instance" => Term::Instance(self.number(name, 31)?),
+            "instance_type" => Term::InstanceType(self.number(name, 31)?),
+            "source" => Term::Source(self.number(name, 63)?),
+            "source_group" => Term::SourceGroup(self.number(name, 31)?),
+            "after" => Term::After(self.seconds(name)?),
+            "before" => Term::Before(self.seconds(name)?),
+            _ => return self.error(pos, format!("Unknown filter term '{}'", name)),
+        })
+    }
+}
+
+/// A parsed filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    /// Check if a frame matches. `elapsed` is the time since the start of
+    /// monitoring, used by `after` and `before`.
+    pub fn matches(&self, frame: &DecodedFrame, elapsed: Duration) -> bool {
+        self.expr.matches(frame, elapsed)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            next: 0,
+            end: s.len(),
+        };
+        let expr = parser.or_expr()?;
+        if parser.next < parser.tokens.len() {
+            return parser.error(parser.pos(), "Unexpected input");
+        }
+        Ok(Filter { expr })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::decode::DecoderState;
+
+    fn filter(s: &str) -> Filter {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn matching() {
+        let mut decoder = DecoderState::new();
+        let group3_off = decoder.decode(&[0x87, 0x00]);
+        let addr1_query = decoder.decode(&[0x03, 0x90]);
+        let broadcast_level = decoder.decode(&[0xfe, 0x80]);
+        let instance_event = decoder.decode(&[0x02, 0x90, 0x10]);
+        let instance_cmd = decoder.decode(&[0x03, 0x02, 0x8c]);
+        let answer = DecodedFrame::Answer {
+            query: Box::new(addr1_query.clone()),
+            value: 4,
+            description: "Lamp on".to_string(),
+        };
+        let ms = Duration::from_millis;
+
+        let f = filter("group 3");
+        assert!(f.matches(&group3_off, ms(0)));
+        assert!(!f.matches(&addr1_query, ms(0)));
+
+        let f = filter("addr 1 and type answer");
+        assert!(f.matches(&answer, ms(0)));
+        assert!(!f.matches(&addr1_query, ms(0)));
+
+        let f = filter("not (broadcast or group 3)");
+        assert!(!f.matches(&broadcast_level, ms(0)));
+        assert!(!f.matches(&group3_off, ms(0)));
+        assert!(f.matches(&addr1_query, ms(0)));
+
+        let f = filter("cmd \"QUERY STATUS\" or opcode 0x00");
+        assert!(f.matches(&addr1_query, ms(0)));
+        assert!(f.matches(&answer, ms(0)));
+        assert!(f.matches(&group3_off, ms(0)));
+        assert!(!f.matches(&broadcast_level, ms(0)));
+
+        let f = filter("type event and source 1 and instance 4");
+        assert!(f.matches(&instance_event, ms(0)), "{:?}", instance_event);
+        assert!(!f.matches(&instance_cmd, ms(0)));
+        assert!(filter("instance 2").matches(&instance_cmd, ms(0)));
+
+        let f = filter("after 1.5 and before 3");
+        assert!(!f.matches(&group3_off, ms(1000)));
+        assert!(f.matches(&group3_off, ms(2000)));
+        assert!(!f.matches(&group3_off, ms(3000)));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |s: &str| s.parse::<Filter>().unwrap_err();
+        assert_eq!(err("group 32").pos, 6);
+        assert_eq!(err("addr 1 and").pos, 10);
+        assert_eq!(err("(addr 1").pos, 0);
+        assert_eq!(err("addr 1 addr 2").pos, 7);
+        assert_eq!(err("type frame").pos, 5);
+        assert_eq!(err("foo").message, "Unknown filter term 'foo'");
+        assert_eq!(err("cmd \"off").message, "Unterminated string");
+    }
+}
@@ -0,0 +1,199 @@
+//! Printing of decoded bus traffic, as text or one JSON object per line.
+//!
+//! Used by `dali_monitor` and `dali_replay`. Each event is first decoded
+//! with [`FramePrinter::decode`] and then printed with
+//! [`FramePrinter::print`], or both at once with
+//! [`FramePrinter::print_event`]. Frames not matching the filter are not
+//! printed. Events that aren't frames are always printed.
+
+use crate::drivers::driver::{DaliBusEvent, DaliBusEventType};
+use crate::utils::decode::{DecodedFrame, DecoderState};
+use crate::utils::frame_filter::Filter;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// The result of decoding one bus event
+pub struct DecodedEvent {
+    /// Milliseconds since the previous event
+    pub delta_ms: u128,
+    /// Raw frame data, `None` if the event isn't a frame
+    pub data: Option<Vec<u8>>,
+    /// Frames not belonging to this event, e.g. a query that wasn't
+    /// answered before this frame
+    pub unattributed: Vec<DecodedFrame>,
+    /// The decoded frame of this event
+    pub frame: Option<DecodedFrame>,
+}
+
+pub struct FramePrinter {
+    json: bool,
+    decoder: DecoderState,
+    filter: Option<Filter>,
+    start: Option<Instant>,
+    last_ts: Option<Instant>,
+}
+
+impl FramePrinter {
+    /// Create a printer. Print JSON if `json` is true, otherwise text.
+    pub fn new(json: bool, filter: Option<Filter>) -> FramePrinter {
+        FramePrinter {
+            json,
+            decoder: DecoderState::new(),
+            filter,
+            start: None,
+            last_ts: None,
+        }
+    }
+
+    /// Set the time the `after` and `before` filter terms are relative
+    /// to. The timestamp of the first event is used if not set.
+    pub fn set_start(&mut self, start: Instant) {
+        self.start = Some(start);
+    }
+
+    /// Decode an event. Events must be decoded in the order they occurred.
+    pub fn decode(&mut self, event: &DaliBusEvent) -> DecodedEvent {
+        let delta_ms = self
+            .last_ts
+            .map(|last| event.timestamp.saturating_duration_since(last).as_millis())
+            .unwrap_or(0);
+        self.last_ts = Some(event.timestamp);
+        self.start.get_or_insert(event.timestamp);
+        let data = frame_data(&event.event_type);
+        let mut unattributed = self.decoder.decode_event(event);
+        // The frame of this event is always last, preceded by any
+        // unanswered query
+        let frame = if data.is_some() {
+            unattributed.pop()
+        } else {
+            None
+        };
+        DecodedEvent {
+            delta_ms,
+            data,
+            unattributed,
+            frame,
+        }
+    }
+
+    /// Print an event decoded by [`FramePrinter::decode`]
+    pub fn print(&self, event: &DaliBusEvent, decoded: &DecodedEvent) {
+        for frame in &decoded.unattributed {
+            self.print_unattributed(frame, event.timestamp);
+        }
+        if decoded
+            .frame
+            .as_ref()
+            .is_some_and(|frame| !self.show(frame, event.timestamp))
+        {
+            return;
+        }
+        let delta = decoded.delta_ms;
+        if self.json {
+            let line = match &decoded.frame {
+                Some(frame) => json!({"delta_ms": delta, "data": decoded.data, "decoded": frame}),
+                None => json!({"delta_ms": delta, "event": format!("{:?}", event.event_type)}),
+            };
+            println!("{}", line);
+        } else {
+            print!("{:5}:", delta);
+            match (&decoded.data, &decoded.frame) {
+                (Some(data), Some(frame)) => {
+                    let hex: String = data.iter().map(|b| format!(" {:02x}", b)).collect();
+                    println!("{:9} {}", hex, frame)
+                }
+                _ => println!("{:?}", event.event_type),
+            }
+        }
+    }
+
+    /// Decode and print an event
+    pub fn print_event(&mut self, event: &DaliBusEvent) {
+        let decoded = self.decode(event);
+        self.print(event, &decoded);
+    }
+
+    /// Print a query at the end of the traffic that wasn't answered
+    pub fn finish(&mut self) {
+        if let Some(last) = self.last_ts
+            && let Some(frame) = self.decoder.expire(last + Duration::from_secs(1))
+        {
+            self.print_unattributed(&frame, last);
+        }
+    }
+
+    fn show(&self, frame: &DecodedFrame, timestamp: Instant) -> bool {
+        let elapsed = self
+            .start
+            .map(|start| timestamp.saturating_duration_since(start))
+            .unwrap_or_default();
+        self.filter
+            .as_ref()
+            .is_none_or(|f| f.matches(frame, elapsed))
+    }
+
+    fn print_unattributed(&self, frame: &DecodedFrame, timestamp: Instant) {
+        if !self.show(frame, timestamp) {
+            return;
+        }
+        if self.json {
+            println!("{}", json!({ "decoded": frame }));
+        } else {
+            println!("     :{:9} {}", "", frame);
+        }
+    }
+}
+
+/// Raw data of a frame event, `None` for other events
+pub fn frame_data(event_type: &DaliBusEventType) -> Option<Vec<u8>> {
+    match event_type {
+        DaliBusEventType::Frame8(pkt) => Some(vec![*pkt]),
+        DaliBusEventType::Frame16(pkt) => Some(pkt.to_vec()),
+        DaliBusEventType::Frame24(pkt) => Some(pkt.to_vec()),
+        DaliBusEventType::Frame25(pkt) => Some(pkt.to_vec()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_events() {
+        let mut printer = FramePrinter::new(false, None);
+        let start = Instant::now();
+        let mut decode = |ms: u64, event_type: DaliBusEventType| {
+            printer.decode(&DaliBusEvent {
+                timestamp: start + Duration::from_millis(ms),
+                event_type,
+            })
+        };
+        let decoded = decode(0, DaliBusEventType::Frame16([0x05, 0x91]));
+        assert_eq!(decoded.delta_ms, 0);
+        assert_eq!(decoded.data, Some(vec![0x05, 0x91]));
+        assert!(decoded.unattributed.is_empty());
+        assert_eq!(
+            decoded.frame.map(|f| f.to_string()).as_deref(),
+            Some("Addr: 2: Query ballast")
+        );
+        let decoded = decode(100, DaliBusEventType::Frame16([0xfe, 0x00]));
+        assert_eq!(decoded.delta_ms, 100);
+        assert_eq!(
+            decoded
+                .unattributed
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>(),
+            ["Addr: 2: Query ballast → no answer"]
+        );
+        assert_eq!(
+            decoded.frame.map(|f| f.to_string()).as_deref(),
+            Some("Broadcast: Set power = 0")
+        );
+        let decoded = decode(150, DaliBusEventType::BusPowerOff);
+        assert_eq!(decoded.delta_ms, 50);
+        assert!(decoded.data.is_none());
+        assert!(decoded.frame.is_none());
+    }
+}
@@ -4,12 +4,23 @@ use crate::drivers::driver::{DaliDriver, DaliSendResult};
 use crate::drivers::driver_utils::DaliDriverExt;
 use crate::drivers::send_flags::NO_FLAG;
 use crate::gear::cmd_defs as cmd;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
 use std::convert::TryInto;
 use std::error::Error;
 use std::fmt;
+/// Location of the lock byte in memory banks other than 0
+pub const LOCK_BYTE: u8 = 0x02;
+/// Value of the lock byte that allows writing the rest of the bank
+pub const UNLOCKED: u8 = 0x55;
+
 pub enum MemoryError {
     LengthMismatch,
     InvalidMemoryArea,
+    /// Memory bank 0 and locations 0 and 1 of other banks can't be written
+    ReadOnly,
+    /// The location didn't accept the written value
+    VerifyFailed(u8),
 }
 
 impl Error for MemoryError {}
@@ -21,6 +32,10 @@ impl fmt::Display for MemoryError {
             MemoryError::InvalidMemoryArea => {
                 write!(f, "Trying to read an unimplemented memory area")
             }
+            MemoryError::ReadOnly => write!(f, "Trying to write a read-only memory area"),
+            MemoryError::VerifyFailed(location) => {
+                write!(f, "Writing memory location 0x{:02x} failed", location)
+            }
         }
     }
 }
@@ -32,6 +47,7 @@ impl fmt::Debug for MemoryError {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct MemoryBank0Info {
     pub gtin: u64,
     pub firmware_version: u16,
@@ -115,6 +131,154 @@ impl fmt::Display for MemoryBank0Info {
     }
 }
 
+/// Luminaire information in memory bank 1, as defined by DiiA
+/// Specification Part 251. Fields that are unknown or not filled in by
+/// the manufacturer are None.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MemoryBank1Info {
+    pub oem_gtin: Option<u64>,
+    pub oem_id: Option<u64>,
+    pub content_format: u16,
+    /// Last two digits of the year of manufacture
+    pub year: Option<u8>,
+    pub week: Option<u8>,
+    /// Nominal input power in W
+    pub input_power: Option<u16>,
+    /// Input power at minimum dim level in W
+    pub input_power_min_dim: Option<u16>,
+    /// Nominal minimum AC mains voltage in V
+    pub min_mains_voltage: Option<u16>,
+    /// Nominal maximum AC mains voltage in V
+    pub max_mains_voltage: Option<u16>,
+    /// Nominal light output in lm
+    pub light_output: Option<u32>,
+    pub cri: Option<u8>,
+    /// Correlated colour temperature in K
+    pub cct: Option<u16>,
+    pub light_distribution: Option<u8>,
+    pub luminaire_color: Option<String>,
+    pub luminaire_id: Option<String>,
+}
+
+// Locations in memory bank 1
+const OEM_GTIN: usize = 0x03;
+const OEM_ID: usize = 0x09;
+const CONTENT_FORMAT: usize = 0x11;
+const YEAR: usize = 0x13;
+const WEEK: usize = 0x14;
+const INPUT_POWER: usize = 0x15;
+const INPUT_POWER_MIN_DIM: usize = 0x17;
+const MIN_MAINS_VOLTAGE: usize = 0x19;
+const MAX_MAINS_VOLTAGE: usize = 0x1b;
+const LIGHT_OUTPUT: usize = 0x1d;
+const CRI: usize = 0x20;
+const CCT: usize = 0x21;
+const LIGHT_DISTRIBUTION: usize = 0x23;
+const LUMINAIRE_COLOR: usize = 0x24;
+/// Start of the luminaire identification text in memory bank 1
+pub const LUMINAIRE_ID: u8 = 0x3c;
+const BANK_1_LAST: usize = 0x77;
+
+// Big endian value of the bytes, or None if all bytes are 0xff
+fn known(bytes: &[u8]) -> Option<u64> {
+    if bytes.iter().all(|&b| b == 0xff) {
+        None
+    } else {
+        Some(bytes.iter().fold(0, |v, &b| (v << 8) | b as u64))
+    }
+}
+
+// ASCII text terminated by 0x00 or 0xff
+fn text(bytes: &[u8]) -> Option<String> {
+    let end = bytes
+        .iter()
+        .position(|&b| b == 0x00 || b == 0xff)
+        .unwrap_or(bytes.len());
+    let text = String::from_utf8_lossy(&bytes[..end])
+        .trim_end()
+        .to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+impl MemoryBank1Info {
+    /// Decode the content of memory bank 1, starting at location 0
+    pub fn from_bytes(bank: &[u8]) -> Result<MemoryBank1Info, MemoryError> {
+        if bank.len() <= BANK_1_LAST {
+            return Err(MemoryError::InvalidMemoryArea);
+        }
+        let value = |start: usize, len: usize| known(&bank[start..start + len]);
+        Ok(MemoryBank1Info {
+            oem_gtin: value(OEM_GTIN, 6),
+            oem_id: value(OEM_ID, 8),
+            content_format: u16::from_be_bytes([bank[CONTENT_FORMAT], bank[CONTENT_FORMAT + 1]]),
+            year: value(YEAR, 1).map(|v| v as u8),
+            week: value(WEEK, 1).map(|v| v as u8),
+            input_power: value(INPUT_POWER, 2).map(|v| v as u16),
+            input_power_min_dim: value(INPUT_POWER_MIN_DIM, 2).map(|v| v as u16),
+            min_mains_voltage: value(MIN_MAINS_VOLTAGE, 2).map(|v| v as u16),
+            max_mains_voltage: value(MAX_MAINS_VOLTAGE, 2).map(|v| v as u16),
+            light_output: value(LIGHT_OUTPUT, 3).map(|v| v as u32),
+            cri: value(CRI, 1).map(|v| v as u8),
+            cct: value(CCT, 2).map(|v| v as u16),
+            light_distribution: value(LIGHT_DISTRIBUTION, 1).map(|v| v as u8),
+            luminaire_color: text(&bank[LUMINAIRE_COLOR..LUMINAIRE_ID as usize]),
+            luminaire_id: text(&bank[LUMINAIRE_ID as usize..=BANK_1_LAST]),
+        })
+    }
+}
+
+fn opt_str<T: fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(v) => v.to_string(),
+        None => String::from("-"),
+    }
+}
+
+impl fmt::Display for MemoryBank1Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "OEM GTIN: {}", opt_str(&self.oem_gtin))?;
+        writeln!(f, "OEM identification number: {}", opt_str(&self.oem_id))?;
+        writeln!(f, "Content format: 0x{:04x}", self.content_format)?;
+        match (self.year, self.week) {
+            (Some(year), Some(week)) => writeln!(f, "Manufactured: 20{:02} week {}", year, week)?,
+            (Some(year), None) => writeln!(f, "Manufactured: 20{:02}", year)?,
+            _ => writeln!(f, "Manufactured: -")?,
+        }
+        writeln!(f, "Nominal input power: {} W", opt_str(&self.input_power))?;
+        writeln!(
+            f,
+            "Power at minimum dim level: {} W",
+            opt_str(&self.input_power_min_dim)
+        )?;
+        writeln!(
+            f,
+            "Mains voltage: {} - {} V",
+            opt_str(&self.min_mains_voltage),
+            opt_str(&self.max_mains_voltage)
+        )?;
+        writeln!(
+            f,
+            "Nominal light output: {} lm",
+            opt_str(&self.light_output)
+        )?;
+        writeln!(f, "CRI: {}", opt_str(&self.cri))?;
+        writeln!(f, "CCT: {} K", opt_str(&self.cct))?;
+        writeln!(
+            f,
+            "Light distribution type: {}",
+            opt_str(&self.light_distribution)
+        )?;
+        writeln!(f, "Luminaire color: {}", opt_str(&self.luminaire_color))?;
+        writeln!(
+            f,
+            "Luminaire identification: {}",
+            opt_str(&self.luminaire_id)
+        )?;
+        Ok(())
+    }
+}
+
 pub async fn read_range(
     d: &mut dyn DaliDriver,
     addr: Short,
@@ -140,16 +304,53 @@ pub async fn read_range(
     let dtr = send16::query(d, cmd::QUERY_CONTENT_DTR0(addr), NO_FLAG)
         .await
         .check_answer()?;
-    if length as usize == data.len() {
-        if dtr != length + start {
-            return Err(Box::new(MemoryError::LengthMismatch));
-        }
-    } else if dtr != data.len() as u8 + 1 + start {
+    // DTR0 stops incrementing at 0xff
+    let read = if length as usize == data.len() {
+        length as u16
+    } else {
+        data.len() as u16 + 1
+    };
+    if dtr as u16 != (start as u16 + read).min(0xff) {
         return Err(Box::new(MemoryError::LengthMismatch));
     }
     Ok(data)
 }
 
+/// Last accessible location of a memory bank, or None if the bank isn't
+/// implemented
+pub async fn last_location(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    bank: u8,
+) -> Result<Option<u8>, Box<dyn Error>> {
+    Ok(read_range(d, addr, bank, 0, 1).await?.first().copied())
+}
+
+/// Number of the last implemented memory bank
+pub async fn last_memory_bank(d: &mut dyn DaliDriver, addr: Short) -> Result<u8, Box<dyn Error>> {
+    match read_range(d, addr, 0, 0x02, 1).await?.first() {
+        Some(&last) => Ok(last),
+        None => Err(Box::new(MemoryError::InvalidMemoryArea)),
+    }
+}
+
+/// Read a whole memory bank, from location 0 up to the last accessible
+/// location
+pub async fn read_bank(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    bank: u8,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let Some(last) = last_location(d, addr, bank).await? else {
+        return Err(Box::new(MemoryError::InvalidMemoryArea));
+    };
+    let mut data = vec![last];
+    if last > 0 {
+        data.extend(read_range(d, addr, bank, 1, last).await?);
+    }
+    Ok(data)
+}
+
 pub async fn read_bank_0(
     d: &mut dyn DaliDriver,
     addr: Short,
@@ -179,3 +380,195 @@ pub async fn read_bank_0(
 
     Ok(info)
 }
+
+// Write consecutive locations and check that each one is accepted
+async fn write_locations(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    bank: u8,
+    start: u8,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    d.send_frame16(&cmd::DTR1(bank).0, NO_FLAG)
+        .await
+        .check_send()?;
+    d.send_frame16(&cmd::DTR0(start).0, NO_FLAG)
+        .await
+        .check_send()?;
+    send16::cmd(d, cmd::ENABLE_WRITE_MEMORY(addr), NO_FLAG)
+        .await
+        .check_send()?;
+    for (location, &value) in (start..).zip(data) {
+        match send16::query(d, cmd::WRITE_MEMORY_LOCATION(value), NO_FLAG).await {
+            DaliSendResult::Answer(a) if a == value => {}
+            DaliSendResult::Answer(_) | DaliSendResult::Timeout => {
+                return Err(Box::new(MemoryError::VerifyFailed(location)));
+            }
+            e => return Err(Box::new(e)),
+        }
+    }
+    Ok(())
+}
+
+/// Write to a memory bank and verify by reading back.
+///
+/// The range must start at the lock byte or later. If it includes
+/// lockable locations the bank is unlocked while writing, and the lock
+/// byte is then restored, unless the range sets it.
+pub async fn write_range(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    bank: u8,
+    start: u8,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    if bank == 0 || start < LOCK_BYTE {
+        return Err(Box::new(MemoryError::ReadOnly));
+    }
+    if data.is_empty() {
+        return Ok(());
+    }
+    let Some(last) = last_location(d, addr, bank).await? else {
+        return Err(Box::new(MemoryError::InvalidMemoryArea));
+    };
+    if start as usize + data.len() - 1 > last as usize {
+        return Err(Box::new(MemoryError::InvalidMemoryArea));
+    }
+    // Lock byte to write last, either from the range or the one to restore
+    let (mut lock, body_start, body) = if start == LOCK_BYTE {
+        (Some(data[0]), start + 1, &data[1..])
+    } else {
+        (None, start, data)
+    };
+    if !body.is_empty() {
+        let old_lock = read_range(d, addr, bank, LOCK_BYTE, 1).await?;
+        let Some(&old_lock) = old_lock.first() else {
+            return Err(Box::new(MemoryError::InvalidMemoryArea));
+        };
+        if old_lock != UNLOCKED {
+            write_locations(d, addr, bank, LOCK_BYTE, &[UNLOCKED]).await?;
+            lock.get_or_insert(old_lock);
+        }
+        let res = write_locations(d, addr, bank, body_start, body).await;
+        if res.is_err() {
+            if let Some(lock) = lock {
+                let _ = write_locations(d, addr, bank, LOCK_BYTE, &[lock]).await;
+            }
+            return res;
+        }
+    }
+    if let Some(lock) = lock {
+        write_locations(d, addr, bank, LOCK_BYTE, &[lock]).await?;
+    }
+    let read = read_range(d, addr, bank, start, data.len() as u8).await?;
+    if let Some(pos) = (0..data.len()).find(|&i| read.get(i) != Some(&data[i])) {
+        return Err(Box::new(MemoryError::VerifyFailed(start + pos as u8)));
+    }
+    Ok(())
+}
+
+/// Read the luminaire information in memory bank 1. Returns None if the
+/// gear doesn't implement memory bank 1.
+pub async fn read_bank_1(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+) -> Result<Option<MemoryBank1Info>, Box<dyn Error>> {
+    match last_location(d, addr, 1).await? {
+        Some(last) if last as usize >= BANK_1_LAST => {}
+        _ => return Ok(None),
+    }
+    let bank = read_bank(d, addr, 1).await?;
+    Ok(Some(MemoryBank1Info::from_bytes(&bank)?))
+}
+
+/// Set the luminaire identification text in memory bank 1. The text is
+/// truncated to fit and padded with zeros.
+pub async fn write_luminaire_id(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    id: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut data = vec![0x00; BANK_1_LAST + 1 - LUMINAIRE_ID as usize];
+    let len = id.len().min(data.len());
+    data[..len].copy_from_slice(&id.as_bytes()[..len]);
+    write_range(d, addr, 1, LUMINAIRE_ID, &data).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn values() {
+        assert_eq!(known(&[0x12, 0x34]), Some(0x1234));
+        assert_eq!(known(&[0xff, 0x00]), Some(0xff00));
+        assert_eq!(known(&[0xff, 0xff]), None);
+        assert_eq!(text(b"Hall  \0xyz"), Some(String::from("Hall")));
+        assert_eq!(text(b"Hall\xff"), Some(String::from("Hall")));
+        assert_eq!(text(b"Hall"), Some(String::from("Hall")));
+        assert_eq!(text(b"\0Hall"), None);
+        assert_eq!(text(&[0xff; 4]), None);
+    }
+
+    #[test]
+    fn bank_1() {
+        // Locations as listed in the memory bank 1 table of DiiA Part 251
+        let mut bank = vec![0xff; 0x78];
+        bank[0x00] = 0x77;
+        bank[0x03..=0x08].copy_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x04, 0xd2]);
+        bank[0x09..=0x10].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+        bank[0x11..=0x12].copy_from_slice(&[0x00, 0x03]);
+        bank[0x13] = 24;
+        bank[0x14] = 17;
+        bank[0x15..=0x16].copy_from_slice(&[0x00, 0x28]);
+        bank[0x17..=0x18].copy_from_slice(&[0x01, 0x02]);
+        bank[0x19..=0x1a].copy_from_slice(&[0x00, 0xdc]);
+        bank[0x1b..=0x1c].copy_from_slice(&[0x00, 0xf0]);
+        bank[0x1d..=0x1f].copy_from_slice(&[0x01, 0x86, 0xa0]);
+        bank[0x20] = 90;
+        bank[0x21..=0x22].copy_from_slice(&[0x0f, 0xa0]);
+        bank[0x23] = 3;
+        bank[0x24..=0x3b].copy_from_slice(b"Signal white RAL 9003\0\0\0");
+        bank[0x3c..0x41].copy_from_slice(b"Desk\0");
+        let info = MemoryBank1Info::from_bytes(&bank).unwrap();
+        assert_eq!(
+            info,
+            MemoryBank1Info {
+                oem_gtin: Some(1234),
+                oem_id: Some(0x1234),
+                content_format: 3,
+                year: Some(24),
+                week: Some(17),
+                input_power: Some(40),
+                input_power_min_dim: Some(0x0102),
+                min_mains_voltage: Some(220),
+                max_mains_voltage: Some(240),
+                light_output: Some(100000),
+                cri: Some(90),
+                cct: Some(4000),
+                light_distribution: Some(3),
+                luminaire_color: Some(String::from("Signal white RAL 9003")),
+                luminaire_id: Some(String::from("Desk")),
+            }
+        );
+        let text = info.to_string();
+        assert!(text.contains("Manufactured: 2024 week 17\n"));
+        assert!(text.contains("Mains voltage: 220 - 240 V\n"));
+        assert!(text.contains("Nominal light output: 100000 lm\n"));
+        assert!(text.contains("CCT: 4000 K\n"));
+
+        // Unknown values
+        bank[0x17..=0x18].fill(0xff);
+        bank[0x1d..=0x1f].fill(0xff);
+        let info = MemoryBank1Info::from_bytes(&bank).unwrap();
+        assert_eq!(info.input_power_min_dim, None);
+        assert_eq!(info.light_output, None);
+        assert_eq!(info.min_mains_voltage, Some(220));
+        assert_eq!(info.cri, Some(90));
+
+        assert!(matches!(
+            MemoryBank1Info::from_bytes(&bank[..BANK_1_LAST]),
+            Err(MemoryError::InvalidMemoryArea)
+        ));
+    }
+}
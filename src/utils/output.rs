@@ -0,0 +1,203 @@
+//! Machine-readable output for the command line tools.
+//!
+//! Records are written either as JSON, one object per line, or as CSV. CSV
+//! columns are the top-level fields of the first record, in alphabetical
+//! order, preceded by a header line. Nested values like lists or structs
+//! are written as JSON text in a single cell and missing values as empty
+//! cells.
+//!
+//! The columns can't change once the header is written, so records should
+//! always serialize the same fields. Write `None` as null rather than
+//! skipping it. A record with a field that isn't in the header is an
+//! error.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Debug)]
+pub struct UnknownFormat(String);
+
+impl fmt::Display for UnknownFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown output format '{}'", self.0)
+    }
+}
+
+impl std::error::Error for UnknownFormat {}
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+    fn from_str(s: &str) -> Result<Format, UnknownFormat> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+/// Values for a `--format` option, `text` being the normal human-readable
+/// output of the tool
+pub const FORMAT_NAMES: [&str; 3] = ["text", "json", "csv"];
+
+fn csv_cell(value: Option<&Value>) -> String {
+    let text = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(v) => v.to_string(),
+    };
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+/// Writes serializable records in one of the formats
+pub struct RecordWriter<W: Write> {
+    writer: W,
+    format: Format,
+    // CSV columns, set by the first record
+    columns: Option<Vec<String>>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(writer: W, format: Format) -> RecordWriter<W> {
+        RecordWriter {
+            writer,
+            format,
+            columns: None,
+        }
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&mut self, record: &T) -> io::Result<()> {
+        match self.format {
+            Format::Json => {
+                serde_json::to_writer(&mut self.writer, record)?;
+                writeln!(self.writer)
+            }
+            Format::Csv => {
+                let fields = match serde_json::to_value(record)? {
+                    Value::Object(fields) => fields,
+                    value => Map::from_iter([("value".to_string(), value)]),
+                };
+                if let Some(field) = self
+                    .columns
+                    .as_ref()
+                    .and_then(|columns| fields.keys().find(|f| !columns.contains(f)))
+                {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Field '{}' is not a CSV column", field),
+                    ));
+                }
+                if self.columns.is_none() {
+                    let mut columns: Vec<String> = fields.keys().cloned().collect();
+                    columns.sort();
+                    let header: Vec<String> = columns
+                        .iter()
+                        .map(|c| csv_cell(Some(&Value::String(c.clone()))))
+                        .collect();
+                    writeln!(self.writer, "{}", header.join(","))?;
+                    self.columns = Some(columns);
+                }
+                let cells: Vec<String> = self
+                    .columns
+                    .iter()
+                    .flatten()
+                    .map(|c| csv_cell(fields.get(c)))
+                    .collect();
+                writeln!(self.writer, "{}", cells.join(","))
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_derive::Serialize;
+
+    #[derive(Serialize)]
+    struct Record {
+        name: String,
+        level: Option<u8>,
+        groups: Vec<u8>,
+    }
+
+    fn records() -> [Record; 2] {
+        [
+            Record {
+                name: "Hall, east".to_string(),
+                level: Some(254),
+                groups: vec![1, 2],
+            },
+            Record {
+                name: "Office \"A\"".to_string(),
+                level: None,
+                groups: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn json() {
+        let mut writer = RecordWriter::new(Vec::new(), Format::Json);
+        for record in &records() {
+            writer.write(record).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "{\"name\":\"Hall, east\",\"level\":254,\"groups\":[1,2]}\n\
+             {\"name\":\"Office \\\"A\\\"\",\"level\":null,\"groups\":[]}\n"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let mut writer = RecordWriter::new(Vec::new(), Format::Csv);
+        for record in &records() {
+            writer.write(record).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "groups,level,name\n\
+             \"[1,2]\",254,\"Hall, east\"\n\
+             [],,\"Office \"\"A\"\"\"\n"
+        );
+    }
+
+    #[test]
+    fn csv_columns() {
+        #[derive(Serialize)]
+        struct Level {
+            level: u8,
+        }
+        let mut writer = RecordWriter::new(Vec::new(), Format::Csv);
+        writer.write(&Level { level: 1 }).unwrap();
+        // Missing fields are empty, extra fields can't be written
+        writer.write(&serde_json::json!({})).unwrap();
+        assert!(writer.write(&records()[0]).is_err());
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "level\n1\n\n"
+        );
+    }
+}
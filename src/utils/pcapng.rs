@@ -0,0 +1,266 @@
+//! Export of bus traffic as pcapng files that can be opened in Wireshark.
+//!
+//! There is no registered link-layer type for DALI so the packets use one of
+//! the user types, [`LINKTYPE_USER0`] by default. Each packet starts with a
+//! four byte header followed by the frame data:
+//!
+//! | Offset | Content                                                     |
+//! |--------|-------------------------------------------------------------|
+//! | 0      | Event: 1 frame, 2 framing error, 3 bus power off, 4 bus power on, 5 overrun |
+//! | 1      | Frame length in bits (8, 16, 24 or 25), 0 if not a frame    |
+//! | 2      | Direction: 0 unknown, 1 forward, 2 backward                 |
+//! | 3      | Reserved, always 0                                          |
+//! | 4..    | Frame data as in [`DaliBusEventType`]                       |
+//!
+//! The drivers don't report which device sent a frame, so the direction is
+//! derived from the frame length. Backward frames are always 8 bits long.
+//!
+//! A comment, e.g. the decoded frame, can be attached to each packet. It is
+//! shown by Wireshark and can be used in filters like
+//! `frame.comment contains "QUERY"`.
+
+use crate::drivers::driver::{DaliBusEvent, DaliBusEventType};
+use std::io::{self, Write};
+use std::time::{Instant, SystemTime};
+
+/// Link-layer type used by default
+pub const LINKTYPE_USER0: u16 = 147;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_DESCRIPTION: u16 = 3;
+
+pub const EVENT_FRAME: u8 = 1;
+pub const EVENT_FRAMING_ERROR: u8 = 2;
+pub const EVENT_BUS_POWER_OFF: u8 = 3;
+pub const EVENT_BUS_POWER_ON: u8 = 4;
+pub const EVENT_OVERRUN: u8 = 5;
+
+pub const DIRECTION_UNKNOWN: u8 = 0;
+pub const DIRECTION_FORWARD: u8 = 1;
+pub const DIRECTION_BACKWARD: u8 = 2;
+
+/// Build the packet data for an event
+pub fn packet_data(event_type: &DaliBusEventType) -> Vec<u8> {
+    let (event, bits, direction, data): (u8, u8, u8, &[u8]) = match event_type {
+        DaliBusEventType::Frame8(d) => {
+            (EVENT_FRAME, 8, DIRECTION_BACKWARD, std::slice::from_ref(d))
+        }
+        DaliBusEventType::Frame16(d) => (EVENT_FRAME, 16, DIRECTION_FORWARD, d),
+        DaliBusEventType::Frame24(d) => (EVENT_FRAME, 24, DIRECTION_FORWARD, d),
+        DaliBusEventType::Frame25(d) => (EVENT_FRAME, 25, DIRECTION_FORWARD, d),
+        DaliBusEventType::FramingError => (EVENT_FRAMING_ERROR, 0, DIRECTION_UNKNOWN, &[]),
+        DaliBusEventType::BusPowerOff => (EVENT_BUS_POWER_OFF, 0, DIRECTION_UNKNOWN, &[]),
+        DaliBusEventType::BusPowerOn => (EVENT_BUS_POWER_ON, 0, DIRECTION_UNKNOWN, &[]),
+        DaliBusEventType::Overrun => (EVENT_OVERRUN, 0, DIRECTION_UNKNOWN, &[]),
+    };
+    let mut packet = vec![event, bits, direction, 0];
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn padding(len: usize) -> usize {
+    (4 - len % 4) % 4
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    buf.resize(buf.len() + padding(value.len()), 0);
+}
+
+// Options that don't fit in a single option are truncated
+fn push_str_option(buf: &mut Vec<u8>, code: u16, value: &str) {
+    if !value.is_empty() {
+        let bytes = value.as_bytes();
+        push_option(buf, code, &bytes[..bytes.len().min(u16::MAX as usize - 3)]);
+    }
+}
+
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_len.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_len.to_le_bytes())
+}
+
+/// Writes bus events to a pcapng file
+pub struct PcapngWriter<W: Write> {
+    writer: W,
+    start: Instant,
+    // Microseconds since the Unix epoch at start
+    start_us: u64,
+}
+
+impl<W: Write> PcapngWriter<W> {
+    /// Write the section header and a single interface description.
+    ///
+    /// `start` is the time the capture started. It's used to convert the
+    /// event timestamps to wall clock time.
+    pub fn new(writer: W, driver: &str, site: &str, start: Instant) -> io::Result<PcapngWriter<W>> {
+        Self::with_link_type(writer, driver, site, start, LINKTYPE_USER0)
+    }
+
+    /// Like [`PcapngWriter::new`] but with a different link-layer type,
+    /// e.g. if USER0 is already used for something else.
+    pub fn with_link_type(
+        mut writer: W,
+        driver: &str,
+        site: &str,
+        start: Instant,
+        link_type: u16,
+    ) -> io::Result<PcapngWriter<W>> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length not specified
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        push_str_option(&mut shb, SHB_USERAPPL, "dali_tools");
+        push_option(&mut shb, OPT_END, &[]);
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &shb)?;
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&link_type.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snap length limit
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        push_str_option(&mut idb, IF_NAME, driver);
+        push_str_option(&mut idb, IF_DESCRIPTION, site);
+        push_option(&mut idb, OPT_END, &[]);
+        write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &idb)?;
+
+        let start_us = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|t| t.as_micros() as u64)
+            .unwrap_or(0)
+            .saturating_sub(start.elapsed().as_micros() as u64);
+        Ok(PcapngWriter {
+            writer,
+            start,
+            start_us,
+        })
+    }
+
+    /// Append an event as a packet, optionally with a comment
+    pub fn write_event(&mut self, event: &DaliBusEvent, comment: Option<&str>) -> io::Result<()> {
+        let ts = self.start_us
+            + event
+                .timestamp
+                .saturating_duration_since(self.start)
+                .as_micros() as u64;
+        let data = packet_data(&event.event_type);
+        let mut epb = Vec::new();
+        // Interface ID
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ts as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&data);
+        epb.resize(epb.len() + padding(data.len()), 0);
+        if let Some(comment) = comment {
+            push_str_option(&mut epb, OPT_COMMENT, comment);
+            push_option(&mut epb, OPT_END, &[]);
+        }
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &epb)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    // Split the file into (type, body) blocks, checking the lengths
+    fn blocks(data: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut blocks = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let len = u32_at(data, pos + 4) as usize;
+            assert_eq!(len % 4, 0);
+            assert_eq!(u32_at(data, pos + len - 4) as usize, len);
+            blocks.push((u32_at(data, pos), &data[pos + 8..pos + len - 4]));
+            pos += len;
+        }
+        assert_eq!(pos, data.len());
+        blocks
+    }
+
+    #[test]
+    fn write_packets() {
+        let start = Instant::now();
+        let mut writer = PcapngWriter::new(Vec::new(), "dali_rpi", "Office", start).unwrap();
+        writer
+            .write_event(
+                &DaliBusEvent {
+                    timestamp: start + Duration::from_millis(3),
+                    event_type: DaliBusEventType::Frame16([0x03, 0x99]),
+                },
+                Some("Addr: 1: Query device type"),
+            )
+            .unwrap();
+        writer
+            .write_event(
+                &DaliBusEvent {
+                    timestamp: start + Duration::from_millis(15),
+                    event_type: DaliBusEventType::Frame8(0x06),
+                },
+                None,
+            )
+            .unwrap();
+        let data = writer.into_inner();
+        let blocks = blocks(&data);
+        assert_eq!(blocks.len(), 4);
+
+        let (block_type, shb) = blocks[0];
+        assert_eq!(block_type, SECTION_HEADER_BLOCK);
+        assert_eq!(u32_at(shb, 0), BYTE_ORDER_MAGIC);
+
+        let (block_type, idb) = blocks[1];
+        assert_eq!(block_type, INTERFACE_DESCRIPTION_BLOCK);
+        assert_eq!(&idb[0..2], &LINKTYPE_USER0.to_le_bytes());
+        assert_eq!(&idb[8..20], b"\x02\x00\x08\x00dali_rpi");
+
+        let (block_type, epb) = blocks[2];
+        assert_eq!(block_type, ENHANCED_PACKET_BLOCK);
+        let ts1 = (u32_at(epb, 4) as u64) << 32 | u32_at(epb, 8) as u64;
+        assert_eq!(u32_at(epb, 12), 6);
+        assert_eq!(
+            &epb[20..26],
+            &[EVENT_FRAME, 16, DIRECTION_FORWARD, 0, 0x03, 0x99]
+        );
+        // Padding, then the comment
+        assert_eq!(&epb[28..30], &OPT_COMMENT.to_le_bytes());
+        assert_eq!(&epb[32..58], b"Addr: 1: Query device type");
+
+        let (_, epb) = blocks[3];
+        let ts2 = (u32_at(epb, 4) as u64) << 32 | u32_at(epb, 8) as u64;
+        assert_eq!(ts2 - ts1, 12_000);
+        assert_eq!(u32_at(epb, 12), 5);
+        assert_eq!(&epb[20..25], &[EVENT_FRAME, 8, DIRECTION_BACKWARD, 0, 0x06]);
+        // No options
+        assert_eq!(epb.len(), 28);
+    }
+}
@@ -0,0 +1,229 @@
+//! Replacement of failed control gear.
+//!
+//! A replacement gear comes up without a short address. It is matched to a
+//! gear in a saved [`BusSnapshot`] that is missing from the bus by the GTIN
+//! in memory bank 0. The replacement gets the short address of the missing
+//! gear and a copy of its groups, scenes, levels and fade settings.
+//!
+//! The GTIN can only be read through a short address, so each unaddressed
+//! gear is temporarily given the short address of a missing gear while it's
+//! examined. Gear that doesn't match any missing gear is left unaddressed.
+
+use crate::common::address::Long;
+use crate::common::commands::Commands;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::gear::commands_102::Commands102;
+use crate::utils::address_assignment::{self, Error};
+use crate::utils::discover::{self, Discovered};
+use crate::utils::memory_banks;
+use crate::utils::snapshot::{self, BusSnapshot, GearSnapshot, SettingDiff};
+use log::debug;
+use std::fmt;
+
+/// A missing gear that has been replaced
+#[derive(Debug, Clone)]
+pub struct Replacement {
+    /// Saved configuration of the missing gear
+    pub old: GearSnapshot,
+    /// The replacement as found, before the settings were copied
+    pub new: GearSnapshot,
+    /// Settings copied from the missing gear
+    pub settings: Vec<SettingDiff>,
+}
+
+impl fmt::Display for Replacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: replaced by", self.old)?;
+        if let Some(random) = self.new.random_address {
+            write!(f, " 0x{random:06x}")?;
+        }
+        if let Some(id) = self.new.id_number {
+            write!(f, " S/N {id}")?;
+        }
+        write!(f, ", {} settings copied", self.settings.len())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplaceReport {
+    pub replaced: Vec<Replacement>,
+    /// Missing gear without a replacement
+    pub missing: Vec<GearSnapshot>,
+    /// Random addresses of unaddressed gear that didn't match any missing
+    /// gear
+    pub unmatched: Vec<Long>,
+}
+
+/// Saved gear that isn't found on the bus and whose short address is
+/// free. Only gear with a known GTIN can be replaced.
+pub fn missing_gear(saved: &BusSnapshot, live: &BusSnapshot) -> Vec<GearSnapshot> {
+    saved
+        .gear
+        .iter()
+        .filter(|gear| {
+            gear.gtin.is_some_and(|gtin| gtin != 0)
+                && live.find(gear).is_none()
+                && !live
+                    .gear
+                    .iter()
+                    .any(|g| g.short_address == gear.short_address)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Random addresses of all gear without a short address
+pub async fn find_unaddressed(d: &mut dyn DaliDriver) -> Result<Vec<Long>, DaliSendResult> {
+    let mut unaddressed = Vec::new();
+    let mut found = async |device: Discovered| {
+        if let (None, Some(long), false) = (device.short, device.long, device.long_conflict) {
+            unaddressed.push(long);
+        }
+    };
+    discover::find_quick(&mut Commands102::new(d), &mut found).await?;
+    Ok(unaddressed)
+}
+
+// Give the unaddressed gear a temporary address and check if it matches
+// any of the missing gear. The gear is left at the matching address.
+async fn try_gear(
+    d: &mut dyn DaliDriver,
+    long: Long,
+    missing: &[GearSnapshot],
+) -> Result<Option<usize>, Error<DaliSendResult>> {
+    let Some(temp) = missing.first().map(|gear| gear.short_addr()) else {
+        return Ok(None);
+    };
+    let mut commands = Commands102::new(d);
+    address_assignment::program_short_address(&mut commands, long, temp).await?;
+    let matched = match memory_banks::read_bank_0(d, temp, 0, 0, 0).await {
+        Ok(bank0) => missing.iter().position(|gear| {
+            gear.gtin == Some(bank0.gtin)
+                && gear
+                    .gear_index
+                    .is_none_or(|index| index == bank0.control_gear_index)
+        }),
+        Err(e) => {
+            debug!("Failed to read memory bank 0 of 0x{:06x}: {}", long, e);
+            None
+        }
+    };
+    let mut commands = Commands102::new(d);
+    match matched {
+        Some(index) => {
+            let short = missing[index].short_addr();
+            if short != temp {
+                address_assignment::program_short_address(&mut commands, long, short).await?;
+            }
+        }
+        None => address_assignment::clear_short_address(&mut commands, long).await?,
+    }
+    Ok(matched)
+}
+
+/// Find replacements for missing gear among the unaddressed gear and
+/// configure them as the missing gear.
+pub async fn replace(
+    d: &mut dyn DaliDriver,
+    saved: &BusSnapshot,
+) -> Result<ReplaceReport, Error<DaliSendResult>> {
+    let live = snapshot::read_snapshot(d, &saved.site).await?;
+    let mut report = ReplaceReport {
+        missing: missing_gear(saved, &live),
+        ..ReplaceReport::default()
+    };
+    if report.missing.is_empty() {
+        return Ok(report);
+    }
+    let unaddressed = find_unaddressed(d).await?;
+
+    Commands102::new(d).initialise_no_addr().await?;
+    let mut assigned = Vec::new();
+    for long in unaddressed {
+        let res = try_gear(d, long, &report.missing).await;
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                let _ = Commands102::new(d).terminate().await;
+                return Err(e);
+            }
+        };
+        match res {
+            Some(index) => assigned.push(report.missing.remove(index)),
+            None => report.unmatched.push(long),
+        }
+    }
+    Commands102::new(d).terminate().await?;
+
+    for old in assigned {
+        let addr = old.short_addr();
+        let Some(new) = snapshot::read_gear(d, addr).await? else {
+            return Err(Error::AddressValidation);
+        };
+        let settings = old.changed_settings(&new);
+        snapshot::restore_settings(d, addr, &old, &settings).await?;
+        report.replaced.push(Replacement { old, new, settings });
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gear(short_address: u8, random: u32, gtin: u64, id_number: u64) -> GearSnapshot {
+        GearSnapshot {
+            short_address,
+            random_address: Some(random),
+            gtin: Some(gtin),
+            id_number: Some(id_number),
+            gear_index: Some(0),
+            ..GearSnapshot::default()
+        }
+    }
+
+    #[test]
+    fn missing() {
+        let saved = BusSnapshot {
+            gear: vec![
+                gear(0, 0x111111, 1234, 1),
+                gear(1, 0x222222, 1234, 2),
+                gear(2, 0x333333, 0, 3),
+                gear(3, 0x444444, 1234, 4),
+                gear(4, 0x555555, 1234, 5),
+            ],
+            ..BusSnapshot::default()
+        };
+        let live = BusSnapshot {
+            gear: vec![
+                // Same gear, found by random address
+                gear(0, 0x111111, 1234, 1),
+                // Same gear with a new random address, found by identity
+                gear(1, 0x666666, 1234, 2),
+                // Another gear using the short address of a missing one
+                gear(3, 0x777777, 5678, 7),
+            ],
+            ..BusSnapshot::default()
+        };
+        // Gear 2 has no GTIN and gear 3 has its short address taken
+        let missing = missing_gear(&saved, &live);
+        assert_eq!(missing, [saved.gear[4].clone()]);
+        assert!(missing_gear(&saved, &saved).is_empty());
+    }
+
+    #[test]
+    fn display() {
+        let replacement = Replacement {
+            old: gear(4, 0x555555, 1234, 5),
+            new: gear(63, 0x888888, 1234, 8),
+            settings: Vec::new(),
+        };
+        assert_eq!(
+            replacement.to_string(),
+            format!(
+                "{}: replaced by 0x888888 S/N 8, 0 settings copied",
+                replacement.old
+            )
+        );
+    }
+}
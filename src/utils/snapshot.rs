@@ -0,0 +1,609 @@
+//! Backup and restore of the configuration of all control gear on a bus.
+//!
+//! A [`BusSnapshot`] contains the settings read by
+//! [`read_gear_info`](crate::utils::device_info::read_gear_info) for every
+//! gear together with its identity, the random address and the GTIN and
+//! identification number from memory bank 0. It can be saved as JSON, with
+//! the `serde` feature, and later compared to the bus with [`diff`] or
+//! written back with [`restore`].
+//!
+//! The status byte and the device type specific state are saved as well.
+//! Only the settings in [`Setting`] are compared and restored, the rest is
+//! state the gear reports, such as failure flags and battery charge, and
+//! can't be written.
+//!
+//! Gear is matched by random address or, if that has changed, by GTIN,
+//! identification number and logical unit index. The short address is not
+//! used for matching since it is often changed when an installation is
+//! re-commissioned.
+
+use crate::common::address::Short;
+use crate::common::commands::Commands;
+use crate::drivers::driver::{DaliDriver, DaliSendResult};
+use crate::gear::address::Address;
+use crate::gear::cmd_defs as cmd;
+use crate::gear::commands_102::Commands102;
+use crate::gear::dt1::{self, Dt1Info};
+use crate::gear::dt6::{self, Dt6Info};
+use crate::gear::dt7::{self, Dt7Info};
+use crate::utils::device_info::{self, GearInfo};
+use crate::utils::memory_banks;
+#[cfg(feature = "serde")]
+use serde_derive::{Deserialize, Serialize};
+#[cfg(feature = "serde")]
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "serde")]
+use std::io::{Read, Write};
+use std::time::SystemTime;
+
+#[cfg(feature = "serde")]
+const FORMAT_NAME: &str = "dali-snapshot";
+#[cfg(feature = "serde")]
+const FORMAT_VERSION: u32 = 1;
+
+/// Identity and settings of a single control gear
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GearSnapshot {
+    /// Short address, 0 to 63
+    pub short_address: u8,
+    pub random_address: Option<u32>,
+    pub gtin: Option<u64>,
+    pub id_number: Option<u64>,
+    /// Index of this logical unit within the bus unit
+    pub gear_index: Option<u8>,
+    pub version: Option<u8>,
+    pub device_types: Vec<u8>,
+    pub light_source_types: Vec<u8>,
+    pub operating_mode: Option<u8>,
+    pub physical_min: Option<u8>,
+    pub actual_level: Option<u8>,
+    pub groups: Option<u16>,
+    /// 255 for scenes the gear isn't part of
+    pub scenes: Option<[u8; 16]>,
+    pub min_level: Option<u8>,
+    pub max_level: Option<u8>,
+    pub power_on_level: Option<u8>,
+    pub failure_level: Option<u8>,
+    /// Fade time in the upper four bits, fade rate in the lower
+    pub fade: Option<u8>,
+    pub extended_fade_time: Option<u8>,
+    /// Answer to QUERY STATUS
+    pub status: Option<u8>,
+    pub dt1: Option<Dt1Info>,
+    pub dt6: Option<Dt6Info>,
+    pub dt7: Option<Dt7Info>,
+}
+
+impl GearSnapshot {
+    pub fn from_info(info: &GearInfo) -> GearSnapshot {
+        GearSnapshot {
+            short_address: info.short_addr().value(),
+            version: info.version(),
+            device_types: info.device_types().iter().map(|dt| dt.value()).collect(),
+            light_source_types: info.light_source_types().to_vec(),
+            operating_mode: info.operating_mode(),
+            physical_min: info.physical_min(),
+            actual_level: info.actual_level(),
+            groups: info.groups(),
+            scenes: info.scenes(),
+            min_level: info.min_level(),
+            max_level: info.max_level(),
+            power_on_level: info.power_on_level(),
+            failure_level: info.failure_level(),
+            fade: info.fade(),
+            extended_fade_time: info.extended_fade_time(),
+            status: info.status().map(|status| status.value()),
+            dt1: info.dt1().cloned(),
+            dt6: info.dt6().cloned(),
+            dt7: info.dt7().cloned(),
+            ..GearSnapshot::default()
+        }
+    }
+
+    pub fn short_addr(&self) -> Short {
+        Short::new(self.short_address & 0x3f)
+    }
+
+    /// True if both describe the same physical gear
+    pub fn same_gear(&self, other: &GearSnapshot) -> bool {
+        self.same_random_address(other) || self.same_identity(other)
+    }
+
+    fn same_random_address(&self, other: &GearSnapshot) -> bool {
+        self.random_address.is_some() && self.random_address == other.random_address
+    }
+
+    // GTIN 0 and identification number 0 mean that they aren't set
+    fn same_identity(&self, other: &GearSnapshot) -> bool {
+        self.gtin.is_some_and(|gtin| gtin != 0)
+            && self.id_number.is_some_and(|id| id != 0)
+            && self.gtin == other.gtin
+            && self.id_number == other.id_number
+            && self.gear_index == other.gear_index
+    }
+
+    fn setting(&self, setting: Setting) -> Option<u16> {
+        match setting {
+            Setting::Groups => self.groups,
+            Setting::Scene(scene) => self.scenes.map(|s| s[scene as usize & 0x0f] as u16),
+            Setting::MinLevel => self.min_level.map(u16::from),
+            Setting::MaxLevel => self.max_level.map(u16::from),
+            Setting::PowerOnLevel => self.power_on_level.map(u16::from),
+            Setting::FailureLevel => self.failure_level.map(u16::from),
+            Setting::Fade => self.fade.map(u16::from),
+            Setting::ExtendedFadeTime => self.extended_fade_time.map(u16::from),
+            Setting::EmergencyLevel => self.dt1.as_ref()?.emergency_level.map(u16::from),
+            Setting::DimmingCurve => self.dt6.as_ref()?.dimming_curve.map(u16::from),
+            Setting::FastFadeTime => self.dt6.as_ref()?.fast_fade_time.map(u16::from),
+            Setting::UpSwitchOnThreshold => {
+                self.dt7.as_ref()?.up_switch_on_threshold.map(u16::from)
+            }
+            Setting::UpSwitchOffThreshold => {
+                self.dt7.as_ref()?.up_switch_off_threshold.map(u16::from)
+            }
+            Setting::DownSwitchOnThreshold => {
+                self.dt7.as_ref()?.down_switch_on_threshold.map(u16::from)
+            }
+            Setting::DownSwitchOffThreshold => {
+                self.dt7.as_ref()?.down_switch_off_threshold.map(u16::from)
+            }
+            Setting::ErrorHoldOffTime => self.dt7.as_ref()?.error_hold_off_time.map(u16::from),
+        }
+    }
+
+    /// Settings that differ from `live`. Settings that weren't read in
+    /// this snapshot are ignored.
+    pub fn changed_settings(&self, live: &GearSnapshot) -> Vec<SettingDiff> {
+        Setting::ALL
+            .iter()
+            .filter_map(|&setting| {
+                let saved = self.setting(setting)?;
+                let live = live.setting(setting);
+                (live != Some(saved)).then_some(SettingDiff {
+                    setting,
+                    saved,
+                    live,
+                })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for GearSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.short_addr())?;
+        if let Some(random) = self.random_address {
+            write!(f, " (0x{random:06x})")?;
+        }
+        if let (Some(gtin), Some(id)) = (self.gtin, self.id_number) {
+            write!(f, " GTIN {gtin} S/N {id}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings that are restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Groups,
+    Scene(u8),
+    MinLevel,
+    MaxLevel,
+    PowerOnLevel,
+    FailureLevel,
+    Fade,
+    ExtendedFadeTime,
+    /// Device type 1
+    EmergencyLevel,
+    /// Device type 6
+    DimmingCurve,
+    /// Device type 6
+    FastFadeTime,
+    /// Device type 7
+    UpSwitchOnThreshold,
+    /// Device type 7
+    UpSwitchOffThreshold,
+    /// Device type 7
+    DownSwitchOnThreshold,
+    /// Device type 7
+    DownSwitchOffThreshold,
+    /// Device type 7
+    ErrorHoldOffTime,
+}
+
+impl Setting {
+    const ALL: [Setting; 31] = [
+        Setting::Groups,
+        Setting::Scene(0),
+        Setting::Scene(1),
+        Setting::Scene(2),
+        Setting::Scene(3),
+        Setting::Scene(4),
+        Setting::Scene(5),
+        Setting::Scene(6),
+        Setting::Scene(7),
+        Setting::Scene(8),
+        Setting::Scene(9),
+        Setting::Scene(10),
+        Setting::Scene(11),
+        Setting::Scene(12),
+        Setting::Scene(13),
+        Setting::Scene(14),
+        Setting::Scene(15),
+        Setting::MinLevel,
+        Setting::MaxLevel,
+        Setting::PowerOnLevel,
+        Setting::FailureLevel,
+        Setting::Fade,
+        Setting::ExtendedFadeTime,
+        Setting::EmergencyLevel,
+        Setting::DimmingCurve,
+        Setting::FastFadeTime,
+        Setting::UpSwitchOnThreshold,
+        Setting::UpSwitchOffThreshold,
+        Setting::DownSwitchOnThreshold,
+        Setting::DownSwitchOffThreshold,
+        Setting::ErrorHoldOffTime,
+    ];
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Setting::Groups => write!(f, "Groups"),
+            Setting::Scene(s) => write!(f, "Scene {s}"),
+            Setting::MinLevel => write!(f, "Min level"),
+            Setting::MaxLevel => write!(f, "Max level"),
+            Setting::PowerOnLevel => write!(f, "Power on level"),
+            Setting::FailureLevel => write!(f, "System failure level"),
+            Setting::Fade => write!(f, "Fade time/rate"),
+            Setting::ExtendedFadeTime => write!(f, "Extended fade time"),
+            Setting::EmergencyLevel => write!(f, "Emergency level"),
+            Setting::DimmingCurve => write!(f, "Dimming curve"),
+            Setting::FastFadeTime => write!(f, "Fast fade time"),
+            Setting::UpSwitchOnThreshold => write!(f, "Up switch-on threshold"),
+            Setting::UpSwitchOffThreshold => write!(f, "Up switch-off threshold"),
+            Setting::DownSwitchOnThreshold => write!(f, "Down switch-on threshold"),
+            Setting::DownSwitchOffThreshold => write!(f, "Down switch-off threshold"),
+            Setting::ErrorHoldOffTime => write!(f, "Error hold-off time"),
+        }
+    }
+}
+
+/// A setting that differs between a snapshot and the bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingDiff {
+    pub setting: Setting,
+    pub saved: u16,
+    /// None if the setting couldn't be read from the bus
+    pub live: Option<u16>,
+}
+
+impl fmt::Display for SettingDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = |f: &mut fmt::Formatter<'_>, v: u16| match self.setting {
+            Setting::Groups => device_info::fmt_groups(f, v),
+            Setting::Fade => write!(f, "{}/{}", v >> 4, v & 0x0f),
+            _ if v == 0xff => write!(f, "-"),
+            _ => write!(f, "{v}"),
+        };
+        write!(f, "{}: ", self.setting)?;
+        value(f, self.saved)?;
+        write!(f, " saved, ")?;
+        match self.live {
+            Some(live) => value(f, live)?,
+            None => write!(f, "unknown")?,
+        }
+        write!(f, " on bus")
+    }
+}
+
+/// Difference between a saved and a live gear
+#[derive(Debug, Clone, PartialEq)]
+pub enum GearDiff {
+    /// In the snapshot but not found on the bus
+    Missing(GearSnapshot),
+    /// On the bus but not in the snapshot
+    Added(GearSnapshot),
+    /// Found on the bus with different settings or short address
+    Changed {
+        saved: Box<GearSnapshot>,
+        live: Box<GearSnapshot>,
+        settings: Vec<SettingDiff>,
+    },
+}
+
+impl fmt::Display for GearDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GearDiff::Missing(saved) => writeln!(f, "{saved}: missing"),
+            GearDiff::Added(live) => writeln!(f, "{live}: not in snapshot"),
+            GearDiff::Changed {
+                saved,
+                live,
+                settings,
+            } => {
+                write!(f, "{saved}:")?;
+                if saved.short_address != live.short_address {
+                    write!(f, " now at {}", live.short_addr())?;
+                }
+                writeln!(f)?;
+                for setting in settings {
+                    writeln!(f, "  {setting}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Configuration of all gear on a bus
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BusSnapshot {
+    /// Free text describing the site or installation
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "String::is_empty")
+    )]
+    pub site: String,
+    /// Wall clock time when the snapshot was taken, in milliseconds since
+    /// the Unix epoch
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub time_ms: Option<u64>,
+    pub gear: Vec<GearSnapshot>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    format: String,
+    version: u32,
+    #[serde(flatten)]
+    snapshot: BusSnapshot,
+}
+
+#[cfg(feature = "serde")]
+impl BusSnapshot {
+    pub fn save<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let file = SnapshotFile {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            snapshot: self.clone(),
+        };
+        serde_json::to_writer_pretty(writer, &file)?;
+        Ok(())
+    }
+
+    pub fn load<R: Read>(reader: R) -> Result<BusSnapshot, Box<dyn Error + Send + Sync>> {
+        let file: SnapshotFile = serde_json::from_reader(reader)?;
+        if file.format != FORMAT_NAME {
+            return Err(format!("Not a snapshot file (format \"{}\")", file.format).into());
+        }
+        if file.version > FORMAT_VERSION {
+            return Err(format!("Unsupported snapshot version {}", file.version).into());
+        }
+        Ok(file.snapshot)
+    }
+}
+
+impl BusSnapshot {
+    /// Find the gear in this snapshot that matches `gear`
+    pub fn find(&self, gear: &GearSnapshot) -> Option<&GearSnapshot> {
+        self.gear
+            .iter()
+            .find(|g| g.same_random_address(gear))
+            .or_else(|| self.gear.iter().find(|g| g.same_identity(gear)))
+    }
+}
+
+async fn query_random_address(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+) -> Result<Option<u32>, DaliSendResult> {
+    match Commands102::new(d).query_random_address(addr).await {
+        Ok(random) => Ok(Some(random)),
+        Err(DaliSendResult::Timeout) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the snapshot of a single gear. Returns None if there's no gear at
+/// the address.
+pub async fn read_gear(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+) -> Result<Option<GearSnapshot>, DaliSendResult> {
+    match Commands102::new(d)
+        .query(cmd::QUERY_CONTROL_GEAR_PRESENT(addr))
+        .await
+    {
+        Ok(_) => {}
+        Err(DaliSendResult::Timeout) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let info = device_info::read_gear_info(d, addr).await?;
+    let mut gear = GearSnapshot::from_info(&info);
+    gear.random_address = query_random_address(d, addr).await?;
+    // Memory bank 0 is optional for older gear
+    if let Ok(bank0) = memory_banks::read_bank_0(d, addr, 0, 0, 0).await {
+        gear.gtin = Some(bank0.gtin);
+        gear.id_number = Some(bank0.id_number);
+        gear.gear_index = Some(bank0.control_gear_index);
+    }
+    Ok(Some(gear))
+}
+
+/// Read the snapshot of all gear on the bus
+pub async fn read_snapshot(
+    d: &mut dyn DaliDriver,
+    site: &str,
+) -> Result<BusSnapshot, DaliSendResult> {
+    let mut snapshot = BusSnapshot {
+        site: site.to_string(),
+        time_ms: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|t| t.as_millis() as u64),
+        gear: Vec::new(),
+    };
+    for a in 0..64 {
+        if let Some(gear) = read_gear(d, Short::new(a)).await? {
+            snapshot.gear.push(gear);
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Compare a saved snapshot to one read from the bus. Gear with the same
+/// settings and short address is not included.
+pub fn diff(saved: &BusSnapshot, live: &BusSnapshot) -> Vec<GearDiff> {
+    let mut diffs = Vec::new();
+    for saved_gear in &saved.gear {
+        match live.find(saved_gear) {
+            Some(live_gear) => {
+                let settings = saved_gear.changed_settings(live_gear);
+                if !settings.is_empty() || saved_gear.short_address != live_gear.short_address {
+                    diffs.push(GearDiff::Changed {
+                        saved: Box::new(saved_gear.clone()),
+                        live: Box::new(live_gear.clone()),
+                        settings,
+                    });
+                }
+            }
+            None => diffs.push(GearDiff::Missing(saved_gear.clone())),
+        }
+    }
+    for live_gear in &live.gear {
+        if saved.find(live_gear).is_none() {
+            diffs.push(GearDiff::Added(live_gear.clone()));
+        }
+    }
+    diffs
+}
+
+/// Write the settings in `settings` from `saved` to the gear at `addr`
+pub async fn restore_settings(
+    d: &mut dyn DaliDriver,
+    addr: Short,
+    saved: &GearSnapshot,
+    settings: &[SettingDiff],
+) -> Result<(), DaliSendResult> {
+    let mut levels_changed = false;
+    for diff in settings {
+        let value = diff.saved;
+        let mut commands = Commands102::new(&mut *d);
+        match diff.setting {
+            Setting::Groups => {
+                let live = diff.live.unwrap_or(!value);
+                for group in 0..16 {
+                    let bit = 1 << group;
+                    if value & bit == live & bit {
+                        continue;
+                    }
+                    if value & bit != 0 {
+                        commands.cmd(cmd::ADD_TO_GROUP(addr, group)).await?;
+                    } else {
+                        commands.cmd(cmd::REMOVE_FROM_GROUP(addr, group)).await?;
+                    }
+                }
+            }
+            Setting::Scene(scene) if value == 0xff => {
+                commands.cmd(cmd::REMOVE_FROM_SCENE(addr, scene)).await?;
+            }
+            Setting::Scene(scene) => {
+                commands.dtr0(value as u8).await?;
+                commands.cmd(cmd::SET_SCENE(addr, scene)).await?;
+            }
+            Setting::MinLevel | Setting::MaxLevel => levels_changed = true,
+            Setting::PowerOnLevel => {
+                commands.dtr0(value as u8).await?;
+                commands.cmd(cmd::SET_POWER_ON_LEVEL(addr)).await?;
+            }
+            Setting::FailureLevel => {
+                commands.dtr0(value as u8).await?;
+                commands.cmd(cmd::SET_SYSTEM_FAILURE_LEVEL(addr)).await?;
+            }
+            Setting::Fade => {
+                commands.dtr0((value >> 4) as u8).await?;
+                commands.cmd(cmd::SET_FADE_TIME(addr)).await?;
+                commands.dtr0((value & 0x0f) as u8).await?;
+                commands.cmd(cmd::SET_FADE_RATE(addr)).await?;
+            }
+            Setting::ExtendedFadeTime => {
+                commands.dtr0(value as u8).await?;
+                commands.cmd(cmd::SET_EXTENDED_FADE_TIME(addr)).await?;
+            }
+            Setting::EmergencyLevel => dt1::set_emergency_level(d, addr, value as u8).await?,
+            Setting::DimmingCurve => {
+                dt6::select_dimming_curve(d, Address::Short(addr), value as u8).await?
+            }
+            Setting::FastFadeTime => {
+                dt6::set_fast_fade_time(d, Address::Short(addr), value as u8).await?
+            }
+            Setting::UpSwitchOnThreshold => {
+                let cmd = dt7::STORE_DTR_AS_UP_SWITCH_ON_THRESHOLD;
+                dt7::store_threshold(d, cmd, Address::Short(addr), value as u8).await?
+            }
+            Setting::UpSwitchOffThreshold => {
+                let cmd = dt7::STORE_DTR_AS_UP_SWITCH_OFF_THRESHOLD;
+                dt7::store_threshold(d, cmd, Address::Short(addr), value as u8).await?
+            }
+            Setting::DownSwitchOnThreshold => {
+                let cmd = dt7::STORE_DTR_AS_DOWN_SWITCH_ON_THRESHOLD;
+                dt7::store_threshold(d, cmd, Address::Short(addr), value as u8).await?
+            }
+            Setting::DownSwitchOffThreshold => {
+                let cmd = dt7::STORE_DTR_AS_DOWN_SWITCH_OFF_THRESHOLD;
+                dt7::store_threshold(d, cmd, Address::Short(addr), value as u8).await?
+            }
+            Setting::ErrorHoldOffTime => {
+                dt7::set_error_hold_off_time(d, Address::Short(addr), value as u8).await?
+            }
+        }
+    }
+    let mut commands = Commands102::new(d);
+    if levels_changed {
+        // The max level can't be set below the min level and vice versa.
+        // Setting max, min and then max again works in all cases.
+        let max = saved.max_level;
+        let min = saved.min_level;
+        for (level, set) in [(max, true), (min, false), (max, true)] {
+            if let Some(level) = level {
+                commands.dtr0(level).await?;
+                if set {
+                    commands.cmd(cmd::SET_MAX_LEVEL(addr)).await?;
+                } else {
+                    commands.cmd(cmd::SET_MIN_LEVEL(addr)).await?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Restore the settings of all gear in `saved` that can be found on the
+/// bus. Returns the differences found before restoring.
+pub async fn restore(
+    d: &mut dyn DaliDriver,
+    saved: &BusSnapshot,
+) -> Result<Vec<GearDiff>, DaliSendResult> {
+    let live = read_snapshot(d, &saved.site).await?;
+    let diffs = diff(saved, &live);
+    for gear_diff in &diffs {
+        if let GearDiff::Changed {
+            saved,
+            live,
+            settings,
+        } = gear_diff
+        {
+            restore_settings(d, live.short_addr(), saved, settings).await?;
+        }
+    }
+    Ok(diffs)
+}